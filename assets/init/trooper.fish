@@ -0,0 +1,11 @@
+# Runs trooper and changes to the directory it was in on quit. Load it with
+#     trooper --init fish | source
+function t --wraps trooper
+    set -l dir_file (mktemp); or return
+    command trooper --choose-dir $dir_file $argv
+    set -l dir (cat -- $dir_file)
+    rm -f -- $dir_file
+    if test -d "$dir"; and test "$dir" != "$PWD"
+        cd -- $dir
+    end
+end
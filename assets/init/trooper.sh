@@ -0,0 +1,13 @@
+# Runs trooper and changes to the directory it was in on quit. Load it with
+#     eval "$(trooper --init bash)"
+# or the same with zsh in place of bash.
+t() {
+    local dir_file dir
+    dir_file="$(mktemp)" || return
+    command trooper --choose-dir "$dir_file" "$@"
+    dir="$(cat -- "$dir_file")"
+    rm -f -- "$dir_file"
+    if [ -d "$dir" ] && [ "$dir" != "$PWD" ]; then
+        cd -- "$dir"
+    fi
+}
@@ -1,25 +1,63 @@
 use core::fmt;
 use std::{
-    collections::HashMap,
-    ffi::{OsStr, OsString},
-    fs::{self, DirEntry, File},
-    io::{self, BufReader},
+    collections::{BTreeMap, BTreeSet, HashMap},
+    ffi::OsStr,
+    fs::{self, File},
+    io::{self, BufReader, Write},
     path::{Path, PathBuf},
+    process::{ExitStatus, Stdio},
     str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::Receiver,
+    },
+    time::{Duration, Instant},
 };
 
 use configparser::ini::Ini;
-use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
-use fs_extra::dir::CopyOptions;
+use crossterm::event::{
+    KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+};
+use ratatui::{backend::Backend, Terminal};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use strum::EnumString;
-use tui::{backend::Backend, Terminal};
+use strum::{EnumIter, EnumString, IntoEnumIterator};
 
-use crate::ui::Ui;
+use crate::{
+    archive,
+    checksum::{self, Algorithm},
+    clipboard,
+    devices::{self, Device},
+    disk::{self, DiskSpace},
+    du::DirSizes,
+    error::{AppError, Context},
+    filetypes::{self, FileTypes},
+    fuzzy,
+    gitignore::{self, GitIgnore},
+    glob,
+    ipc::{self, Server},
+    jobs::{
+        self, free_copy_name, symlink, Job, JobEvent, JobKind, JobState, Preserve, Task, Transfer,
+    },
+    lua::{Plugins, Request},
+    mode::{
+        continuations, ChordMode, CommandLine, FilterLine, HistoryPicker, Mode, Outcome, Palette,
+        PaletteEntry,
+    },
+    paths, permissions, properties,
+    protected::{ProtectedPaths, Protection},
+    sftp::{SftpFs, Target},
+    shell::{self, ShellCommand},
+    snapshots::{self, Snapshot},
+    state::{Session, State},
+    tail::Tail,
+    ui::{AppView, Level, Popup, SelectionStats, Ui},
+    vfs::{self, Entry, LocalFs, Vfs},
+    wake::{self, Waker},
+};
 
-#[derive(Debug, Clone, Copy, EnumString, PartialEq, Eq)]
-enum AppActions {
+#[derive(Debug, Clone, Copy, EnumString, EnumIter, PartialEq, Eq, Deserialize)]
+pub enum AppActions {
     MoveDown,
     MoveUp,
     MoveUpDir,
@@ -27,6 +65,20 @@ enum AppActions {
     Quit,
     MoveToTop,
     MoveToBottom,
+    /// Moves to the next entry starting with the key typed next
+    FindForward,
+    /// Moves to the previous entry starting with the key typed next
+    FindBackward,
+    /// Repeats the last `f` or `F`
+    RepeatFind,
+    /// Repeats the last `f` or `F` in the other direction
+    RepeatFindReversed,
+    /// Enters the home directory
+    GoHome,
+    /// Enters the root of the file system
+    GoToRoot,
+    /// Enters the mount point of the file system holding the current directory
+    GoToMountPoint,
     CopyFiles,
     CutFiles,
     PasteFiles,
@@ -41,9 +93,248 @@ enum AppActions {
     MoveEntry,
     ToggleHiddenFiles,
     CreateDir,
+    ChangeDir,
+    ToggleTail,
+    CalculateSizes,
+    ShowProperties,
+    RunShell,
+    ChangeMode,
+    ChangeOwner,
+    PickSnapshot,
+    RestoreSnapshot,
+    CreateFile,
+    CreateLink,
+    Duplicate,
+    Sha256Sum,
+    Md5Sum,
+    Compress,
+    ConnectSftp,
+    Download,
+    Upload,
+    ToggleDevices,
+    MountDevice,
+    UnmountDevice,
+    ShowMessages,
+    ShowHelp,
+    OpenPalette,
+    ShowHistory,
+    Search,
+    SearchNext,
+    SearchPrevious,
+    Filter,
+    RepeatLastChange,
+    RecordMacro,
+    PlayMacro,
+    SelectRegister,
+    ShowRegisters,
+    YankNames,
+    YankPaths,
+    YankContents,
+    PasteSymlinks,
+    PasteHardlinks,
+    CancelJobs,
+    RetryJob,
+    ToggleJobs,
+    Set,
+    ChooseFiles,
+    ReloadConfig,
+    WriteDefaultConfig,
+    /// Runs the shell command bound to the keys which were pressed
+    ShellBinding,
+    /// Runs a command added by a plugin, named by the first argument
+    PluginCommand,
+    /// Moves the cursor to the path given as argument
+    Reveal,
+    /// Adds the entries matching a pattern to the selection, or reveals a path
+    Select,
+    /// Removes the entries matching a pattern from the selection
+    Unselect,
+    /// Selects the entry under the cursor, or unselects it if it is
+    ToggleSelected,
+    /// Lists every selected entry, to look over or unselect them
+    ReviewSelection,
+    /// Selects every entry in the listing
+    SelectAll,
+    /// Selects the entries in the listing which are not, and the other way around
+    InvertSelection,
+    /// Reads the current directory again
+    Refresh,
+    WidenBookmarks,
+    NarrowBookmarks,
+}
+
+impl AppActions {
+    /// Whether the action does nothing useful without arguments from the
+    /// command line.
+    fn takes_arguments(&self) -> bool {
+        matches!(
+            self,
+            AppActions::MoveEntry
+                | AppActions::CreateDir
+                | AppActions::CreateFile
+                | AppActions::ChangeDir
+                | AppActions::RunShell
+                | AppActions::ChangeMode
+                | AppActions::ChangeOwner
+                | AppActions::Compress
+                | AppActions::ConnectSftp
+                | AppActions::Set
+                | AppActions::Reveal
+                | AppActions::Select
+                | AppActions::Unselect
+        )
+    }
+
+    /// Whether the action only makes sense for local files, which is not the
+    /// case while connected to a remote host.
+    fn needs_local_files(&self) -> bool {
+        matches!(
+            self,
+            AppActions::CopyFiles
+                | AppActions::CutFiles
+                | AppActions::PasteFiles
+                | AppActions::CreateBookmark
+                | AppActions::ChangeDir
+                | AppActions::GoHome
+                | AppActions::GoToRoot
+                | AppActions::GoToMountPoint
+                | AppActions::ToggleTail
+                | AppActions::CalculateSizes
+                | AppActions::ShowProperties
+                | AppActions::RunShell
+                | AppActions::ShellBinding
+                | AppActions::ChangeMode
+                | AppActions::ChangeOwner
+                | AppActions::PickSnapshot
+                | AppActions::RestoreSnapshot
+                | AppActions::CreateLink
+                | AppActions::Duplicate
+                | AppActions::Sha256Sum
+                | AppActions::Md5Sum
+                | AppActions::Compress
+                | AppActions::YankContents
+                | AppActions::PasteSymlinks
+                | AppActions::PasteHardlinks
+                | AppActions::ChooseFiles
+        )
+    }
+
+    /// Whether the action writes to the file system, which is not possible
+    /// inside a snapshot.
+    fn modifies_files(&self) -> bool {
+        matches!(
+            self,
+            AppActions::CutFiles
+                | AppActions::PasteFiles
+                | AppActions::DeleteFile
+                | AppActions::MoveEntry
+                | AppActions::CreateDir
+                | AppActions::CreateFile
+                | AppActions::CreateLink
+                | AppActions::Duplicate
+                | AppActions::Compress
+                | AppActions::ChangeMode
+                | AppActions::ChangeOwner
+                | AppActions::PasteSymlinks
+                | AppActions::PasteHardlinks
+        )
+    }
+
+    /// Whether the action writes anything at all, including downloads and
+    /// restored snapshots outside the current directory. Shell commands,
+    /// plugins and mounting can change anything, so they count as well. None
+    /// of these are allowed in read-only mode.
+    fn writes_files(&self) -> bool {
+        self.modifies_files()
+            || matches!(
+                self,
+                AppActions::Upload
+                    | AppActions::Download
+                    | AppActions::RestoreSnapshot
+                    | AppActions::RetryJob
+                    | AppActions::WriteDefaultConfig
+                    | AppActions::RunShell
+                    | AppActions::ShellBinding
+                    | AppActions::PluginCommand
+                    | AppActions::MountDevice
+                    | AppActions::UnmountDevice
+            )
+    }
+}
+
+/// Files larger than this are not yanked as text.
+const MAX_YANKED_CONTENTS: u64 = 64 * 1024;
+
+/// The text put on the clipboard by the yank variants other than `yy`, one
+/// line per path for names and paths.
+fn text_to_yank(action: AppActions, paths: &[PathBuf]) -> Result<String, AppError> {
+    let mut lines = Vec::new();
+    for p in paths {
+        lines.push(match action {
+            AppActions::YankNames => p
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            AppActions::YankPaths => p.to_string_lossy().into_owned(),
+            _ => {
+                let md = fs::metadata(p).context("yank", p)?;
+                if !md.is_file() || md.len() > MAX_YANKED_CONTENTS {
+                    let e = io::Error::new(io::ErrorKind::Other, "Not a small text file");
+                    return Err(e).context("yank", p);
+                }
+                let contents = fs::read(p).context("yank", p)?;
+                String::from_utf8(contents)
+                    .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Not a text file"))
+                    .context("yank", p)?
+            }
+        });
+    }
+    match action {
+        AppActions::YankContents => Ok(lines.concat()),
+        _ => Ok(lines.join("\n")),
+    }
+}
+
+/// A line of the register viewer, e.g. `cut  notes.txt, todo.md`.
+fn describe_register(paths: &[PathBuf], mode: YankMode) -> String {
+    let names: Vec<String> = paths
+        .iter()
+        .map(|p| match p.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => p.display().to_string(),
+        })
+        .collect();
+    let verb = match mode {
+        YankMode::Copying => "copy",
+        YankMode::Cutting => "cut ",
+    };
+    format!("{}  {}", verb, names.join(", "))
 }
 
-#[derive(PartialEq, Clone, Copy)]
+/// Finished jobs kept in the jobs panel.
+const FINISHED_JOBS: usize = 20;
+
+/// Counts are capped so that a mistyped one cannot hang the interface.
+const MAX_COUNT: usize = 10000;
+
+/// Ticks slow down after this long without input.
+const IDLE_AFTER: Duration = Duration::from_secs(10);
+
+/// Idle ticks don't slow down past this, unless the tick rate is even slower.
+const MAX_IDLE_TICK: Duration = Duration::from_secs(2);
+
+/// Two clicks on the same row this close together are a double click.
+const DOUBLE_CLICK: Duration = Duration::from_millis(400);
+
+/// Rows moved by one step of the mouse wheel.
+const WHEEL_STEP: i32 = 3;
+
+/// Numbers the sandboxes of deterministic apps, so that apps in the same
+/// process, such as tests running side by side, don't share one.
+static SANDBOXES: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, PartialEq, Clone, Copy)]
 enum YankMode {
     Copying,
     Cutting,
@@ -55,10 +346,176 @@ pub struct Bookmark {
     pub path: Box<PathBuf>,
 }
 
-#[derive(PartialEq, Clone, Copy)]
+/// Options read from the `[settings]` section of the config file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    pub icons: bool,
+    pub classify: bool,
+    /// Key sequences which leave visual and command mode, such as `jk`.
+    pub escape: Vec<Vec<KeyEvent>>,
+    /// Milliseconds to wait for the rest of an escape sequence.
+    pub escape_timeout: u64,
+    /// Milliseconds between ticks while input is arriving.
+    pub tick_rate: u64,
+    pub status_format: String,
+    /// Moving past either end of a list continues from the other end.
+    pub wrap_around: bool,
+    /// Rows of the file list kept visible above and below the cursor.
+    pub scrolloff: u16,
+    /// Entries of the file list are numbered.
+    pub number: bool,
+    /// By their distance from the cursor, which shows its own number when
+    /// `number` is set as well.
+    pub relativenumber: bool,
+    /// Searching and filtering ignore case.
+    pub ignorecase: bool,
+    /// Except for patterns with capitals in them, when `ignorecase` is set.
+    pub smartcase: bool,
+    /// Metadata kept on pasted files.
+    pub preserve: Preserve,
+    /// Starting where the last session left off.
+    pub restore: bool,
+    /// Glob patterns of names hidden like dotfiles, such as `*.pyc`.
+    pub hide: Vec<String>,
+    /// Whether entries ignored by git are dimmed or hidden.
+    pub gitignore: GitIgnore,
+    /// Styles of file names, from the `[filetypes]` section.
+    pub filetypes: FileTypes,
+    /// Paths guarded against deletion, from the `[protected]` section.
+    pub protected: ProtectedPaths,
+    /// From the `[hooks]` section.
+    pub hooks: Hooks,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            icons: false,
+            classify: true,
+            escape: Vec::new(),
+            escape_timeout: 300,
+            tick_rate: 100,
+            status_format: String::from(
+                "{mode} {recording} {message}%={snapshot} {readonly} {chord} {selection} {progress} {free} {position}",
+            ),
+            wrap_around: false,
+            scrolloff: 0,
+            number: false,
+            relativenumber: false,
+            ignorecase: true,
+            smartcase: true,
+            preserve: Preserve::default(),
+            restore: false,
+            hide: Vec::new(),
+            gitignore: GitIgnore::default(),
+            filetypes: FileTypes::default(),
+            protected: ProtectedPaths::default(),
+            hooks: Hooks::default(),
+        }
+    }
+}
+
+impl Settings {
+    /// Sets `key` from its value in the config. A value which can't be
+    /// parsed leaves the setting as it was.
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let flag = || {
+            value
+                .parse()
+                .map_err(|_| format!("Expected true or false, not {}", value))
+        };
+        match key {
+            "icons" => self.icons = flag()?,
+            "classify" => self.classify = flag()?,
+            "escape" => {
+                self.escape = value
+                    .split_whitespace()
+                    .map(parse_key_events)
+                    .collect::<Result<_, _>>()?
+            }
+            "escape_timeout" => {
+                self.escape_timeout = value
+                    .parse()
+                    .map_err(|_| format!("Not a number of milliseconds: {}", value))?
+            }
+            "tick_rate" => {
+                self.tick_rate =
+                    value.parse().ok().filter(|ms| *ms > 0).ok_or_else(|| {
+                        format!("Not a positive number of milliseconds: {}", value)
+                    })?
+            }
+            "status_format" => self.status_format = String::from(value),
+            "wrap_around" => self.wrap_around = flag()?,
+            "scrolloff" => {
+                self.scrolloff = value
+                    .parse()
+                    .map_err(|_| format!("Not a number of rows: {}", value))?
+            }
+            "number" => self.number = flag()?,
+            "relativenumber" => self.relativenumber = flag()?,
+            "ignorecase" => self.ignorecase = flag()?,
+            "smartcase" => self.smartcase = flag()?,
+            "preserve" => self.preserve = Preserve::parse(value)?,
+            "restore" => self.restore = flag()?,
+            "hide" => self.hide = value.split_whitespace().map(String::from).collect(),
+            "gitignore" => self.gitignore = GitIgnore::parse(value)?,
+            _ => return Err(String::from("Unknown setting")),
+        }
+        Ok(())
+    }
+
+    /// Whether searching for `pattern` ignores case.
+    pub fn ignores_case(&self, pattern: &str) -> bool {
+        self.ignorecase && !(self.smartcase && pattern.chars().any(char::is_uppercase))
+    }
+}
+
+/// What to do when something happens. Each hook is an action or, like a
+/// binding, a shell command starting with `!` or `&`. Hooks wait for `!`
+/// commands to finish, with their output thrown away.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Hooks {
+    /// Entering a directory, including the one trooper starts in
+    pub on_dir_change: Option<String>,
+    /// The cursor moving to another entry
+    pub on_select: Option<String>,
+    pub on_quit: Option<String>,
+}
+
+impl Hooks {
+    /// Sets the hook for `event`, or removes it when `value` is empty.
+    fn set(&mut self, event: &str, value: &str) -> Result<(), String> {
+        let hook = match event {
+            "on_dir_change" => &mut self.on_dir_change,
+            "on_select" => &mut self.on_select,
+            "on_quit" => &mut self.on_quit,
+            _ => return Err(String::from("Unknown event")),
+        };
+        if value.trim().is_empty() {
+            *hook = None;
+            return Ok(());
+        }
+        if value.starts_with(['!', '&']) {
+            if value[1..].trim().is_empty() {
+                return Err(String::from("Missing command"));
+            }
+        } else if AppActions::from_str(value).is_err() {
+            return Err(format!("Unknown action {}", value));
+        }
+        *hook = Some(String::from(value));
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum ActivePanel {
     Main,
     Bookmarks,
+    Devices,
+    Jobs,
+    /// Every selected entry, wherever it is
+    Selection,
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -67,6 +524,10 @@ pub enum ActiveMode {
     Normal,
     Command,
     Visual,
+    Palette,
+    History,
+    Search,
+    Filter,
 }
 
 impl fmt::Display for ActiveMode {
@@ -76,13 +537,40 @@ impl fmt::Display for ActiveMode {
     }
 }
 
+/// Options given on the command line.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /* Makes runs reproducible for end-to-end tests: persistence goes to a
+     * throwaway directory, the user config is ignored, ticks follow input
+     * instead of the clock and background work is waited for.
+     */
+    pub deterministic: bool,
+    /// Start with `dryrun` set
+    pub dry_run: bool,
+    /// Start with `readonly` set
+    pub read_only: bool,
+    /// Enter chooses the selected entries and quits, for `--choose-files`
+    pub picking: bool,
+    /// Commands come from a script, for `--batch`
+    pub batch: bool,
+    /// Read in place of `config.ini`, even in deterministic mode
+    pub config: Option<PathBuf>,
+    /// Milliseconds between ticks, in place of `tick_rate` from the config
+    pub tick_rate: Option<u64>,
+    /// Whether to resume the last session, in place of `restore` from the
+    /// config
+    pub restore: Option<bool>,
+}
+
 pub struct App {
     pub title: String,
 
     pub should_quit: bool,
+    /// The entries chosen with `--choose-files`
+    pub chosen: Vec<PathBuf>,
     pub current_dir: Box<PathBuf>,
 
-    pub dir_contents: Vec<DirEntry>,
+    pub dir_contents: Vec<Entry>,
 
     pub bookmarks: Vec<Bookmark>,
 
@@ -90,856 +578,5436 @@ pub struct App {
 
     // Vim Controls
     last_key: KeyEvent,
-    key_chord: Vec<KeyEvent>,
-    normal_bindings: HashMap<Vec<KeyEvent>, AppActions>,
-    visual_bindings: HashMap<Vec<KeyEvent>, AppActions>,
+    /// Typed ahead of a chord, as in `5j`
+    count: Option<usize>,
+    /// The last action which changed files, repeated by `.`
+    last_change: Option<(AppActions, Vec<String>)>,
+    /// Changes to the file system are described instead of made
+    dry_run: bool,
+    /// Every action which writes to the file system is refused
+    read_only: bool,
+    /// A change to protected paths which has to be repeated to go through
+    unconfirmed: Option<(AppActions, Vec<PathBuf>)>,
+    /// `q` or `@` waiting for the register to use, or `f` for the letter to
+    /// find, and the count given
+    awaiting_register: Option<(AppActions, usize)>,
+    /// The letter found last and whether that was forwards, for `;` and `,`
+    last_find: Option<(char, bool)>,
+    macros: HashMap<char, Vec<KeyEvent>>,
+    recording: Option<(char, Vec<KeyEvent>)>,
+    last_macro: Option<char>,
+    replaying: bool,
+    normal: ChordMode,
+    visual: ChordMode,
     commands: HashMap<String, AppActions>,
+    /// Where the config was read from, for `:config_reload`
+    config_path: PathBuf,
     active_panel: ActivePanel,
     active_mode: ActiveMode,
     // ---
     yank_reg: Box<PathBuf>,
     yank_mode: Option<YankMode>,
+    /// Named registers, which unlike the unnamed one are not shared with
+    /// other instances
+    registers: HashMap<char, (Vec<PathBuf>, YankMode)>,
+    /// Chosen with `"` for the next yank or paste
+    register: Option<char>,
 
     bookmark_store: Box<PathBuf>,
+    state_store: PathBuf,
+    state: State,
+    /// Set while the divider next to the bookmarks is dragged
+    resizing_bookmarks: bool,
 
-    command_buffer: String,
-    command_buffer_tmp: String,
-    command_history: Vec<String>,
-    command_history_index: i32,
-    command_completion_index: i32,
-    command_matches: Vec<String>,
+    command_line: CommandLine,
+    palette: Palette,
+    history_picker: HistoryPicker,
+    /// The `/` line, which keeps a history apart from the command line
+    search_line: CommandLine,
+    /// What was searched for last, for `n` and `N`
+    search: Option<String>,
+    filter_line: FilterLine,
+    /// Only entries matching this are listed, until the directory changes
+    filter: Option<String>,
+    /// The listing from before filtering, while the filter is typed
+    unfiltered: Vec<Entry>,
 
     show_hidden_files: bool,
 
     selection_start: i32,
+    /// Entries selected by `:select`, which operations act on instead of the
+    /// cursor while there are any
+    marked: BTreeSet<PathBuf>,
+
+    settings: Settings,
+
+    tail: Option<Tail>,
+
+    escape_pending: Vec<KeyEvent>,
+    escape_pending_since: Instant,
+    last_input: Instant,
+    // When and where the left button was last pressed, to tell double clicks
+    last_click: Option<(Instant, ActivePanel, i32)>,
+
+    disk_space: Option<DiskSpace>,
+
+    popup: Option<Popup>,
+    // Pending hashes for the rows of a checksum popup, and what they should match
+    checksums: Option<(
+        Receiver<(usize, Result<String, String>)>,
+        Vec<Option<String>>,
+    )>,
+
+    shell_command: Option<ShellCommand>,
+    plugins: Option<Plugins>,
+    /// Receives commands from other programs
+    server: Option<Server>,
+    /// The directory and entry under the cursor when the hooks last ran
+    hooked_dir: PathBuf,
+    hooked_entry: Option<PathBuf>,
+
+    options: Options,
+    sandbox: Option<PathBuf>,
+
+    // Snapshots offered by :snapshots, the picker is open while non-empty
+    snapshots: Vec<Snapshot>,
+    snapshot_index: usize,
+    /// The snapshot being browsed
+    snapshot: Option<Snapshot>,
+
+    devices: Vec<Device>,
+
+    vfs: Box<dyn Vfs>,
+    // The host `vfs` is connected to, and the local directory to go back to
+    remote: Option<Target>,
+    local_dir: PathBuf,
+
+    jobs: Vec<Job>,
+    dir_sizes: DirSizes,
+    next_job_id: usize,
+    job_sender: wake::Sender<JobEvent>,
+    job_receiver: Receiver<JobEvent>,
+    waker: Waker,
+    /// Set when something shown has changed since the last draw
+    dirty: bool,
 }
 
 impl App {
-    pub fn new(title: String, current_dir: &Path) -> App {
-        let config_path = home::home_dir().unwrap().join(".config/trooper/config.ini");
-        let (normal_bindings, visual_bindings) = read_config(&config_path).unwrap();
-
-        let mut commands = HashMap::new();
-        commands.insert(String::from("delete"), AppActions::DeleteFile);
-        commands.insert(String::from("up"), AppActions::MoveUp);
-        commands.insert(String::from("bookmark"), AppActions::CreateBookmark);
-        commands.insert(String::from("del_bookmark"), AppActions::DeleteBookmark);
-        commands.insert(String::from("bm"), AppActions::CreateBookmark);
-        commands.insert(String::from("dbm"), AppActions::DeleteBookmark);
-        commands.insert(String::from("mv"), AppActions::MoveEntry);
-        commands.insert(String::from("mkdir"), AppActions::CreateDir);
+    pub fn new(title: String, current_dir: &Path, options: Options) -> App {
+        // Without its sandbox a deterministic app still keeps away from the
+        // user's files, it just can't keep anything itself
+        let mut problems = Vec::new();
+        let sandbox = if options.deterministic {
+            let dir = std::env::temp_dir().join(format!(
+                "trooper-deterministic-{}-{}",
+                std::process::id(),
+                SANDBOXES.fetch_add(1, Ordering::Relaxed)
+            ));
+            if let Err(e) = fs::create_dir_all(&dir) {
+                problems.push((
+                    dir.display().to_string(),
+                    format!("Could not create the sandbox: {}", e),
+                ));
+            }
+            Some(dir)
+        } else {
+            None
+        };
+
+        let config_path = match (&options.config, &sandbox) {
+            (Some(config), _) => config.clone(),
+            (None, Some(dir)) => dir.join("config.ini"),
+            (None, None) => paths::config_file(),
+        };
+        // Trooper still starts, with the default config, to say what is wrong
+        let mut config = read_config(&config_path).unwrap_or_else(|e| {
+            let mut config = default_config();
+            config.problems.push((
+                config_path.display().to_string(),
+                format!("Could not read the config: {}", e),
+            ));
+            config
+        });
+        config.problems.extend(problems);
+        let (plugins, problems) = load_plugins(&config_path);
+        config.problems.extend(problems);
+        config.settings.tick_rate = options.tick_rate.unwrap_or(config.settings.tick_rate);
+
+        let mut commands = default_commands();
+        commands.extend(config.commands);
+        commands.extend(plugin_commands(&plugins));
+
+        let waker = Waker::default();
+        let (job_sender, job_receiver) = wake::channel(&waker);
 
         App {
             title,
             should_quit: false,
+            chosen: Vec::new(),
             current_dir: Box::<PathBuf>::new(current_dir.to_path_buf().clone()),
             dir_contents: Vec::new(),
+            devices: Vec::new(),
+            vfs: Box::new(LocalFs),
+            remote: None,
+            local_dir: PathBuf::new(),
             bookmarks: vec![],
-            ui: Ui::new(current_dir.to_str().unwrap()),
+            ui: Ui::new(current_dir),
             last_key: KeyEvent::new(KeyCode::Null, KeyModifiers::empty()),
-            key_chord: Vec::new(),
-            normal_bindings,
-            visual_bindings,
+            count: None,
+            last_change: None,
+            dry_run: options.dry_run,
+            read_only: options.read_only,
+            unconfirmed: None,
+            awaiting_register: None,
+            last_find: None,
+            macros: HashMap::new(),
+            recording: None,
+            last_macro: None,
+            replaying: false,
+            normal: ChordMode::new(config.normal, config.normal_shell),
+            visual: ChordMode::new(config.visual, config.visual_shell),
             commands,
+            config_path,
             active_panel: ActivePanel::Main,
             active_mode: ActiveMode::Normal,
-            yank_reg: Box::<PathBuf>::new("/tmp/rust_fm_yank.txt".into()),
+            yank_reg: Box::<PathBuf>::new(match &sandbox {
+                Some(dir) => dir.join("yank.txt"),
+                None => paths::yank_register(),
+            }),
             yank_mode: None,
-            bookmark_store: Box::<PathBuf>::new(
-                dirs::home_dir()
-                    .unwrap_or(Path::new("/tmp/").to_path_buf())
-                    .join(".trooper/bookmarks.txt"),
-            ),
-            command_buffer: String::from(""),
-            command_buffer_tmp: String::from(""),
-            command_history: Vec::new(),
-            command_history_index: -1,
-            command_completion_index: -1,
-            command_matches: Vec::new(),
+            registers: HashMap::new(),
+            register: None,
+            bookmark_store: Box::<PathBuf>::new(match &sandbox {
+                Some(dir) => dir.join("bookmarks.txt"),
+                None => paths::bookmark_file(),
+            }),
+            state_store: match &sandbox {
+                Some(dir) => dir.join("state.json"),
+                None => paths::state_file(),
+            },
+            state: State::default(),
+            resizing_bookmarks: false,
+            command_line: CommandLine::default(),
+            palette: Palette::default(),
+            history_picker: HistoryPicker::default(),
+            search_line: CommandLine::new('/'),
+            search: None,
+            filter_line: FilterLine::default(),
+            filter: None,
+            unfiltered: Vec::new(),
             show_hidden_files: false,
             selection_start: -1,
+            marked: BTreeSet::new(),
+            settings: config.settings,
+            tail: None,
+            escape_pending: Vec::new(),
+            escape_pending_since: Instant::now(),
+            last_input: Instant::now(),
+            last_click: None,
+            disk_space: None,
+            popup: problems_popup(config.problems),
+            checksums: None,
+            shell_command: None,
+            plugins,
+            server: None,
+            hooked_dir: PathBuf::new(),
+            hooked_entry: None,
+            options,
+            sandbox,
+            snapshots: Vec::new(),
+            snapshot_index: 0,
+            snapshot: None,
+            jobs: Vec::new(),
+            dir_sizes: DirSizes::new(&waker),
+            next_job_id: 0,
+            job_sender,
+            job_receiver,
+            waker,
+            dirty: true,
         }
     }
 
     pub fn init(&mut self) {
         self.enter_dir(&self.current_dir.to_owned());
-        fs::create_dir_all(self.bookmark_store.parent().unwrap()).unwrap();
+        self.refresh_devices();
+        if let Err(e) = self.load_bookmarks() {
+            self.report(e);
+        }
+        self.state = State::load(&self.state_store);
+
+        self.update_bookmark_width();
+        if self.options.restore.unwrap_or(self.settings.restore) {
+            if let Some(session) = self.state.session.clone() {
+                self.restore_session(&session);
+            }
+        }
+    }
+
+    /// Goes back to where `session` left off, as far as that still exists.
+    fn restore_session(&mut self, session: &Session) {
+        if !session.dir.is_dir() {
+            return;
+        }
+        // Scrolling either panel to a row starts the file list from the top
+        self.ui.scroll_abs(
+            session.bookmark as i32,
+            self.bookmarks.len() as i32,
+            &ActivePanel::Bookmarks,
+        );
+        self.show_hidden_files = session.hidden;
+        self.enter_dir(&session.dir);
+        if let Some(index) = session
+            .entry
+            .as_ref()
+            .and_then(|e| self.find_name(e.as_ref()))
+        {
+            self.ui
+                .scroll_abs(index, self.dir_contents.len() as i32, &ActivePanel::Main);
+        }
+        // The selection and jobs are gone along with the last session
+        self.active_panel = match session.panel {
+            ActivePanel::Bookmarks | ActivePanel::Devices => session.panel,
+            _ => ActivePanel::Main,
+        };
+        if self.active_panel == ActivePanel::Devices && self.devices.is_empty() {
+            self.active_panel = ActivePanel::Main;
+        }
+    }
+
+    /// Where this session is, to be restored by the next one. Remote hosts
+    /// are left for the directory `:sftp` was run from, and the state file
+    /// can only hold paths which are valid UTF-8.
+    fn session(&self) -> Option<Session> {
+        let (dir, entry) = match self.remote {
+            Some(_) => (self.local_dir.clone(), None),
+            None => {
+                let cursor = (self.ui.scroll_y + self.ui.cursor_y) as usize;
+                let entry = self.dir_contents.get(cursor).map(Entry::file_name);
+                (self.current_dir.to_path_buf(), entry)
+            }
+        };
+        dir.to_str()?;
+        Some(Session {
+            dir,
+            entry: entry.and_then(|e| e.into_string().ok()),
+            hidden: self.show_hidden_files,
+            panel: self.active_panel,
+            bookmark: (self.ui.bookmark_scroll_y + self.ui.bookmark_y) as usize,
+        })
+    }
 
-        if !Path::new(self.bookmark_store.as_path()).exists() {
-            fs::write(self.bookmark_store.as_path(), "[]").unwrap();
+    fn load_bookmarks(&mut self) -> Result<(), AppError> {
+        let store = self.bookmark_store.to_path_buf();
+        if let Some(dir) = store.parent() {
+            fs::create_dir_all(dir).context("create", dir)?;
+        }
+        if !store.exists() {
+            fs::write(&store, "[]").context("create", &store)?;
         }
 
-        let f = File::open(self.bookmark_store.as_path()).unwrap();
+        let f = File::open(&store).context("read", &store)?;
         let bookmark_file = BufReader::new(f);
         self.bookmarks = serde_json::from_reader(bookmark_file).unwrap_or(vec![]);
+        Ok(())
+    }
 
-        self.update_bookmark_width();
+    /// Scrolls the open popup for movement actions, returning whether the
+    /// action was used up.
+    fn scroll_popup(&mut self, action: AppActions) -> bool {
+        let popup = match &mut self.popup {
+            Some(popup) => popup,
+            None => return false,
+        };
+        let max = popup.rows.len().saturating_sub(self.ui.popup_rows()) as u16;
+        popup.scroll = match action {
+            AppActions::MoveDown => std::cmp::min(popup.scroll + 1, max),
+            AppActions::MoveUp => popup.scroll.saturating_sub(1),
+            AppActions::MoveToTop => 0,
+            AppActions::MoveToBottom => max,
+            _ => return false,
+        };
+        true
+    }
+
+    /// Shows `error` in the status line.
+    fn report(&mut self, error: AppError) {
+        log::warn!("{}", error);
+        self.ui.error(error.to_string());
     }
 
     pub fn tear_down(&mut self) {
-        fs::write(
+        let on_quit = self.settings.hooks.on_quit.clone();
+        self.run_hook(on_quit);
+
+        // There is nowhere left to show errors, so they end up in the log
+        if let Err(e) = fs::write(
             self.bookmark_store.as_path(),
             serde_json::to_string(&self.bookmarks).unwrap(),
         )
-        .unwrap();
+        .context("save bookmarks to", &self.bookmark_store)
+        {
+            log::error!("{}", e);
+        }
+        // Picking files or running a script is not where the next session
+        // should pick up, so the last interactive one is kept
+        if !self.options.picking && !self.options.batch {
+            self.state.session = self.session();
+        }
+        if let Err(e) = self.state.save(&self.state_store) {
+            log::error!("{}", e);
+        }
+
+        if let Some(dir) = &self.sandbox {
+            let _ = fs::remove_dir_all(dir);
+        }
     }
 
-    pub fn on_key(&mut self, key: KeyEvent) {
-        self.last_key = key;
+    /// Wakes the event loop whenever background work has something to show.
+    pub fn waker(&self) -> Waker {
+        self.waker.clone()
+    }
 
-        self.key_chord.push(key);
-        let mut matched = true;
+    pub fn is_deterministic(&self) -> bool {
+        self.options.deterministic
+    }
 
-        match self.active_mode {
-            ActiveMode::Normal => {
-                // Figure out some way to do this shit with borrowing
-                let maybe_action = self.get_binding();
-                match maybe_action {
-                    Some(action) => {
-                        self.handle_action(action, vec![]);
-                    }
-                    None => matched = false,
-                }
-            }
-            ActiveMode::Command => match key.code {
-                KeyCode::Char(c) => {
-                    self.command_buffer.push(c);
-                    self.command_matches.clear();
-                    self.command_buffer_tmp.clear();
-                    self.command_completion_index = -1;
-                }
-                _ => {}
-            },
-            ActiveMode::Visual => {
-                let maybe_action = self.get_binding();
-                match maybe_action {
-                    Some(action) => {
-                        self.handle_action(action, vec![]);
-                    }
-                    None => matched = false,
-                }
+    /* Every key press goes through here first so that macros see all of them.
+     * Esc, Enter and the other keys which don't type a character only fall
+     * back to what they do in every mode when they aren't bound. Modes which
+     * read text get all keys.
+     */
+    /// Handles a key pressed, or released, in the terminal.
+    pub fn handle_event(&mut self, key: KeyEvent) {
+        // Only some terminals report releases, so they are ignored unless bound
+        if key.kind == KeyEventKind::Release && !self.is_bound(key) {
+            return;
+        }
+        self.dirty = true;
+        self.last_input = Instant::now();
+        if !self.replaying {
+            if let Some((_, keys)) = &mut self.recording {
+                keys.push(key);
             }
         }
+        if self.is_bound(key) || self.mode().bindings().is_none() {
+            return self.on_key(key);
+        }
+        match key.code {
+            KeyCode::Char(_) => self.on_key(key),
+            KeyCode::Esc => self.on_esc(),
+            KeyCode::Enter => self.on_enter(),
+            _ => {}
+        }
+    }
 
-        // TODO: How does this work when in visual mode
-        if matched {
-            self.key_chord.clear();
-        } else {
-            let mut starting = false;
-            let chord_len = self.key_chord.len();
-
-            let bindings = match self.active_mode {
-                ActiveMode::Normal => &self.normal_bindings,
-                ActiveMode::Command => {
-                    panic!("It is impossible to not match a key chord in command mode.")
-                }
-                ActiveMode::Visual => &self.visual_bindings,
-            };
-
-            for chord in bindings.keys() {
-                if chord.len() >= chord_len {
-                    if chord[0..chord_len] == self.key_chord[..] {
-                        starting = true;
-                    }
-                }
+    /// Handles the mouse. Clicking a row moves the cursor there and double
+    /// clicking enters it, while the wheel scrolls the active panel. The
+    /// divider next to the bookmarks can be dragged to resize them.
+    pub fn on_mouse(&mut self, mouse: MouseEvent) {
+        // Modes reading text and the pickers only take keys
+        if self.mode().bindings().is_none() || !self.snapshots.is_empty() {
+            return;
+        }
+        match mouse.kind {
+            MouseEventKind::Down(MouseButton::Left)
+                if self.ui.on_divider(mouse.column, mouse.row) =>
+            {
+                self.resizing_bookmarks = true
+            }
+            MouseEventKind::Down(MouseButton::Left) => self.on_click(mouse.column, mouse.row),
+            MouseEventKind::Drag(MouseButton::Left) if self.resizing_bookmarks => {
+                let width = mouse.column.saturating_sub(self.ui.bookmark_area.x);
+                self.resize_bookmarks(width);
             }
+            MouseEventKind::Up(MouseButton::Left) if self.resizing_bookmarks => {
+                self.resizing_bookmarks = false;
+                return;
+            }
+            MouseEventKind::ScrollDown => self.on_wheel(WHEEL_STEP),
+            MouseEventKind::ScrollUp => self.on_wheel(-WHEEL_STEP),
+            _ => return,
+        }
+        self.dirty = true;
+        self.last_input = Instant::now();
+    }
+
+    fn on_click(&mut self, column: u16, row: u16) {
+        // The jobs panel and popups are drawn over the others
+        if self.popup.is_some()
+            || matches!(
+                self.active_panel,
+                ActivePanel::Jobs | ActivePanel::Selection
+            )
+        {
+            return;
+        }
+        let (panel, row) = match self.ui.panel_at(column, row) {
+            Some(hit) => hit,
+            None => return,
+        };
+        self.flush_escape_pending();
+        self.mode_mut().clear_chord();
+        let (index, len) = match panel {
+            ActivePanel::Main => (self.ui.scroll_y + row, self.dir_contents.len()),
+            ActivePanel::Bookmarks => (self.ui.bookmark_scroll_y + row, self.bookmarks.len()),
+            ActivePanel::Devices => (row, self.devices.len()),
+            ActivePanel::Jobs | ActivePanel::Selection => return,
+        };
+        // Clicking below the last row only switches to the panel
+        self.active_panel = panel;
+        if index as usize >= len {
+            self.last_click = None;
+            return;
+        }
+        match panel {
+            ActivePanel::Main => self.ui.cursor_y = row,
+            ActivePanel::Bookmarks => self.ui.bookmark_y = row,
+            _ => self.ui.device_y = row,
+        }
 
-            if !starting {
-                self.key_chord.clear();
+        let now = Instant::now();
+        let double = matches!(
+            self.last_click,
+            Some((at, p, i)) if p == panel && i == index && now - at < DOUBLE_CLICK
+        );
+        if double {
+            self.last_click = None;
+            let is_file = panel == ActivePanel::Main && !self.dir_contents[index as usize].is_dir;
+            if is_file {
+                self.on_enter();
+            } else {
+                self.handle_action(AppActions::EnterDir, Vec::new());
             }
+        } else {
+            self.last_click = Some((now, panel, index));
         }
     }
 
-    fn get_binding(&mut self) -> Option<AppActions> {
-        return match self.active_mode {
-            ActiveMode::Normal => self.normal_bindings.get(&self.key_chord).copied(),
-            ActiveMode::Command => None,
-            ActiveMode::Visual => self.visual_bindings.get(&self.key_chord).copied(),
+    fn on_wheel(&mut self, rows: i32) {
+        let action = if rows > 0 {
+            AppActions::MoveDown
+        } else {
+            AppActions::MoveUp
+        };
+        if self.scroll_popup(action) {
+            return;
+        }
+        let len = match self.active_panel {
+            ActivePanel::Main => self.dir_contents.len(),
+            ActivePanel::Bookmarks => self.bookmarks.len(),
+            ActivePanel::Devices => self.devices.len(),
+            ActivePanel::Jobs => self.jobs.len(),
+            ActivePanel::Selection => self.marked.len(),
         };
+        self.ui.scroll(rows, len as i32, &self.active_panel);
     }
 
-    pub(crate) fn on_tick(&self) {
-        return;
+    /// Whether `key` continues the chord typed so far into a binding of the
+    /// current mode.
+    fn is_bound(&self, key: KeyEvent) -> bool {
+        let mode = self.mode();
+        let bindings = match mode.bindings() {
+            Some(bindings) => bindings,
+            None => return false,
+        };
+        let mut chord = mode.chord().to_vec();
+        chord.push(normalize_key(key));
+        bindings.contains_key(&chord) || !continuations(bindings, &chord).is_empty()
     }
 
-    pub(crate) fn enter_dir(&mut self, dir: &Path) {
-        self.current_dir = Box::new(dir.to_path_buf());
-        self.dir_contents = self.read_dir_sorted(dir);
+    /// The active mode.
+    fn mode(&self) -> &dyn Mode {
+        match self.active_mode {
+            ActiveMode::Normal => &self.normal,
+            ActiveMode::Visual => &self.visual,
+            ActiveMode::Command => &self.command_line,
+            ActiveMode::Palette => &self.palette,
+            ActiveMode::History => &self.history_picker,
+            ActiveMode::Search => &self.search_line,
+            ActiveMode::Filter => &self.filter_line,
+        }
     }
 
-    pub(crate) fn move_up_dir(&mut self) {
-        let parent = self.current_dir.parent().unwrap().to_path_buf();
-        self.dir_contents = self.read_dir_sorted(&parent);
-        self.current_dir = Box::new(parent);
+    fn mode_mut(&mut self) -> &mut dyn Mode {
+        self.mode_of(self.active_mode)
     }
 
-    pub(crate) fn draw<B: Backend>(&mut self, term: &mut Terminal<B>) -> io::Result<()> {
-        if self.active_mode == ActiveMode::Normal {
-            self.selection_start = self.ui.scroll_y + self.ui.cursor_y;
+    fn mode_of(&mut self, mode: ActiveMode) -> &mut dyn Mode {
+        match mode {
+            ActiveMode::Normal => &mut self.normal,
+            ActiveMode::Visual => &mut self.visual,
+            ActiveMode::Command => &mut self.command_line,
+            ActiveMode::Palette => &mut self.palette,
+            ActiveMode::History => &mut self.history_picker,
+            ActiveMode::Search => &mut self.search_line,
+            ActiveMode::Filter => &mut self.filter_line,
         }
-        let disp_chord = key_events_to_string(&self.key_chord);
-        self.ui.draw_app(
-            term,
-            self.current_dir.to_str().unwrap(),
-            &self.bookmarks,
-            &self.dir_contents,
-            self.active_mode == ActiveMode::Command,
-            &self.command_buffer,
-            &self.command_matches,
-            self.command_completion_index,
-            &self.active_panel,
-            &self.active_mode,
-            self.selection_start,
-            &disp_chord,
-        )
     }
 
-    fn find_name(&self, name: String) -> Option<i32> {
-        for (j, d) in self.dir_contents.iter().enumerate() {
-            if d.file_name().into_string().unwrap() == name {
-                return Some(i32::try_from(j).unwrap());
+    /// Switches to `mode`, letting the mode which is left and the one which
+    /// is entered know.
+    fn enter_mode(&mut self, mode: ActiveMode) {
+        self.mode_mut().on_exit();
+        self.active_mode = mode;
+        match mode {
+            ActiveMode::Command => {
+                self.command_line.commands = self.commands.keys().cloned().collect()
+            }
+            ActiveMode::Palette => self.palette.entries = self.palette_entries(),
+            ActiveMode::History => self.history_picker.lines = self.history_lines(),
+            ActiveMode::Search => {}
+            ActiveMode::Filter => {
+                // The filter starts over from the whole listing
+                if self.filter.take().is_some() {
+                    self.update_dir_contents();
+                }
+                self.unfiltered = self.dir_contents.clone();
             }
+            ActiveMode::Normal | ActiveMode::Visual => {}
         }
-
-        return None;
+        self.mode_mut().on_enter();
     }
 
-    fn copy_files(&mut self, paths: Vec<PathBuf>) {
-        let mut output = String::new();
-        for p in paths {
-            output.push_str(p.as_path().to_str().unwrap());
-            output.push('\n');
+    /// Passes `key` to the active mode and does what it asks for.
+    fn feed_mode(&mut self, key: KeyEvent) {
+        let mode = self.active_mode;
+        match (self.mode_mut().handle_key(key), mode) {
+            (Outcome::Run(action), _) => {
+                let count = self.count.take();
+                self.handle_counted_action(action, count);
+                // The action may have switched modes, so the chord is
+                // cleared in the mode which took it
+                self.mode_of(mode).clear_chord();
+            }
+            (Outcome::Unbound, _) => self.count = None,
+            (Outcome::Stay, ActiveMode::Filter) => self.narrow_listing(),
+            (Outcome::Stay, _) => {}
+            (Outcome::Leave, ActiveMode::Filter) => {
+                self.enter_mode(ActiveMode::Normal);
+                self.restore_listing();
+            }
+            (Outcome::Leave, _) => self.enter_mode(ActiveMode::Normal),
+            // The mode is left first so that the line can open another one
+            (Outcome::Submit, ActiveMode::Command) => {
+                let line = self.command_line.line().to_string();
+                self.enter_mode(ActiveMode::Normal);
+                self.run_command_line(&line);
+            }
+            (Outcome::Submit, ActiveMode::History) => self.run_history_entry(),
+            (Outcome::Submit, ActiveMode::Filter) => {
+                let query = self.filter_line.query().to_string();
+                self.enter_mode(ActiveMode::Normal);
+                self.unfiltered.clear();
+                if !query.is_empty() {
+                    self.filter = Some(query);
+                }
+                if self.dir_contents.len() == 1 {
+                    if self.dir_contents[0].is_dir {
+                        self.handle_action(AppActions::EnterDir, Vec::new());
+                    } else {
+                        self.on_enter();
+                    }
+                }
+            }
+            (Outcome::Submit, ActiveMode::Search) => {
+                let pattern = self.search_line.line().to_string();
+                self.enter_mode(ActiveMode::Normal);
+                if !pattern.is_empty() {
+                    self.search = Some(pattern);
+                }
+                self.search_next(true);
+            }
+            (Outcome::Submit, _) => self.run_palette_entry(),
         }
-        fs::write(self.yank_reg.as_path(), output).unwrap();
-
-        self.yank_mode = Some(YankMode::Copying);
     }
 
-    fn delete_files(&mut self, paths: Vec<PathBuf>) {
-        for p in paths {
-            let md = fs::metadata(&p).unwrap();
-            if md.is_dir() {
-                fs::remove_dir_all(&p).unwrap();
-            } else if md.is_file() {
-                fs::remove_file(&p).unwrap();
+    pub fn on_key(&mut self, key: KeyEvent) {
+        let key = normalize_key(key);
+        let modifiers = key.modifiers;
+
+        if let Some((action, count)) = self.awaiting_register.take() {
+            let finding = matches!(action, AppActions::FindForward | AppActions::FindBackward);
+            match key.code {
+                // Letters are found in either case
+                KeyCode::Char(c) if finding && (modifiers - KeyModifiers::SHIFT).is_empty() => {
+                    self.use_register(action, c, count)
+                }
+                KeyCode::Char(c) if modifiers.is_empty() => self.use_register(action, c, count),
+                _ => {}
             }
+            return;
         }
 
-        self.update_dir_contents();
-    }
-
-    fn cut_files(&mut self, paths: Vec<PathBuf>) {
-        let mut output = String::new();
-        for p in paths {
-            output.push_str(p.as_path().to_str().unwrap());
-            output.push('\n');
+        if self.active_mode == ActiveMode::Normal || self.settings.escape.is_empty() {
+            self.handle_key(key);
+            return;
         }
-        fs::write(self.yank_reg.as_path(), output).unwrap();
 
-        self.yank_mode = Some(YankMode::Cutting);
-    }
+        /* Keys which could be the start of an escape sequence are held back
+         * until the sequence either completes, is broken by another key or
+         * times out in on_tick.
+         */
+        self.escape_pending.push(key);
+        loop {
+            if self.settings.escape.contains(&self.escape_pending) {
+                self.escape_pending.clear();
+                self.on_esc();
+                return;
+            }
 
-    fn get_selected_entries(&self) -> &[DirEntry] {
-        if !&self.dir_contents.is_empty() {
-            let selection_start = self.selection_start as usize;
-            let selection_end = (self.ui.scroll_y + self.ui.cursor_y) as usize;
-            return &self.dir_contents[std::cmp::min(selection_end, selection_start)
-                ..=std::cmp::max(selection_end, selection_start)];
-        } else {
-            return &[];
+            let is_prefix = self
+                .settings
+                .escape
+                .iter()
+                .any(|seq| seq.starts_with(&self.escape_pending));
+            if self.escape_pending.is_empty() || is_prefix {
+                self.escape_pending_since = Instant::now();
+                return;
+            }
+
+            let k = self.escape_pending.remove(0);
+            self.handle_key(k);
+            if self.active_mode == ActiveMode::Normal {
+                self.flush_escape_pending();
+                return;
+            }
         }
     }
 
-    fn get_selected_bookmark(&self) -> Option<&Bookmark> {
-        self.bookmarks
-            .get((self.ui.bookmark_y + self.ui.bookmark_scroll_y) as usize)
+    fn flush_escape_pending(&mut self) {
+        for k in std::mem::take(&mut self.escape_pending) {
+            self.handle_key(k);
+        }
     }
 
-    fn paste_yanked_files(&mut self) {
-        let contents = fs::read_to_string(self.yank_reg.as_path()).unwrap();
-        let lines = contents.split("\n");
+    fn handle_key(&mut self, key: KeyEvent) {
+        self.last_key = key;
 
-        let dest_dir = self.current_dir.clone();
+        if let Some(digit) = self.count_digit(key) {
+            self.count = Some(
+                self.count
+                    .unwrap_or(0)
+                    .saturating_mul(10)
+                    .saturating_add(digit)
+                    .min(MAX_COUNT),
+            );
+            return;
+        }
 
-        for l in lines {
-            if l.len() > 0 {
-                let p = Path::new(l);
-                let mut dest = dest_dir.join(p.file_name().unwrap());
-                let md = fs::metadata(&p).unwrap();
-
-                if md.is_dir() {
-                    while dest.exists() {
-                        dest.set_file_name(format!(
-                            "{} (Copy)",
-                            dest.file_stem().unwrap().to_str().unwrap(),
-                        ));
-                    }
-                    let mut copy_options = CopyOptions::new();
-                    copy_options.copy_inside = true;
-                    let copy_success = fs_extra::dir::copy(&p, &dest, &copy_options);
-
-                    match copy_success {
-                        Ok(_) => {
-                            if let Some(ym) = self.yank_mode {
-                                if ym == YankMode::Cutting {
-                                    fs::remove_dir_all(&p).unwrap();
-                                }
-                            }
-                        }
-                        Err(_) => {}
-                    }
-                } else if md.is_file() {
-                    while dest.exists() {
-                        dest.set_file_name(format!(
-                            "{} (Copy).{}",
-                            dest.file_stem().unwrap().to_str().unwrap(),
-                            dest.extension()
-                                .unwrap_or(&OsString::from(""))
-                                .to_str()
-                                .unwrap()
-                        ));
-                    }
-                    let copy_success = fs::copy(&p, dest);
+        self.feed_mode(key);
+    }
 
-                    if let Ok(_) = copy_success {
-                        if let Some(ym) = self.yank_mode {
-                            if ym == YankMode::Cutting {
-                                fs::remove_file(&p).unwrap();
-                            }
-                        }
+    /// Chooses the yank register after `"`, or starts recording into or plays
+    /// the macro in `register` after `q` and `@`. `""` is the unnamed yank
+    /// register, `"+` the system clipboard and `@@` plays the macro played
+    /// last.
+    fn use_register(&mut self, action: AppActions, register: char, count: usize) {
+        match action {
+            AppActions::SelectRegister if register == '"' => self.register = None,
+            AppActions::SelectRegister if register == '+' || register.is_alphanumeric() => {
+                self.register = Some(register);
+            }
+            AppActions::RecordMacro if register.is_alphanumeric() => {
+                self.recording = Some((register, Vec::new()));
+            }
+            AppActions::PlayMacro => {
+                let register = match (register, self.last_macro) {
+                    ('@', Some(last)) => last,
+                    _ => register,
+                };
+                if self.replaying {
+                    return self.ui.warn("Macros cannot play other macros");
+                }
+                let keys = match self.macros.get(&register) {
+                    Some(keys) => keys.clone(),
+                    None => return self.ui.info(format!("No macro in register {}", register)),
+                };
+                self.last_macro = Some(register);
+                self.replaying = true;
+                for _ in 0..count {
+                    for k in &keys {
+                        self.handle_event(*k);
                     }
                 }
+                self.replaying = false;
             }
+            AppActions::FindForward | AppActions::FindBackward => {
+                let forward = action == AppActions::FindForward;
+                self.last_find = Some((register, forward));
+                self.find_letter(register, forward, count);
+            }
+            _ => {}
         }
-
-        self.update_dir_contents();
     }
 
-    fn update_dir_contents(&mut self) {
-        self.dir_contents = self.read_dir_sorted(self.current_dir.as_path());
+    /// The value of `key` if it is part of a count. Zero only continues a
+    /// count, so that it can still be bound on its own.
+    fn count_digit(&self, key: KeyEvent) -> Option<usize> {
+        if self.mode().bindings().is_none()
+            || !self.mode().chord().is_empty()
+            || !key.modifiers.is_empty()
+        {
+            return None;
+        }
+        match key.code {
+            KeyCode::Char('0') if self.count.is_none() => None,
+            KeyCode::Char(c) => c.to_digit(10).map(|d| d as usize),
+            _ => None,
+        }
+    }
 
-        self.ui.scroll_abs(
-            self.ui.cursor_y + self.ui.scroll_y,
-            self.dir_contents.len() as i32,
-            &self.active_panel,
-        );
+    /* Movements are repeated, jumps go to the given line like in vim and other
+     * actions in normal mode act on that many entries from the cursor, so 3dd
+     * cuts three files.
+     */
+    fn handle_counted_action(&mut self, action: AppActions, count: Option<usize>) {
+        let count = match count {
+            Some(count) if count > 0 => count,
+            _ => return self.handle_action(action, vec![]),
+        };
+        let len = self.dir_contents.len() as i32;
+        match action {
+            AppActions::PlayMacro | AppActions::FindForward | AppActions::FindBackward => {
+                self.awaiting_register = Some((action, count))
+            }
+            AppActions::MoveDown
+            | AppActions::MoveUp
+            | AppActions::MoveUpDir
+            | AppActions::RepeatFind
+            | AppActions::RepeatFindReversed => {
+                for _ in 0..count {
+                    self.handle_action(action, vec![]);
+                }
+            }
+            AppActions::MoveToTop | AppActions::MoveToBottom
+                if self.active_panel == ActivePanel::Main && self.popup.is_none() =>
+            {
+                self.ui
+                    .scroll_abs(count as i32 - 1, len, &self.active_panel);
+            }
+            _ if self.active_mode == ActiveMode::Normal
+                && self.active_panel == ActivePanel::Main
+                && len > 0 =>
+            {
+                let cursor = self.ui.scroll_y + self.ui.cursor_y;
+                self.selection_start = std::cmp::min(cursor + count as i32 - 1, len - 1);
+                self.handle_action(action, vec![]);
+            }
+            _ => self.handle_action(action, vec![]),
+        }
     }
 
-    fn handle_action(&mut self, action: AppActions, args: Vec<String>) {
-        let selected_paths: Vec<PathBuf> = self
-            .get_selected_entries()
-            .iter()
-            .map(|d| d.path())
-            .collect();
-        match self.active_panel {
-            ActivePanel::Main => match action {
-                AppActions::MoveDown => {
-                    self.ui
-                        .scroll(1, self.dir_contents.len() as i32, &self.active_panel)
-                }
-                AppActions::MoveUp => {
-                    self.ui
-                        .scroll(-1, self.dir_contents.len() as i32, &self.active_panel)
-                }
-                AppActions::MoveUpDir => {
-                    self.move_up_dir();
-                    let index = self.find_name(self.ui.last_name.clone()).unwrap_or(0);
-                    self.ui
-                        .scroll_abs(index, self.dir_contents.len() as i32, &self.active_panel);
-                    self.ui.last_name = self
-                        .current_dir
-                        .file_name()
-                        .unwrap_or(OsStr::new(""))
-                        .to_str()
-                        .unwrap()
-                        .to_string();
-                    self.ui.debug_msg = format!("{}", index);
-                }
-                AppActions::EnterDir => {
-                    if self.dir_contents[(self.ui.cursor_y + self.ui.scroll_y) as usize]
-                        .file_type()
-                        .unwrap()
-                        .is_dir()
-                    {
-                        let path =
-                            &self.dir_contents[(self.ui.cursor_y + self.ui.scroll_y) as usize];
-                        self.ui.last_name =
-                            path.file_name().to_owned().to_str().unwrap().to_string();
-                        self.enter_dir(&path.path());
-                        self.ui
-                            .scroll_abs(0, self.dir_contents.len() as i32, &self.active_panel);
-                    }
-                }
-                AppActions::Quit => {
-                    self.should_quit = true;
-                }
-                AppActions::MoveToTop => {
-                    self.ui
-                        .scroll_abs(0, self.dir_contents.len() as i32, &self.active_panel)
-                }
-                AppActions::MoveToBottom => self.ui.scroll_abs(
-                    self.dir_contents.len() as i32 - 1,
-                    self.dir_contents.len() as i32,
-                    &self.active_panel,
-                ),
-                AppActions::CopyFiles => {
-                    self.copy_files(selected_paths);
-                    self.active_mode = ActiveMode::Normal;
-                }
-                AppActions::CutFiles => {
-                    self.cut_files(selected_paths);
-                    self.active_mode = ActiveMode::Normal;
-                }
-                AppActions::PasteFiles => self.paste_yanked_files(),
-                AppActions::OpenCommandMode => {
-                    self.command_buffer = String::from("");
-                    self.active_mode = ActiveMode::Command;
-                }
-                AppActions::DeleteFile => self.delete_files(selected_paths),
-                AppActions::CreateBookmark => self.create_bookmark(),
-                AppActions::DeleteBookmark => {}
-                AppActions::ToggleBookmark => {
-                    self.active_panel = ActivePanel::Bookmarks;
-                }
-                AppActions::MoveToLeftPanel => {
-                    self.active_panel = ActivePanel::Bookmarks;
-                }
-                AppActions::MoveEntry => {
-                    if args.len() > 0 && selected_paths.len() == 1 {
-                        self.mv_entry(&selected_paths[0], &args[0]);
-                    }
-                }
-                AppActions::ToggleHiddenFiles => {
-                    self.show_hidden_files = !self.show_hidden_files;
-                    self.update_dir_contents();
-                }
-                AppActions::ToggleVisualMode => {
-                    if self.active_mode == ActiveMode::Normal {
-                        self.active_mode = ActiveMode::Visual;
-                        self.selection_start = self.ui.cursor_y + self.ui.scroll_y;
-                    } else if self.active_mode == ActiveMode::Visual {
-                        self.active_mode = ActiveMode::Normal;
-                    }
-                }
-                AppActions::MoveToRightPanel => {}
-                AppActions::CreateDir => {}
-            },
-            ActivePanel::Bookmarks => match action {
-                AppActions::MoveDown => {
-                    self.ui
-                        .scroll(1, self.bookmarks.len() as i32, &self.active_panel)
-                }
-                AppActions::MoveUp => {
-                    self.ui
-                        .scroll(-1, self.bookmarks.len() as i32, &self.active_panel)
-                }
-                AppActions::EnterDir => {
-                    if let Some(b) = self.get_selected_bookmark() {
-                        let path = b.path.clone();
-                        self.enter_dir(&path);
-                    }
-                    self.active_panel = ActivePanel::Main;
-                    self.ui
-                        .scroll_abs(0, self.dir_contents.len() as i32, &self.active_panel);
-                }
-                AppActions::Quit => self.should_quit = true,
-                AppActions::DeleteBookmark => self.delete_bookmark(),
-                AppActions::ToggleBookmark => match self.active_panel {
-                    ActivePanel::Main => self.active_panel = ActivePanel::Bookmarks,
-                    ActivePanel::Bookmarks => self.active_panel = ActivePanel::Main,
-                },
-                AppActions::OpenCommandMode => {
-                    self.command_buffer = String::from("");
-                    self.active_mode = ActiveMode::Command;
-                }
-                AppActions::MoveToRightPanel => {
-                    self.active_panel = ActivePanel::Main;
-                }
-                _ => {}
-            },
+    pub fn on_tick(&mut self) {
+        if !self.escape_pending.is_empty()
+            && self.escape_pending_since.elapsed()
+                >= Duration::from_millis(self.settings.escape_timeout)
+        {
+            self.flush_escape_pending();
+            self.dirty = true;
         }
 
-        match action {
-            AppActions::CreateDir => {
-                for arg in &args {
-                    self.create_dir(arg);
+        if let Some(tail) = &mut self.tail {
+            match tail.refresh() {
+                Ok(refreshed) => self.dirty |= refreshed,
+                Err(e) => {
+                    log::warn!("Stopped following {:?}: {}", tail.path, e);
+                    self.tail = None;
+                    self.dirty = true;
                 }
-                self.update_dir_contents();
             }
-            _ => {}
         }
+
+        self.dirty |= self.poll_jobs();
+        self.dirty |= self.poll_server();
+        self.dirty |= self.poll_checksums(false);
+        self.dirty |= self.dir_sizes.poll();
     }
 
-    pub(crate) fn on_esc(&mut self) {
-        match self.active_mode {
-            ActiveMode::Visual => {
-                self.active_mode = ActiveMode::Normal;
+    /// How long to wait between ticks. Without input for `IDLE_AFTER` the
+    /// ticks slow down to save power, which delays followed files and the
+    /// escape timeout but nothing finished in the background.
+    pub fn tick_rate(&self) -> Duration {
+        idle_tick_rate(
+            Duration::from_millis(self.settings.tick_rate),
+            self.last_input.elapsed(),
+        )
+    }
+
+    /// Draws the next frame even if nothing has changed, such as after the
+    /// terminal was resized.
+    pub fn invalidate(&mut self) {
+        self.dirty = true;
+    }
+
+    /// Starts listening for commands from other programs, which find the
+    /// socket through `TROOPER_SOCKET`.
+    pub fn listen(&mut self) {
+        if self.options.deterministic {
+            return;
+        }
+        match Server::start(&ipc::socket_path(std::process::id()), &self.waker) {
+            Ok(server) => {
+                std::env::set_var("TROOPER_SOCKET", server.path());
+                self.server = Some(server);
             }
-            ActiveMode::Command => {
-                if self.command_completion_index != -1 {
-                    self.command_completion_index = -1;
-                    self.command_matches.clear();
-                    self.command_buffer = self.command_buffer_tmp.clone();
-                    self.command_buffer_tmp.clear();
-                } else {
-                    self.active_mode = ActiveMode::Normal;
-                    self.command_buffer.clear();
-                }
+            Err(e) => self
+                .ui
+                .warn(format!("Could not listen for commands: {}", e)),
+        }
+    }
+
+    /// Runs the commands which have arrived. Returns true if there were any.
+    fn poll_server(&mut self) -> bool {
+        let mut received = false;
+        while let Some(line) = self.server.as_ref().and_then(Server::try_recv) {
+            log::info!("Received command: {}", line);
+            if !self.run_command_line(&line) {
+                self.ui.warn(format!("Unknown command {}", line));
             }
-            _ => {}
+            received = true;
         }
+        received
     }
 
-    pub(crate) fn on_enter(&mut self) {
-        match self.active_mode {
-            ActiveMode::Command => {
-                let words: Vec<&str> = self.command_buffer.split(" ").collect();
-
-                if self.command_completion_index != -1 && !self.command_matches.is_empty() {
-                    self.command_buffer =
-                        self.command_matches[self.command_completion_index as usize].clone();
-                    self.command_completion_index = -1;
-                    self.command_matches.clear();
-                    self.command_buffer_tmp.clear();
-                } else {
-                    if let Some(cmd) = words.get(0) {
-                        match self.commands.get(*cmd) {
-                            Some(action) => {
-                                let args =
-                                    words[1..].into_iter().map(|x| String::from(*x)).collect();
-                                /* TODO: This is kind of inconsistent behaviour. Should there be a
-                                 * third command_handle_action?
-                                 */
-                                self.handle_action(*action, args);
-                            }
-                            None => (),
-                        }
+    /// Applies the job events which have arrived. Returns true if there were
+    /// any.
+    fn poll_jobs(&mut self) -> bool {
+        let mut received = false;
+        let mut finished = false;
+        while let Ok(event) = self.job_receiver.try_recv() {
+            finished |= self.on_job_event(event);
+            received = true;
+        }
 
-                        self.command_history.push(self.command_buffer.clone());
-                        self.on_esc();
-                    }
-                }
-            }
-            _ => {}
+        if finished {
+            self.update_dir_contents();
         }
+        received
     }
 
-    pub(crate) fn on_backspace(&mut self) {
-        match self.active_mode {
-            ActiveMode::Command => {
-                if self.command_buffer.len() > 0 {
-                    self.command_buffer.pop();
+    /// Blocks until every job has finished, used in deterministic mode.
+    fn wait_for_jobs(&mut self) {
+        while self.jobs.iter().any(Job::is_active) {
+            match self.job_receiver.recv() {
+                Ok(event) => {
+                    self.on_job_event(event);
                 }
+                Err(_) => break,
             }
-            _ => {}
         }
+        self.update_dir_contents();
     }
 
-    pub(crate) fn on_down(&mut self) {
-        match self.active_mode {
-            ActiveMode::Command => {
-                if self.command_completion_index == -1 {
-                    if self.command_history_index > 0 {
-                        self.command_history_index = self.command_history_index - 1;
-                        self.command_buffer =
-                            self.command_history[(self.command_history.len() as i32
-                                - self.command_history_index
-                                - 1) as usize]
-                                .clone();
-                    } else if self.command_history_index == 0 {
-                        self.command_history_index = -1;
-                        self.command_buffer = self.command_buffer_tmp.clone();
+    /// Applies a job event. Returns true if a job finished.
+    fn on_job_event(&mut self, event: JobEvent) -> bool {
+        match event {
+            JobEvent::Progress { id, done, total } => {
+                if let Some(job) = self.jobs.iter_mut().find(|j| j.id == id) {
+                    job.update(done, total);
+                }
+                false
+            }
+            JobEvent::Finished { id, result } => {
+                if let Some(job) = self.jobs.iter_mut().find(|j| j.id == id) {
+                    for p in &job.paths {
+                        self.dir_sizes.invalidate(p);
                     }
+                    job.state = match result {
+                        Err(_) if job.is_cancelled() => {
+                            self.ui.info(format!("{} cancelled", job.description));
+                            JobState::Cancelled
+                        }
+                        Err(e) => {
+                            self.ui.error(format!("{} failed: {}", job.description, e));
+                            JobState::Failed(e)
+                        }
+                        Ok(note) => {
+                            if let Some(note) = note {
+                                self.ui.info(note);
+                            }
+                            JobState::Done
+                        }
+                    };
                 }
+                self.forget_old_jobs();
+                self.run_next_job();
+                true
             }
-            _ => {}
         }
     }
 
-    pub(crate) fn on_up(&mut self) {
-        match self.active_mode {
-            ActiveMode::Command => {
-                if self.command_completion_index == -1 {
-                    if self.command_history_index + 1 < self.command_history.len() as i32 {
-                        if self.command_history_index == -1 {
-                            self.command_buffer_tmp = self.command_buffer.clone();
-                        }
-                        self.command_history_index = self.command_history_index + 1;
+    /// Keeps only the last `FINISHED_JOBS` finished jobs in the jobs panel.
+    fn forget_old_jobs(&mut self) {
+        let finished = self.jobs.iter().filter(|j| !j.is_active()).count();
+        let mut excess = finished.saturating_sub(FINISHED_JOBS);
+        self.jobs.retain(|j| {
+            let forget = excess > 0 && !j.is_active();
+            if forget {
+                excess -= 1;
+            }
+            !forget
+        });
+        self.ui.job_y = self.ui.job_y.min(self.jobs.len() as i32 - 1).max(0);
+    }
 
-                        self.command_buffer =
-                            self.command_history[(self.command_history.len() as i32
-                                - self.command_history_index
-                                - 1) as usize]
-                                .clone();
-                    }
-                }
+    /// Lists `dir` and makes it the current directory. Directories which
+    /// cannot be read are reported and not entered.
+    pub(crate) fn enter_dir(&mut self, dir: &Path) {
+        if dir != self.current_dir.as_path() {
+            self.filter = None;
+        }
+        match self.read_dir_sorted(dir).context("open", dir) {
+            Ok(contents) => {
+                self.current_dir = Box::new(dir.to_path_buf());
+                self.leave_snapshot_if_outside();
+                self.dir_contents = contents;
+                self.disk_space = disk::disk_space(dir);
             }
-            _ => {}
+            Err(e) => self.report(e),
         }
     }
 
-    pub(crate) fn on_shift_tab(&mut self) {
-        match self.active_mode {
-            ActiveMode::Command => {
-                if self.command_completion_index == -1 {
-                    self.command_buffer_tmp = self.command_buffer.clone();
-                    self.command_matches = matching_strings(
-                        &self.command_buffer,
-                        &self.commands.keys().cloned().collect::<Vec<String>>(),
-                    );
-                    self.command_matches.sort();
-                }
-                self.scroll_completion(-1);
-            }
-            _ => {}
+    /* The chord is only cleared once the action has been handled, so it still
+     * names the binding here.
+     */
+    /// Runs the command bound to the keys just pressed. Commands starting with
+    /// `!` take over the terminal like `:!` and ones starting with `&` are
+    /// left running in the background.
+    fn run_shell_binding(&mut self) {
+        let mode = match self.active_mode {
+            ActiveMode::Visual => &self.visual,
+            _ => &self.normal,
+        };
+        let bound = match mode.shell_command() {
+            Some(bound) => bound.clone(),
+            None => return,
+        };
+        let selected = self.selected_paths();
+        let (detached, command) = match bound.strip_prefix('&') {
+            Some(command) => (true, command),
+            None => (false, bound.strip_prefix('!').unwrap_or(&bound)),
+        };
+        let command = shell::expand_placeholders(command, &self.current_dir, &selected);
+        self.active_mode = ActiveMode::Normal;
+
+        match ShellCommand::new(&command, &self.current_dir, &selected) {
+            Ok(c) if detached => match c.spawn() {
+                Ok(()) => self.ui.info(format!("Started {}", command.to_string_lossy())),
+                Err(e) => self.ui.error(format!("Could not run command: {}", e)),
+            },
+            Ok(c) => self.shell_command = Some(c),
+            Err(e) => self.ui.error(format!("Could not run command: {}", e)),
         }
     }
 
-    pub(crate) fn on_tab(&mut self) {
-        match self.active_mode {
-            ActiveMode::Command => {
-                if self.command_completion_index == -1 {
-                    self.command_buffer_tmp = self.command_buffer.clone();
-                    self.command_matches = matching_strings(
-                        &self.command_buffer,
-                        &self.commands.keys().cloned().collect::<Vec<String>>(),
-                    );
-                    self.command_matches.sort();
+    /// Runs the plugin command named by the first of `args` and then what it
+    /// asked for.
+    fn run_plugin_command(&mut self, args: Vec<String>) {
+        let (name, args) = match (args.split_first(), &self.plugins) {
+            (Some((name, args)), Some(_)) => (name.clone(), args.to_vec()),
+            _ => return,
+        };
+        let selected = self.selected_paths();
+        let result = match &self.plugins {
+            Some(plugins) => plugins.run_command(&name, args, &self.current_dir, selected),
+            None => return,
+        };
+        match result {
+            Ok(requests) => {
+                for request in requests {
+                    match request {
+                        Request::Action(action, args) => match AppActions::from_str(&action) {
+                            Ok(action) => self.handle_action(action, args),
+                            Err(_) => self.ui.error(format!("Unknown action {}", action)),
+                        },
+                        Request::Notify(message) => self.ui.info(message),
+                    }
                 }
-                self.scroll_completion(1);
             }
-            _ => {}
+            Err(e) => self.ui.error(format!("{} failed: {}", name, e)),
         }
     }
 
-    fn scroll_completion(&mut self, amount: i32) {
-        assert!(amount.abs() <= 1);
-        self.command_completion_index += amount;
-
-        if self.command_completion_index == self.command_matches.len() as i32 {
-            self.command_completion_index = -1;
-            self.command_buffer = self.command_buffer_tmp.clone();
-            self.command_buffer_tmp.clear();
-        } else if self.command_completion_index < -1 {
-            self.command_completion_index = self.command_matches.len() as i32 - 1;
-            self.command_buffer =
-                self.command_matches[self.command_completion_index as usize].clone();
-        } else if self.command_completion_index == -1 {
-            self.command_buffer = self.command_buffer_tmp.clone();
-            self.command_buffer_tmp.clear();
-        } else {
-            self.command_buffer =
-                self.command_matches[self.command_completion_index as usize].clone();
+    /// Reads the config again, replacing the bindings, commands and settings.
+    /// A broken config leaves the current ones in place.
+    fn reload_config(&mut self) {
+        match read_config(&self.config_path) {
+            Ok(mut config) => {
+                let (plugins, problems) = load_plugins(&self.config_path);
+                config.problems.extend(problems);
+                self.plugins = plugins;
+                self.normal = ChordMode::new(config.normal, config.normal_shell);
+                self.visual = ChordMode::new(config.visual, config.visual_shell);
+                self.settings = config.settings;
+                self.settings.tick_rate = self.options.tick_rate.unwrap_or(self.settings.tick_rate);
+                self.commands = default_commands();
+                self.commands.extend(config.commands);
+                self.commands.extend(plugin_commands(&self.plugins));
+                self.ui
+                    .info(format!("Reloaded {}", self.config_path.display()));
+                if !config.problems.is_empty() {
+                    self.checksums = None;
+                    self.popup = problems_popup(config.problems);
+                }
+            }
+            Err(e) => self.ui.error(format!("Could not reload the config: {}", e)),
         }
     }
 
-    fn create_bookmark(&mut self) {
-        self.bookmarks.push(Bookmark {
-            name: String::from(
-                self.current_dir
-                    .file_name()
-                    .unwrap_or(&OsStr::new("No file name"))
-                    .to_str()
-                    .unwrap_or("No file name"),
-            ),
-            path: self.current_dir.to_owned(),
-        });
-
-        self.update_bookmark_width();
+    /// Writes the default config to `path`, or next to the config in use
+    /// without one. Existing files are left alone.
+    fn write_default_config(&mut self, path: Option<&String>) -> Result<(), AppError> {
+        let path = match path {
+            Some(path) => self.current_dir.join(paths::expand_home(path)),
+            None => self.config_path.with_extension("ini"),
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).context("create", dir)?;
+        }
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .context("write", &path)?;
+        file.write_all(DEFAULT_CONFIG.as_bytes())
+            .context("write", &path)?;
+        self.ui
+            .info(format!("Wrote the default config to {}", path.display()));
+        Ok(())
     }
 
-    fn delete_bookmark(&mut self) {
-        let i = (self.ui.bookmark_scroll_y + self.ui.bookmark_y) as usize;
-        if i < self.bookmarks.len() {
-            self.bookmarks.remove(i);
+    /// Remembers `paths` to be written out by `--choose-files` and quits.
+    fn choose_files(&mut self, paths: Vec<PathBuf>) {
+        if !self.options.picking {
+            return self
+                .ui
+                .info("Start trooper with --choose-files to pick files");
         }
-
-        self.update_bookmark_width();
+        if paths.is_empty() {
+            return;
+        }
+        self.chosen = paths;
+        self.should_quit = true;
     }
 
-    fn update_bookmark_width(&mut self) {
-        let mut max_len: u16 = 15;
-        for b in &self.bookmarks {
-            if b.name.len() > max_len.into() {
-                max_len = b.name.len() as u16;
+    /// Opens the directory holding `path` with the cursor on it, showing
+    /// hidden files if it is one of them.
+    pub fn reveal(&mut self, path: &Path) {
+        let (dir, name) = match (path.parent(), path.file_name()) {
+            (Some(dir), Some(name)) => (dir, name),
+            _ => return self.enter_dir(path),
+        };
+        self.enter_dir(dir);
+        if self.find_name(name).is_none() && !self.show_hidden_files {
+            self.show_hidden_files = true;
+            self.update_dir_contents();
+        }
+        match self.find_name(name) {
+            Some(index) => {
+                self.ui
+                    .scroll_abs(index, self.dir_contents.len() as i32, &self.active_panel)
             }
+            None => self.ui.warn(format!("{} does not exist", path.display())),
         }
-        self.ui.bookmark_width = max_len + 1;
     }
 
-    fn mv_entry(&mut self, src: &Path, dest: &str) {
-        let new_name = src.parent().unwrap().join(dest);
-        fs::rename(src, new_name).unwrap();
-        self.update_dir_contents();
+    pub(crate) fn move_up_dir(&mut self) {
+        let parent = match self.current_dir.parent() {
+            Some(parent) => parent.to_path_buf(),
+            None => return,
+        };
+        self.enter_dir(&parent);
     }
 
-    fn read_dir_sorted<P: AsRef<Path>>(&self, path: P) -> Vec<DirEntry> {
-        let mut contents: Vec<DirEntry> = fs::read_dir(path).unwrap().map(|x| x.unwrap()).collect();
-        contents.sort_unstable_by_key(|item| {
-            (
-                item.metadata().unwrap().is_file(),
-                item.path().as_path().to_str().unwrap().to_lowercase(),
-            )
-        });
-        contents = contents
-            .into_iter()
-            .filter(|item| {
-                if item
-                    .path()
-                    .file_stem()
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
-                    .starts_with(".")
-                {
-                    self.show_hidden_files
-                } else {
-                    true
-                }
-            })
-            .collect();
+    fn leave_snapshot_if_outside(&mut self) {
+        if let Some(s) = &self.snapshot {
+            if !self.current_dir.starts_with(&s.root) {
+                self.snapshot = None;
+            }
+        }
+    }
 
-        return contents;
+    /// The current directory, or its counterpart in the live tree when
+    /// browsing a snapshot.
+    fn live_dir(&self) -> PathBuf {
+        self.snapshot
+            .as_ref()
+            .and_then(|s| s.live_path(&self.current_dir))
+            .unwrap_or(self.current_dir.to_path_buf())
     }
 
-    fn create_dir(&self, name: &str) {
-        match PathBuf::from_str(name) {
-            Ok(_) => {
-                let new_path = self.current_dir.join(name);
-                fs::create_dir_all(new_path).unwrap();
+    fn pick_snapshot(&mut self, action: AppActions) {
+        match action {
+            AppActions::MoveDown => {
+                self.snapshot_index = (self.snapshot_index + 1).min(self.snapshots.len() - 1);
             }
-            Err(_) => {}
+            AppActions::MoveUp => self.snapshot_index = self.snapshot_index.saturating_sub(1),
+            AppActions::EnterDir => self.open_snapshot(),
+            AppActions::Quit | AppActions::PickSnapshot => self.snapshots.clear(),
+            _ => {}
         }
     }
-}
-
-fn str_to_key_events(s: &str) -> Vec<KeyEvent> {
-    let mut output = Vec::with_capacity(s.len());
 
-    let re = Regex::new(r"<[.|[^<>]]+>|.").unwrap();
-
-    for cap in re.captures_iter(s) {
-        let symbol = &cap[0];
+    fn open_snapshot(&mut self) {
+        let snapshot = self.snapshots[self.snapshot_index].clone();
+        self.snapshots.clear();
 
-        if symbol.len() == 1 {
-            output.push(KeyEvent::new(
-                KeyCode::Char(symbol.chars().next().unwrap()),
-                KeyModifiers::empty(),
-            ));
-        } else if symbol == "<lt>" {
-            output.push(KeyEvent::new(KeyCode::Char('<'), KeyModifiers::empty()));
-        } else if symbol == "<gt>" {
-            output.push(KeyEvent::new(KeyCode::Char('>'), KeyModifiers::empty()));
-        } else if symbol == "<Space>" {
-            output.push(KeyEvent::new(KeyCode::Char(' '), KeyModifiers::empty()));
-        } else if symbol.len() == 5 {
-            if symbol.chars().nth(1).unwrap() == 'C' || symbol.chars().nth(1).unwrap() == 'c' {
-                output.push(KeyEvent::new(
-                    KeyCode::Char(symbol.chars().nth(3).unwrap()),
-                    KeyModifiers::CONTROL,
-                ));
-            }
+        // The directory may not have existed yet when the snapshot was taken
+        let mut target = snapshot
+            .snapshot_path(&self.live_dir())
+            .unwrap_or(snapshot.root.clone());
+        while !target.is_dir() && target != snapshot.root {
+            target.pop();
         }
-    }
 
-    return output;
-}
+        self.enter_dir(&target);
+        self.snapshot = Some(snapshot);
+        self.active_panel = ActivePanel::Main;
+        self.ui
+            .scroll_abs(0, self.dir_contents.len() as i32, &self.active_panel);
+    }
 
-fn key_events_to_string(key_seq: &Vec<KeyEvent>) -> String {
-    let mut output = String::new();
-    for ke in key_seq {
-        if ke.modifiers.intersects(KeyModifiers::CONTROL) {
-            output.push('^');
+    /* The hashes are shown in a popup as they come in and compared against
+     * `expected` if given, or otherwise against any checksum list such as
+     * SHA256SUMS in the current directory.
+     */
+    fn compute_checksums(
+        &mut self,
+        paths: &[PathBuf],
+        algorithm: Algorithm,
+        expected: Option<&String>,
+    ) {
+        let files: Vec<PathBuf> = paths.iter().filter(|p| p.is_file()).cloned().collect();
+        if files.is_empty() {
+            self.ui.info("No files selected");
+            return;
         }
+        let names: Vec<String> = files
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
 
-        match ke.code {
-            KeyCode::Char(c) => {
-                output.push(c);
+        let expected = match expected {
+            Some(hash) if files.len() == 1 => vec![Some(hash.to_lowercase())],
+            Some(_) => {
+                self.ui.info("A hash can only be compared to a single file");
+                return;
             }
-            _ => {}
+            None => {
+                let sums = checksum::expected_sums(&self.current_dir, algorithm);
+                names.iter().map(|n| sums.get(n).cloned()).collect()
+            }
+        };
+
+        let (sender, receiver) = wake::channel(&self.waker);
+        checksum::spawn(files, algorithm, sender);
+        self.popup = Some(Popup {
+            title: String::from(algorithm.name()),
+            rows: names
+                .into_iter()
+                .map(|n| (n, String::from("...")))
+                .collect(),
+            scroll: 0,
+        });
+        self.checksums = Some((receiver, expected));
+        if self.options.deterministic {
+            self.poll_checksums(true);
         }
     }
 
-    return output;
-}
-
-fn read_config(
-    p: &Path,
-) -> Result<
-    (
-        HashMap<Vec<KeyEvent>, AppActions>,
-        HashMap<Vec<KeyEvent>, AppActions>,
-    ),
-    io::Error,
-> {
-    let mut normal_output = HashMap::new();
-    let mut visual_output = HashMap::new();
-
-    let mut config = Ini::new();
-    let mut default = config.defaults();
-    default.delimiters = vec!['='];
-    default.case_sensitive = true;
-    config.load_defaults(default);
+    /// Fills in the checksum popup, waiting for every hash if `block` is set.
+    /// Returns true if any hash arrived.
+    fn poll_checksums(&mut self, block: bool) -> bool {
+        let (receiver, expected) = match &self.checksums {
+            Some(checksums) => checksums,
+            None => return false,
+        };
+        let popup = match &mut self.popup {
+            Some(popup) => popup,
+            None => {
+                // Dropping the receiver stops the worker
+                self.checksums = None;
+                return false;
+            }
+        };
 
-    let user_map = if p.exists() {
-        match config.read(fs::read_to_string(p)?) {
-            Err(msg) => return Err(io::Error::new(io::ErrorKind::Other, msg)),
-            Ok(inner) => inner,
+        let mut any = false;
+        loop {
+            let received = if block {
+                receiver.recv().ok()
+            } else {
+                receiver.try_recv().ok()
+            };
+            let (i, result) = match received {
+                Some(r) => r,
+                None => break,
+            };
+            any = true;
+            popup.rows[i].1 = match (result, &expected[i]) {
+                (Ok(hash), Some(e)) if hash == *e => format!("{}  OK", hash),
+                (Ok(hash), Some(_)) => format!("{}  MISMATCH", hash),
+                (Ok(hash), None) => hash,
+                (Err(e), _) => e,
+            };
         }
-    } else {
-        HashMap::new()
-    };
+        any
+    }
 
-    let default_map = match config.read(String::from(include_str!("../assets/default_config.ini")))
-    {
-        Err(msg) => return Err(io::Error::new(io::ErrorKind::Other, msg)),
-        Ok(inner) => inner,
-    };
+    fn duplicate(&mut self, path: &Path, name: &str) {
+        let dest = if name.is_empty() {
+            free_copy_name(path.to_path_buf(), path.is_dir())
+        } else {
+            path.with_file_name(name)
+        };
+        if fs::symlink_metadata(&dest).is_ok() {
+            self.ui.info(format!("{} already exists", dest.display()));
+            return;
+        }
 
-    for (k, v) in default_map["normal"]
-        .iter()
-        .chain(user_map.get("normal").unwrap_or(&HashMap::new()).iter())
-    {
-        if let Some(v_str) = v {
-            if let Ok(action) = AppActions::from_str(v_str) {
-                normal_output.insert(str_to_key_events(&k), action);
+        let transfer = Transfer {
+            from: path.to_path_buf(),
+            to: dest,
+        };
+        self.start_transfer("Duplicating", JobKind::Copy, vec![transfer]);
+    }
+
+    fn restore_from_snapshot(&mut self, paths: &[PathBuf]) {
+        let snapshot = match &self.snapshot {
+            Some(s) => s.clone(),
+            None => {
+                self.ui.info("Not inside a snapshot");
+                return;
             }
+        };
+
+        let mut transfers = Vec::new();
+        for p in paths {
+            let live = match snapshot.live_path(p) {
+                Some(live) => live,
+                None => continue,
+            };
+            // Whatever is in the live tree is moved aside by the job, rather
+            // than overwritten
+            transfers.push(Transfer {
+                from: p.clone(),
+                to: live,
+            });
+        }
+
+        // What is moved aside is still replaced as far as protection goes
+        let live: Vec<PathBuf> = transfers.iter().map(|t| t.to.clone()).collect();
+        if !transfers.is_empty() && self.may_change(AppActions::RestoreSnapshot, &live) {
+            self.start_transfer("Restoring", JobKind::Restore, transfers);
         }
     }
 
-    for (k, v) in default_map["visual"]
-        .iter()
-        .chain(user_map.get("visual").unwrap_or(&HashMap::new()).iter())
-    {
-        if let Some(v_str) = v {
-            if let Ok(action) = AppActions::from_str(v_str) {
-                visual_output.insert(str_to_key_events(&k), action);
+    /// Draws the interface, unless nothing has changed since the last time.
+    pub fn draw<B: Backend>(&mut self, term: &mut Terminal<B>) -> io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        self.dirty = false;
+        self.follow_cursor();
+        let mut disp_chord = String::new();
+        if let Some(register) = self.register {
+            disp_chord.push_str(&format!("\"{}", register));
+        }
+        if let Some(count) = self.count {
+            disp_chord.push_str(&count.to_string());
+        }
+        disp_chord.push_str(&key_events_to_string(self.mode().chord()));
+        let chord_hints = self.chord_hints();
+        // Not through mode(), which would borrow the ui as well
+        let view = match self.active_mode {
+            ActiveMode::Normal => self.normal.view(),
+            ActiveMode::Visual => self.visual.view(),
+            ActiveMode::Command => self.command_line.view(),
+            ActiveMode::Palette => self.palette.view(),
+            ActiveMode::History => self.history_picker.view(),
+            ActiveMode::Search => self.search_line.view(),
+            ActiveMode::Filter => self.filter_line.view(),
+        };
+        self.ui.plugin_segments = match &self.plugins {
+            Some(plugins) => {
+                let selected = self.selected_paths();
+                plugins.status_segments(&self.current_dir, selected)
             }
+            None => Vec::new(),
+        };
+        let title = match &self.remote {
+            Some(target) => format!("[sftp {}] {}", target, self.current_dir.display()),
+            None => self.current_dir.display().to_string(),
+        };
+        let app = AppView {
+            title: &title,
+            bookmarks: &self.bookmarks,
+            devices: &self.devices,
+            dir_contents: &self.dir_contents,
+            view,
+            active_panel: &self.active_panel,
+            active_mode: &self.active_mode,
+            selection_start: self.selection_start,
+            marked: &self.marked,
+            selection: self.selection_stats(),
+            key_chord: &disp_chord,
+            settings: &self.settings,
+            tail: self.tail.as_ref(),
+            // Free space differs between machines and runs, and is unknown
+            // for remote hosts
+            disk_space: if self.options.deterministic || self.remote.is_some() {
+                None
+            } else {
+                self.disk_space
+            },
+            jobs: &self.jobs,
+            dir_sizes: &self.dir_sizes,
+            recording: self.recording.as_ref().map(|(register, _)| *register),
+            popup: self.popup.as_ref(),
+            chord_hints: chord_hints.as_ref(),
+            snapshot_picker: if self.snapshots.is_empty() {
+                None
+            } else {
+                Some((&self.snapshots, self.snapshot_index))
+            },
+            snapshot: self.snapshot.as_ref(),
+        };
+        self.ui.draw_app(term, app)
+    }
+
+    /// What the keys pressed so far could still become, shown while a chord
+    /// such as `g` or `<C-w>` is pending.
+    fn chord_hints(&self) -> Option<Popup> {
+        let chord = self.mode().chord();
+        let bindings = match self.mode().bindings() {
+            Some(bindings) if !chord.is_empty() => bindings,
+            _ => return None,
+        };
+        let mut rows: Vec<(String, String)> = continuations(bindings, chord)
+            .into_iter()
+            .map(|(keys, action)| {
+                (
+                    key_events_to_string(&keys[chord.len()..]),
+                    format!("{:?}", action),
+                )
+            })
+            .collect();
+        if rows.is_empty() {
+            return None;
         }
+        rows.sort();
+        Some(Popup {
+            title: key_events_to_string(chord),
+            rows,
+            scroll: 0,
+        })
     }
 
-    return Ok((normal_output, visual_output));
-}
+    /// Lists the entries matching the filter being typed.
+    fn narrow_listing(&mut self) {
+        let query = self.filter_line.query();
+        let ignore_case = self.settings.ignores_case(query);
+        self.dir_contents = self
+            .unfiltered
+            .iter()
+            .filter(|e| {
+                fuzzy::score_case(query, &e.file_name().to_string_lossy(), ignore_case).is_some()
+            })
+            .cloned()
+            .collect();
+        self.ui
+            .scroll_abs(0, self.dir_contents.len() as i32, &ActivePanel::Main);
+    }
 
-fn matching_strings(prefix: &str, strings: &[String]) -> Vec<String> {
-    let mut output = vec![];
+    /// Lists every entry again after the filter was abandoned, keeping the
+    /// cursor on the entry it was on.
+    fn restore_listing(&mut self) {
+        let name = self.get_selected_entries().first().map(|e| e.file_name());
+        self.dir_contents = std::mem::take(&mut self.unfiltered);
+        let index = name.and_then(|n| self.find_name(&n)).unwrap_or(0);
+        self.ui
+            .scroll_abs(index, self.dir_contents.len() as i32, &ActivePanel::Main);
+    }
 
-    for s in strings {
-        if s.starts_with(prefix) {
-            output.push(s.clone());
+    /// Moves the cursor to the next entry, or the previous one when not
+    /// `forward`, whose name contains what was searched for last. The search
+    /// continues from the other end of the listing.
+    fn search_next(&mut self, forward: bool) {
+        let pattern = match &self.search {
+            Some(pattern) => pattern.clone(),
+            None => return self.ui.info("Nothing has been searched for"),
+        };
+        let ignore_case = self.settings.ignores_case(&pattern);
+        let wanted = if ignore_case {
+            pattern.to_lowercase()
+        } else {
+            pattern.clone()
+        };
+        let len = self.dir_contents.len() as i32;
+        let cursor = self.ui.scroll_y + self.ui.cursor_y;
+        let step = if forward { 1 } else { -1 };
+        let found = (1..=len)
+            .map(|i| (cursor + i * step).rem_euclid(len))
+            .find(|i| {
+                let name = self.dir_contents[*i as usize].file_name();
+                let name = name.to_string_lossy();
+                if ignore_case {
+                    name.to_lowercase().contains(&wanted)
+                } else {
+                    name.contains(&wanted)
+                }
+            });
+        match found {
+            Some(index) => self.ui.scroll(index - cursor, len, &ActivePanel::Main),
+            None => self.ui.warn(format!("Pattern not found: {}", pattern)),
         }
     }
 
-    return output;
-}
+    fn find_name(&self, name: &OsStr) -> Option<i32> {
+        for (j, d) in self.dir_contents.iter().enumerate() {
+            if d.file_name() == name {
+                return Some(i32::try_from(j).unwrap());
+            }
+        }
 
-#[cfg(test)]
-mod tests {
-    use std::{collections::HashMap, path::PathBuf, str::FromStr};
+        return None;
+    }
+
+    /// Paths are stored one per line as raw bytes, since file names need not
+    /// be valid UTF-8.
+    fn write_yank_register(&self, paths: &[PathBuf]) -> Result<(), AppError> {
+        let mut output = Vec::new();
+        for p in paths {
+            output.extend_from_slice(&paths::os_to_bytes(p.as_os_str()));
+            output.push(b'\n');
+        }
+        fs::write(self.yank_reg.as_path(), output).context("write", &self.yank_reg)
+    }
+
+    fn read_yank_register(&self) -> Result<Vec<PathBuf>, AppError> {
+        let contents = match fs::read(self.yank_reg.as_path()) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(AppError::NothingYanked),
+            Err(e) => return Err(e).context("read", &self.yank_reg),
+        };
+        Ok(contents
+            .split(|b| *b == b'\n')
+            .filter(|l| !l.is_empty())
+            .map(|l| PathBuf::from(paths::os_from_bytes(l)))
+            .collect())
+    }
+
+    /// Puts `paths` in the chosen register, or the unnamed one.
+    fn yank(&mut self, paths: Vec<PathBuf>, mode: YankMode) {
+        match self.register.take() {
+            Some('+') => {
+                if let Err(e) = clipboard::write_files(&paths) {
+                    self.ui
+                        .error(format!("Could not write to the clipboard: {}", e));
+                }
+            }
+            Some(register) => {
+                self.registers.insert(register, (paths, mode));
+            }
+            None => {
+                if let Err(e) = self.write_yank_register(&paths) {
+                    return self.report(e);
+                }
+                self.yank_mode = Some(mode);
+                // Not having a clipboard tool installed is no reason to complain
+                if mode == YankMode::Copying && !self.options.deterministic {
+                    if let Err(e) = clipboard::write_files(&paths) {
+                        log::warn!("Could not write to the clipboard: {}", e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The contents of the chosen register, or the unnamed one.
+    fn yanked(&mut self) -> Result<(Vec<PathBuf>, YankMode), AppError> {
+        match self.register.take() {
+            Some('+') => match clipboard::read_files() {
+                Ok((paths, _)) if paths.is_empty() => Err(AppError::EmptyRegister('+')),
+                Ok((paths, true)) => Ok((paths, YankMode::Cutting)),
+                Ok((paths, false)) => Ok((paths, YankMode::Copying)),
+                Err(e) => Err(AppError::Clipboard(e)),
+            },
+            Some(register) => self
+                .registers
+                .get(&register)
+                .cloned()
+                .ok_or(AppError::EmptyRegister(register)),
+            None => Ok((
+                self.read_yank_register()?,
+                self.yank_mode.unwrap_or(YankMode::Copying),
+            )),
+        }
+    }
+
+    /// Puts the names, paths or contents of `paths` on the system clipboard,
+    /// as there is nowhere to paste text within trooper itself.
+    fn yank_text(&mut self, action: AppActions, paths: &[PathBuf]) {
+        self.register = None;
+        let text = match text_to_yank(action, paths) {
+            Ok(text) => text,
+            Err(e) => return self.report(e),
+        };
+        if !self.options.deterministic {
+            if let Err(e) = clipboard::write_text(&text) {
+                return self
+                    .ui
+                    .error(format!("Could not write to the clipboard: {}", e));
+            }
+        }
+        match text.lines().count() {
+            1 if text.chars().count() <= 60 => self.ui.info(format!("Yanked {}", text)),
+            n => self.ui.info(format!("Yanked {} lines", n)),
+        }
+    }
+
+    fn copy_files(&mut self, paths: Vec<PathBuf>) {
+        self.yank(paths, YankMode::Copying);
+    }
+
+    /// Deletes local files on a worker thread, like pastes. Remote files are
+    /// deleted right away.
+    fn delete_files(&mut self, paths: Vec<PathBuf>) {
+        if !self.may_change(AppActions::DeleteFile, &paths) {
+            return;
+        }
+        if self.remote.is_none() && !paths.is_empty() {
+            let description = job_description("Deleting", &paths);
+            return self.start_job(description, paths.clone(), Task::Delete { paths });
+        }
+
+        for p in paths {
+            self.dir_sizes.invalidate(&p);
+            if let Err(e) = self.vfs.remove(&p).context("delete", &p) {
+                self.report(e);
+                break;
+            }
+        }
+
+        self.update_dir_contents();
+    }
+
+    fn cut_files(&mut self, paths: Vec<PathBuf>) {
+        self.yank(paths, YankMode::Cutting);
+    }
+
+    /// Outside visual mode the selection is just the entry under the cursor.
+    fn follow_cursor(&mut self) {
+        if self.active_mode == ActiveMode::Normal {
+            self.selection_start = self.ui.scroll_y + self.ui.cursor_y;
+        }
+    }
+
+    /// The paths operations act on. That is the visual selection in visual
+    /// mode, otherwise the entries selected by `:select` if there are any and
+    /// otherwise the entry under the cursor.
+    fn selected_paths(&self) -> Vec<PathBuf> {
+        if self.active_mode != ActiveMode::Visual && !self.marked.is_empty() {
+            return self.marked.iter().cloned().collect();
+        }
+        self.get_selected_entries()
+            .iter()
+            .map(|d| d.path())
+            .collect()
+    }
+
+    /// Adds the entries in the listing whose names match `pattern` to the
+    /// selection, or removes them. Patterns are globs, or regular expressions
+    /// between slashes such as `/\.log$/`.
+    fn select_matching(&mut self, pattern: &str, select: bool) {
+        let matches: Box<dyn Fn(&str) -> bool> = match regex_in(pattern) {
+            Some(re) => match Regex::new(re) {
+                Ok(re) => Box::new(move |name| re.is_match(name)),
+                Err(e) => return self.ui.error(e.to_string()),
+            },
+            None => Box::new(|name| glob::matches(pattern, name)),
+        };
+        let paths: Vec<PathBuf> = self
+            .dir_contents
+            .iter()
+            .filter(|e| matches(&e.file_name().to_string_lossy()))
+            .map(|e| e.path())
+            .collect();
+        if paths.is_empty() {
+            return self.ui.warn(format!("Pattern not found: {}", pattern));
+        }
+        let changed = paths
+            .into_iter()
+            .filter(|p| {
+                if select {
+                    self.marked.insert(p.clone())
+                } else {
+                    self.marked.remove(p)
+                }
+            })
+            .count();
+        let verb = if select { "Selected" } else { "Unselected" };
+        self.ui.info(format!(
+            "{} {} entries, {} selected",
+            verb,
+            changed,
+            self.marked.len()
+        ));
+    }
+
+    /// How many entries the visual selection or the entries selected by
+    /// `:select` hold, and their size. None when neither is in use.
+    fn selection_stats(&self) -> Option<SelectionStats> {
+        let entries: Vec<Entry> = if self.active_mode == ActiveMode::Visual {
+            self.get_selected_entries().to_vec()
+        } else if !self.marked.is_empty() {
+            // Entries in other directories are looked up one by one
+            self.marked
+                .iter()
+                .filter_map(|p| match self.dir_contents.iter().find(|e| e.path == *p) {
+                    Some(e) => Some(e.clone()),
+                    None => self.vfs.entry(p).ok(),
+                })
+                .collect()
+        } else {
+            return None;
+        };
+        let mut stats = SelectionStats {
+            count: entries.len(),
+            size: 0,
+            partial: false,
+        };
+        for e in &entries {
+            if !e.is_dir {
+                stats.size += e.len;
+            } else if e.is_symlink {
+                continue;
+            } else if let Some(size) = self.dir_sizes.get(&e.path) {
+                stats.size += size;
+            } else {
+                stats.partial = true;
+            }
+        }
+        Some(stats)
+    }
+
+    /// Moves the cursor to the `count`th entry after it, or before it, whose
+    /// name starts with `letter`. Case is ignored as when searching.
+    fn find_letter(&mut self, letter: char, forward: bool, count: usize) {
+        let pattern = letter.to_string();
+        let ignore_case = self.settings.ignores_case(&pattern);
+        let starts = |e: &Entry| {
+            let name = e.file_name().to_string_lossy().into_owned();
+            match name.chars().next() {
+                Some(first) if ignore_case => first.to_lowercase().eq(letter.to_lowercase()),
+                Some(first) => first == letter,
+                None => false,
+            }
+        };
+        let len = self.dir_contents.len() as i32;
+        let step = if forward { 1 } else { -1 };
+        let mut index = self.ui.scroll_y + self.ui.cursor_y;
+        for _ in 0..count {
+            let mut next = index;
+            loop {
+                next += step;
+                if self.settings.wrap_around {
+                    next = next.rem_euclid(len.max(1));
+                }
+                if next < 0 || next >= len || next == index {
+                    return self.ui.info(format!("No entry starts with {}", letter));
+                }
+                if starts(&self.dir_contents[next as usize]) {
+                    break;
+                }
+            }
+            index = next;
+        }
+        let cursor = self.ui.scroll_y + self.ui.cursor_y;
+        self.ui.scroll(index - cursor, len, &ActivePanel::Main);
+    }
+
+    /// Opens the selection panel, or closes it if it is open.
+    fn review_selection(&mut self) {
+        if self.active_panel == ActivePanel::Selection {
+            self.active_panel = ActivePanel::Main;
+        } else if self.marked.is_empty() {
+            self.ui.info("Nothing is selected");
+        } else {
+            self.ui.selection_y = 0;
+            self.active_panel = ActivePanel::Selection;
+        }
+    }
+
+    /// The entry under the cursor in the selection panel.
+    fn marked_at_cursor(&self) -> Option<PathBuf> {
+        self.marked
+            .iter()
+            .nth(self.ui.selection_y as usize)
+            .cloned()
+    }
+
+    /// Adds the entry under the cursor to the selection, or removes it, and
+    /// moves on to the next one. In the selection panel it is always removed,
+    /// and the panel closes once nothing is left.
+    fn toggle_selected(&mut self) {
+        if self.active_panel == ActivePanel::Selection {
+            if let Some(path) = self.marked_at_cursor() {
+                self.marked.remove(&path);
+            }
+            if self.marked.is_empty() {
+                self.active_panel = ActivePanel::Main;
+            }
+            let len = self.marked.len() as i32;
+            return self.ui.scroll(0, len, &ActivePanel::Selection);
+        }
+        let index = (self.ui.scroll_y + self.ui.cursor_y) as usize;
+        if let Some(path) = self.dir_contents.get(index).map(|e| e.path()) {
+            if !self.marked.remove(&path) {
+                self.marked.insert(path);
+            }
+            let len = self.dir_contents.len() as i32;
+            self.ui.step(1, len, &ActivePanel::Main, false);
+        }
+    }
+
+    /// Selects every entry in the listing, or flips whether each of them is
+    /// selected when inverting. Entries selected in other directories stay so.
+    fn select_listing(&mut self, invert: bool) {
+        for path in self.dir_contents.iter().map(|e| e.path()) {
+            if !(invert && self.marked.remove(&path)) {
+                self.marked.insert(path);
+            }
+        }
+        self.ui.info(format!("{} selected", self.marked.len()));
+    }
+
+    fn get_selected_entries(&self) -> &[Entry] {
+        if !&self.dir_contents.is_empty() {
+            let selection_end = (self.ui.scroll_y + self.ui.cursor_y) as usize;
+            // Nothing has been drawn to follow the cursor yet
+            let selection_start = usize::try_from(self.selection_start).unwrap_or(selection_end);
+            return &self.dir_contents[std::cmp::min(selection_end, selection_start)
+                ..=std::cmp::max(selection_end, selection_start)];
+        } else {
+            return &[];
+        }
+    }
+
+    fn get_selected_bookmark(&self) -> Option<&Bookmark> {
+        self.bookmarks
+            .get((self.ui.bookmark_y + self.ui.bookmark_scroll_y) as usize)
+    }
+
+    fn selected_device(&self) -> Option<&Device> {
+        if self.active_panel != ActivePanel::Devices {
+            return None;
+        }
+        self.devices.get(self.ui.device_y as usize)
+    }
+
+    /// Lists the devices again, keeping the cursor inside the list. Devices
+    /// differ between machines, so there are none in deterministic mode.
+    fn refresh_devices(&mut self) {
+        if self.options.deterministic {
+            return;
+        }
+        self.devices = devices::list_devices();
+        self.ui.device_y = self.ui.device_y.min(self.devices.len() as i32 - 1).max(0);
+        if self.devices.is_empty() && self.active_panel == ActivePanel::Devices {
+            self.active_panel = ActivePanel::Main;
+        }
+        self.update_bookmark_width();
+    }
+
+    /// Enters the mount point of the selected device, mounting it first if
+    /// needed.
+    fn open_device(&mut self) {
+        let device = match self.selected_device() {
+            Some(device) => device.clone(),
+            None => return,
+        };
+        let mount_point = match device.mount_point {
+            Some(mount_point) => mount_point,
+            None => match devices::mount(&device.device) {
+                Ok(mount_point) => {
+                    self.refresh_devices();
+                    mount_point
+                }
+                Err(e) => {
+                    self.ui
+                        .error(format!("Could not mount {}: {}", device.name, e));
+                    return;
+                }
+            },
+        };
+
+        self.disconnect_sftp();
+        self.enter_dir(&mount_point);
+        self.active_panel = ActivePanel::Main;
+        self.ui
+            .scroll_abs(0, self.dir_contents.len() as i32, &self.active_panel);
+    }
+
+    fn paste_yanked_files(&mut self) {
+        let (yanked, mode) = match self.yanked() {
+            Ok(yanked) => yanked,
+            Err(e) => return self.report(e),
+        };
+        if mode == YankMode::Cutting && !self.may_change(AppActions::PasteFiles, &yanked) {
+            return;
+        }
+
+        let dest_dir = self.current_dir.clone();
+        let mut transfers = Vec::new();
+
+        for p in yanked {
+            // Yanked entries may have been removed since
+            let md = match fs::metadata(&p).context("paste", &p) {
+                Ok(md) => md,
+                Err(e) => {
+                    self.report(e);
+                    continue;
+                }
+            };
+
+            // Such as `/` or `..` from the clipboard
+            let name = match p.file_name() {
+                Some(name) => name.to_owned(),
+                None => {
+                    self.ui
+                        .error(format!("Can't paste {}, it has no name", p.display()));
+                    continue;
+                }
+            };
+            if md.is_dir() || md.is_file() {
+                transfers.push(Transfer {
+                    to: dest_dir.join(name),
+                    from: p,
+                });
+            }
+        }
+
+        if transfers.is_empty() {
+            return;
+        }
+
+        match mode {
+            YankMode::Cutting => self.start_transfer("Moving", JobKind::Move, transfers),
+            YankMode::Copying => self.start_transfer("Copying", JobKind::Copy, transfers),
+        }
+    }
+
+    /// Links to the yanked entries from the current directory instead of
+    /// copying them. Directories and files on other file systems cannot be
+    /// hard linked, so those are symlinked instead.
+    fn paste_links(&mut self, hard: bool) {
+        let (yanked, _) = match self.yanked() {
+            Ok(yanked) => yanked,
+            Err(e) => return self.report(e),
+        };
+
+        let mut symlinked = 0;
+        for target in yanked {
+            let md = match fs::symlink_metadata(&target).context("link", &target) {
+                Ok(md) => md,
+                Err(e) => {
+                    self.report(e);
+                    continue;
+                }
+            };
+            let name = match target.file_name() {
+                Some(name) => name,
+                None => {
+                    self.ui.error(format!(
+                        "Can't link to {}, it has no name",
+                        target.display()
+                    ));
+                    continue;
+                }
+            };
+            let link = free_copy_name(self.current_dir.join(name), md.is_dir());
+            if hard && fs::hard_link(&target, &link).is_ok() {
+                continue;
+            }
+            match symlink(&target, &link).context("link", &target) {
+                Ok(()) if hard => symlinked += 1,
+                Ok(()) => {}
+                Err(e) => self.report(e),
+            }
+        }
+
+        if symlinked > 0 {
+            self.ui.info(format!(
+                "Symlinked {} entries which could not be hard linked",
+                symlinked
+            ));
+        }
+        self.update_dir_contents();
+    }
+
+    /* Large transfers run on a worker thread so the interface stays
+     * responsive. The directory listing is refreshed in on_tick once the job
+     * reports back.
+     */
+    fn start_transfer(&mut self, verb: &str, kind: JobKind, transfers: Vec<Transfer>) {
+        let sources: Vec<PathBuf> = transfers.iter().map(|t| t.from.clone()).collect();
+        let description = job_description(verb, &sources);
+
+        let paths = transfers
+            .iter()
+            .flat_map(|t| [t.from.clone(), t.to.clone()])
+            .collect();
+        let task = Task::Transfer {
+            kind,
+            transfers,
+            preserve: self.settings.preserve,
+        };
+        self.start_job(description, paths, task);
+    }
+
+    /// Queues a job shown in the status line and the jobs panel. Jobs run one
+    /// at a time, in the order they were started.
+    fn start_job(&mut self, description: String, paths: Vec<PathBuf>, task: Task) {
+        let id = self.next_job_id;
+        self.next_job_id += 1;
+        self.jobs.push(Job::new(id, description, paths, task));
+        self.run_next_job();
+        if self.options.deterministic {
+            self.wait_for_jobs();
+        }
+    }
 
-    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+    /// Starts the first queued job, unless one is running already.
+    fn run_next_job(&mut self) {
+        if self.jobs.iter().any(|j| j.state == JobState::Running) {
+            return;
+        }
+        if let Some(job) = self.jobs.iter_mut().find(|j| j.state == JobState::Queued) {
+            job.state = JobState::Running;
+            let task = job.task.clone();
+            jobs::spawn(job.id, task, job.cancel.clone(), self.job_sender.clone());
+        }
+    }
+
+    /// The job under the cursor in the jobs panel, or else the last one
+    /// matching `wanted`.
+    fn target_job(&mut self, wanted: impl Fn(&Job) -> bool) -> Option<&mut Job> {
+        if self.active_panel == ActivePanel::Jobs {
+            self.jobs.get_mut(self.ui.job_y as usize)
+        } else {
+            self.jobs.iter_mut().rev().find(|j| wanted(j))
+        }
+    }
+
+    fn connect_sftp(&mut self, spec: &str) {
+        let target = match Target::parse(spec) {
+            Some(target) => target,
+            None => {
+                self.ui.info("Usage: sftp [user@]host[:path]");
+                return;
+            }
+        };
+        let connection = SftpFs::connect(&target).and_then(|remote| {
+            let dir = remote.canonicalize(target.path.as_deref().unwrap_or(Path::new(".")))?;
+            Ok((remote, dir))
+        });
+
+        match connection {
+            Ok((remote, dir)) => {
+                if self.remote.is_none() {
+                    self.local_dir = self.current_dir.to_path_buf();
+                }
+                self.vfs = Box::new(remote);
+                self.remote = Some(target);
+                self.snapshot = None;
+                self.tail = None;
+                self.enter_dir(&dir);
+                self.active_panel = ActivePanel::Main;
+                self.ui
+                    .scroll_abs(0, self.dir_contents.len() as i32, &self.active_panel);
+            }
+            Err(e) => self
+                .ui
+                .error(format!("Could not connect to {}: {}", target, e)),
+        }
+    }
 
-    use super::{read_config, str_to_key_events, AppActions};
+    fn disconnect_sftp(&mut self) {
+        if let Some(target) = self.remote.take() {
+            self.vfs = Box::new(LocalFs);
+            let dir = self.local_dir.clone();
+            self.enter_dir(&dir);
+            self.ui
+                .scroll_abs(0, self.dir_contents.len() as i32, &self.active_panel);
+            self.ui.info(format!("Disconnected from {}", target));
+        }
+    }
+
+    /// Copies remote entries into the local directory `:sftp` was run from.
+    fn download(&mut self, paths: &[PathBuf]) {
+        let target = match &self.remote {
+            Some(target) => target.clone(),
+            None => return self.ui.info("Not connected to a remote host"),
+        };
+        let mut transfers = Vec::new();
+        for p in paths {
+            let name = match p.file_name() {
+                Some(name) => name,
+                None => {
+                    return self
+                        .ui
+                        .error(format!("Could not download {}: it has no name", p.display()))
+                }
+            };
+            transfers.push(Transfer {
+                from: p.clone(),
+                to: self.local_dir.join(name),
+            });
+        }
+        let description = job_description("Downloading", paths);
+        let local = transfers.iter().map(|t| t.to.clone()).collect();
+        self.start_job(description, local, Task::Download { target, transfers });
+    }
+
+    /// Copies the entries in the yank register into the current remote
+    /// directory.
+    fn upload(&mut self) {
+        if self.remote.is_none() {
+            self.ui.info("Not connected to a remote host");
+            return;
+        }
+        let yanked = match self.yanked() {
+            Ok((yanked, _)) => yanked,
+            Err(e) => return self.report(e),
+        };
+        for src in yanked {
+            let dest = self.current_dir.join(src.file_name().unwrap_or_default());
+            let result = if self.vfs.entry(&dest).is_ok() {
+                Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "Destination already exists",
+                ))
+            } else {
+                vfs::copy_between(&LocalFs, &src, &*self.vfs, &dest)
+            };
+            if let Err(e) = result {
+                self.ui
+                    .error(format!("Could not upload {}: {}", src.display(), e));
+                break;
+            }
+        }
+        self.update_dir_contents();
+    }
+
+    fn compress(&mut self, paths: &[PathBuf], name: &str) {
+        if name.is_empty() {
+            self.ui.info("Usage: compress <name>.tar.gz|.zip");
+            return;
+        }
+        let format = match archive::Format::from_name(name) {
+            Some(format) => format,
+            None => {
+                self.ui.info(format!(
+                    "Unknown archive format: {} (use .tar.gz or .zip)",
+                    name
+                ));
+                return;
+            }
+        };
+        if paths.is_empty() {
+            self.ui.info("Nothing to compress");
+            return;
+        }
+        let archive = self.current_dir.join(name);
+        if archive.exists() {
+            self.ui.info(format!("{} already exists", name));
+            return;
+        }
+
+        let task = Task::Compress {
+            archive: archive.clone(),
+            format,
+            sources: paths.to_vec(),
+        };
+        self.start_job(format!("Compressing {}", name), vec![archive], task);
+    }
+
+    fn update_dir_contents(&mut self) {
+        // The current directory may have been removed from under us
+        self.dir_contents = match self
+            .read_dir_sorted(self.current_dir.as_path())
+            .context("read", &self.current_dir)
+        {
+            Ok(contents) => contents,
+            Err(e) => {
+                self.report(e);
+                Vec::new()
+            }
+        };
+        self.disk_space = disk::disk_space(self.current_dir.as_path());
+        // Selected entries may have been deleted or moved away
+        let vfs = &self.vfs;
+        self.marked.retain(|p| vfs.entry(p).is_ok());
+
+        self.ui.scroll_abs(
+            self.ui.cursor_y + self.ui.scroll_y,
+            self.dir_contents.len() as i32,
+            &self.active_panel,
+        );
+    }
+
+    fn handle_action(&mut self, action: AppActions, args: Vec<String>) {
+        // Anything but repeating the change, by `.` or the command line, cancels
+        if !matches!(
+            action,
+            AppActions::OpenCommandMode | AppActions::RepeatLastChange
+        ) && self
+            .unconfirmed
+            .as_ref()
+            .map_or(false, |(a, _)| *a != action)
+        {
+            self.unconfirmed = None;
+        }
+        if !self.snapshots.is_empty() {
+            self.pick_snapshot(action);
+            return;
+        }
+        if self.scroll_popup(action) {
+            return;
+        }
+        if self.read_only && action.writes_files() {
+            self.ui
+                .warn(format!("{:?} is not available in read-only mode", action));
+            return;
+        }
+        if self.snapshot.is_some() && action.modifies_files() {
+            self.ui.warn("Snapshots are read-only");
+            return;
+        }
+        if self.remote.is_some() && action.needs_local_files() {
+            self.ui
+                .warn(format!("{:?} is not available over SFTP", action));
+            return;
+        }
+        if action == AppActions::RepeatLastChange {
+            match self.last_change.clone() {
+                Some((action, args)) => self.handle_action(action, args),
+                None => self.ui.info("Nothing to repeat"),
+            }
+            return;
+        }
+        if action.modifies_files() {
+            self.last_change = Some((action, args.clone()));
+        }
+
+        let selected_paths = self.selected_paths();
+        // Cutting only fills the yank register, the move happens on paste
+        if self.dry_run && action.modifies_files() && action != AppActions::CutFiles {
+            return self.describe_dry_run(action, &args, &selected_paths);
+        }
+        match self.active_panel {
+            ActivePanel::Main => match action {
+                AppActions::MoveDown => self.ui.step(
+                    1,
+                    self.dir_contents.len() as i32,
+                    &self.active_panel,
+                    self.settings.wrap_around,
+                ),
+                AppActions::MoveUp => self.ui.step(
+                    -1,
+                    self.dir_contents.len() as i32,
+                    &self.active_panel,
+                    self.settings.wrap_around,
+                ),
+                AppActions::MoveUpDir => {
+                    self.move_up_dir();
+                    let index = self.find_name(&self.ui.last_name).unwrap_or(0);
+                    self.ui
+                        .scroll_abs(index, self.dir_contents.len() as i32, &self.active_panel);
+                    self.ui.last_name = self.current_dir.file_name().unwrap_or_default().to_owned();
+                }
+                AppActions::EnterDir => {
+                    // Follows symlinks, entering the directory a link points to
+                    if self.dir_contents[(self.ui.cursor_y + self.ui.scroll_y) as usize].is_dir {
+                        let path =
+                            &self.dir_contents[(self.ui.cursor_y + self.ui.scroll_y) as usize];
+                        self.ui.last_name = path.file_name();
+                        self.enter_dir(&path.path());
+                        self.ui
+                            .scroll_abs(0, self.dir_contents.len() as i32, &self.active_panel);
+                    }
+                }
+                AppActions::Quit => {
+                    self.should_quit = true;
+                }
+                AppActions::MoveToTop => {
+                    self.ui
+                        .scroll_abs(0, self.dir_contents.len() as i32, &self.active_panel)
+                }
+                AppActions::MoveToBottom => self.ui.scroll_abs(
+                    self.dir_contents.len() as i32 - 1,
+                    self.dir_contents.len() as i32,
+                    &self.active_panel,
+                ),
+                AppActions::CopyFiles => {
+                    self.copy_files(selected_paths);
+                    self.active_mode = ActiveMode::Normal;
+                }
+                AppActions::CutFiles => {
+                    self.cut_files(selected_paths);
+                    self.active_mode = ActiveMode::Normal;
+                }
+                AppActions::PasteFiles => self.paste_yanked_files(),
+                AppActions::PasteSymlinks => self.paste_links(false),
+                AppActions::PasteHardlinks => self.paste_links(true),
+                AppActions::YankNames | AppActions::YankPaths | AppActions::YankContents => {
+                    self.yank_text(action, &selected_paths);
+                    self.active_mode = ActiveMode::Normal;
+                }
+                AppActions::OpenCommandMode => self.enter_mode(ActiveMode::Command),
+                AppActions::DeleteFile => self.delete_files(selected_paths),
+                AppActions::CreateBookmark => self.create_bookmark(),
+                AppActions::DeleteBookmark => {}
+                AppActions::ToggleBookmark => {
+                    self.active_panel = ActivePanel::Bookmarks;
+                }
+                AppActions::MoveToLeftPanel => {
+                    self.active_panel = ActivePanel::Bookmarks;
+                }
+                AppActions::MoveEntry => {
+                    if args.len() > 0 && selected_paths.len() == 1 {
+                        self.mv_entry(&selected_paths[0], &args[0]);
+                    }
+                }
+                AppActions::ToggleHiddenFiles => {
+                    self.show_hidden_files = !self.show_hidden_files;
+                    self.update_dir_contents();
+                }
+                AppActions::ToggleVisualMode => {
+                    if self.active_mode == ActiveMode::Normal {
+                        self.active_mode = ActiveMode::Visual;
+                        self.selection_start = self.ui.cursor_y + self.ui.scroll_y;
+                    } else if self.active_mode == ActiveMode::Visual {
+                        self.active_mode = ActiveMode::Normal;
+                    }
+                }
+                AppActions::MoveToRightPanel => {}
+                AppActions::CreateDir => {}
+                AppActions::CreateFile => {}
+                AppActions::CreateLink => {}
+                AppActions::ChangeDir => {}
+                AppActions::FindForward => {}
+                AppActions::FindBackward => {}
+                AppActions::RepeatFind => {}
+                AppActions::RepeatFindReversed => {}
+                AppActions::GoHome => {}
+                AppActions::GoToRoot => {}
+                AppActions::GoToMountPoint => {}
+                AppActions::RunShell => {}
+                AppActions::PickSnapshot => {}
+                AppActions::ToggleTail => {
+                    if self.options.deterministic {
+                        self.ui
+                            .info("Following files is disabled in deterministic mode");
+                    } else if self.tail.is_some() {
+                        self.tail = None;
+                    } else if let Some(p) = selected_paths.first() {
+                        if p.is_file() {
+                            match Tail::new(p) {
+                                Ok(tail) => self.tail = Some(tail),
+                                Err(e) => self.ui.error(format!("Could not follow file: {}", e)),
+                            }
+                        }
+                    }
+                }
+                AppActions::CalculateSizes => {
+                    let dirs = self
+                        .dir_contents
+                        .iter()
+                        .filter(|d| d.is_dir && !d.is_symlink)
+                        .map(|d| d.path())
+                        .collect();
+                    self.dir_sizes.request(dirs);
+                    if self.options.deterministic {
+                        self.dir_sizes.wait();
+                    }
+                }
+                AppActions::ChangeMode => match args.as_slice() {
+                    [spec] => self.change_mode(&selected_paths, spec),
+                    _ => self.ui.info("Usage: chmod <mode>"),
+                },
+                AppActions::ChangeOwner => match args.as_slice() {
+                    [spec] => self.change_owner(&selected_paths, spec),
+                    _ => self.ui.info("Usage: chown <user>[:<group>]"),
+                },
+                AppActions::Duplicate => match selected_paths.as_slice() {
+                    [p] => self.duplicate(p, &args.join(" ")),
+                    _ => self.ui.info("Select a single entry to duplicate"),
+                },
+                AppActions::Sha256Sum => {
+                    self.compute_checksums(&selected_paths, Algorithm::Sha256, args.first())
+                }
+                AppActions::Md5Sum => {
+                    self.compute_checksums(&selected_paths, Algorithm::Md5, args.first())
+                }
+                AppActions::Compress => self.compress(&selected_paths, &args.join(" ")),
+                AppActions::Download => self.download(&selected_paths),
+                AppActions::ChooseFiles => self.choose_files(selected_paths),
+                AppActions::Upload => self.upload(),
+                AppActions::ConnectSftp => {}
+                AppActions::ShowMessages => {}
+                AppActions::ShowHelp => {}
+                AppActions::OpenPalette => {}
+                AppActions::ShowHistory => {}
+                AppActions::Search => self.enter_mode(ActiveMode::Search),
+                AppActions::SearchNext => self.search_next(true),
+                AppActions::SearchPrevious => self.search_next(false),
+                AppActions::Filter => self.enter_mode(ActiveMode::Filter),
+                AppActions::RepeatLastChange => {}
+                AppActions::RecordMacro => {}
+                AppActions::PlayMacro => {}
+                AppActions::SelectRegister => {}
+                AppActions::ShowRegisters => {}
+                AppActions::CancelJobs => {}
+                AppActions::RetryJob => {}
+                AppActions::ToggleJobs => {}
+                AppActions::Set => {}
+                AppActions::ReloadConfig => {}
+                AppActions::WriteDefaultConfig => {}
+                AppActions::ShellBinding => {}
+                AppActions::PluginCommand => {}
+                AppActions::Reveal => {}
+                AppActions::Select => {}
+                AppActions::Unselect => {}
+                AppActions::ToggleSelected => {}
+                AppActions::ReviewSelection => {}
+                AppActions::SelectAll => {}
+                AppActions::InvertSelection => {}
+                AppActions::Refresh => {}
+                AppActions::WidenBookmarks => {}
+                AppActions::NarrowBookmarks => {}
+                AppActions::ToggleDevices => {}
+                AppActions::MountDevice => {}
+                AppActions::UnmountDevice => {}
+                AppActions::RestoreSnapshot => self.restore_from_snapshot(&selected_paths),
+                AppActions::ShowProperties => {
+                    if self.popup.is_some() {
+                        self.popup = None;
+                    } else if let Some(p) = selected_paths.first() {
+                        match properties::properties(p) {
+                            Ok(rows) => {
+                                self.checksums = None;
+                                self.popup = Some(Popup {
+                                    title: String::from("Properties"),
+                                    rows: rows
+                                        .into_iter()
+                                        .map(|(label, value)| (String::from(label), value))
+                                        .collect(),
+                                    scroll: 0,
+                                })
+                            }
+                            Err(e) => self.ui.error(format!("Could not read metadata: {}", e)),
+                        }
+                    }
+                }
+            },
+            ActivePanel::Bookmarks => match action {
+                AppActions::MoveDown => self.ui.step(
+                    1,
+                    self.bookmarks.len() as i32,
+                    &self.active_panel,
+                    self.settings.wrap_around,
+                ),
+                AppActions::MoveUp => self.ui.step(
+                    -1,
+                    self.bookmarks.len() as i32,
+                    &self.active_panel,
+                    self.settings.wrap_around,
+                ),
+                AppActions::EnterDir => {
+                    if let Some(b) = self.get_selected_bookmark() {
+                        let path = b.path.clone();
+                        // Bookmarks are local
+                        self.disconnect_sftp();
+                        self.enter_dir(&path);
+                    }
+                    self.active_panel = ActivePanel::Main;
+                    self.ui
+                        .scroll_abs(0, self.dir_contents.len() as i32, &self.active_panel);
+                }
+                AppActions::Quit => self.should_quit = true,
+                AppActions::DeleteBookmark => self.delete_bookmark(),
+                AppActions::ToggleBookmark => match self.active_panel {
+                    ActivePanel::Bookmarks => self.active_panel = ActivePanel::Main,
+                    _ => self.active_panel = ActivePanel::Bookmarks,
+                },
+                AppActions::OpenCommandMode => self.enter_mode(ActiveMode::Command),
+                AppActions::MoveToRightPanel => {
+                    self.active_panel = ActivePanel::Main;
+                }
+                _ => {}
+            },
+            ActivePanel::Devices => match action {
+                AppActions::MoveDown => self.ui.step(
+                    1,
+                    self.devices.len() as i32,
+                    &self.active_panel,
+                    self.settings.wrap_around,
+                ),
+                AppActions::MoveUp => self.ui.step(
+                    -1,
+                    self.devices.len() as i32,
+                    &self.active_panel,
+                    self.settings.wrap_around,
+                ),
+                AppActions::EnterDir => self.open_device(),
+                AppActions::Quit => self.should_quit = true,
+                AppActions::ToggleBookmark => self.active_panel = ActivePanel::Bookmarks,
+                AppActions::OpenCommandMode => self.enter_mode(ActiveMode::Command),
+                AppActions::MoveToRightPanel => {
+                    self.active_panel = ActivePanel::Main;
+                }
+                _ => {}
+            },
+            ActivePanel::Jobs => match action {
+                AppActions::MoveDown => self.ui.step(
+                    1,
+                    self.jobs.len() as i32,
+                    &self.active_panel,
+                    self.settings.wrap_around,
+                ),
+                AppActions::MoveUp => self.ui.step(
+                    -1,
+                    self.jobs.len() as i32,
+                    &self.active_panel,
+                    self.settings.wrap_around,
+                ),
+                AppActions::Quit => self.should_quit = true,
+                AppActions::OpenCommandMode => self.enter_mode(ActiveMode::Command),
+                _ => {}
+            },
+            ActivePanel::Selection => match action {
+                AppActions::MoveDown => self.ui.step(
+                    1,
+                    self.marked.len() as i32,
+                    &self.active_panel,
+                    self.settings.wrap_around,
+                ),
+                AppActions::MoveUp => self.ui.step(
+                    -1,
+                    self.marked.len() as i32,
+                    &self.active_panel,
+                    self.settings.wrap_around,
+                ),
+                AppActions::EnterDir => {
+                    if let Some(path) = self.marked_at_cursor() {
+                        self.active_panel = ActivePanel::Main;
+                        self.reveal(&path);
+                    }
+                }
+                // Operations act on the whole selection, as from the listing
+                AppActions::CopyFiles | AppActions::CutFiles | AppActions::DeleteFile => {
+                    self.active_panel = ActivePanel::Main;
+                    return self.handle_action(action, args);
+                }
+                AppActions::Quit => self.should_quit = true,
+                AppActions::OpenCommandMode => self.enter_mode(ActiveMode::Command),
+                _ => {}
+            },
+        }
+
+        match action {
+            AppActions::CreateDir => {
+                for arg in &args {
+                    if let Err(e) = self.create_dir(arg) {
+                        self.report(e);
+                        break;
+                    }
+                }
+                self.update_dir_contents();
+            }
+            AppActions::CreateFile => {
+                for arg in &args {
+                    if let Err(e) = self.create_file(arg) {
+                        self.ui.error(format!("touch {}: {}", arg, e));
+                    }
+                }
+                self.update_dir_contents();
+                if let Some(index) = args.first().and_then(|a| self.find_name(OsStr::new(a))) {
+                    self.active_panel = ActivePanel::Main;
+                    self.ui
+                        .scroll_abs(index, self.dir_contents.len() as i32, &self.active_panel);
+                }
+            }
+            AppActions::CreateLink => {
+                let symbolic = args.iter().any(|a| a == "-s");
+                let operands: Vec<&String> = args.iter().filter(|a| *a != "-s").collect();
+                let result = match operands.as_slice() {
+                    [] => match self.yanked() {
+                        Ok((yanked, _)) => self.link_yanked_files(&yanked, symbolic),
+                        Err(e) => return self.report(e),
+                    },
+                    [target] => self.create_link(target, None, symbolic),
+                    [target, name] => self.create_link(target, Some(name), symbolic),
+                    _ => Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "Usage: ln [-s] [target [name]]",
+                    )),
+                };
+                if let Err(e) = result {
+                    self.ui.error(format!("ln: {}", e));
+                }
+                self.update_dir_contents();
+            }
+            AppActions::ToggleDevices => {
+                if self.active_panel == ActivePanel::Devices {
+                    self.active_panel = ActivePanel::Main;
+                } else {
+                    self.refresh_devices();
+                    if self.devices.is_empty() {
+                        self.ui.info("No devices found");
+                    } else {
+                        self.active_panel = ActivePanel::Devices;
+                    }
+                }
+            }
+            AppActions::MountDevice | AppActions::UnmountDevice => {
+                match self.selected_device().cloned() {
+                    Some(device) if action == AppActions::MountDevice => {
+                        if let Err(e) = devices::mount(&device.device) {
+                            self.ui
+                                .error(format!("Could not mount {}: {}", device.name, e));
+                        }
+                    }
+                    Some(device) => {
+                        if let Err(e) = devices::unmount(&device.device) {
+                            self.ui
+                                .error(format!("Could not unmount {}: {}", device.name, e));
+                        }
+                    }
+                    None => self.ui.info("Select a device in the devices panel"),
+                }
+                self.refresh_devices();
+            }
+            AppActions::ShowHelp => {
+                if self.popup.take().is_none() {
+                    self.checksums = None;
+                    self.popup = Some(Popup {
+                        title: String::from("Key bindings"),
+                        rows: help_rows(&self.normal.bindings, &self.visual.bindings),
+                        scroll: 0,
+                    });
+                }
+            }
+            AppActions::RecordMacro => match self.recording.take() {
+                Some((register, mut keys)) => {
+                    // Leave out the keys which stopped the recording
+                    keys.truncate(keys.len().saturating_sub(self.mode().chord().len()));
+                    self.macros.insert(register, keys);
+                }
+                None => self.awaiting_register = Some((action, 1)),
+            },
+            AppActions::Set => match args.as_slice() {
+                [] => {
+                    let rows = self
+                        .option_values()
+                        .into_iter()
+                        .map(|(name, value)| (String::from(name), value))
+                        .collect();
+                    self.checksums = None;
+                    self.popup = Some(Popup {
+                        title: String::from("Options"),
+                        rows,
+                        scroll: 0,
+                    });
+                }
+                _ => {
+                    // A value runs to the end of the line, as it may hold spaces
+                    let assignment = args.iter().position(|a| a.contains('='));
+                    let (flags, value) = args.split_at(assignment.unwrap_or(args.len()));
+                    flags.iter().for_each(|arg| self.set_option(arg));
+                    if !value.is_empty() {
+                        self.set_option(&value.join(" "));
+                    }
+                }
+            },
+            AppActions::CancelJobs => {
+                if self.active_panel == ActivePanel::Jobs {
+                    if let Some(job) = self.jobs.get_mut(self.ui.job_y as usize) {
+                        job.cancel();
+                    }
+                } else if self.jobs.iter().any(Job::is_active) {
+                    self.jobs.iter_mut().for_each(Job::cancel);
+                } else {
+                    self.ui.info("No jobs are running");
+                }
+            }
+            AppActions::RetryJob => {
+                let retryable =
+                    |j: &Job| matches!(j.state, JobState::Failed(_) | JobState::Cancelled);
+                match self.target_job(retryable) {
+                    Some(job) if retryable(job) => job.retry(),
+                    _ => return self.ui.info("Only failed or cancelled jobs can be retried"),
+                }
+                self.run_next_job();
+                if self.options.deterministic {
+                    self.wait_for_jobs();
+                }
+            }
+            AppActions::ReloadConfig => self.reload_config(),
+            AppActions::WriteDefaultConfig => {
+                // `:config_default > path` reads like a shell redirection
+                let path = args.iter().find(|a| *a != ">");
+                if let Err(e) = self.write_default_config(path) {
+                    self.report(e);
+                }
+            }
+            AppActions::ToggleJobs => {
+                if self.active_panel == ActivePanel::Jobs {
+                    self.active_panel = ActivePanel::Main;
+                } else if self.jobs.is_empty() {
+                    self.ui.info("No jobs have been started");
+                } else {
+                    self.active_panel = ActivePanel::Jobs;
+                }
+            }
+            AppActions::PlayMacro
+            | AppActions::SelectRegister
+            | AppActions::FindForward
+            | AppActions::FindBackward => self.awaiting_register = Some((action, 1)),
+            AppActions::RepeatFind | AppActions::RepeatFindReversed => match self.last_find {
+                Some((letter, forward)) => {
+                    let forward = forward == (action == AppActions::RepeatFind);
+                    self.find_letter(letter, forward, 1);
+                }
+                None => self.ui.info("Nothing to repeat"),
+            },
+            AppActions::ShowRegisters => {
+                let mut rows = Vec::new();
+                if let Ok(yanked) = self.read_yank_register() {
+                    let mode = self.yank_mode.unwrap_or(YankMode::Copying);
+                    rows.push((String::from("\""), describe_register(&yanked, mode)));
+                }
+                let mut named: Vec<_> = self.registers.iter().collect();
+                named.sort_by_key(|(r, _)| **r);
+                for (register, (paths, mode)) in named {
+                    rows.push((register.to_string(), describe_register(paths, *mode)));
+                }
+                self.checksums = None;
+                self.popup = Some(Popup {
+                    title: String::from("Registers"),
+                    rows,
+                    scroll: 0,
+                });
+            }
+            AppActions::OpenPalette => self.enter_mode(ActiveMode::Palette),
+            AppActions::ShowHistory => {
+                if self.history_lines().is_empty() {
+                    self.ui.info("No commands have been run");
+                } else {
+                    self.enter_mode(ActiveMode::History)
+                }
+            }
+            AppActions::ShowMessages => {
+                // Newest first, so the latest messages fit when there are many
+                let rows: Vec<(String, String)> = self
+                    .ui
+                    .messages
+                    .iter()
+                    .rev()
+                    .map(|(level, message)| (level.to_string(), message.clone()))
+                    .collect();
+                if rows.is_empty() {
+                    self.ui.info("No messages");
+                } else {
+                    self.checksums = None;
+                    self.popup = Some(Popup {
+                        title: String::from("Messages"),
+                        rows,
+                        scroll: 0,
+                    });
+                }
+            }
+            AppActions::ConnectSftp => match args.as_slice() {
+                [] if self.remote.is_some() => self.disconnect_sftp(),
+                [spec] => self.connect_sftp(spec),
+                _ => self.ui.info("Usage: sftp [user@]host[:path]"),
+            },
+            AppActions::ChangeDir => {
+                if !args.is_empty() {
+                    // Paths containing spaces were split into several arguments
+                    self.change_dir(&args.join(" "));
+                }
+            }
+            AppActions::GoHome => self.change_dir_to(&paths::home_dir()),
+            AppActions::GoToRoot => self.change_dir_to(&paths::root_dir()),
+            AppActions::GoToMountPoint => {
+                let mount = disk::mount_point(&self.current_dir);
+                self.change_dir_to(&mount);
+            }
+            AppActions::PickSnapshot => {
+                self.snapshots = snapshots::find_snapshots(&self.live_dir());
+                self.snapshot_index = 0;
+                if self.snapshots.is_empty() {
+                    self.ui.info("No snapshots found");
+                }
+            }
+            AppActions::ShellBinding => self.run_shell_binding(),
+            AppActions::PluginCommand => self.run_plugin_command(args),
+            AppActions::Reveal => {
+                if !args.is_empty() {
+                    let path = self.current_dir.join(paths::expand_home(&args.join(" ")));
+                    self.reveal(&path);
+                }
+            }
+            AppActions::Select => {
+                let arg = args.join(" ");
+                if is_pattern(&arg) {
+                    self.select_matching(&arg, true);
+                } else if !arg.is_empty() {
+                    let path = self.current_dir.join(paths::expand_home(&arg));
+                    self.reveal(&path);
+                }
+            }
+            AppActions::Unselect => {
+                let arg = args.join(" ");
+                if !arg.is_empty() {
+                    self.select_matching(&arg, false);
+                }
+            }
+            AppActions::ToggleSelected => self.toggle_selected(),
+            AppActions::ReviewSelection => self.review_selection(),
+            AppActions::SelectAll => self.select_listing(false),
+            AppActions::InvertSelection => self.select_listing(true),
+            AppActions::Refresh => self.update_dir_contents(),
+            AppActions::WidenBookmarks => self.resize_bookmarks(self.ui.bookmark_width + 1),
+            AppActions::NarrowBookmarks => {
+                self.resize_bookmarks(self.ui.bookmark_width.saturating_sub(1))
+            }
+            AppActions::RunShell => {
+                // The command line was split on single spaces, so this restores it exactly
+                let mut command = args.join(" ");
+                if command.trim().is_empty() {
+                    command = shell::interactive_shell();
+                }
+                let selected = self.selected_paths();
+                match ShellCommand::new(&command, &self.current_dir, &selected) {
+                    Ok(c) => self.shell_command = Some(c),
+                    Err(e) => self.ui.error(format!("Could not run command: {}", e)),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub(crate) fn on_esc(&mut self) {
+        self.flush_escape_pending();
+        self.count = None;
+        self.awaiting_register = None;
+        self.register = None;
+        // Popups opened from the command line have to survive leaving it
+        if self.mode().bindings().is_none() {
+            return self.feed_mode(KeyEvent::new(KeyCode::Esc, KeyModifiers::empty()));
+        }
+        if self.popup.take().is_some() {
+            return;
+        }
+        if !self.snapshots.is_empty() {
+            self.snapshots.clear();
+            return;
+        }
+        if self.active_panel == ActivePanel::Selection {
+            self.active_panel = ActivePanel::Main;
+            return;
+        }
+        if self.active_mode == ActiveMode::Visual {
+            self.active_mode = ActiveMode::Normal;
+        }
+    }
+
+    pub(crate) fn on_enter(&mut self) {
+        self.flush_escape_pending();
+        match self.active_mode {
+            ActiveMode::Normal if !self.snapshots.is_empty() => self.open_snapshot(),
+            ActiveMode::Normal | ActiveMode::Visual
+                if self.options.picking && self.active_panel == ActivePanel::Main =>
+            {
+                self.handle_action(AppActions::ChooseFiles, Vec::new())
+            }
+            _ => {}
+        }
+    }
+
+    /* Nothing is drawn, so the selection is updated by hand, and jobs and
+     * shell commands are waited for before going on with the next line.
+     */
+    /// Runs each line of `script` as a command, for `--batch`. Empty lines
+    /// and lines starting with `#` are skipped. Messages are printed to
+    /// stderr and the script stops at the first warning or error.
+    pub fn run_batch(&mut self, script: &str) -> Result<(), String> {
+        for (i, line) in script.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            self.follow_cursor();
+            self.ui.messages.clear();
+            let line = line.strip_prefix(':').unwrap_or(line);
+            if !self.run_command_line(line) {
+                return Err(format!("line {}: Unknown command {}", i + 1, line));
+            }
+            if let Some(command) = self.take_shell_command() {
+                let status = command.run(Stdio::inherit());
+                self.on_shell_command_done(status);
+            }
+            self.wait_for_jobs();
+
+            for (level, message) in &self.ui.messages {
+                eprintln!("{}", message);
+                if *level != Level::Info {
+                    return Err(format!("line {}: {}", i + 1, message));
+                }
+            }
+            if self.should_quit {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs `line` as if it was entered on the command line, returning false
+    /// for unknown commands.
+    pub fn run_command_line(&mut self, line: &str) -> bool {
+        // `:!cmd` is accepted as well as `:! cmd`
+        let buffer = match line.strip_prefix('!') {
+            Some(rest) => format!("! {}", rest),
+            None => line.to_string(),
+        };
+        let words: Vec<&str> = buffer.split(" ").collect();
+        let action = match self.commands.get(words[0]) {
+            Some(action) => *action,
+            None => return false,
+        };
+        // Plugin commands are told which of them was run
+        let first = match action {
+            AppActions::PluginCommand => 0,
+            _ => 1,
+        };
+        let args = words[first..].iter().map(|x| String::from(*x)).collect();
+        /* TODO: This is kind of inconsistent behaviour. Should there be a
+         * third command_handle_action?
+         */
+        self.handle_action(action, args);
+        true
+    }
+
+    /// Every action, with the commands running them and the keys they are
+    /// bound to in normal mode.
+    fn palette_entries(&self) -> Vec<PaletteEntry> {
+        AppActions::iter()
+            .map(|action| {
+                let name = format!("{:?}", action);
+                let mut commands: Vec<String> = self
+                    .commands
+                    .iter()
+                    .filter(|(_, a)| **a == action)
+                    .map(|(c, _)| c.clone())
+                    .collect();
+                commands.sort();
+                let mut keys: Vec<String> = self
+                    .normal
+                    .bindings
+                    .iter()
+                    .filter(|(_, a)| **a == action)
+                    .map(|(k, _)| key_events_to_string(k))
+                    .collect();
+                keys.sort();
+                PaletteEntry {
+                    action,
+                    name,
+                    commands,
+                    keys,
+                }
+            })
+            .collect()
+    }
+
+    /// Runs the selected palette entry. Actions needing arguments open the
+    /// command line with their command filled in instead.
+    fn run_palette_entry(&mut self) {
+        let entry = self.palette.selected().cloned();
+        self.enter_mode(ActiveMode::Normal);
+        if let Some(entry) = entry {
+            match entry.commands.first() {
+                Some(command) if entry.action.takes_arguments() => {
+                    self.enter_mode(ActiveMode::Command);
+                    self.command_line.set_line(format!("{} ", command));
+                }
+                _ => self.handle_action(entry.action, vec![]),
+            }
+        }
+    }
+
+    /// The lines in the history, newest first and each only once. Those
+    /// opening the history itself are left out.
+    fn history_lines(&self) -> Vec<String> {
+        let mut lines: Vec<String> = Vec::new();
+        for line in self.command_line.history().iter().rev() {
+            let name = line.split(' ').next().unwrap_or_default();
+            if self.commands.get(name) != Some(&AppActions::ShowHistory) && !lines.contains(line) {
+                lines.push(line.clone());
+            }
+        }
+        lines
+    }
+
+    /// Runs the line chosen from the history again, or puts it on the command
+    /// line when it was chosen for editing.
+    fn run_history_entry(&mut self) {
+        let line = self.history_picker.selected().cloned();
+        let editing = self.history_picker.editing();
+        self.enter_mode(ActiveMode::Normal);
+        match line {
+            Some(line) if editing => {
+                self.enter_mode(ActiveMode::Command);
+                self.command_line.set_line(line);
+            }
+            Some(line) => {
+                self.command_line.remember(line.clone());
+                self.run_command_line(&line);
+            }
+            None => {}
+        }
+    }
+
+    /// Runs the hooks of whatever changed since they last ran.
+    pub fn run_hooks(&mut self) {
+        if self.hooked_dir != *self.current_dir {
+            self.hooked_dir = self.current_dir.to_path_buf();
+            let on_dir_change = self.settings.hooks.on_dir_change.clone();
+            self.run_hook(on_dir_change);
+        }
+        let entry = self
+            .dir_contents
+            .get((self.ui.scroll_y + self.ui.cursor_y) as usize)
+            .map(|e| e.path());
+        if self.hooked_entry != entry {
+            self.hooked_entry = entry;
+            let on_select = self.settings.hooks.on_select.clone();
+            self.run_hook(on_select);
+        }
+    }
+
+    fn run_hook(&mut self, hook: Option<String>) {
+        let hook = match hook {
+            Some(hook) => hook,
+            None => return,
+        };
+        let (detached, command) = match (hook.strip_prefix('!'), hook.strip_prefix('&')) {
+            (Some(command), _) => (false, command),
+            (_, Some(command)) => (true, command),
+            _ => {
+                if let Ok(action) = AppActions::from_str(&hook) {
+                    self.handle_action(action, vec![]);
+                }
+                return;
+            }
+        };
+        // Shell commands can't be run in a remote directory, and aren't run
+        // at all in read-only mode. Hooks run too often to warn about it.
+        if self.remote.is_some() || self.read_only {
+            return;
+        }
+        let selected = self.selected_paths();
+        let command = shell::expand_placeholders(command, &self.current_dir, &selected);
+        let result =
+            ShellCommand::new(&command, &self.current_dir, &selected).and_then(
+                |c| match detached {
+                    true => c.spawn(),
+                    false => c.run_quietly().map(|_| ()),
+                },
+            );
+        if let Err(e) = result {
+            self.ui
+                .error(format!("Could not run hook {}: {}", command.to_string_lossy(), e));
+        }
+    }
+
+    /// The shell command to run in place of the interface, if one was entered.
+    pub fn take_shell_command(&mut self) -> Option<ShellCommand> {
+        self.shell_command.take()
+    }
+
+    pub fn on_shell_command_done(&mut self, status: io::Result<ExitStatus>) {
+        self.dirty = true;
+        match status {
+            Ok(status) if !status.success() => {
+                self.ui.info(format!("Command exited with {}", status));
+            }
+            Ok(_) => {}
+            Err(e) => self.ui.error(format!("Could not run command: {}", e)),
+        }
+        self.update_dir_contents();
+    }
+
+    /// Inserts text pasted in the terminal into the command line or the
+    /// palette. Line breaks are dropped, so a pasted line is not run before
+    /// it can be looked at. Outside of those, pastes are ignored rather
+    /// than taken for keys.
+    pub fn on_paste(&mut self, text: &str) {
+        self.dirty = true;
+        self.flush_escape_pending();
+        let text: String = text.chars().filter(|c| *c != '\n' && *c != '\r').collect();
+        self.mode_mut().paste(&text);
+    }
+
+    fn create_bookmark(&mut self) {
+        // Bookmarks are stored as JSON, which has no room for such paths
+        if self.current_dir.to_str().is_none() {
+            self.ui
+                .warn("Cannot bookmark a path which is not valid UTF-8");
+            return;
+        }
+        self.bookmarks.push(Bookmark {
+            name: String::from(
+                self.current_dir
+                    .file_name()
+                    .unwrap_or(&OsStr::new("No file name"))
+                    .to_str()
+                    .unwrap_or("No file name"),
+            ),
+            path: self.current_dir.to_owned(),
+        });
+
+        self.update_bookmark_width();
+    }
+
+    fn delete_bookmark(&mut self) {
+        let i = (self.ui.bookmark_scroll_y + self.ui.bookmark_y) as usize;
+        if i < self.bookmarks.len() {
+            self.bookmarks.remove(i);
+        }
+
+        self.update_bookmark_width();
+    }
+
+    /// Fits the bookmark panel to the names in it, unless it was resized.
+    fn update_bookmark_width(&mut self) {
+        if let Some(width) = self.state.bookmark_width {
+            self.ui.bookmark_width = width;
+            return;
+        }
+        let mut max_len: u16 = 15;
+        for b in &self.bookmarks {
+            if b.name.len() > max_len.into() {
+                max_len = b.name.len() as u16;
+            }
+        }
+        for d in &self.devices {
+            if d.name.len() > max_len.into() {
+                max_len = d.name.len() as u16;
+            }
+        }
+        if self.settings.icons {
+            max_len += 2;
+        }
+        self.ui.bookmark_width = max_len + 1;
+    }
+
+    /// Resizes the bookmark panel, keeping the width for later sessions.
+    fn resize_bookmarks(&mut self, width: u16) {
+        self.ui.resize_bookmarks(width);
+        self.state.bookmark_width = Some(self.ui.bookmark_width);
+    }
+
+    /// The options `:set` can change, with their current values.
+    fn option_values(&self) -> Vec<(&'static str, String)> {
+        let on_off = |on: bool| String::from(if on { "on" } else { "off" });
+        vec![
+            ("classify", on_off(self.settings.classify)),
+            ("dryrun", on_off(self.dry_run)),
+            ("escape_timeout", self.settings.escape_timeout.to_string()),
+            ("gitignore", self.settings.gitignore.to_string()),
+            ("hidden", on_off(self.show_hidden_files)),
+            ("hide", self.settings.hide.join(" ")),
+            ("icons", on_off(self.settings.icons)),
+            ("ignorecase", on_off(self.settings.ignorecase)),
+            ("number", on_off(self.settings.number)),
+            ("preserve", self.settings.preserve.to_string()),
+            ("readonly", on_off(self.read_only)),
+            ("relativenumber", on_off(self.settings.relativenumber)),
+            ("restore", on_off(self.settings.restore)),
+            ("scrolloff", self.settings.scrolloff.to_string()),
+            ("smartcase", on_off(self.settings.smartcase)),
+            ("status_format", self.settings.status_format.clone()),
+            ("tick_rate", self.settings.tick_rate.to_string()),
+            ("wrap_around", on_off(self.settings.wrap_around)),
+        ]
+    }
+
+    /// Applies `:set name`, `:set noname` or `:set name!`, which toggles it,
+    /// or `:set name=value` for options which are not on or off.
+    fn set_option(&mut self, arg: &str) {
+        if let Some((name, value)) = arg.split_once('=') {
+            return match self.set_value(name, value) {
+                Ok(()) => self.ui.info(format!("{}={}", name, value)),
+                Err(e) => self.ui.error(e),
+            };
+        }
+
+        let (name, value) = match (arg.strip_suffix('!'), arg.strip_prefix("no")) {
+            (Some(name), _) => (name, None),
+            (None, Some(name)) => (name, Some(false)),
+            (None, None) => (arg, Some(true)),
+        };
+        if name == "readonly" && self.options.read_only && value != Some(true) {
+            return self
+                .ui
+                .error("Started with --read-only, which can't be turned off");
+        }
+        let option = match name {
+            "classify" => &mut self.settings.classify,
+            "dryrun" => &mut self.dry_run,
+            "hidden" => &mut self.show_hidden_files,
+            "icons" => &mut self.settings.icons,
+            "ignorecase" => &mut self.settings.ignorecase,
+            "number" => &mut self.settings.number,
+            "readonly" => &mut self.read_only,
+            "relativenumber" => &mut self.settings.relativenumber,
+            "restore" => &mut self.settings.restore,
+            "smartcase" => &mut self.settings.smartcase,
+            "wrap_around" => &mut self.settings.wrap_around,
+            _ => return self.ui.error(format!("Unknown option: {}", name)),
+        };
+        *option = value.unwrap_or(!*option);
+        let prefix = if *option { "" } else { "no" };
+        self.ui.info(format!("{}{}", prefix, name));
+        if name == "hidden" {
+            self.update_dir_contents();
+        }
+    }
+
+    fn set_value(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name {
+            "escape" | "escape_timeout" | "preserve" | "scrolloff" | "status_format"
+            | "tick_rate" => self.settings.set(name, value),
+            "gitignore" | "hide" => {
+                self.settings.set(name, value)?;
+                self.update_dir_contents();
+                Ok(())
+            }
+            _ => Err(format!("Unknown option: {}", name)),
+        }
+    }
+
+    /// Lists what `action` would change in the messages instead of changing
+    /// it, for `:set dryrun`.
+    fn describe_dry_run(&mut self, action: AppActions, args: &[String], selected: &[PathBuf]) {
+        let mut changes = Vec::new();
+        match action {
+            AppActions::DeleteFile => {
+                changes.extend(selected.iter().map(|p| format!("delete {}", p.display())))
+            }
+            AppActions::PasteFiles | AppActions::PasteSymlinks | AppActions::PasteHardlinks => {
+                let (yanked, mode) = match self.yanked() {
+                    Ok(yanked) => yanked,
+                    Err(e) => return self.report(e),
+                };
+                let verb = match (action, mode) {
+                    (AppActions::PasteSymlinks, _) => "symlink",
+                    (AppActions::PasteHardlinks, _) => "hard link",
+                    (_, YankMode::Cutting) => "move",
+                    (_, YankMode::Copying) => "copy",
+                };
+                for p in yanked {
+                    let name = p.file_name().unwrap_or_default();
+                    let dest = free_copy_name(self.current_dir.join(name), p.is_dir());
+                    changes.push(format!("{} {} to {}", verb, p.display(), dest.display()));
+                }
+            }
+            AppActions::MoveEntry => {
+                if let ([src], [name, ..]) = (selected, args) {
+                    let dest = src.parent().unwrap_or(src).join(name);
+                    changes.push(format!("rename {} to {}", src.display(), dest.display()));
+                }
+            }
+            _ => changes.push(format!("run {:?} {}", action, args.join(" "))),
+        }
+
+        if changes.is_empty() {
+            self.ui.info("Dry run: nothing would change");
+        }
+        for change in changes {
+            self.ui
+                .info(format!("Dry run: would {}", change.trim_end()));
+        }
+    }
+
+    /// Whether `action` may delete or overwrite `paths`. Protected paths are
+    /// refused, or let through once the same change has been asked for twice
+    /// in a row.
+    fn may_change(&mut self, action: AppActions, paths: &[PathBuf]) -> bool {
+        let (protection, path) = match self.settings.protected.protection(paths) {
+            Some(protected) => protected,
+            None => return true,
+        };
+        match protection {
+            Protection::Refuse => {
+                self.ui.warn(format!("{} is protected", path.display()));
+                false
+            }
+            Protection::Confirm => {
+                let change = (action, paths.to_vec());
+                if self.unconfirmed.take().as_ref() == Some(&change) {
+                    return true;
+                }
+                self.ui.warn(format!(
+                    "{} is protected, repeat to confirm",
+                    path.display()
+                ));
+                self.unconfirmed = Some(change);
+                false
+            }
+        }
+    }
+
+    fn mv_entry(&mut self, src: &Path, dest: &str) {
+        let new_name = src.parent().unwrap_or(src).join(dest);
+        if !self.may_change(
+            AppActions::MoveEntry,
+            &[src.to_path_buf(), new_name.clone()],
+        ) {
+            return;
+        }
+        if let Err(e) = self.vfs.rename(src, &new_name).context("rename", src) {
+            return self.report(e);
+        }
+        self.dir_sizes.invalidate(src);
+        self.update_dir_contents();
+    }
+
+    #[cfg(unix)]
+    fn change_mode(&mut self, paths: &[PathBuf], spec: &str) {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mut changed = 0;
+        for p in paths {
+            let result = fs::metadata(p).and_then(|md| {
+                let mode =
+                    permissions::apply_mode(spec, md.permissions().mode() & 0o7777, md.is_dir())
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                fs::set_permissions(p, fs::Permissions::from_mode(mode))
+            });
+            match result {
+                Ok(_) => changed += 1,
+                Err(e) => {
+                    self.ui.error(format!("chmod {}: {}", p.display(), e));
+                    break;
+                }
+            }
+        }
+        if changed == paths.len() {
+            self.ui
+                .info(format!("Changed the mode of {} entries", changed));
+        }
+        self.update_dir_contents();
+    }
+
+    #[cfg(not(unix))]
+    fn change_mode(&mut self, _paths: &[PathBuf], _spec: &str) {
+        self.ui.info("chmod is only supported on Unix");
+    }
+
+    #[cfg(unix)]
+    fn change_owner(&mut self, paths: &[PathBuf], spec: &str) {
+        let (uid, gid) = match permissions::parse_owner(spec) {
+            Ok(ids) => ids,
+            Err(e) => {
+                self.ui.error(e);
+                return;
+            }
+        };
+
+        let mut changed = 0;
+        for p in paths {
+            match std::os::unix::fs::chown(p, uid, gid) {
+                Ok(_) => changed += 1,
+                Err(e) => {
+                    self.ui.error(format!("chown {}: {}", p.display(), e));
+                    break;
+                }
+            }
+        }
+        if changed == paths.len() {
+            self.ui
+                .info(format!("Changed the owner of {} entries", changed));
+        }
+        self.update_dir_contents();
+    }
+
+    #[cfg(not(unix))]
+    fn change_owner(&mut self, _paths: &[PathBuf], _spec: &str) {
+        self.ui.info("chown is only supported on Unix");
+    }
+
+    fn change_dir(&mut self, target: &str) {
+        let path = self.current_dir.join(paths::expand_home(target));
+        self.change_dir_to(&path);
+    }
+
+    fn change_dir_to(&mut self, path: &Path) {
+        if let Ok(path) = path.canonicalize().map(|p| paths::simplify(&p)) {
+            if self.vfs.is_dir(&path) {
+                self.ui.last_name = path.file_name().unwrap_or_default().to_owned();
+                self.enter_dir(&path);
+                self.active_panel = ActivePanel::Main;
+                self.ui
+                    .scroll_abs(0, self.dir_contents.len() as i32, &self.active_panel);
+            }
+        }
+    }
+
+    fn read_dir_sorted<P: AsRef<Path>>(&self, path: P) -> io::Result<Vec<Entry>> {
+        let mut contents = self.vfs.read_dir(path.as_ref())?;
+        // The exact name breaks ties between names differing only in case,
+        // which would otherwise come out in file system order
+        contents.sort_unstable_by_key(|item| {
+            (
+                !item.is_dir,
+                item.path().to_string_lossy().to_lowercase(),
+                item.file_name(),
+            )
+        });
+        for item in contents.iter_mut() {
+            let name = item.file_name();
+            let name = name.to_string_lossy();
+            if self.settings.hide.iter().any(|p| glob::matches(p, &name)) {
+                item.hidden = true;
+            }
+        }
+        // Git only knows about local repositories
+        if self.settings.gitignore != GitIgnore::Off && self.remote.is_none() {
+            let names: Vec<_> = contents.iter().map(Entry::file_name).collect();
+            let ignored = gitignore::ignored(path.as_ref(), &names);
+            for item in contents.iter_mut() {
+                if ignored.contains(&item.file_name()) {
+                    match self.settings.gitignore {
+                        GitIgnore::Hide => item.hidden = true,
+                        _ => item.ignored = true,
+                    }
+                }
+            }
+        }
+        contents = contents
+            .into_iter()
+            .filter(|item| self.show_hidden_files || !item.hidden)
+            .filter(|item| match &self.filter {
+                Some(filter) => fuzzy::score_case(
+                    filter,
+                    &item.file_name().to_string_lossy(),
+                    self.settings.ignores_case(filter),
+                )
+                .is_some(),
+                None => true,
+            })
+            .collect();
+
+        Ok(contents)
+    }
+
+    fn create_dir(&self, name: &str) -> Result<(), AppError> {
+        let new_path = self.current_dir.join(name);
+        self.vfs.create_dir(&new_path).context("create", &new_path)
+    }
+
+    /// Links `name`, or the file name of `target`, in the current directory to
+    /// `target`. Like ln, the target of a symlink is stored as given.
+    fn create_link(&self, target: &str, name: Option<&String>, symbolic: bool) -> io::Result<()> {
+        let target = Path::new(target);
+        let link = match name {
+            Some(name) => self.current_dir.join(name),
+            None => self
+                .current_dir
+                .join(target.file_name().ok_or(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Target has no file name",
+                ))?),
+        };
+
+        if symbolic {
+            symlink(target, &link)
+        } else {
+            fs::hard_link(self.current_dir.join(target), &link)
+        }
+    }
+
+    /// Links every entry of `yanked` into the current directory.
+    fn link_yanked_files(&self, yanked: &[PathBuf], symbolic: bool) -> io::Result<()> {
+        for target in yanked {
+            let link = self
+                .current_dir
+                .join(target.file_name().unwrap_or_default());
+            if symbolic {
+                symlink(target, &link)?;
+            } else {
+                fs::hard_link(target, &link)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates an empty file unless one by that name already exists.
+    fn create_file(&self, name: &str) -> io::Result<()> {
+        self.vfs.create_file(&self.current_dir.join(name))
+    }
+}
+
+/// E.g. `Copying foo` for a single path, or `Copying 3 items`.
+fn job_description(verb: &str, paths: &[PathBuf]) -> String {
+    match paths {
+        [path] => format!(
+            "{} {}",
+            verb,
+            path.file_name().unwrap_or_default().to_string_lossy()
+        ),
+        _ => format!("{} {} items", verb, paths.len()),
+    }
+}
+
+/// The regular expression in a pattern between slashes, such as `/\.log$/`.
+/// Anything with more slashes in it is taken to be a path.
+fn regex_in(pattern: &str) -> Option<&str> {
+    pattern
+        .strip_prefix('/')?
+        .strip_suffix('/')
+        .filter(|re| !re.is_empty() && !re.contains('/'))
+}
+
+/// Whether `:select` was given a pattern rather than the path of an entry.
+fn is_pattern(arg: &str) -> bool {
+    regex_in(arg).is_some() || arg.contains(['*', '?'])
+}
+
+/// Keys which don't type a character, by the names used for them in the
+/// config. The first name of a key is the one it is shown as. Function keys
+/// are named `F1` to `F24`.
+const NAMED_KEYS: [(&str, KeyCode); 14] = [
+    ("Esc", KeyCode::Esc),
+    ("CR", KeyCode::Enter),
+    ("Enter", KeyCode::Enter),
+    ("BS", KeyCode::Backspace),
+    ("Tab", KeyCode::Tab),
+    ("Up", KeyCode::Up),
+    ("Down", KeyCode::Down),
+    ("Left", KeyCode::Left),
+    ("Right", KeyCode::Right),
+    ("Home", KeyCode::Home),
+    ("End", KeyCode::End),
+    ("PageUp", KeyCode::PageUp),
+    ("PageDown", KeyCode::PageDown),
+    ("Del", KeyCode::Delete),
+];
+
+/// Characters which have to be named between `<` and `>`.
+const NAMED_CHARS: [(&str, char); 4] = [
+    ("lt", '<'),
+    ("gt", '>'),
+    ("Space", ' '),
+    // Lines starting with `;` are comments in the config
+    ("Semicolon", ';'),
+];
+
+/// Modifiers as in `<C-w>`, in the order they are shown.
+const MODIFIERS: [(&str, KeyModifiers); 5] = [
+    ("C", KeyModifiers::CONTROL),
+    ("S", KeyModifiers::SHIFT),
+    ("A", KeyModifiers::ALT),
+    ("M", KeyModifiers::ALT),
+    ("D", KeyModifiers::SUPER),
+];
+
+/* Terminals speaking the kitty protocol report lock keys in the event state,
+ * which would otherwise keep the chord from matching a binding. Shift is part
+ * of symbols such as `?` already, but some terminals report it anyway, and
+ * others leave it out of Shift+Tab or send Tab with Shift. Held keys repeat
+ * as presses.
+ */
+/// `key` as written in the config.
+fn normalize_key(key: KeyEvent) -> KeyEvent {
+    let (code, modifiers) = match key.code {
+        KeyCode::Char(c) if !c.is_alphabetic() => (key.code, key.modifiers - KeyModifiers::SHIFT),
+        KeyCode::Tab if key.modifiers.contains(KeyModifiers::SHIFT) => {
+            (KeyCode::BackTab, key.modifiers)
+        }
+        KeyCode::BackTab => (key.code, key.modifiers | KeyModifiers::SHIFT),
+        _ => (key.code, key.modifiers),
+    };
+    let kind = match key.kind {
+        KeyEventKind::Release => KeyEventKind::Release,
+        KeyEventKind::Press | KeyEventKind::Repeat => KeyEventKind::Press,
+    };
+    KeyEvent::new_with_kind(code, modifiers, kind)
+}
+
+/// Parses a chord such as `gg` or `<C-w><C-h>`, failing on special keys
+/// which are not known.
+fn parse_key_events(s: &str) -> Result<Vec<KeyEvent>, String> {
+    let mut output = Vec::with_capacity(s.len());
+
+    let re = Regex::new(r"<[.|[^<>]]+>|.").unwrap();
+
+    for cap in re.captures_iter(s) {
+        let symbol = &cap[0];
+
+        if symbol.chars().count() == 1 {
+            output.push(KeyEvent::new(
+                KeyCode::Char(symbol.chars().next().unwrap()),
+                KeyModifiers::empty(),
+            ));
+        } else {
+            match parse_special_key(&symbol[1..symbol.len() - 1]) {
+                Some(key) => output.push(normalize_key(key)),
+                None => return Err(format!("Unknown key {}", symbol)),
+            }
+        }
+    }
+
+    if output.is_empty() {
+        return Err(String::from("No keys given"));
+    }
+    Ok(output)
+}
+
+/// Parses what is written between `<` and `>`: a key or character, preceded
+/// by modifiers as in `<C-S-Enter>`. A `Release-` prefix stands for letting
+/// go of the key, which only terminals speaking the kitty protocol report.
+fn parse_special_key(name: &str) -> Option<KeyEvent> {
+    let mut modifiers = KeyModifiers::empty();
+    let mut kind = KeyEventKind::Press;
+    let mut rest = name;
+    while let Some((prefix, key)) = rest.split_once('-').filter(|(_, key)| !key.is_empty()) {
+        if prefix.eq_ignore_ascii_case("Release") {
+            kind = KeyEventKind::Release;
+        } else {
+            modifiers |= MODIFIERS
+                .iter()
+                .find(|(m, _)| m.eq_ignore_ascii_case(prefix))?
+                .1;
+        }
+        rest = key;
+    }
+
+    let code = if rest.chars().count() == 1 {
+        KeyCode::Char(rest.chars().next().unwrap())
+    } else if let Some((_, c)) = NAMED_CHARS
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(rest))
+    {
+        KeyCode::Char(*c)
+    } else if let Some((_, code)) = NAMED_KEYS
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(rest))
+    {
+        *code
+    } else {
+        let n = rest.strip_prefix(['F', 'f'])?.parse().ok()?;
+        if !(1..=24).contains(&n) {
+            return None;
+        }
+        KeyCode::F(n)
+    };
+    Some(KeyEvent::new_with_kind(code, modifiers, kind))
+}
+
+/// Writes keys as in the config, except that a character with only Ctrl held
+/// is shown as `^w`.
+fn key_events_to_string(key_seq: &[KeyEvent]) -> String {
+    let mut output = String::new();
+    for ke in key_seq {
+        let release = ke.kind == KeyEventKind::Release;
+        let mut modifiers = ke.modifiers;
+        let name = match ke.code {
+            KeyCode::Char(c) => {
+                if c.is_alphabetic() {
+                    modifiers -= KeyModifiers::SHIFT;
+                }
+                match NAMED_CHARS.iter().find(|(_, n)| *n == c) {
+                    Some((name, _)) if release || !modifiers.is_empty() => name.to_string(),
+                    _ => c.to_string(),
+                }
+            }
+            // Shown as <S-Tab>, as Shift is among the modifiers
+            KeyCode::BackTab => String::from("Tab"),
+            KeyCode::F(n) => format!("F{}", n),
+            code => match NAMED_KEYS.iter().find(|(_, c)| *c == code) {
+                Some((name, _)) => name.to_string(),
+                None => continue,
+            },
+        };
+
+        match ke.code {
+            KeyCode::Char(_) if !release && modifiers.is_empty() => output.push_str(&name),
+            KeyCode::Char(_) if !release && modifiers == KeyModifiers::CONTROL => {
+                output.push('^');
+                output.push_str(&name);
+            }
+            _ => {
+                output.push('<');
+                if release {
+                    output.push_str("Release-");
+                }
+                let mut shown = KeyModifiers::empty();
+                for (m, flag) in MODIFIERS {
+                    if modifiers.contains(flag) && !shown.contains(flag) {
+                        output.push_str(&format!("{}-", m));
+                        shown |= flag;
+                    }
+                }
+                output.push_str(&name);
+                output.push('>');
+            }
+        }
+    }
+
+    return output;
+}
+
+/// The rows of the help popup, listing the keys bound to each action in
+/// normal and visual mode.
+fn help_rows(
+    normal_bindings: &HashMap<Vec<KeyEvent>, AppActions>,
+    visual_bindings: &HashMap<Vec<KeyEvent>, AppActions>,
+) -> Vec<(String, String)> {
+    let mut rows = Vec::new();
+    for (mode, bindings) in [("Normal", normal_bindings), ("Visual", visual_bindings)] {
+        let mut keys_by_action: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for (keys, action) in bindings {
+            keys_by_action
+                .entry(format!("{:?}", action))
+                .or_default()
+                .push(key_events_to_string(keys));
+        }
+
+        if !rows.is_empty() {
+            rows.push((String::new(), String::new()));
+        }
+        rows.push((format!("{} mode", mode), String::new()));
+        for (action, mut keys) in keys_by_action {
+            keys.sort();
+            rows.push((action, keys.join("  ")));
+        }
+    }
+    rows
+}
+
+/// The names of the actions on the command line, before any from the
+/// `[commands]` section.
+fn default_commands() -> HashMap<String, AppActions> {
+    let mut commands = HashMap::new();
+    commands.insert(String::from("delete"), AppActions::DeleteFile);
+    commands.insert(String::from("up"), AppActions::MoveUp);
+    commands.insert(String::from("bookmark"), AppActions::CreateBookmark);
+    commands.insert(String::from("del_bookmark"), AppActions::DeleteBookmark);
+    commands.insert(String::from("bm"), AppActions::CreateBookmark);
+    commands.insert(String::from("dbm"), AppActions::DeleteBookmark);
+    commands.insert(String::from("mv"), AppActions::MoveEntry);
+    commands.insert(String::from("mkdir"), AppActions::CreateDir);
+    commands.insert(String::from("touch"), AppActions::CreateFile);
+    commands.insert(String::from("ln"), AppActions::CreateLink);
+    commands.insert(String::from("duplicate"), AppActions::Duplicate);
+    commands.insert(String::from("sha256"), AppActions::Sha256Sum);
+    commands.insert(String::from("md5"), AppActions::Md5Sum);
+    commands.insert(String::from("compress"), AppActions::Compress);
+    commands.insert(String::from("sftp"), AppActions::ConnectSftp);
+    commands.insert(String::from("messages"), AppActions::ShowMessages);
+    commands.insert(String::from("help"), AppActions::ShowHelp);
+    commands.insert(String::from("palette"), AppActions::OpenPalette);
+    commands.insert(String::from("history"), AppActions::ShowHistory);
+    commands.insert(String::from("filter"), AppActions::Filter);
+    commands.insert(String::from("selectall"), AppActions::SelectAll);
+    commands.insert(String::from("selection"), AppActions::ReviewSelection);
+    commands.insert(String::from("invert"), AppActions::InvertSelection);
+    commands.insert(String::from("registers"), AppActions::ShowRegisters);
+    commands.insert(String::from("cancel"), AppActions::CancelJobs);
+    commands.insert(String::from("retry"), AppActions::RetryJob);
+    commands.insert(String::from("jobs"), AppActions::ToggleJobs);
+    commands.insert(String::from("set"), AppActions::Set);
+    commands.insert(String::from("choose"), AppActions::ChooseFiles);
+    commands.insert(String::from("config_reload"), AppActions::ReloadConfig);
+    commands.insert(
+        String::from("config_default"),
+        AppActions::WriteDefaultConfig,
+    );
+    commands.insert(String::from("q"), AppActions::Quit);
+    commands.insert(String::from("quit"), AppActions::Quit);
+    commands.insert(String::from("download"), AppActions::Download);
+    commands.insert(String::from("upload"), AppActions::Upload);
+    commands.insert(String::from("devices"), AppActions::ToggleDevices);
+    commands.insert(String::from("mount"), AppActions::MountDevice);
+    commands.insert(String::from("unmount"), AppActions::UnmountDevice);
+    commands.insert(String::from("cd"), AppActions::ChangeDir);
+    commands.insert(String::from("copy"), AppActions::CopyFiles);
+    commands.insert(String::from("cut"), AppActions::CutFiles);
+    commands.insert(String::from("paste"), AppActions::PasteFiles);
+    commands.insert(String::from("select"), AppActions::Select);
+    commands.insert(String::from("select!"), AppActions::Unselect);
+    commands.insert(String::from("reveal"), AppActions::Reveal);
+    commands.insert(String::from("reload"), AppActions::Refresh);
+    commands.insert(String::from("tail"), AppActions::ToggleTail);
+    commands.insert(String::from("du"), AppActions::CalculateSizes);
+    commands.insert(String::from("properties"), AppActions::ShowProperties);
+    commands.insert(String::from("sh"), AppActions::RunShell);
+    commands.insert(String::from("!"), AppActions::RunShell);
+    commands.insert(String::from("chmod"), AppActions::ChangeMode);
+    commands.insert(String::from("chown"), AppActions::ChangeOwner);
+    commands.insert(String::from("snapshots"), AppActions::PickSnapshot);
+    commands.insert(String::from("restore"), AppActions::RestoreSnapshot);
+    commands
+}
+
+/// The config every user config is laid over, written out by
+/// `--print-default-config` as a starting point.
+pub const DEFAULT_CONFIG: &str = include_str!("../assets/default_config.ini");
+
+/// Everything read from the config, laid over the defaults.
+struct Config {
+    normal: HashMap<Vec<KeyEvent>, AppActions>,
+    visual: HashMap<Vec<KeyEvent>, AppActions>,
+    normal_shell: HashMap<Vec<KeyEvent>, String>,
+    visual_shell: HashMap<Vec<KeyEvent>, String>,
+    settings: Settings,
+    /// Added to the commands from `default_commands`
+    commands: HashMap<String, AppActions>,
+    /// Entries which were skipped, as where they are and what is wrong
+    problems: Vec<(String, String)>,
+}
+
+/// Loads `init.lua` next to the config, if there is one. What went wrong is
+/// returned as problems with the config.
+fn load_plugins(config_path: &Path) -> (Option<Plugins>, Vec<(String, String)>) {
+    let path = config_path.with_file_name("init.lua");
+    if !path.exists() {
+        return (None, Vec::new());
+    }
+    let problem = |e: String| vec![(path.display().to_string(), e)];
+    match Plugins::new() {
+        Ok(plugins) => match plugins.load(&path) {
+            Ok(()) => (Some(plugins), Vec::new()),
+            // What was added before the error still works
+            Err(e) => (Some(plugins), problem(e)),
+        },
+        Err(e) => (None, problem(e.to_string())),
+    }
+}
+
+/// The commands added by plugins, which run `PluginCommand`.
+fn plugin_commands(plugins: &Option<Plugins>) -> Vec<(String, AppActions)> {
+    plugins
+        .iter()
+        .flat_map(|p| p.commands())
+        .map(|name| (name, AppActions::PluginCommand))
+        .collect()
+}
+
+/// The config trooper comes with, which user configs are laid over.
+fn default_config() -> Config {
+    let mut config = Config {
+        normal: HashMap::new(),
+        visual: HashMap::new(),
+        normal_shell: HashMap::new(),
+        visual_shell: HashMap::new(),
+        settings: Settings::default(),
+        commands: HashMap::new(),
+        problems: Vec::new(),
+    };
+    let default_map = ini_parser()
+        .read(String::from(DEFAULT_CONFIG))
+        .expect("The default config is valid");
+    config.apply_ini(default_map);
+    config
+}
+
+fn ini_parser() -> Ini {
+    let mut ini = Ini::new();
+    let mut default = ini.defaults();
+    default.delimiters = vec!['='];
+    default.case_sensitive = true;
+    ini.load_defaults(default);
+    ini
+}
+
+fn read_config(p: &Path) -> Result<Config, io::Error> {
+    let mut config = default_config();
+    if !p.exists() {
+        return Ok(config);
+    }
+    let text = fs::read_to_string(p)?;
+    if p.extension().map_or(false, |e| e == "toml") {
+        match toml::from_str::<TomlConfig>(&text) {
+            Ok(user) => user.apply(&mut config),
+            Err(e) => config
+                .problems
+                .push((p.display().to_string(), e.to_string())),
+        }
+    } else {
+        match ini_parser().read(text) {
+            Ok(user_map) => config.apply_ini(user_map),
+            Err(msg) => config.problems.push((p.display().to_string(), msg)),
+        }
+    }
+
+    Ok(config)
+}
+
+impl Config {
+    /* Sections are hash maps, so keys are sorted to report problems in the
+     * same order every time.
+     */
+    fn apply_ini(&mut self, map: HashMap<String, HashMap<String, Option<String>>>) {
+        let mut sections: Vec<_> = map.into_iter().collect();
+        sections.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for (section, entries) in sections {
+            let mut entries: Vec<(String, String)> = entries
+                .into_iter()
+                .map(|(k, v)| (k, v.unwrap_or_default()))
+                .collect();
+            entries.sort();
+
+            let mut bound: HashMap<Vec<KeyEvent>, String> = HashMap::new();
+            for (key, value) in entries {
+                let result = match section.as_str() {
+                    "normal" | "visual" => {
+                        parse_binding(&key, &value).and_then(|(keys, action)| {
+                            if let Some(other) = bound.insert(keys.clone(), key.clone()) {
+                                return Err(format!("The same keys as {}", other));
+                            }
+                            self.bind(section == "visual", keys, action, &value);
+                            Ok(())
+                        })
+                    }
+                    "commands" => AppActions::from_str(&value)
+                        .map(|action| {
+                            self.commands.insert(key.clone(), action);
+                        })
+                        .map_err(|_| format!("Unknown action {}", value)),
+                    "settings" => self.settings.set(&key, &value),
+                    "filetypes" => filetypes::parse_style(&value)
+                        .map(|style| self.settings.filetypes.insert(&key, style))
+                        .ok_or_else(|| format!("Invalid style {}", value)),
+                    "protected" => Protection::parse(&value)
+                        .map(|protection| self.settings.protected.insert(&key, protection))
+                        .ok_or_else(|| format!("Expected confirm or refuse, not {}", value)),
+                    "hooks" => self.settings.hooks.set(&key, &value),
+                    _ => Err(String::from("Unknown section")),
+                };
+                if let Err(e) = result {
+                    self.problems.push((format!("[{}] {}", section, key), e));
+                }
+            }
+        }
+    }
+
+    /// Binds `keys` to `action`, keeping the command when it is a
+    /// `ShellBinding`.
+    fn bind(&mut self, visual: bool, keys: Vec<KeyEvent>, action: AppActions, value: &str) {
+        let (bindings, shell) = match visual {
+            false => (&mut self.normal, &mut self.normal_shell),
+            true => (&mut self.visual, &mut self.visual_shell),
+        };
+        if action == AppActions::ShellBinding {
+            shell.insert(keys.clone(), value.to_string());
+        }
+        bindings.insert(keys, action);
+    }
+}
+
+/// Lists what was wrong with the config, shown when trooper starts.
+fn problems_popup(problems: Vec<(String, String)>) -> Option<Popup> {
+    if problems.is_empty() {
+        return None;
+    }
+    Some(Popup {
+        title: String::from("Problems in the config"),
+        rows: problems,
+        scroll: 0,
+    })
+}
+
+/// The keys and action of a binding. Values starting with `!` or `&` are
+/// shell commands rather than actions.
+fn parse_binding(keys: &str, action: &str) -> Result<(Vec<KeyEvent>, AppActions), String> {
+    let keys = parse_key_events(keys)?;
+    if action.starts_with(['!', '&']) {
+        if action[1..].trim().is_empty() {
+            return Err(String::from("Missing command"));
+        }
+        return Ok((keys, AppActions::ShellBinding));
+    }
+    let action = AppActions::from_str(action).map_err(|_| format!("Unknown action {}", action))?;
+    Ok((keys, action))
+}
+
+/* config.toml holds the same sections as config.ini, but unknown keys,
+ * actions and values of the wrong type are reported with the line they are on
+ * instead of being skipped.
+ */
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct TomlConfig {
+    settings: TomlSettings,
+    normal: HashMap<String, String>,
+    visual: HashMap<String, String>,
+    commands: HashMap<String, AppActions>,
+    filetypes: HashMap<String, String>,
+    protected: HashMap<String, Protection>,
+    hooks: HashMap<String, String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct TomlSettings {
+    icons: Option<bool>,
+    classify: Option<bool>,
+    escape: Option<Vec<String>>,
+    escape_timeout: Option<u64>,
+    tick_rate: Option<u64>,
+    status_format: Option<String>,
+    wrap_around: Option<bool>,
+    scrolloff: Option<u16>,
+    number: Option<bool>,
+    relativenumber: Option<bool>,
+    ignorecase: Option<bool>,
+    smartcase: Option<bool>,
+    preserve: Option<String>,
+    restore: Option<bool>,
+    hide: Option<Vec<String>>,
+    gitignore: Option<String>,
+}
+
+impl TomlConfig {
+    /// Lays the config over the defaults. Chords, styles, `preserve` and
+    /// `gitignore` are checked here, as they are strings to the TOML parser.
+    fn apply(self, config: &mut Config) {
+        let mut problems = Vec::new();
+        for (section, user) in [("normal", self.normal), ("visual", self.visual)] {
+            let mut user: Vec<(String, String)> = user.into_iter().collect();
+            user.sort();
+            let mut bound: HashMap<Vec<KeyEvent>, String> = HashMap::new();
+            for (keys, value) in user {
+                let result = parse_binding(&keys, &value).and_then(|(events, action)| {
+                    if let Some(other) = bound.insert(events.clone(), keys.clone()) {
+                        return Err(format!("The same keys as {}", other));
+                    }
+                    config.bind(section == "visual", events, action, &value);
+                    Ok(())
+                });
+                if let Err(e) = result {
+                    problems.push((format!("{}.\"{}\"", section, keys), e));
+                }
+            }
+        }
+        config.commands.extend(self.commands);
+
+        let s = self.settings;
+        let settings = &mut config.settings;
+        settings.icons = s.icons.unwrap_or(settings.icons);
+        settings.classify = s.classify.unwrap_or(settings.classify);
+        if let Some(escape) = s.escape {
+            if let Err(e) = settings.set("escape", &escape.join(" ")) {
+                problems.push((String::from("settings.escape"), e));
+            }
+        }
+        settings.escape_timeout = s.escape_timeout.unwrap_or(settings.escape_timeout);
+        if let Some(rate) = s.tick_rate {
+            if let Err(e) = settings.set("tick_rate", &rate.to_string()) {
+                problems.push((String::from("settings.tick_rate"), e));
+            }
+        }
+        if let Some(format) = s.status_format {
+            settings.status_format = format;
+        }
+        settings.wrap_around = s.wrap_around.unwrap_or(settings.wrap_around);
+        settings.scrolloff = s.scrolloff.unwrap_or(settings.scrolloff);
+        settings.number = s.number.unwrap_or(settings.number);
+        settings.relativenumber = s.relativenumber.unwrap_or(settings.relativenumber);
+        settings.ignorecase = s.ignorecase.unwrap_or(settings.ignorecase);
+        settings.smartcase = s.smartcase.unwrap_or(settings.smartcase);
+        if let Some(preserve) = s.preserve {
+            match Preserve::parse(&preserve) {
+                Ok(preserve) => settings.preserve = preserve,
+                Err(e) => problems.push((String::from("settings.preserve"), e)),
+            }
+        }
+        settings.restore = s.restore.unwrap_or(settings.restore);
+        if let Some(hide) = s.hide {
+            settings.hide = hide;
+        }
+        if let Some(gitignore) = s.gitignore {
+            match GitIgnore::parse(&gitignore) {
+                Ok(gitignore) => settings.gitignore = gitignore,
+                Err(e) => problems.push((String::from("settings.gitignore"), e)),
+            }
+        }
+
+        for (pattern, spec) in self.filetypes {
+            match filetypes::parse_style(&spec) {
+                Some(style) => settings.filetypes.insert(&pattern, style),
+                None => problems.push((
+                    format!("filetypes.\"{}\"", pattern),
+                    format!("Invalid style {}", spec),
+                )),
+            }
+        }
+        for (pattern, protection) in self.protected {
+            settings.protected.insert(&pattern, protection);
+        }
+        for (event, hook) in self.hooks {
+            if let Err(e) = settings.hooks.set(&event, &hook) {
+                problems.push((format!("hooks.{}", event), e));
+            }
+        }
+
+        problems.sort();
+        config.problems.extend(problems);
+    }
+}
+
+/// The tick rate after `idle` without input, which doubles every
+/// `IDLE_AFTER` up to `MAX_IDLE_TICK`.
+fn idle_tick_rate(tick_rate: Duration, idle: Duration) -> Duration {
+    let doublings = (idle.as_secs() / IDLE_AFTER.as_secs()).min(16) as u32;
+    tick_rate
+        .saturating_mul(1 << doublings)
+        .min(MAX_IDLE_TICK.max(tick_rate))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        fs,
+        path::{Path, PathBuf},
+        process::Command,
+        str::FromStr,
+        sync::atomic::{AtomicUsize, Ordering},
+        time::{Duration, Instant},
+    };
+
+    use crossterm::event::{
+        KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+    };
+    use ratatui::layout::Rect;
+
+    use super::{
+        continuations, help_rows, idle_tick_rate, key_events_to_string, parse_key_events,
+        read_config, text_to_yank, ActiveMode, ActivePanel, App, AppActions, Entry, Options,
+        YankMode, DEFAULT_CONFIG,
+    };
+    use crate::{
+        error::AppError, ipc::Server, paths, protected::Protection, sftp::Target,
+        snapshots::Snapshot,
+    };
+
+    fn str_to_key_events(s: &str) -> Vec<KeyEvent> {
+        parse_key_events(s).unwrap()
+    }
+
+    static TEST_DIRS: AtomicUsize = AtomicUsize::new(0);
+
+    /// A directory of its own for a test, holding `files`. Names ending in `/`
+    /// are made directories, the rest empty files.
+    fn test_dir(name: &str, files: &[&str]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "trooper_{}_{}_{}",
+            name,
+            std::process::id(),
+            TEST_DIRS.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        for file in files {
+            let path = dir.join(file);
+            if file.ends_with('/') {
+                fs::create_dir_all(&path).unwrap();
+            } else {
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(&path, "").unwrap();
+            }
+        }
+        dir
+    }
+
+    /// A deterministic app started in `dir`.
+    fn open_test_app(dir: &Path, options: Options) -> App {
+        let options = Options {
+            deterministic: true,
+            ..options
+        };
+        let mut app = App::new(String::from("test"), dir, options);
+        app.init();
+        app
+    }
+
+    /// A deterministic app started in a [`test_dir`] holding `files`.
+    fn test_app(name: &str, files: &[&str]) -> (App, PathBuf) {
+        test_app_with(name, files, Options::default())
+    }
+
+    fn test_app_with(name: &str, files: &[&str], options: Options) -> (App, PathBuf) {
+        let dir = test_dir(name, files);
+        (open_test_app(&dir, options), dir)
+    }
+
+    /// Tears `app` down and removes the directory it was tested in.
+    fn clean_up(mut app: App, dir: &Path) {
+        app.tear_down();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Types `s`, where a line break stands for Enter, `\x1b` for Esc and
+    /// `↑` for Up.
+    fn keys(app: &mut App, s: &str) {
+        for c in s.chars() {
+            let code = match c {
+                '\n' => KeyCode::Enter,
+                '\x1b' => KeyCode::Esc,
+                '↑' => KeyCode::Up,
+                c => KeyCode::Char(c),
+            };
+            app.handle_event(KeyEvent::new(code, KeyModifiers::empty()));
+        }
+    }
+
+    #[test]
+    fn counts_repeat_movements_and_extend_operators() {
+        let (mut app, dir) = test_app("counts", &["a", "b", "c", "d", "e"]);
+
+        keys(&mut app, "3j");
+        assert_eq!(app.ui.scroll_y + app.ui.cursor_y, 3);
+
+        keys(&mut app, "gg2yy");
+        assert_eq!(app.yanked().unwrap().0, [dir.join("a"), dir.join("b")]);
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn macros_replay_the_recorded_keys() {
+        let (mut app, dir) = test_app("macros", &["a", "b", "c", "d", "e"]);
+
+        keys(&mut app, "qajjq");
+        assert_eq!(app.macros[&'a'], str_to_key_events("jj"));
+        assert_eq!(app.ui.scroll_y + app.ui.cursor_y, 2);
+
+        keys(&mut app, "gg@a");
+        assert_eq!(app.ui.scroll_y + app.ui.cursor_y, 2);
+        keys(&mut app, "gg2@a");
+        assert_eq!(app.ui.scroll_y + app.ui.cursor_y, 4);
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn escape_sequences_complete_or_time_out_on_ticks() {
+        let (mut app, dir) = test_app("escape", &["a", "b", "c"]);
+        let key = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::empty());
+        app.set_value("escape", "jk").unwrap();
+        app.set_value("escape_timeout", "60000").unwrap();
+
+        // Completed before the timeout
+        app.handle_event(key('v'));
+        app.handle_event(key('j'));
+        app.on_tick();
+        assert_eq!(app.ui.scroll_y + app.ui.cursor_y, 0);
+        app.handle_event(key('k'));
+        assert_eq!(app.active_mode, ActiveMode::Normal);
+        assert_eq!(app.ui.scroll_y + app.ui.cursor_y, 0);
+
+        // Flushed as ordinary keys once it has timed out
+        app.set_value("escape_timeout", "0").unwrap();
+        app.handle_event(key('v'));
+        app.handle_event(key('j'));
+        app.on_tick();
+        assert_eq!(app.active_mode, ActiveMode::Visual);
+        assert_eq!(app.ui.scroll_y + app.ui.cursor_y, 1);
+        app.handle_event(key('k'));
+        assert_eq!(app.active_mode, ActiveMode::Visual);
+        assert_eq!(app.ui.scroll_y + app.ui.cursor_y, 0);
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn only_small_text_files_are_yanked_as_text() {
+        let dir = test_dir("yank_text", &[]);
+        let notes = dir.join("notes.txt");
+        let image = dir.join("image.png");
+        fs::write(&notes, "hello\n").unwrap();
+        fs::write(&image, [0x89, b'P', b'N', b'G', 0xff]).unwrap();
+        let both = [notes.clone(), image.clone()];
+
+        assert_eq!(
+            text_to_yank(AppActions::YankNames, &both).unwrap(),
+            "notes.txt\nimage.png"
+        );
+        assert_eq!(
+            text_to_yank(AppActions::YankPaths, &both[..1]).unwrap(),
+            notes.to_string_lossy()
+        );
+        assert_eq!(
+            text_to_yank(AppActions::YankContents, &both[..1]).unwrap(),
+            "hello\n"
+        );
+        assert!(text_to_yank(AppActions::YankContents, &both).is_err());
+        assert!(text_to_yank(AppActions::YankContents, &[dir.clone()]).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn directories_are_symlinked_when_pasting_hard_links() {
+        use std::os::unix::fs::MetadataExt;
+
+        let (mut app, dir) = test_app("paste_links", &["d/", "f"]);
+
+        keys(&mut app, "yyPh");
+        assert!(fs::symlink_metadata(dir.join("d (Copy)"))
+            .unwrap()
+            .file_type()
+            .is_symlink());
+
+        keys(&mut app, "G");
+        // Normally done by the next draw
+        app.selection_start = app.ui.scroll_y + app.ui.cursor_y;
+        keys(&mut app, "yyPh");
+        assert_eq!(fs::metadata(dir.join("f")).unwrap().nlink(), 2);
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn entries_without_a_name_are_not_pasted() {
+        let (mut app, dir) = test_app("paste_root", &[]);
+
+        // As the clipboard gives them for file:///
+        app.registers
+            .insert('a', (vec![PathBuf::from("/")], YankMode::Copying));
+        app.register = Some('a');
+        app.paste_yanked_files();
+        assert_eq!(app.ui.message, "Can't paste /, it has no name");
+        assert!(app.jobs.is_empty());
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn dry_runs_change_nothing() {
+        let (mut app, dir) = test_app_with(
+            "dry_run",
+            &["a"],
+            Options {
+                dry_run: true,
+                ..Default::default()
+            },
+        );
+
+        app.handle_action(AppActions::DeleteFile, Vec::new());
+        assert!(dir.join("a").exists());
+        assert_eq!(
+            app.ui.message,
+            format!("Dry run: would delete {}", dir.join("a").display())
+        );
+
+        app.handle_action(AppActions::Set, vec![String::from("dryrun!")]);
+        assert!(!app.dry_run);
+        app.handle_action(AppActions::DeleteFile, Vec::new());
+        assert!(!dir.join("a").exists());
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn config_can_be_given_by_path() {
+        let dir = test_dir("config", &[]);
+        fs::write(dir.join("test.ini"), "[settings]\nwrap_around = true\n").unwrap();
+        let app = open_test_app(
+            &dir,
+            Options {
+                config: Some(dir.join("test.ini")),
+                ..Default::default()
+            },
+        );
+        assert!(app.settings.wrap_around);
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn commands_can_be_added_in_the_config() {
+        let dir = test_dir("commands", &[]);
+        fs::write(dir.join("test.ini"), "[commands]\nrm = DeleteFile\n").unwrap();
+        let app = open_test_app(
+            &dir,
+            Options {
+                config: Some(dir.join("test.ini")),
+                ..Default::default()
+            },
+        );
+        assert_eq!(app.commands.get("rm"), Some(&AppActions::DeleteFile));
+        assert_eq!(app.commands.get("delete"), Some(&AppActions::DeleteFile));
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn keys_can_be_bound_to_shell_commands() {
+        let dir = test_dir("shell_keys", &[]);
+        let config = "[normal]\n\
+                      gx = !cat %f\n\
+                      X = &touch %d/started\n";
+        fs::write(dir.join("test.ini"), config).unwrap();
+        let mut app = open_test_app(
+            &dir,
+            Options {
+                config: Some(dir.join("test.ini")),
+                ..Default::default()
+            },
+        );
+        assert!(app.popup.is_none());
+
+        keys(&mut app, "gx");
+        assert!(app.take_shell_command().is_some());
+
+        keys(&mut app, "X");
+        let started = Instant::now();
+        while !dir.join("started").exists() && started.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(dir.join("started").exists());
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn hooks_run_when_things_change() {
+        let dir = test_dir("hooks", &["sub/"]);
+        let config = format!(
+            "[hooks]\n\
+             on_dir_change = !echo %d >> {}\n\
+             on_select = ToggleHiddenFiles\n\
+             on_quit = !touch %d/quit\n\
+             on_start = Quit\n",
+            dir.join("dirs").display()
+        );
+        fs::write(dir.join("test.ini"), config).unwrap();
+        let mut app = open_test_app(
+            &dir.join("sub"),
+            Options {
+                config: Some(dir.join("test.ini")),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            app.popup.as_ref().unwrap().rows,
+            [(
+                String::from("[hooks] on_start"),
+                String::from("Unknown event")
+            )]
+        );
+
+        app.run_hooks();
+        app.run_hooks();
+        assert_eq!(
+            fs::read_to_string(dir.join("dirs")).unwrap(),
+            format!("{}\n", dir.join("sub").display())
+        );
+        app.handle_action(AppActions::MoveUpDir, vec![]);
+        app.run_hooks();
+        assert_eq!(
+            fs::read_to_string(dir.join("dirs"))
+                .unwrap()
+                .lines()
+                .count(),
+            2
+        );
+        // The cursor is on sub in the parent, where there was no entry before
+        assert!(app.show_hidden_files);
+
+        app.tear_down();
+        assert!(dir.join("quit").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn commands_arrive_over_the_socket() {
+        use std::{io::Write, os::unix::net::UnixStream};
+
+        let (mut app, dir) = test_app("socket", &["sub/", "sub/.hidden"]);
+        let socket = dir.join("trooper.sock");
+        app.server = Some(Server::start(&socket, &app.waker).unwrap());
+
+        let mut client = UnixStream::connect(&socket).unwrap();
+        writeln!(client, "select sub/.hidden").unwrap();
+        drop(client);
+        let started = Instant::now();
+        while *app.current_dir == dir && started.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(10));
+            app.on_tick();
+        }
+        assert_eq!(*app.current_dir, dir.join("sub"));
+        assert_eq!(
+            app.dir_contents[(app.ui.cursor_y + app.ui.scroll_y) as usize].file_name(),
+            ".hidden"
+        );
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn scripts_run_commands_until_one_fails() {
+        let dir = test_dir("batch", &["sub/"]);
+        fs::write(dir.join("a.txt"), "a").unwrap();
+        let mut app = open_test_app(&dir, Options::default());
+
+        let script = "# Copies a.txt into sub\n\
+                      select a.txt\n\
+                      :copy\n\
+                      \n\
+                      cd sub\n\
+                      paste\n";
+        assert_eq!(app.run_batch(script), Ok(()));
+        assert_eq!(
+            fs::read_to_string(dir.join("sub").join("a.txt")).unwrap(),
+            "a"
+        );
+        assert_eq!(
+            app.run_batch("cd ..\nfrobnicate\ndelete"),
+            Err(String::from("line 2: Unknown command frobnicate"))
+        );
+        assert_eq!(*app.current_dir, dir);
+        assert_eq!(
+            app.run_batch("select missing"),
+            Err(format!(
+                "line 1: {} does not exist",
+                dir.join("missing").display()
+            ))
+        );
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn special_keys_can_be_bound() {
+        let dir = test_dir("special", &[]);
+        fs::write(
+            dir.join("test.ini"),
+            "[normal]\n<Down> = ToggleHiddenFiles\n",
+        )
+        .unwrap();
+        let mut app = open_test_app(
+            &dir,
+            Options {
+                config: Some(dir.join("test.ini")),
+                ..Default::default()
+            },
+        );
+        assert!(app.popup.is_none());
+
+        app.handle_event(KeyEvent::new(KeyCode::Down, KeyModifiers::empty()));
+        assert!(app.show_hidden_files);
+        // Unbound keys keep doing what they always did
+        app.handle_event(KeyEvent::new(KeyCode::Char(':'), KeyModifiers::empty()));
+        app.handle_event(KeyEvent::new(KeyCode::Esc, KeyModifiers::empty()));
+        assert_eq!(app.active_mode, ActiveMode::Normal);
+
+        let keys = parse_key_events("g<cr><S-Tab><Space>").unwrap();
+        assert_eq!(
+            keys[2],
+            KeyEvent::new(KeyCode::BackTab, KeyModifiers::SHIFT)
+        );
+        assert_eq!(key_events_to_string(&keys), "g<CR><S-Tab> ");
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn modifiers_and_releases_can_be_bound() {
+        let keys = parse_key_events("<C-i><S-Enter><a-Space><Release-x><F5><C-S-Tab>").unwrap();
+        assert_eq!(
+            keys[..5],
+            [
+                KeyEvent::new(KeyCode::Char('i'), KeyModifiers::CONTROL),
+                KeyEvent::new(KeyCode::Enter, KeyModifiers::SHIFT),
+                KeyEvent::new(KeyCode::Char(' '), KeyModifiers::ALT),
+                KeyEvent::new_with_kind(
+                    KeyCode::Char('x'),
+                    KeyModifiers::empty(),
+                    KeyEventKind::Release
+                ),
+                KeyEvent::new(KeyCode::F(5), KeyModifiers::empty()),
+            ]
+        );
+        assert_eq!(
+            key_events_to_string(&keys),
+            "^i<S-CR><A-Space><Release-x><F5><C-S-Tab>"
+        );
+        assert!(parse_key_events("<X-a>").is_err());
+        assert!(parse_key_events("<F25>").is_err());
+
+        let dir = test_dir("release", &[]);
+        fs::write(
+            dir.join("test.ini"),
+            "[normal]\n<Release-z> = ToggleHiddenFiles\n",
+        )
+        .unwrap();
+        let mut app = open_test_app(
+            &dir,
+            Options {
+                config: Some(dir.join("test.ini")),
+                ..Default::default()
+            },
+        );
+        let release = |c| {
+            KeyEvent::new_with_kind(
+                KeyCode::Char(c),
+                KeyModifiers::empty(),
+                KeyEventKind::Release,
+            )
+        };
+
+        app.handle_event(release('z'));
+        assert!(app.show_hidden_files);
+        // Unbound releases don't type anything
+        app.handle_event(KeyEvent::new(KeyCode::Char(':'), KeyModifiers::empty()));
+        app.handle_event(release(':'));
+        app.handle_event(release('z'));
+        assert_eq!(app.command_line.line(), "");
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn ticks_slow_down_while_idle() {
+        let rate = Duration::from_millis(100);
+        assert_eq!(idle_tick_rate(rate, Duration::from_secs(3)), rate);
+        assert_eq!(
+            idle_tick_rate(rate, Duration::from_secs(25)),
+            Duration::from_millis(400)
+        );
+        assert_eq!(
+            idle_tick_rate(rate, Duration::from_secs(3600)),
+            Duration::from_secs(2)
+        );
+        // Slower rates than that are left alone
+        let slow = Duration::from_secs(5);
+        assert_eq!(idle_tick_rate(slow, Duration::from_secs(3600)), slow);
+    }
+
+    #[test]
+    fn only_changes_are_drawn() {
+        let (mut app, dir) = test_app("dirty", &["sub/"]);
+        assert!(app.dirty);
+
+        app.dirty = false;
+        app.on_tick();
+        assert!(!app.dirty);
+        app.handle_event(KeyEvent::new(KeyCode::Char('j'), KeyModifiers::empty()));
+        assert!(app.dirty);
+
+        // Work finishing in the background counts as a change too
+        app.dirty = false;
+        app.dir_sizes.request(vec![dir.join("sub")]);
+        let started = Instant::now();
+        while !app.dirty && started.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(10));
+            app.on_tick();
+        }
+        assert_eq!(app.dir_sizes.get(&dir.join("sub")), Some(0));
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn clicks_move_the_cursor_and_double_clicks_enter() {
+        let (mut app, dir) = test_app("mouse", &["a/", "b", "c"]);
+        // Where the panels would be drawn
+        app.ui.bookmark_area = Rect::new(2, 1, 15, 10);
+        app.ui.list_area = Rect::new(18, 1, 40, 10);
+        let mouse = |kind, column, row| MouseEvent {
+            kind,
+            column,
+            row,
+            modifiers: KeyModifiers::empty(),
+        };
+        let click = |column, row| mouse(MouseEventKind::Down(MouseButton::Left), column, row);
+
+        app.on_mouse(click(20, 2));
+        assert_eq!(app.ui.scroll_y + app.ui.cursor_y, 1);
+        app.on_mouse(mouse(MouseEventKind::ScrollDown, 20, 2));
+        assert_eq!(app.ui.scroll_y + app.ui.cursor_y, 2);
+        app.on_mouse(mouse(MouseEventKind::ScrollUp, 20, 2));
+        assert_eq!(app.ui.scroll_y + app.ui.cursor_y, 0);
+
+        // There are no bookmarks, but the panel is still switched to
+        app.on_mouse(click(4, 5));
+        assert!(app.active_panel == ActivePanel::Bookmarks);
+        app.on_mouse(click(20, 1));
+        assert!(app.active_panel == ActivePanel::Main);
+        app.on_mouse(click(20, 1));
+        assert_eq!(*app.current_dir, dir.join("a"));
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn bookmarks_are_resized_by_dragging_the_divider() {
+        let (mut app, dir) = test_app("resize", &[]);
+        assert_eq!(app.ui.bookmark_width, 16);
+        app.handle_action(AppActions::WidenBookmarks, Vec::new());
+        assert_eq!(app.ui.bookmark_width, 17);
+
+        app.ui.bookmark_area = Rect::new(2, 1, 17, 10);
+        app.ui.list_area = Rect::new(20, 1, 40, 10);
+        let mouse = |kind, column| MouseEvent {
+            kind,
+            column,
+            row: 4,
+            modifiers: KeyModifiers::empty(),
+        };
+        app.on_mouse(mouse(MouseEventKind::Down(MouseButton::Left), 19));
+        app.on_mouse(mouse(MouseEventKind::Drag(MouseButton::Left), 27));
+        app.on_mouse(mouse(MouseEventKind::Up(MouseButton::Left), 27));
+        assert_eq!(app.ui.bookmark_width, 25);
+        // Dragging without having grabbed the divider does nothing
+        app.on_mouse(mouse(MouseEventKind::Drag(MouseButton::Left), 10));
+        assert_eq!(app.ui.bookmark_width, 25);
+
+        // The chosen width wins over fitting the bookmarks
+        app.handle_action(AppActions::CreateBookmark, Vec::new());
+        assert_eq!(app.ui.bookmark_width, 25);
+        assert_eq!(app.state.bookmark_width, Some(25));
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn commands_are_run_again_from_the_history() {
+        let (mut app, dir) = test_app("history", &["sub/"]);
+
+        keys(&mut app, ":cd sub\n:cd ..\n:history\n");
+        assert_eq!(app.active_mode, ActiveMode::History);
+        assert_eq!(app.history_picker.lines, ["cd ..", "cd sub"]);
+        keys(&mut app, "sub\n");
+        assert_eq!(*app.current_dir, dir.join("sub"));
+
+        keys(&mut app, ":history\n");
+        app.handle_event(KeyEvent::new(KeyCode::Char('e'), KeyModifiers::CONTROL));
+        assert_eq!(app.active_mode, ActiveMode::Command);
+        assert_eq!(app.command_line.line(), "cd sub");
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn searches_have_a_history_of_their_own() {
+        let (mut app, dir) = test_app("search", &["alpha.log", "beta", "gamma.log", "delta"]);
+        let name = |app: &App| {
+            let index = (app.ui.scroll_y + app.ui.cursor_y) as usize;
+            app.dir_contents[index]
+                .file_name()
+                .to_string_lossy()
+                .into_owned()
+        };
+
+        keys(&mut app, "/.log\n");
+        assert_eq!(name(&app), "gamma.log");
+        keys(&mut app, "n");
+        assert_eq!(name(&app), "alpha.log");
+        keys(&mut app, "N");
+        assert_eq!(name(&app), "gamma.log");
+
+        keys(&mut app, ":reload\n/et\n");
+        assert_eq!(name(&app), "beta");
+        // Each line only goes back through its own history
+        keys(&mut app, "/↑↑");
+        assert_eq!(app.search_line.line(), ".log");
+        keys(&mut app, "\n:↑");
+        assert_eq!(app.command_line.line(), "reload");
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn listing_is_filtered_while_typing() {
+        let (mut app, dir) = test_app("filter", &["src/", "Cargo.toml", "Cargo.lock", "README.md"]);
+        let names = |app: &App| -> Vec<String> {
+            app.dir_contents
+                .iter()
+                .map(|e| e.file_name().to_string_lossy().into_owned())
+                .collect()
+        };
+
+        keys(&mut app, ":filter\ncrg");
+        assert_eq!(names(&app), ["Cargo.lock", "Cargo.toml"]);
+        keys(&mut app, "t");
+        assert_eq!(names(&app), ["Cargo.toml"]);
+        // Esc brings everything back, with the cursor on the same entry
+        keys(&mut app, "\x1b");
+        assert_eq!(names(&app).len(), 4);
+        assert_eq!(app.ui.scroll_y + app.ui.cursor_y, 2);
+
+        // Enter keeps the filter, or enters the only entry left
+        keys(&mut app, ":filter\nrg\n");
+        assert_eq!(names(&app), ["Cargo.lock", "Cargo.toml"]);
+        keys(&mut app, ":reload\n");
+        assert_eq!(names(&app), ["Cargo.lock", "Cargo.toml"]);
+        keys(&mut app, ":filter\nsr\n");
+        assert_eq!(*app.current_dir, dir.join("src"));
+        assert_eq!(app.filter, None);
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn searches_ignore_case_unless_the_pattern_has_capitals() {
+        let (mut app, dir) = test_app("smartcase", &["Makefile", "main.rs", "notes"]);
+        let name = |app: &App| {
+            let index = (app.ui.scroll_y + app.ui.cursor_y) as usize;
+            app.dir_contents[index]
+                .file_name()
+                .to_string_lossy()
+                .into_owned()
+        };
+
+        keys(&mut app, "/ma\n");
+        assert_eq!(name(&app), "Makefile");
+        keys(&mut app, "n");
+        assert_eq!(name(&app), "main.rs");
+        keys(&mut app, "/Ma\nn");
+        assert_eq!(name(&app), "Makefile");
+        keys(&mut app, ":set nosmartcase\n/Ma\n");
+        assert_eq!(name(&app), "main.rs");
+        keys(&mut app, ":set noignorecase\n/ma\nn");
+        assert_eq!(name(&app), "main.rs");
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn config_problems_are_listed() {
+        let dir = test_dir("problems", &[]);
+        let config = "[normal]\n\
+                      x = DeleteFiles\n\
+                      <C-x>x = DeleteFile\n\
+                      <c-x>x = CutFiles\n\
+                      <Tab2> = EnterDir\n\
+                      [settings]\n\
+                      icons = yes\n\
+                      color = true\n\
+                      [protected]\n\
+                      /etc/** = never\n";
+        fs::write(dir.join("test.ini"), config).unwrap();
+        let app = open_test_app(
+            &dir,
+            Options {
+                config: Some(dir.join("test.ini")),
+                ..Default::default()
+            },
+        );
+
+        let popup = app.popup.as_ref().unwrap();
+        let rows: Vec<(&str, &str)> = popup
+            .rows
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                ("[normal] <Tab2>", "Unknown key <Tab2>"),
+                ("[normal] <c-x>x", "The same keys as <C-x>x"),
+                ("[normal] x", "Unknown action DeleteFiles"),
+                (
+                    "[protected] /etc/**",
+                    "Expected confirm or refuse, not never"
+                ),
+                ("[settings] color", "Unknown setting"),
+                ("[settings] icons", "Expected true or false, not yes"),
+            ]
+        );
+        // Everything else is still read
+        assert_eq!(
+            app.normal.bindings.get(&str_to_key_events("<C-x>x")),
+            Some(&AppActions::DeleteFile)
+        );
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn toml_and_unreadable_configs_are_listed_too() {
+        let rows = |app: &App| app.popup.as_ref().map(|popup| popup.rows.clone());
+        let dir = test_dir("toml_problems", &[]);
+        fs::write(
+            dir.join("test.toml"),
+            "[normal]\n\"<c-a>\" = \"SelectAll\"\n\"<C-a>\" = \"ToggleHiddenFiles\"\n",
+        )
+        .unwrap();
+        fs::write(dir.join("test.ini"), [b'x', b' ', b'=', 0xff]).unwrap();
+
+        let mut app = open_test_app(
+            &dir,
+            Options {
+                config: Some(dir.join("test.toml")),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            rows(&app).unwrap(),
+            [(
+                String::from("normal.\"<c-a>\""),
+                String::from("The same keys as <C-a>")
+            )]
+        );
+        app.tear_down();
+
+        // Not UTF-8
+        let app = open_test_app(
+            &dir,
+            Options {
+                config: Some(dir.join("test.ini")),
+                ..Default::default()
+            },
+        );
+        let rows = rows(&app).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].0, dir.join("test.ini").display().to_string());
+        assert!(rows[0].1.starts_with("Could not read the config"));
+        // The defaults are still bound
+        assert_eq!(
+            app.normal.bindings.get(&str_to_key_events("j")),
+            Some(&AppActions::MoveDown)
+        );
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn default_config_is_written_once() {
+        let (mut app, dir) = test_app("default", &[]);
+
+        let args = vec![String::from(">"), String::from("default.ini")];
+        app.handle_action(AppActions::WriteDefaultConfig, args.clone());
+        assert_eq!(
+            fs::read_to_string(dir.join("default.ini")).unwrap(),
+            DEFAULT_CONFIG
+        );
+        fs::write(dir.join("default.ini"), "[settings]").unwrap();
+        app.handle_action(AppActions::WriteDefaultConfig, args);
+        assert_eq!(
+            fs::read_to_string(dir.join("default.ini")).unwrap(),
+            "[settings]"
+        );
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn config_changes_are_picked_up_on_reload() {
+        let dir = test_dir("reload", &[]);
+        let config = dir.join("test.ini");
+        fs::write(&config, "").unwrap();
+        let mut app = open_test_app(
+            &dir,
+            Options {
+                config: Some(config.clone()),
+                ..Default::default()
+            },
+        );
+        assert!(!app.settings.wrap_around);
+
+        fs::write(
+            &config,
+            "[settings]\nwrap_around = true\n[normal]\nx = DeleteFile\n[commands]\nrm = DeleteFile\n",
+        )
+        .unwrap();
+        app.handle_action(AppActions::ReloadConfig, Vec::new());
+        assert!(app.settings.wrap_around);
+        assert_eq!(
+            app.normal.bindings.get(&str_to_key_events("x")),
+            Some(&AppActions::DeleteFile)
+        );
+        assert_eq!(app.commands.get("rm"), Some(&AppActions::DeleteFile));
+        assert_eq!(app.commands.get("q"), Some(&AppActions::Quit));
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn enter_chooses_the_selection_when_picking() {
+        let (mut app, dir) = test_app_with(
+            "choose",
+            &["a", "b", "c"],
+            Options {
+                picking: true,
+                ..Default::default()
+            },
+        );
+
+        app.handle_action(AppActions::MoveDown, Vec::new());
+        app.handle_action(AppActions::ToggleVisualMode, Vec::new());
+        app.handle_action(AppActions::MoveDown, Vec::new());
+        app.on_enter();
+        assert!(app.should_quit);
+        assert_eq!(
+            app.chosen,
+            [app.current_dir.join("b"), app.current_dir.join("c")]
+        );
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn selections_span_directories_and_are_reviewed_in_a_panel() {
+        let (mut app, dir) = test_app("review", &["sub/", "a", "sub/x"]);
+
+        // The listing is `sub`, then `a`
+        keys(&mut app, "j kl ");
+        assert_eq!(*app.current_dir, dir.join("sub"));
+        keys(&mut app, "S");
+        assert_eq!(app.active_panel, ActivePanel::Selection);
+        assert_eq!(
+            app.selected_paths(),
+            [dir.join("a"), dir.join("sub").join("x")]
+        );
+
+        keys(&mut app, "j ");
+        assert_eq!(app.selected_paths(), [dir.join("a")]);
+        keys(&mut app, "\x1b");
+        assert_eq!(app.active_panel, ActivePanel::Main);
+        keys(&mut app, "Sl");
+        assert_eq!(app.active_panel, ActivePanel::Main);
+        assert_eq!(*app.current_dir, dir);
+        assert_eq!(app.ui.scroll_y + app.ui.cursor_y, 1);
+        // Unselecting the last entry closes the panel
+        keys(&mut app, "S ");
+        assert_eq!(app.active_panel, ActivePanel::Main);
+        assert!(app.marked.is_empty());
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn entries_are_selected_by_pattern() {
+        let dir = test_dir("select", &[]);
+        for name in ["a.log", "b.log", "c.txt"] {
+            fs::write(dir.join(name), name).unwrap();
+        }
+        let mut app = open_test_app(&dir, Options::default());
+
+        assert!(app.run_command_line("select *.log"));
+        assert_eq!(app.selected_paths(), [dir.join("a.log"), dir.join("b.log")]);
+        let stats = app.selection_stats().unwrap();
+        assert_eq!((stats.count, stats.size, stats.partial), (2, 10, false));
+        assert!(app.run_command_line("select! /^a/"));
+        assert_eq!(app.selected_paths(), [dir.join("b.log")]);
+        // A plain path still only moves the cursor
+        assert!(app.run_command_line("select c.txt"));
+        assert_eq!(app.ui.scroll_y + app.ui.cursor_y, 2);
+        assert_eq!(app.selected_paths(), [dir.join("b.log")]);
+
+        fs::remove_file(dir.join("b.log")).unwrap();
+        app.run_command_line("reload");
+        assert!(app.marked.is_empty());
+
+        assert!(app.run_command_line("select a.log"));
+        app.handle_event(KeyEvent::new(KeyCode::Char('a'), KeyModifiers::CONTROL));
+        assert_eq!(app.selected_paths(), [dir.join("a.log"), dir.join("c.txt")]);
+        assert!(app.run_command_line("select! c*"));
+        app.handle_event(KeyEvent::new(KeyCode::Char('*'), KeyModifiers::empty()));
+        assert_eq!(app.selected_paths(), [dir.join("c.txt")]);
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn f_jumps_to_entries_starting_with_a_letter() {
+        let (mut app, dir) = test_app("find", &["apple", "banana", "Berry", "blue", "cherry"]);
+        let cursor = |app: &App| app.ui.scroll_y + app.ui.cursor_y;
+
+        keys(&mut app, "fb");
+        assert_eq!(cursor(&app), 1);
+        keys(&mut app, ";;");
+        assert_eq!(cursor(&app), 3);
+        keys(&mut app, ",");
+        assert_eq!(cursor(&app), 2);
+        keys(&mut app, "Fa");
+        assert_eq!(cursor(&app), 0);
+        keys(&mut app, "2fb");
+        assert_eq!(cursor(&app), 2);
+        // Capitals only find themselves, as when searching
+        keys(&mut app, "ggfB");
+        assert_eq!(cursor(&app), 2);
+        keys(&mut app, "fz");
+        assert_eq!(cursor(&app), 2);
+
+        clean_up(app, &dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn g_jumps_to_the_home_and_root_directories() {
+        let (mut app, dir) = test_app("jumps", &[]);
+
+        keys(&mut app, "gr");
+        assert_eq!(*app.current_dir, Path::new("/"));
+        app.change_dir_to(&dir);
+        keys(&mut app, "g/");
+        assert!(dir.canonicalize().unwrap().starts_with(&*app.current_dir));
+        keys(&mut app, "gh");
+        assert_eq!(*app.current_dir, paths::home_dir().canonicalize().unwrap());
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn revealed_files_are_under_the_cursor() {
+        let (mut app, dir) = test_app("reveal", &["sub/", "sub/a", "sub/b", "sub/.hidden"]);
+
+        app.reveal(&dir.join("sub").join("b"));
+        assert_eq!(*app.current_dir, dir.join("sub"));
+        let cursor = (app.ui.cursor_y + app.ui.scroll_y) as usize;
+        assert_eq!(app.dir_contents[cursor].file_name(), "b");
+
+        app.reveal(&dir.join("sub").join(".hidden"));
+        assert!(app.show_hidden_files);
+        let cursor = (app.ui.cursor_y + app.ui.scroll_y) as usize;
+        assert_eq!(app.dir_contents[cursor].file_name(), ".hidden");
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn options_are_set_by_name() {
+        let (mut app, dir) = test_app("set", &[".hidden"]);
+        assert!(app.dir_contents.is_empty());
+
+        let set = |app: &mut App, line: &str| {
+            let args = line.split(' ').map(String::from).collect();
+            app.handle_action(AppActions::Set, args);
+        };
+        set(&mut app, "hidden wrap_around!");
+        assert_eq!(app.dir_contents.len(), 1);
+        assert!(app.settings.wrap_around);
+
+        set(&mut app, "noicons status_format={mode} {message}");
+        assert!(!app.settings.icons);
+        assert_eq!(app.settings.status_format, "{mode} {message}");
+
+        set(&mut app, "preserve=mode,ownership");
+        assert_eq!(app.settings.preserve.to_string(), "mode,ownership");
+        set(&mut app, "escape_timeout=soon");
+        assert_eq!(app.ui.message, "Not a number of milliseconds: soon");
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn sessions_are_resumed_where_they_left_off() {
+        let dir = test_dir("restore", &["sub/", "sub/a", "sub/b", "sub/.c"]);
+        // Each deterministic app has a sandbox of its own, so the state is
+        // kept next to the test files instead
+        let state = dir.join("state.json");
+        let start = |options: Options| {
+            let options = Options {
+                deterministic: true,
+                restore: Some(true),
+                ..options
+            };
+            let mut app = App::new(String::from("test"), &dir, options);
+            app.state_store = state.clone();
+            app.init();
+            app
+        };
+
+        let mut app = start(Options::default());
+        app.reveal(&dir.join("sub").join(".c"));
+        app.active_panel = ActivePanel::Bookmarks;
+        app.tear_down();
+
+        let mut app = start(Options::default());
+        assert_eq!(*app.current_dir, dir.join("sub"));
+        assert!(app.show_hidden_files);
+        let cursor = (app.ui.cursor_y + app.ui.scroll_y) as usize;
+        assert_eq!(app.dir_contents[cursor].file_name(), ".c");
+        assert_eq!(app.active_panel, ActivePanel::Bookmarks);
+        app.tear_down();
+
+        // Picking files and scripts leave the last session alone
+        for options in [
+            Options {
+                picking: true,
+                ..Default::default()
+            },
+            Options {
+                batch: true,
+                ..Default::default()
+            },
+        ] {
+            let mut app = start(options);
+            app.handle_action(AppActions::MoveUpDir, Vec::new());
+            app.tear_down();
+        }
+
+        // Gone directories leave trooper where it was started
+        let mut app = start(Options::default());
+        assert_eq!(*app.current_dir, dir.join("sub"));
+        app.tear_down();
+        fs::remove_dir_all(dir.join("sub")).unwrap();
+        let app = start(Options::default());
+        assert_eq!(*app.current_dir, dir);
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn entries_are_hidden_by_name_and_by_git() {
+        let (mut app, dir) = test_app("hide", &["__pycache__/", "build/", "main.py", "main.pyc"]);
+        let names =
+            |app: &App| -> Vec<_> { app.dir_contents.iter().map(Entry::file_name).collect() };
+        assert_eq!(names(&app), ["__pycache__", "build", "main.py", "main.pyc"]);
+
+        app.set_value("hide", "__pycache__ *.pyc").unwrap();
+        assert_eq!(names(&app), ["build", "main.py"]);
+
+        let init = Command::new("git").arg("init").arg("-q").arg(&dir).status();
+        if init.map_or(false, |s| s.success()) {
+            fs::write(dir.join(".gitignore"), "build/\n").unwrap();
+            app.set_value("gitignore", "dim").unwrap();
+            assert_eq!(names(&app), ["build", "main.py"]);
+            assert!(app.dir_contents[0].ignored);
+            assert!(!app.dir_contents[1].ignored);
+
+            app.set_value("gitignore", "hide").unwrap();
+            assert_eq!(names(&app), ["main.py"]);
+        }
+        assert!(app.set_value("gitignore", "grey").is_err());
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn read_only_mode_refuses_changes() {
+        let (mut app, dir) = test_app_with(
+            "read_only",
+            &["a"],
+            Options {
+                read_only: true,
+                ..Default::default()
+            },
+        );
+
+        app.handle_action(AppActions::DeleteFile, Vec::new());
+        app.handle_action(AppActions::CreateDir, vec![String::from("b")]);
+        assert!(dir.join("a").exists());
+        assert!(!dir.join("b").exists());
+        assert_eq!(
+            app.ui.message,
+            "CreateDir is not available in read-only mode"
+        );
+        // Yanking only reads, so it still works
+        app.handle_action(AppActions::CopyFiles, Vec::new());
+        assert!(app.yanked().is_ok());
+        // Shell commands could do anything
+        app.handle_action(AppActions::RunShell, vec![String::from("touch b")]);
+        assert!(app.take_shell_command().is_none());
+        assert_eq!(
+            app.ui.message,
+            "RunShell is not available in read-only mode"
+        );
+
+        // What --read-only started stays on
+        app.handle_action(AppActions::Set, vec![String::from("noreadonly")]);
+        app.handle_action(AppActions::DeleteFile, Vec::new());
+        assert!(dir.join("a").exists());
+        app.tear_down();
+
+        let mut app = open_test_app(&dir, Options::default());
+        app.handle_action(AppActions::Set, vec![String::from("readonly")]);
+        app.handle_action(AppActions::DeleteFile, Vec::new());
+        assert!(dir.join("a").exists());
+        app.handle_action(AppActions::Set, vec![String::from("noreadonly")]);
+        app.handle_action(AppActions::DeleteFile, Vec::new());
+        assert!(!dir.join("a").exists());
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn protected_paths_need_confirming() {
+        let (mut app, dir) = test_app("protected", &["a", "b"]);
+        let a = app.current_dir.join("a");
+        let b = app.current_dir.join("b");
+        app.settings
+            .protected
+            .insert(&a.to_string_lossy(), Protection::Confirm);
+        app.settings.protected.insert(
+            &app.current_dir.join("*").to_string_lossy(),
+            Protection::Confirm,
+        );
+        app.settings
+            .protected
+            .insert(&b.to_string_lossy(), Protection::Refuse);
+
+        app.handle_action(AppActions::DeleteFile, Vec::new());
+        assert!(a.exists());
+        assert_eq!(
+            app.ui.message,
+            format!("{} is protected, repeat to confirm", a.display())
+        );
+        // Doing something else in between asks again
+        app.handle_action(AppActions::MoveToBottom, Vec::new());
+        app.handle_action(AppActions::MoveToTop, Vec::new());
+        app.handle_action(AppActions::DeleteFile, Vec::new());
+        assert!(a.exists());
+        app.handle_action(AppActions::DeleteFile, Vec::new());
+        assert!(!a.exists());
+
+        app.handle_action(AppActions::DeleteFile, Vec::new());
+        app.handle_action(AppActions::DeleteFile, Vec::new());
+        assert!(b.exists());
+        assert_eq!(app.ui.message, format!("{} is protected", b.display()));
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn restoring_onto_protected_paths_needs_confirming() {
+        let (mut app, dir) = test_app("protected_restore", &["live/", "snapshot/"]);
+        fs::write(dir.join("live/a"), "new").unwrap();
+        fs::write(dir.join("snapshot/a"), "old").unwrap();
+        app.enter_dir(&dir.join("snapshot"));
+        app.snapshot = Some(Snapshot {
+            name: String::from("1"),
+            root: dir.join("snapshot"),
+            live_root: dir.join("live"),
+        });
+        let live = dir.join("live/a");
+        app.settings
+            .protected
+            .insert(&live.to_string_lossy(), Protection::Confirm);
+
+        app.handle_action(AppActions::RestoreSnapshot, Vec::new());
+        assert_eq!(fs::read_to_string(&live).unwrap(), "new");
+        assert_eq!(
+            app.ui.message,
+            format!("{} is protected, repeat to confirm", live.display())
+        );
+        assert!(!dir.join("live/a (Replaced)").exists());
+
+        app.handle_action(AppActions::RestoreSnapshot, Vec::new());
+        assert_eq!(fs::read_to_string(&live).unwrap(), "old");
+        let aside = dir.join("live/a (Replaced)");
+        assert_eq!(fs::read_to_string(&aside).unwrap(), "new");
+        assert_eq!(
+            app.ui.message,
+            format!("Kept what was replaced as {}", aside.display())
+        );
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn downloads_without_a_name_are_refused() {
+        let (mut app, dir) = test_app("nameless_download", &[]);
+        app.remote = Target::parse("user@localhost");
+
+        app.download(&[PathBuf::from("/")]);
+        assert_eq!(app.ui.message, "Could not download /: it has no name");
+        assert!(app.jobs.is_empty());
+
+        app.remote = None;
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn named_registers_hold_their_own_files() {
+        let (mut app, dir) = test_app("registers", &["a", "b"]);
+
+        keys(&mut app, "\"ayyj");
+        // Normally done by the next draw
+        app.selection_start = 1;
+        keys(&mut app, "\"bdd");
+        assert_eq!(
+            app.registers[&'a'],
+            (vec![dir.join("a")], YankMode::Copying)
+        );
+        assert_eq!(
+            app.registers[&'b'],
+            (vec![dir.join("b")], YankMode::Cutting)
+        );
+        assert_eq!(app.register, None);
+
+        keys(&mut app, "\"c");
+        assert!(matches!(app.yanked(), Err(AppError::EmptyRegister('c'))));
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn the_last_change_is_repeated_on_the_selection() {
+        let (mut app, dir) = test_app("repeat", &[]);
+        let key = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::empty());
+
+        app.on_key(key('.'));
+        assert_eq!(app.ui.message, "Nothing to repeat");
+
+        app.handle_action(AppActions::CreateDir, vec![String::from("y")]);
+        fs::remove_dir(dir.join("y")).unwrap();
+        app.on_key(key('.'));
+        assert!(dir.join("y").is_dir());
+
+        clean_up(app, &dir);
+    }
+
+    #[test]
+    fn pending_chords_list_their_continuations() {
+        let mut bindings = HashMap::new();
+        bindings.insert(str_to_key_events("g"), AppActions::MoveToBottom);
+        bindings.insert(str_to_key_events("gg"), AppActions::MoveToTop);
+        bindings.insert(str_to_key_events("yy"), AppActions::CopyFiles);
+
+        let found = continuations(&bindings, &str_to_key_events("g"));
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, str_to_key_events("gg").as_slice());
+        assert_eq!(found[0].1, AppActions::MoveToTop);
+        assert!(continuations(&bindings, &str_to_key_events("gg")).is_empty());
+    }
+
+    #[test]
+    fn help_lists_every_key_of_an_action() {
+        let mut normal = HashMap::new();
+        normal.insert(str_to_key_events("j"), AppActions::MoveDown);
+        normal.insert(str_to_key_events("n"), AppActions::MoveDown);
+        normal.insert(str_to_key_events("<C-w><C-h>"), AppActions::MoveToLeftPanel);
+        let mut visual = HashMap::new();
+        visual.insert(str_to_key_events("y"), AppActions::CopyFiles);
+
+        let rows = help_rows(&normal, &visual);
+        let row = |label: &str, value: &str| (String::from(label), String::from(value));
+        assert_eq!(
+            rows,
+            [
+                row("Normal mode", ""),
+                row("MoveDown", "j  n"),
+                row("MoveToLeftPanel", "^w^h"),
+                row("", ""),
+                row("Visual mode", ""),
+                row("CopyFiles", "y"),
+            ]
+        );
+    }
 
     #[test]
     fn reading_default_config_gives_default_bindings() {
@@ -948,12 +6016,31 @@ mod tests {
         bindings.insert(str_to_key_events("k"), AppActions::MoveUp);
         bindings.insert(str_to_key_events("h"), AppActions::MoveUpDir);
         bindings.insert(str_to_key_events("l"), AppActions::EnterDir);
-        bindings.insert(str_to_key_events("q"), AppActions::Quit);
+        bindings.insert(str_to_key_events("ZZ"), AppActions::Quit);
+        bindings.insert(str_to_key_events("q"), AppActions::RecordMacro);
+        bindings.insert(str_to_key_events("@"), AppActions::PlayMacro);
+        bindings.insert(str_to_key_events("\""), AppActions::SelectRegister);
+        bindings.insert(str_to_key_events("yn"), AppActions::YankNames);
+        bindings.insert(str_to_key_events("yp"), AppActions::YankPaths);
+        bindings.insert(str_to_key_events("yc"), AppActions::YankContents);
+        bindings.insert(str_to_key_events("<C-c>"), AppActions::CancelJobs);
+        bindings.insert(str_to_key_events("J"), AppActions::ToggleJobs);
         bindings.insert(str_to_key_events("gg"), AppActions::MoveToTop);
         bindings.insert(str_to_key_events("G"), AppActions::MoveToBottom);
+        bindings.insert(str_to_key_events("<Home>"), AppActions::MoveToTop);
+        bindings.insert(str_to_key_events("<End>"), AppActions::MoveToBottom);
+        bindings.insert(str_to_key_events("f"), AppActions::FindForward);
+        bindings.insert(str_to_key_events("F"), AppActions::FindBackward);
+        bindings.insert(str_to_key_events(";"), AppActions::RepeatFind);
+        bindings.insert(str_to_key_events(","), AppActions::RepeatFindReversed);
+        bindings.insert(str_to_key_events("gh"), AppActions::GoHome);
+        bindings.insert(str_to_key_events("gr"), AppActions::GoToRoot);
+        bindings.insert(str_to_key_events("g/"), AppActions::GoToMountPoint);
         bindings.insert(str_to_key_events("yy"), AppActions::CopyFiles);
         bindings.insert(str_to_key_events("dd"), AppActions::CutFiles);
         bindings.insert(str_to_key_events("p"), AppActions::PasteFiles);
+        bindings.insert(str_to_key_events("Ps"), AppActions::PasteSymlinks);
+        bindings.insert(str_to_key_events("Ph"), AppActions::PasteHardlinks);
         bindings.insert(str_to_key_events(":"), AppActions::OpenCommandMode);
         bindings.insert(str_to_key_events("b"), AppActions::ToggleBookmark);
         bindings.insert(
@@ -970,6 +6057,8 @@ mod tests {
             ],
             AppActions::MoveToRightPanel,
         );
+        bindings.insert(str_to_key_events("<C-w><gt>"), AppActions::WidenBookmarks);
+        bindings.insert(str_to_key_events("<C-w><lt>"), AppActions::NarrowBookmarks);
         bindings.insert(
             vec![KeyEvent::new(KeyCode::Char('h'), KeyModifiers::CONTROL)],
             AppActions::MoveToLeftPanel,
@@ -980,12 +6069,27 @@ mod tests {
         );
         bindings.insert(str_to_key_events("z"), AppActions::ToggleHiddenFiles);
         bindings.insert(str_to_key_events("v"), AppActions::ToggleVisualMode);
+        bindings.insert(str_to_key_events("i"), AppActions::ShowProperties);
+        bindings.insert(str_to_key_events("D"), AppActions::ToggleDevices);
+        bindings.insert(str_to_key_events("?"), AppActions::ShowHelp);
+        bindings.insert(str_to_key_events("<C-p>"), AppActions::OpenPalette);
+        bindings.insert(str_to_key_events("/"), AppActions::Search);
+        bindings.insert(str_to_key_events("n"), AppActions::SearchNext);
+        bindings.insert(str_to_key_events("N"), AppActions::SearchPrevious);
+        bindings.insert(str_to_key_events("<C-f>"), AppActions::Filter);
+        bindings.insert(str_to_key_events("<C-a>"), AppActions::SelectAll);
+        bindings.insert(str_to_key_events("<Space>"), AppActions::ToggleSelected);
+        bindings.insert(str_to_key_events("S"), AppActions::ReviewSelection);
+        bindings.insert(str_to_key_events("*"), AppActions::InvertSelection);
+        bindings.insert(str_to_key_events("."), AppActions::RepeatLastChange);
 
         let config_path = PathBuf::from_str("./assets/default_config.ini").unwrap();
-        let (normal_bindings, _) = match read_config(&config_path) {
+        let config = match read_config(&config_path) {
             Ok(x) => x,
             Err(msg) => panic!("{}", msg),
         };
+        assert_eq!(config.problems, []);
+        let normal_bindings = config.normal;
 
         for (k, v) in normal_bindings.iter() {
             assert!(bindings.contains_key(k), "{:?}", k);
@@ -0,0 +1,223 @@
+use std::{
+    fs::{self, File, Metadata, OpenOptions},
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use flate2::{write::GzEncoder, Compression};
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+/// Archive formats which can be created, picked by the extension of the
+/// archive name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    TarGz,
+    Zip,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Format> {
+        let name = name.to_lowercase();
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Format::TarGz)
+        } else if name.ends_with(".zip") {
+            Some(Format::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// An entry to archive and its path inside the archive.
+struct Entry {
+    path: PathBuf,
+    name: String,
+    metadata: Metadata,
+}
+
+/// Bytes of file contents written so far, reported after every read.
+struct Progress<F: FnMut(u64, u64) -> io::Result<()>> {
+    done: u64,
+    total: u64,
+    report: F,
+}
+
+impl<F: FnMut(u64, u64) -> io::Result<()>> Progress<F> {
+    fn reader<R: Read>(&mut self, inner: R) -> ProgressReader<'_, R, F> {
+        ProgressReader {
+            inner,
+            progress: self,
+        }
+    }
+}
+
+struct ProgressReader<'a, R: Read, F: FnMut(u64, u64) -> io::Result<()>> {
+    inner: R,
+    progress: &'a mut Progress<F>,
+}
+
+impl<R: Read, F: FnMut(u64, u64) -> io::Result<()>> Read for ProgressReader<'_, R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.progress.done += read as u64;
+        (self.progress.report)(self.progress.done, self.progress.total)?;
+        Ok(read)
+    }
+}
+
+/// Writes `sources` and everything below them into a new archive at
+/// `archive`, calling `report` with the bytes done and the total as it goes.
+/// An error from `report` stops the compression, and a partially written
+/// archive is removed if anything fails.
+pub fn compress(
+    archive: &Path,
+    format: Format,
+    sources: &[PathBuf],
+    report: impl FnMut(u64, u64) -> io::Result<()>,
+) -> io::Result<()> {
+    let entries = collect_entries(sources)?;
+    let mut progress = Progress {
+        done: 0,
+        total: entries
+            .iter()
+            .filter(|e| e.metadata.is_file())
+            .map(|e| e.metadata.len())
+            .sum(),
+        report,
+    };
+
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(archive)?;
+    let result = match format {
+        Format::TarGz => write_tar_gz(file, &entries, &mut progress),
+        Format::Zip => write_zip(file, &entries, &mut progress),
+    };
+    if result.is_err() {
+        let _ = fs::remove_file(archive);
+    }
+    result
+}
+
+/// Every entry below `sources`, named relative to the directory containing
+/// them. Symlinks are stored as links rather than followed.
+fn collect_entries(sources: &[PathBuf]) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for s in sources {
+        let name = s.file_name().unwrap().to_string_lossy().into_owned();
+        walk(s, name, &mut entries)?;
+    }
+    Ok(entries)
+}
+
+fn walk(path: &Path, name: String, entries: &mut Vec<Entry>) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(path)?;
+    let is_dir = metadata.is_dir();
+    entries.push(Entry {
+        path: path.to_path_buf(),
+        name: name.clone(),
+        metadata,
+    });
+
+    if is_dir {
+        let mut children = fs::read_dir(path)?.collect::<io::Result<Vec<_>>>()?;
+        children.sort_by_key(|c| c.file_name());
+        for c in children {
+            let child_name = format!("{}/{}", name, c.file_name().to_string_lossy());
+            walk(&c.path(), child_name, entries)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_tar_gz<F: FnMut(u64, u64) -> io::Result<()>>(
+    file: File,
+    entries: &[Entry],
+    progress: &mut Progress<F>,
+) -> io::Result<()> {
+    let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    builder.follow_symlinks(false);
+    for e in entries {
+        if e.metadata.is_file() {
+            let mut header = tar::Header::new_gnu();
+            header.set_metadata(&e.metadata);
+            builder.append_data(&mut header, &e.name, progress.reader(File::open(&e.path)?))?;
+        } else {
+            // Only the entry itself, the contents of directories come later
+            builder.append_path_with_name(&e.path, &e.name)?;
+        }
+    }
+    builder.into_inner()?.finish()?;
+    Ok(())
+}
+
+fn write_zip<F: FnMut(u64, u64) -> io::Result<()>>(
+    file: File,
+    entries: &[Entry],
+    progress: &mut Progress<F>,
+) -> io::Result<()> {
+    let mut zip = ZipWriter::new(file);
+    for e in entries {
+        let options = FileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .unix_permissions(permissions(&e.metadata));
+        let file_type = e.metadata.file_type();
+        if file_type.is_dir() {
+            zip.add_directory(e.name.clone(), options)?;
+        } else if file_type.is_symlink() {
+            let target = fs::read_link(&e.path)?;
+            zip.add_symlink(e.name.clone(), target.to_string_lossy(), options)?;
+        } else if file_type.is_file() {
+            zip.start_file(e.name.clone(), options)?;
+            io::copy(&mut progress.reader(File::open(&e.path)?), &mut zip)?;
+        }
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn permissions(metadata: &Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn permissions(metadata: &Metadata) -> u32 {
+    if metadata.is_dir() {
+        0o755
+    } else {
+        0o644
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{collect_entries, Format};
+
+    #[test]
+    fn entries_are_named_relative_to_the_selection() {
+        let root = std::env::temp_dir().join(format!("trooper_archive_{}", std::process::id()));
+        fs::create_dir_all(root.join("dir/nested")).unwrap();
+        fs::write(root.join("dir/nested/b"), [0; 20]).unwrap();
+        fs::write(root.join("dir/a"), [0; 10]).unwrap();
+        fs::write(root.join("top"), [0; 5]).unwrap();
+
+        let entries = collect_entries(&[root.join("dir"), root.join("top")]).unwrap();
+        let names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["dir", "dir/a", "dir/nested", "dir/nested/b", "top"]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn formats_are_picked_by_extension() {
+        assert_eq!(Format::from_name("backup.tar.gz"), Some(Format::TarGz));
+        assert_eq!(Format::from_name("backup.TGZ"), Some(Format::TarGz));
+        assert_eq!(Format::from_name("backup.zip"), Some(Format::Zip));
+        assert_eq!(Format::from_name("backup.rar"), None);
+    }
+}
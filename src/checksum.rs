@@ -0,0 +1,138 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+    thread,
+};
+
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+use crate::wake::Sender;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    Sha256,
+    Md5,
+}
+
+impl Algorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "SHA-256",
+            Algorithm::Md5 => "MD5",
+        }
+    }
+
+    /// Whether `name` looks like a checksum list as written by sha256sum or
+    /// md5sum, e.g. `SHA256SUMS` or `release.sha256sums`.
+    fn is_sums_file(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        let suffix = match self {
+            Algorithm::Sha256 => "sha256sums",
+            Algorithm::Md5 => "md5sums",
+        };
+        name == suffix || name.ends_with(&format!(".{}", suffix))
+    }
+}
+
+/// Hashes the contents of `path` as a lowercase hex string.
+pub fn hash_file(path: &Path, algorithm: Algorithm) -> io::Result<String> {
+    match algorithm {
+        Algorithm::Sha256 => hash_with::<Sha256>(path),
+        Algorithm::Md5 => hash_with::<Md5>(path),
+    }
+}
+
+fn hash_with<D: Digest>(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = D::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// Hashes `paths` on a worker thread, sending each result along with the
+/// index of its path. Stops early once the receiver is gone.
+pub fn spawn(
+    paths: Vec<PathBuf>,
+    algorithm: Algorithm,
+    results: Sender<(usize, Result<String, String>)>,
+) {
+    thread::spawn(move || {
+        for (i, p) in paths.iter().enumerate() {
+            let hash = hash_file(p, algorithm).map_err(|e| e.to_string());
+            if results.send((i, hash)).is_err() {
+                return;
+            }
+        }
+    });
+}
+
+/// Expected checksums by file name, collected from every checksum list of
+/// the given algorithm in `dir`.
+pub fn expected_sums(dir: &Path, algorithm: Algorithm) -> HashMap<String, String> {
+    let mut sums = HashMap::new();
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return sums,
+    };
+    for e in entries.flatten() {
+        if algorithm.is_sums_file(&e.file_name().to_string_lossy()) {
+            if let Ok(contents) = fs::read_to_string(e.path()) {
+                sums.extend(parse_sums(&contents));
+            }
+        }
+    }
+    sums
+}
+
+/// Parses lines of the form `<hash>  <name>`, where binary mode puts a `*`
+/// in front of the name instead of the second space.
+fn parse_sums(contents: &str) -> HashMap<String, String> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (hash, name) = line.split_once(' ')?;
+            if hash.is_empty() || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                return None;
+            }
+            let name = name.strip_prefix(|c| c == ' ' || c == '*')?;
+            let name = name.strip_prefix("./").unwrap_or(name);
+            Some((String::from(name), hash.to_lowercase()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_sums, Algorithm};
+
+    #[test]
+    fn sums_files_are_parsed_in_text_and_binary_mode() {
+        let sums = parse_sums("ABC123  notes.txt\ndef456 *./image.iso\nnot a checksum line\n");
+
+        assert_eq!(sums.get("notes.txt").map(String::as_str), Some("abc123"));
+        assert_eq!(sums.get("image.iso").map(String::as_str), Some("def456"));
+        assert_eq!(sums.len(), 2);
+    }
+
+    #[test]
+    fn sums_files_are_recognized_by_name() {
+        assert!(Algorithm::Sha256.is_sums_file("SHA256SUMS"));
+        assert!(Algorithm::Sha256.is_sums_file("release.sha256sums"));
+        assert!(!Algorithm::Sha256.is_sums_file("MD5SUMS"));
+        assert!(Algorithm::Md5.is_sums_file("MD5SUMS"));
+    }
+}
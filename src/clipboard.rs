@@ -0,0 +1,222 @@
+use std::{
+    env,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use crate::paths;
+
+/// Files copied or cut in a graphical file manager, read from the system
+/// clipboard through wl-paste or xclip. Nautilus offers them as
+/// `x-special/gnome-copied-files` and Dolphin as `text/uri-list`. The flag is
+/// set when the files were cut rather than copied.
+pub fn read_files() -> io::Result<(Vec<PathBuf>, bool)> {
+    if let Some(files) = read_target("x-special/gnome-copied-files")
+        .ok()
+        .and_then(|copied| parse_gnome_copied_files(&copied))
+    {
+        return Ok(files);
+    }
+    let list = read_target("text/uri-list")?;
+    let cut = read_target("application/x-kde-cutselection").is_ok_and(|c| c.starts_with(b"1"));
+    Ok((parse_uri_list(&list), cut))
+}
+
+/// Offers `paths` as a `text/uri-list`, which graphical file managers paste
+/// as files and browsers accept as uploads.
+pub fn write_files(paths: &[PathBuf]) -> io::Result<()> {
+    write(Some("text/uri-list"), &uri_list(paths))
+}
+
+pub fn write_text(text: &str) -> io::Result<()> {
+    write(None, text.as_bytes())
+}
+
+/// Puts `data` on the clipboard as `target`, or as plain text without one.
+fn write(target: Option<&str>, data: &[u8]) -> io::Result<()> {
+    supported()?;
+    let wayland = env::var_os("WAYLAND_DISPLAY").is_some();
+    let program = if wayland { "wl-copy" } else { "xclip" };
+    let mut command = Command::new(program);
+    match (wayland, target) {
+        (true, Some(target)) => command.args(["--type", target]),
+        (true, None) => &mut command,
+        (false, Some(target)) => {
+            command.args(["-selection", "clipboard", "-in", "-target", target])
+        }
+        (false, None) => command.args(["-selection", "clipboard", "-in"]),
+    };
+    /* Both tools stay in the background to serve the clipboard, holding on to
+     * any pipes they were given. Only stdin is piped so waiting for them
+     * returns once they have forked.
+     */
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(data)?;
+    }
+    let status = child.wait()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(
+            format!("{} failed with {}", program, status),
+        ))
+    }
+}
+
+fn supported() -> io::Result<()> {
+    if cfg!(all(unix, not(target_os = "macos"))) {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "The clipboard is only supported on Linux and the BSDs",
+        ))
+    }
+}
+
+fn read_target(target: &str) -> io::Result<Vec<u8>> {
+    supported()?;
+    let mut command = if env::var_os("WAYLAND_DISPLAY").is_some() {
+        let mut command = Command::new("wl-paste");
+        command.args(["--no-newline", "--type", target]);
+        command
+    } else {
+        let mut command = Command::new("xclip");
+        command.args(["-selection", "clipboard", "-out", "-target", target]);
+        command
+    };
+    let output = command.stdin(Stdio::null()).output()?;
+    if output.status.success() {
+        Ok(output.stdout)
+    } else {
+        Err(io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ))
+    }
+}
+
+/// The format used by GNOME, which is a uri-list preceded by a line reading
+/// `copy` or `cut`.
+fn parse_gnome_copied_files(copied: &[u8]) -> Option<(Vec<PathBuf>, bool)> {
+    let mut lines = copied.splitn(2, |b| *b == b'\n');
+    let cut = match lines.next()? {
+        b"copy" => false,
+        b"cut" => true,
+        _ => return None,
+    };
+    Some((parse_uri_list(lines.next().unwrap_or_default()), cut))
+}
+
+/// The local paths of the `file://` URIs in a `text/uri-list`. Lines starting
+/// with `#` are comments, and other schemes and files on other hosts are
+/// skipped.
+fn parse_uri_list(list: &[u8]) -> Vec<PathBuf> {
+    list.split(|b| *b == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .filter(|line| !line.starts_with(b"#"))
+        .filter_map(|line| line.strip_prefix(b"file://"))
+        .filter_map(|rest| {
+            let (host, path) = rest.split_at(rest.iter().position(|b| *b == b'/')?);
+            (host.is_empty() || host.eq_ignore_ascii_case(b"localhost")).then_some(path)
+        })
+        .map(|path| PathBuf::from(paths::os_from_bytes(&percent_decode(path))))
+        .collect()
+}
+
+fn uri_list(paths: &[PathBuf]) -> Vec<u8> {
+    let mut list = Vec::new();
+    for path in paths {
+        list.extend_from_slice(b"file://");
+        list.extend(percent_encode(path));
+        list.extend_from_slice(b"\r\n");
+    }
+    list
+}
+
+fn percent_encode(path: &Path) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    for b in paths::os_to_bytes(path.as_os_str()) {
+        if b.is_ascii_alphanumeric() || b"/-._~".contains(&b) {
+            encoded.push(b);
+        } else {
+            encoded.extend(format!("%{:02X}", b).into_bytes());
+        }
+    }
+    encoded
+}
+
+fn percent_decode(s: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(s.len());
+    let mut i = 0;
+    while i < s.len() {
+        let escaped = s
+            .get(i + 1..i + 3)
+            .filter(|_| s[i] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(b) => {
+                decoded.push(b);
+                i += 3;
+            }
+            None => {
+                decoded.push(s[i]);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{parse_gnome_copied_files, parse_uri_list, uri_list};
+
+    #[test]
+    fn only_local_files_are_pasted() {
+        let list = b"# from Dolphin\r\n\
+                     file:///home/user/My%20Notes.txt\r\n\
+                     https://example.com/index.html\r\n\
+                     file://laptop/home/user/elsewhere\r\n\
+                     file://localhost/home/user/100%\r\n";
+        assert_eq!(
+            parse_uri_list(list),
+            [
+                PathBuf::from("/home/user/My Notes.txt"),
+                PathBuf::from("/home/user/100%"),
+            ]
+        );
+    }
+
+    #[test]
+    fn written_uris_are_read_back() {
+        let paths = [
+            PathBuf::from("/home/user/My Notes.txt"),
+            PathBuf::from("/tmp/100%/ö#1"),
+        ];
+        let list = uri_list(&paths);
+        assert!(list.starts_with(b"file:///home/user/My%20Notes.txt\r\n"));
+        assert_eq!(parse_uri_list(&list), paths);
+    }
+
+    #[test]
+    fn gnome_says_whether_files_were_cut() {
+        assert_eq!(
+            parse_gnome_copied_files(b"cut\nfile:///tmp/a\nfile:///tmp/b"),
+            Some((vec![PathBuf::from("/tmp/a"), PathBuf::from("/tmp/b")], true))
+        );
+        assert_eq!(
+            parse_gnome_copied_files(b"copy\nfile:///tmp/a"),
+            Some((vec![PathBuf::from("/tmp/a")], false))
+        );
+        assert_eq!(parse_gnome_copied_files(b"file:///tmp/a"), None);
+    }
+}
@@ -0,0 +1,241 @@
+#[cfg(target_os = "linux")]
+use std::fs;
+use std::{
+    ffi::OsString,
+    io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::paths;
+
+/// A mounted filesystem or a removable drive which could be mounted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Device {
+    pub name: String,
+    /// The block device, e.g. `/dev/sdb1`
+    pub device: PathBuf,
+    pub mount_point: Option<PathBuf>,
+}
+
+/// Mounted block devices followed by unmounted partitions of removable
+/// drives.
+#[cfg(target_os = "linux")]
+pub fn list_devices() -> Vec<Device> {
+    let mut devices: Vec<Device> = fs::read_to_string("/proc/self/mounts")
+        .map(|mounts| parse_mounts(&mounts))
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(device, mount_point)| Device {
+            name: match mount_point.file_name() {
+                Some(name) => name.to_string_lossy().into_owned(),
+                None => mount_point.to_string_lossy().into_owned(),
+            },
+            device,
+            mount_point: Some(mount_point),
+        })
+        .collect();
+
+    for device in removable_partitions() {
+        if devices.iter().all(|d| d.device != device) {
+            devices.push(Device {
+                name: label(&device).unwrap_or_else(|| {
+                    device
+                        .file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .into_owned()
+                }),
+                device,
+                mount_point: None,
+            });
+        }
+    }
+    devices
+}
+
+/// The drive letters in use.
+#[cfg(windows)]
+pub fn list_devices() -> Vec<Device> {
+    (b'A'..=b'Z')
+        .map(|letter| PathBuf::from(format!("{}:\\", letter as char)))
+        .filter(|root| root.exists())
+        .map(|root| Device {
+            name: root.to_string_lossy().trim_end_matches('\\').to_string(),
+            device: root.clone(),
+            mount_point: Some(root),
+        })
+        .collect()
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+pub fn list_devices() -> Vec<Device> {
+    Vec::new()
+}
+
+/// Mounts `device` with udisksctl, returning where it ended up.
+pub fn mount(device: &Path) -> io::Result<PathBuf> {
+    let output = udisksctl("mount", device)?;
+    // The output reads "Mounted /dev/sdb1 at /media/user/LABEL"
+    output
+        .trim()
+        .trim_end_matches('.')
+        .split_once(" at ")
+        .map(|(_, mount_point)| PathBuf::from(mount_point))
+        .ok_or(io::Error::other(
+            format!("Unexpected output from udisksctl: {}", output.trim()),
+        ))
+}
+
+pub fn unmount(device: &Path) -> io::Result<()> {
+    udisksctl("unmount", device).map(|_| ())
+}
+
+fn udisksctl(action: &str, device: &Path) -> io::Result<String> {
+    if !cfg!(target_os = "linux") {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Mounting is only supported on Linux",
+        ));
+    }
+    let output = Command::new("udisksctl")
+        .arg(action)
+        .arg("--no-user-interaction")
+        .arg("-b")
+        .arg(device)
+        .output()?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ))
+    }
+}
+
+/// Block devices and where they are mounted, from the contents of
+/// `/proc/self/mounts`. Loop devices are left out as they are mostly snap
+/// packages and the like.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_mounts(mounts: &str) -> Vec<(PathBuf, PathBuf)> {
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let device = fields.next()?;
+            let mount_point = fields.next()?;
+            if !device.starts_with("/dev/") || device.starts_with("/dev/loop") {
+                return None;
+            }
+            Some((PathBuf::from(device), PathBuf::from(unescape(mount_point))))
+        })
+        .collect()
+}
+
+/// Undoes the escapes used for whitespace in mount points, which are octal
+/// such as `\040`, and in udev links, which are hex such as `\x20`. Escaped
+/// bytes can be parts of one UTF-8 character, so they are decoded together.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn unescape(field: &str) -> OsString {
+    let mut result = Vec::new();
+    let mut rest = field;
+    while let Some(i) = rest.find('\\') {
+        result.extend_from_slice(&rest.as_bytes()[..i]);
+        let code = match rest.get(i + 1..i + 4) {
+            Some(c) if c.starts_with('x') => u8::from_str_radix(&c[1..], 16).ok(),
+            Some(c) => u8::from_str_radix(c, 8).ok(),
+            None => None,
+        };
+        match code {
+            Some(c) => {
+                result.push(c);
+                rest = &rest[i + 4..];
+            }
+            None => {
+                result.push(b'\\');
+                rest = &rest[i + 1..];
+            }
+        }
+    }
+    result.extend_from_slice(rest.as_bytes());
+    paths::os_from_bytes(&result)
+}
+
+/// Partitions of removable drives, or the drives themselves when they have
+/// no partitions.
+#[cfg(target_os = "linux")]
+fn removable_partitions() -> Vec<PathBuf> {
+    let mut partitions = Vec::new();
+    let drives = match fs::read_dir("/sys/block") {
+        Ok(drives) => drives,
+        Err(_) => return partitions,
+    };
+
+    for drive in drives.flatten() {
+        let sys = drive.path();
+        let removable = fs::read_to_string(sys.join("removable")).unwrap_or_default();
+        let size = fs::read_to_string(sys.join("size")).unwrap_or_default();
+        // Empty card readers and optical drives report a size of zero
+        if removable.trim() != "1" || size.trim() == "0" {
+            continue;
+        }
+
+        let name = drive.file_name();
+        let mut own: Vec<PathBuf> = fs::read_dir(&sys)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter(|p| p.path().join("partition").exists())
+            .map(|p| Path::new("/dev").join(p.file_name()))
+            .collect();
+        own.sort();
+        if own.is_empty() {
+            own.push(Path::new("/dev").join(name));
+        }
+        partitions.extend(own);
+    }
+    partitions
+}
+
+/// The filesystem label of `device`, found through the links udev keeps in
+/// `/dev/disk/by-label`.
+#[cfg(target_os = "linux")]
+fn label(device: &Path) -> Option<String> {
+    fs::read_dir("/dev/disk/by-label")
+        .ok()?
+        .flatten()
+        .find(|l| l.path().canonicalize().ok().as_deref() == Some(device))
+        .map(|l| {
+            unescape(&l.file_name().to_string_lossy())
+                .to_string_lossy()
+                .into_owned()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::parse_mounts;
+
+    #[test]
+    fn only_block_devices_are_listed() {
+        let mounts = "proc /proc proc rw 0 0\n\
+                      /dev/nvme0n1p2 / ext4 rw 0 0\n\
+                      /dev/loop3 /snap/core/1 squashfs ro 0 0\n\
+                      /dev/sdb1 /media/user/My\\040Drive vfat rw 0 0\n\
+                      /dev/sdc1 /media/user/Caf\\303\\251 exfat rw 0 0\n";
+
+        assert_eq!(
+            parse_mounts(mounts),
+            [
+                (PathBuf::from("/dev/nvme0n1p2"), PathBuf::from("/")),
+                (
+                    PathBuf::from("/dev/sdb1"),
+                    PathBuf::from("/media/user/My Drive")
+                ),
+                (PathBuf::from("/dev/sdc1"), PathBuf::from("/media/user/Café")),
+            ]
+        );
+    }
+}
@@ -0,0 +1,96 @@
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiskSpace {
+    pub available: u64,
+    pub total: u64,
+}
+
+/// Available and total space of the filesystem containing `path`.
+#[cfg(unix)]
+pub fn disk_space(path: &Path) -> Option<DiskSpace> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let c_path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+
+    // The field types differ between platforms, hence the casts
+    let block_size = stat.f_frsize as u64;
+    Some(DiskSpace {
+        available: stat.f_bavail as u64 * block_size,
+        total: stat.f_blocks as u64 * block_size,
+    })
+}
+
+#[cfg(not(unix))]
+pub fn disk_space(_path: &Path) -> Option<DiskSpace> {
+    None
+}
+
+/// Where the file system holding `path` is mounted, i.e. the highest
+/// directory above it which is still on the same device.
+#[cfg(unix)]
+pub fn mount_point(path: &Path) -> PathBuf {
+    use std::os::unix::fs::MetadataExt;
+
+    let device = |p: &Path| std::fs::metadata(p).map(|m| m.dev()).ok();
+    let own = device(path);
+    let mut mount = path;
+    while let Some(parent) = mount.parent() {
+        if device(parent) != own {
+            break;
+        }
+        mount = parent;
+    }
+    mount.to_path_buf()
+}
+
+/// The drive holding `path`.
+#[cfg(not(unix))]
+pub fn mount_point(path: &Path) -> PathBuf {
+    path.ancestors().last().unwrap_or(path).to_path_buf()
+}
+
+/// Formats a byte count with a binary unit suffix, e.g. `1.5G`.
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["B", "K", "M", "G", "T", "P"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{}{}", bytes, UNITS[unit])
+    } else {
+        format!("{:.1}{}", size, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{human_size, mount_point};
+
+    #[test]
+    fn sizes_are_scaled_to_the_largest_unit() {
+        assert_eq!(human_size(0), "0B");
+        assert_eq!(human_size(1023), "1023B");
+        assert_eq!(human_size(1536), "1.5K");
+        assert_eq!(human_size(5 * 1024 * 1024 * 1024), "5.0G");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn mount_points_are_above_the_path() {
+        let dir = std::env::temp_dir().canonicalize().unwrap();
+        assert!(dir.starts_with(mount_point(&dir)));
+        assert_eq!(mount_point(Path::new("/")), Path::new("/"));
+    }
+}
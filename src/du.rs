@@ -0,0 +1,146 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::mpsc::Receiver,
+    thread,
+};
+
+use crate::wake::{self, Sender, Waker};
+
+/// Recursive directory sizes, calculated on a worker thread and cached per
+/// path until something below them changes.
+pub struct DirSizes {
+    sizes: HashMap<PathBuf, u64>,
+    pending: HashSet<PathBuf>,
+    sender: Sender<(PathBuf, u64)>,
+    receiver: Receiver<(PathBuf, u64)>,
+}
+
+impl DirSizes {
+    pub fn new(waker: &Waker) -> DirSizes {
+        let (sender, receiver) = wake::channel(waker);
+        DirSizes {
+            sizes: HashMap::new(),
+            pending: HashSet::new(),
+            sender,
+            receiver,
+        }
+    }
+
+    pub fn get(&self, path: &Path) -> Option<u64> {
+        self.sizes.get(path).copied()
+    }
+
+    pub fn is_pending(&self, path: &Path) -> bool {
+        self.pending.contains(path)
+    }
+
+    /// Starts calculating the sizes of all `dirs` which are neither cached
+    /// nor already being calculated.
+    pub fn request(&mut self, dirs: Vec<PathBuf>) {
+        let dirs: Vec<PathBuf> = dirs
+            .into_iter()
+            .filter(|d| !self.sizes.contains_key(d) && !self.pending.contains(d))
+            .collect();
+        if dirs.is_empty() {
+            return;
+        }
+        self.pending.extend(dirs.iter().cloned());
+
+        let sender = self.sender.clone();
+        thread::spawn(move || {
+            for dir in dirs {
+                let size = dir_size(&dir);
+                if sender.send((dir, size)).is_err() {
+                    return;
+                }
+            }
+        });
+    }
+
+    /// Moves finished calculations into the cache. Returns true if any arrived.
+    pub fn poll(&mut self) -> bool {
+        let mut received = false;
+        while let Ok((dir, size)) = self.receiver.try_recv() {
+            self.pending.remove(&dir);
+            self.sizes.insert(dir, size);
+            received = true;
+        }
+        received
+    }
+
+    /// Blocks until every requested size has been calculated.
+    pub fn wait(&mut self) {
+        while !self.pending.is_empty() {
+            match self.receiver.recv() {
+                Ok((dir, size)) => {
+                    self.pending.remove(&dir);
+                    self.sizes.insert(dir, size);
+                }
+                Err(_) => break,
+            }
+        }
+    }
+
+    /// Forgets the sizes of `path` and every directory containing it.
+    pub fn invalidate(&mut self, path: &Path) {
+        self.sizes.retain(|dir, _| !path.starts_with(dir));
+    }
+}
+
+/// Total size of the files below `path`. Symlinks are not followed and
+/// unreadable entries are skipped.
+fn dir_size(path: &Path) -> u64 {
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+
+    let mut size = 0;
+    for entry in entries.flatten() {
+        match entry.file_type() {
+            Ok(t) if t.is_dir() => size += dir_size(&entry.path()),
+            Ok(_) => size += entry.metadata().map(|md| md.len()).unwrap_or(0),
+            Err(_) => {}
+        }
+    }
+    size
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    use super::{dir_size, DirSizes};
+    use crate::wake::Waker;
+
+    #[test]
+    fn sizes_include_nested_directories() {
+        let root = std::env::temp_dir().join(format!("trooper_du_{}", std::process::id()));
+        fs::create_dir_all(root.join("nested")).unwrap();
+        fs::write(root.join("a"), [0; 100]).unwrap();
+        fs::write(root.join("nested/b"), [0; 50]).unwrap();
+
+        assert_eq!(dir_size(&root), 150);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn invalidation_reaches_parent_directories() {
+        let mut sizes = DirSizes::new(&Waker::default());
+        for dir in ["/a", "/a/b", "/a/c"] {
+            sizes.sizes.insert(PathBuf::from(dir), 1);
+        }
+
+        sizes.invalidate(Path::new("/a/b/file"));
+
+        assert_eq!(sizes.get(Path::new("/a")), None);
+        assert_eq!(sizes.get(Path::new("/a/b")), None);
+        assert_eq!(sizes.get(Path::new("/a/c")), Some(1));
+    }
+}
@@ -0,0 +1,83 @@
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+/// A failed operation. These are shown in the status line rather than taking
+/// down the whole application.
+#[derive(Debug)]
+pub enum AppError {
+    /// `action` could not be done to `path`
+    Io {
+        action: &'static str,
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// Pasting or linking before anything has been yanked
+    NothingYanked,
+    /// Pasting from a named register which has not been yanked into
+    EmptyRegister(char),
+    /// The system clipboard could not be read
+    Clipboard(io::Error),
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::Io {
+                action,
+                path,
+                source,
+            } => write!(f, "Could not {} {}: {}", action, path.display(), source),
+            AppError::NothingYanked => write!(f, "Nothing has been yanked"),
+            AppError::EmptyRegister(r) => write!(f, "Register {} is empty", r),
+            AppError::Clipboard(e) => write!(f, "Could not read the clipboard: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for AppError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AppError::Io { source, .. } | AppError::Clipboard(source) => Some(source),
+            AppError::NothingYanked | AppError::EmptyRegister(_) => None,
+        }
+    }
+}
+
+/// Attaches what was being done, and to which path, to an I/O error.
+pub trait Context<T> {
+    fn context(self, action: &'static str, path: &Path) -> Result<T, AppError>;
+}
+
+impl<T> Context<T> for io::Result<T> {
+    fn context(self, action: &'static str, path: &Path) -> Result<T, AppError> {
+        self.map_err(|source| AppError::Io {
+            action,
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, path::Path};
+
+    use super::Context;
+
+    #[test]
+    fn errors_name_the_action_and_path() {
+        let result: io::Result<()> = Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "Permission denied",
+        ));
+        let e = result
+            .context("rename", Path::new("/etc/passwd"))
+            .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Could not rename /etc/passwd: Permission denied"
+        );
+    }
+}
@@ -0,0 +1,154 @@
+use ratatui::style::{Color, Modifier, Style};
+
+use crate::glob;
+
+/// Colors for file names matching glob patterns, read from the `[filetypes]`
+/// section of the config.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FileTypes {
+    rules: Vec<(String, Style)>,
+}
+
+impl FileTypes {
+    pub fn insert(&mut self, pattern: &str, style: Style) {
+        self.rules.retain(|(p, _)| p != pattern);
+        self.rules.push((String::from(pattern), style));
+        /* The config sections are hash maps, so the order rules are inserted in
+         * means nothing. Exact names are tried before patterns and longer
+         * patterns, which tend to be more specific, before shorter ones.
+         */
+        self.rules.sort_by_key(|(p, _)| {
+            (
+                p.contains(['*', '?']),
+                std::cmp::Reverse(p.len()),
+            )
+        });
+    }
+
+    pub fn style_for(&self, name: &str) -> Option<Style> {
+        self.rules
+            .iter()
+            .find(|(pattern, _)| glob::matches(pattern, name))
+            .map(|(_, style)| *style)
+    }
+}
+
+/// Parses a style such as `green bold` or `white on red`. Colors are given by
+/// name, as a 256 color index or as `rgb:rrggbb`. The more common `#rrggbb`
+/// would be cut off as a comment by the ini parser.
+pub fn parse_style(spec: &str) -> Option<Style> {
+    let mut style = Style::default();
+    let mut background = false;
+    for word in spec.split_whitespace() {
+        let word = word.to_lowercase();
+        if word == "on" {
+            background = true;
+            continue;
+        }
+
+        let modifier = match word.as_str() {
+            "bold" => Some(Modifier::BOLD),
+            "dim" => Some(Modifier::DIM),
+            "italic" => Some(Modifier::ITALIC),
+            "underline" | "underlined" => Some(Modifier::UNDERLINED),
+            "reversed" => Some(Modifier::REVERSED),
+            _ => None,
+        };
+        if let Some(modifier) = modifier {
+            style = style.add_modifier(modifier);
+            continue;
+        }
+
+        let color = parse_color(&word)?;
+        if background {
+            style = style.bg(color);
+            background = false;
+        } else {
+            style = style.fg(color);
+        }
+    }
+    Some(style)
+}
+
+fn parse_color(word: &str) -> Option<Color> {
+    if let Some(hex) = word.strip_prefix("rgb:") {
+        if hex.len() != 6 || !hex.is_ascii() {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        return Some(Color::Rgb(channel(0)?, channel(2)?, channel(4)?));
+    }
+    if let Ok(index) = word.parse::<u8>() {
+        return Some(Color::Indexed(index));
+    }
+
+    let color = match word {
+        "black" => Color::Black,
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "magenta" => Color::Magenta,
+        "cyan" => Color::Cyan,
+        "gray" | "grey" => Color::Gray,
+        "darkgray" | "darkgrey" => Color::DarkGray,
+        "lightred" => Color::LightRed,
+        "lightgreen" => Color::LightGreen,
+        "lightyellow" => Color::LightYellow,
+        "lightblue" => Color::LightBlue,
+        "lightmagenta" => Color::LightMagenta,
+        "lightcyan" => Color::LightCyan,
+        "white" => Color::White,
+        _ => return None,
+    };
+    Some(color)
+}
+
+#[cfg(test)]
+mod tests {
+    use ratatui::style::{Color, Modifier, Style};
+
+    use super::{parse_style, FileTypes};
+
+    #[test]
+    fn styles_are_parsed_from_words() {
+        assert_eq!(
+            parse_style("green bold"),
+            Some(
+                Style::default()
+                    .fg(Color::Green)
+                    .add_modifier(Modifier::BOLD)
+            )
+        );
+        assert_eq!(
+            parse_style("rgb:ff8000 on 236"),
+            Some(
+                Style::default()
+                    .fg(Color::Rgb(255, 128, 0))
+                    .bg(Color::Indexed(236))
+            )
+        );
+        assert_eq!(parse_style("greenish"), None);
+    }
+
+    #[test]
+    fn exact_names_take_precedence_over_patterns() {
+        let mut filetypes = FileTypes::default();
+        filetypes.insert("*", Style::default().fg(Color::Red));
+        filetypes.insert("Makefile", Style::default().fg(Color::Green));
+        filetypes.insert("*.rs", Style::default().fg(Color::Yellow));
+
+        assert_eq!(
+            filetypes.style_for("Makefile"),
+            Some(Style::default().fg(Color::Green))
+        );
+        assert_eq!(
+            filetypes.style_for("main.rs"),
+            Some(Style::default().fg(Color::Yellow))
+        );
+        assert_eq!(
+            filetypes.style_for("README"),
+            Some(Style::default().fg(Color::Red))
+        );
+    }
+}
@@ -0,0 +1,73 @@
+/// How well `pattern` matches `text`, or None if the characters of `pattern`
+/// do not all appear in `text` in order. Case is ignored. Characters following
+/// each other in `text` and characters starting a word, including the humps
+/// of CamelCase, score higher.
+pub fn score(pattern: &str, text: &str) -> Option<i32> {
+    score_case(pattern, text, true)
+}
+
+/// Like `score`, but only ignores case when `ignore_case` is set.
+pub fn score_case(pattern: &str, text: &str, ignore_case: bool) -> Option<i32> {
+    let text: Vec<char> = text.chars().collect();
+    let mut score = 0;
+    let mut next = 0;
+    let mut previous: Option<usize> = None;
+
+    for p in pattern.chars() {
+        let i = next
+            + text[next..]
+                .iter()
+                .position(|c| same_letter(*c, p, ignore_case))?;
+        score += 1;
+        if previous.is_some_and(|prev| prev + 1 == i) {
+            score += 5;
+        } else if let Some(prev) = previous {
+            score -= std::cmp::min((i - prev) as i32, 5);
+        }
+        if starts_word(&text, i) {
+            score += 3;
+        }
+        previous = Some(i);
+        next = i + 1;
+    }
+
+    Some(score)
+}
+
+fn same_letter(a: char, b: char, ignore_case: bool) -> bool {
+    if ignore_case {
+        a.to_lowercase().eq(b.to_lowercase())
+    } else {
+        a == b
+    }
+}
+
+fn starts_word(text: &[char], i: usize) -> bool {
+    if i == 0 {
+        return true;
+    }
+    let prev = text[i - 1];
+    !prev.is_alphanumeric() || (prev.is_lowercase() && text[i].is_uppercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{score, score_case};
+
+    #[test]
+    fn characters_have_to_appear_in_order() {
+        assert!(score("mkd", "mkdir").is_some());
+        assert!(score("MKD", "mkdir").is_some());
+        assert!(score("dkm", "mkdir").is_none());
+        assert_eq!(score("", "mkdir"), Some(0));
+        assert!(score_case("MKD", "mkdir", false).is_none());
+        assert!(score_case("Mkd", "Mkdir", false).is_some());
+    }
+
+    #[test]
+    fn word_starts_and_runs_score_higher() {
+        assert!(score("td", "ToggleDevices") > score("td", "ToggleHiddenFiles"));
+        assert!(score("copy", "CopyFiles") > score("copy", "CalculateSizesOrPaY"));
+        assert!(score("sftp", "ConnectSftp") > score("sftp", "ShowFileTypesPopup"));
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    fmt,
+    io::Write,
+    path::Path,
+    process::{Command, Stdio},
+    thread,
+};
+
+/// What becomes of entries ignored by git, for the `gitignore` setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GitIgnore {
+    /// Git is not asked
+    #[default]
+    Off,
+    /// Ignored entries are shown dimmed
+    Dim,
+    /// Ignored entries are hidden along with dotfiles
+    Hide,
+}
+
+impl GitIgnore {
+    pub fn parse(value: &str) -> Result<GitIgnore, String> {
+        match value {
+            "off" => Ok(GitIgnore::Off),
+            "dim" => Ok(GitIgnore::Dim),
+            "hide" => Ok(GitIgnore::Hide),
+            _ => Err(format!("Expected off, dim or hide, not {}", value)),
+        }
+    }
+}
+
+impl fmt::Display for GitIgnore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GitIgnore::Off => write!(f, "off"),
+            GitIgnore::Dim => write!(f, "dim"),
+            GitIgnore::Hide => write!(f, "hide"),
+        }
+    }
+}
+
+/* Reimplementing .gitignore, with its nested files, global excludes and
+ * negated patterns, is a lot of work for something git answers in one call.
+ */
+/// Which of the `names` in `dir` git ignores. Outside a repository, or
+/// without git installed, that is none of them.
+pub fn ignored(dir: &Path, names: &[OsString]) -> HashSet<OsString> {
+    let child = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["check-ignore", "-z", "--stdin"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(_) => return HashSet::new(),
+    };
+
+    let mut input = Vec::new();
+    for name in names {
+        input.extend_from_slice(name.to_string_lossy().as_bytes());
+        input.push(0);
+    }
+    // Git answers while it reads, so writing everything before reading could
+    // leave both waiting on a full pipe
+    let writer = child.stdin.take().map(|mut stdin| {
+        thread::spawn(move || {
+            let _ = stdin.write_all(&input);
+        })
+    });
+    let output = child.wait_with_output();
+    if let Some(writer) = writer {
+        let _ = writer.join();
+    }
+
+    match output {
+        Ok(output) => output
+            .stdout
+            .split(|b| *b == 0)
+            .filter(|name| !name.is_empty())
+            .map(|name| OsString::from(String::from_utf8_lossy(name).into_owned()))
+            .collect(),
+        Err(_) => HashSet::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsString, fs, process::Command};
+
+    use super::{ignored, GitIgnore};
+
+    #[test]
+    fn modes_are_parsed_by_name() {
+        for mode in [GitIgnore::Off, GitIgnore::Dim, GitIgnore::Hide] {
+            assert_eq!(GitIgnore::parse(&mode.to_string()), Ok(mode));
+        }
+        assert!(GitIgnore::parse("grey").is_err());
+    }
+
+    #[test]
+    fn git_is_asked_which_entries_it_ignores() {
+        let dir = std::env::temp_dir().join(format!("trooper_gitignore_{}", std::process::id()));
+        fs::create_dir_all(dir.join("target")).unwrap();
+        let names: Vec<OsString> = ["target", "main.rs", ".gitignore"]
+            .iter()
+            .map(OsString::from)
+            .collect();
+        // Outside a repository nothing is ignored
+        assert!(ignored(&dir, &names).is_empty());
+
+        let init = Command::new("git").arg("init").arg("-q").arg(&dir).status();
+        if !init.map_or(false, |s| s.success()) {
+            // Without git there is nothing to test
+            return fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::write(dir.join(".gitignore"), "target/\n*.rs\n!main.rs\n").unwrap();
+        assert_eq!(
+            ignored(&dir, &names).into_iter().collect::<Vec<_>>(),
+            ["target"]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
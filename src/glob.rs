@@ -0,0 +1,110 @@
+use std::path::{Component, Path};
+
+/// Matches `name` against a shell style pattern where `*` matches any run of
+/// characters and `?` a single character.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` and the part of the name it has consumed so far
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, consumed)) => {
+                    p = star + 1;
+                    n = consumed + 1;
+                    backtrack = Some((star, consumed + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Matches `path` against a pattern such as `/etc/**`, comparing them one
+/// component at a time. A `**` component matches any number of components,
+/// including none, and the others are matched like names.
+pub fn matches_path(pattern: &Path, path: &Path) -> bool {
+    let pattern: Vec<Component> = pattern.components().collect();
+    let path: Vec<Component> = path.components().collect();
+    matches_components(&pattern, &path)
+}
+
+fn matches_components(pattern: &[Component], path: &[Component]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((Component::Normal(p), rest)) if *p == "**" => {
+            (0..=path.len()).any(|skipped| matches_components(rest, &path[skipped..]))
+        }
+        Some((Component::Normal(p), rest)) => match path.split_first() {
+            Some((Component::Normal(name), path_rest)) => {
+                matches(&p.to_string_lossy(), &name.to_string_lossy())
+                    && matches_components(rest, path_rest)
+            }
+            _ => false,
+        },
+        Some((p, rest)) => match path.split_first() {
+            Some((c, path_rest)) => c == p && matches_components(rest, path_rest),
+            None => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{matches, matches_path};
+
+    #[test]
+    fn wildcards_match_runs_and_single_characters() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(matches("*.rs", ".rs"));
+        assert!(!matches("*.rs", "main.rsx"));
+        assert!(matches("Makefile", "Makefile"));
+        assert!(!matches("Makefile", "makefile"));
+        assert!(matches("file?.txt", "file1.txt"));
+        assert!(!matches("file?.txt", "file.txt"));
+        assert!(matches("*a*b*", "xxaxxbxx"));
+        assert!(!matches("*a*b", "xxaxxbxxc"));
+    }
+
+    #[test]
+    fn double_stars_match_any_depth() {
+        let pattern = Path::new("/home/user/Documents/**");
+        assert!(matches_path(pattern, Path::new("/home/user/Documents")));
+        assert!(matches_path(
+            pattern,
+            Path::new("/home/user/Documents/a/b.txt")
+        ));
+        assert!(!matches_path(pattern, Path::new("/home/user/Downloads/a")));
+        assert!(matches_path(
+            Path::new("/**/.git"),
+            Path::new("/src/trooper/.git")
+        ));
+        assert!(!matches_path(
+            Path::new("/**/.git"),
+            Path::new("/src/trooper/.github")
+        ));
+        assert!(matches_path(
+            Path::new("/etc/*.conf"),
+            Path::new("/etc/fstab.conf")
+        ));
+        assert!(!matches_path(
+            Path::new("/etc/*.conf"),
+            Path::new("/etc/x/y.conf")
+        ));
+    }
+}
@@ -0,0 +1,70 @@
+use std::path::Path;
+
+const DIR_ICON: &str = "\u{f07b}";
+const FILE_ICON: &str = "\u{f15b}";
+
+/// Picks a Nerd Font glyph for an entry based on its name and extension.
+pub fn icon_for(path: &Path, is_dir: bool) -> &'static str {
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+
+    if is_dir {
+        return match name.as_str() {
+            ".git" => "\u{e5fb}",
+            ".config" => "\u{e5fc}",
+            "node_modules" => "\u{e5fa}",
+            _ => DIR_ICON,
+        };
+    }
+
+    match name.as_str() {
+        "makefile" | "cmakelists.txt" => return "\u{e779}",
+        "dockerfile" => return "\u{f308}",
+        "license" => return "\u{f718}",
+        ".gitignore" | ".gitmodules" | ".gitattributes" => return "\u{f1d3}",
+        "cargo.lock" => return "\u{f023}",
+        _ => {}
+    }
+
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+
+    match extension.as_str() {
+        "rs" => "\u{e7a8}",
+        "py" => "\u{e606}",
+        "js" | "mjs" | "cjs" => "\u{e74e}",
+        "ts" | "tsx" => "\u{e628}",
+        "html" | "htm" => "\u{e736}",
+        "css" | "scss" | "sass" => "\u{e749}",
+        "json" => "\u{e60b}",
+        "md" | "markdown" => "\u{e609}",
+        "toml" | "ini" | "conf" | "cfg" | "yaml" | "yml" => "\u{e615}",
+        "sh" | "bash" | "zsh" | "fish" => "\u{f489}",
+        "c" => "\u{e61e}",
+        "cpp" | "cc" | "cxx" | "hpp" => "\u{e61d}",
+        "h" => "\u{f0fd}",
+        "go" => "\u{e626}",
+        "java" | "jar" => "\u{e738}",
+        "lua" => "\u{e620}",
+        "vim" => "\u{e62b}",
+        "png" | "jpg" | "jpeg" | "gif" | "bmp" | "svg" | "webp" | "ico" => "\u{f1c5}",
+        "mp3" | "flac" | "wav" | "ogg" | "m4a" => "\u{f1c7}",
+        "mp4" | "mkv" | "webm" | "avi" | "mov" => "\u{f1c8}",
+        "zip" | "tar" | "gz" | "xz" | "bz2" | "7z" | "rar" | "zst" => "\u{f1c6}",
+        "pdf" => "\u{f1c1}",
+        "txt" | "log" => "\u{f15c}",
+        "lock" => "\u{f023}",
+        _ => FILE_ICON,
+    }
+}
+
+/// Glyph used for entries in the bookmark panel.
+pub fn bookmark_icon() -> &'static str {
+    DIR_ICON
+}
@@ -0,0 +1,317 @@
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::Receiver,
+    thread,
+};
+
+use crate::wake::{self, Sender, Waker};
+
+/// Where the trooper with process id `pid` listens for commands. On Unix this
+/// is a socket in `$XDG_RUNTIME_DIR`, or without one in a directory of our
+/// own in the temporary directory.
+#[cfg(unix)]
+pub fn socket_path(pid: u32) -> PathBuf {
+    socket_dir().join(format!("trooper-{}.sock", pid))
+}
+
+#[cfg(windows)]
+pub fn socket_path(pid: u32) -> PathBuf {
+    socket_dir().join(format!("trooper-{}", pid))
+}
+
+#[cfg(unix)]
+fn socket_dir() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            let uid = unsafe { libc::geteuid() };
+            std::env::temp_dir().join(format!("trooper-{}", uid))
+        })
+}
+
+#[cfg(windows)]
+fn socket_dir() -> PathBuf {
+    PathBuf::from(r"\\.\pipe\")
+}
+
+/// The socket of a running trooper. That is the one in `TROOPER_SOCKET` when
+/// called from a command run by trooper, and otherwise the one started last.
+pub fn find_instance() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("TROOPER_SOCKET") {
+        return Some(PathBuf::from(path));
+    }
+    let mut sockets: Vec<(Option<std::time::SystemTime>, PathBuf)> = fs::read_dir(socket_dir())
+        .ok()?
+        .flatten()
+        .filter(|e| e.file_name().to_string_lossy().starts_with("trooper-"))
+        // Anyone can put a socket in a shared directory
+        .filter(|e| e.metadata().is_ok_and(|m| is_ours(&m)))
+        .map(|e| (e.metadata().and_then(|m| m.modified()).ok(), e.path()))
+        .collect();
+    sockets.sort();
+    // Sockets left behind by a trooper which crashed can't be connected to
+    sockets
+        .into_iter()
+        .rev()
+        .map(|(_, path)| path)
+        .find(|path| connect(path).is_ok())
+}
+
+#[cfg(unix)]
+fn is_ours(md: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    md.uid() == unsafe { libc::geteuid() }
+}
+
+/// Pipes of other users can't be told apart, but neither can they be opened.
+#[cfg(windows)]
+fn is_ours(_md: &fs::Metadata) -> bool {
+    true
+}
+
+/// Sends each of `commands` to the trooper listening on `path`.
+pub fn send(path: &Path, commands: &[String]) -> io::Result<()> {
+    let mut stream = connect(path)?;
+    for command in commands {
+        writeln!(stream, "{}", command)?;
+    }
+    stream.flush()
+}
+
+#[cfg(unix)]
+fn connect(path: &Path) -> io::Result<std::os::unix::net::UnixStream> {
+    std::os::unix::net::UnixStream::connect(path)
+}
+
+/* Opening a pipe takes up the instance waiting for a client, but the server
+ * creates a new one right away.
+ */
+#[cfg(windows)]
+fn connect(path: &Path) -> io::Result<fs::File> {
+    fs::OpenOptions::new().write(true).open(path)
+}
+
+/* Every connection gets a thread of its own which sends each line it reads
+ * to the App. Commands are only run when the App polls for them, so they
+ * never interrupt whatever it is doing.
+ */
+/// Listens for command lines on a socket, or a named pipe on Windows, so
+/// that other programs can control trooper.
+pub struct Server {
+    path: PathBuf,
+    receiver: Receiver<String>,
+}
+
+impl Server {
+    pub fn start(path: &Path, waker: &Waker) -> io::Result<Server> {
+        let (sender, receiver) = wake::channel(waker);
+        listen(path, sender)?;
+        Ok(Server {
+            path: path.to_path_buf(),
+            receiver,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The next command which has arrived, if any.
+    pub fn try_recv(&self) -> Option<String> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/* Every line is run as a command line, shell commands included, so nobody
+ * but us may connect. The socket is made private before the first
+ * connection is accepted, and the directory it goes in is private as well
+ * when we create it.
+ */
+#[cfg(unix)]
+fn listen(path: &Path, sender: Sender<String>) -> io::Result<()> {
+    use std::os::unix::{
+        fs::{DirBuilderExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    };
+
+    if let Some(dir) = path.parent() {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)?;
+    }
+    // A socket left behind by a trooper which crashed can't be bound to
+    if path.exists() && UnixStream::connect(path).is_err() {
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let sender = sender.clone();
+            thread::spawn(move || forward_lines(stream, sender));
+        }
+    });
+    Ok(())
+}
+
+/* std can open named pipes as files but not create them, which takes
+ * CreateNamedPipeW and ConnectNamedPipe from kernel32. A new instance of the
+ * pipe is created for every client.
+ */
+#[cfg(windows)]
+fn listen(path: &Path, sender: Sender<String>) -> io::Result<()> {
+    use std::{
+        ffi::c_void,
+        fs::File,
+        os::windows::{ffi::OsStrExt, io::FromRawHandle},
+        ptr,
+    };
+
+    const PIPE_ACCESS_INBOUND: u32 = 0x1;
+    const PIPE_UNLIMITED_INSTANCES: u32 = 255;
+    const INVALID_HANDLE_VALUE: *mut c_void = -1isize as *mut c_void;
+    const ERROR_PIPE_CONNECTED: i32 = 535;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn CreateNamedPipeW(
+            name: *const u16,
+            open_mode: u32,
+            pipe_mode: u32,
+            max_instances: u32,
+            out_buffer_size: u32,
+            in_buffer_size: u32,
+            default_timeout: u32,
+            security_attributes: *mut c_void,
+        ) -> *mut c_void;
+        fn ConnectNamedPipe(pipe: *mut c_void, overlapped: *mut c_void) -> i32;
+    }
+
+    let name: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let create = move || {
+        let pipe = unsafe {
+            CreateNamedPipeW(
+                name.as_ptr(),
+                PIPE_ACCESS_INBOUND,
+                0,
+                PIPE_UNLIMITED_INSTANCES,
+                0,
+                4096,
+                0,
+                ptr::null_mut(),
+            )
+        };
+        if pipe == INVALID_HANDLE_VALUE {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(unsafe { File::from_raw_handle(pipe) })
+        }
+    };
+
+    // The first instance is created here so that failing to do so is reported
+    let mut pipe = create()?;
+    thread::spawn(move || loop {
+        let connected = unsafe { ConnectNamedPipe(pipe_handle(&pipe), ptr::null_mut()) } != 0;
+        if connected || io::Error::last_os_error().raw_os_error() == Some(ERROR_PIPE_CONNECTED) {
+            let sender = sender.clone();
+            thread::spawn(move || forward_lines(pipe, sender));
+        }
+        pipe = match create() {
+            Ok(pipe) => pipe,
+            Err(_) => return,
+        };
+    });
+    Ok(())
+}
+
+#[cfg(windows)]
+fn pipe_handle(pipe: &std::fs::File) -> *mut std::ffi::c_void {
+    use std::os::windows::io::AsRawHandle;
+
+    pipe.as_raw_handle() as *mut std::ffi::c_void
+}
+
+fn forward_lines<R: Read>(stream: R, sender: Sender<String>) {
+    for line in BufReader::new(stream).lines() {
+        match line {
+            Ok(line) if line.trim().is_empty() => {}
+            Ok(line) => {
+                if sender.send(line).is_err() {
+                    return;
+                }
+            }
+            Err(_) => return,
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{io::Write, os::unix::net::UnixStream, thread, time::Duration};
+
+    use super::{send, Server};
+    use crate::wake::Waker;
+
+    #[test]
+    fn lines_are_received_in_order() {
+        let path = std::env::temp_dir().join(format!("trooper_ipc_{}.sock", std::process::id()));
+        let server = Server::start(&path, &Waker::default()).unwrap();
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        client.write_all(b"cd /tmp\n\nselect /tmp/a b\n").unwrap();
+        drop(client);
+
+        let mut received = Vec::new();
+        for _ in 0..500 {
+            received.extend(server.try_recv());
+            if received.len() == 2 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(received, ["cd /tmp", "select /tmp/a b"]);
+
+        drop(server);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn only_we_may_connect() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path =
+            std::env::temp_dir().join(format!("trooper_private_{}.sock", std::process::id()));
+        let server = Server::start(&path, &Waker::default()).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        drop(server);
+    }
+
+    #[test]
+    fn commands_are_sent_one_per_line() {
+        let path = std::env::temp_dir().join(format!("trooper_send_{}.sock", std::process::id()));
+        let server = Server::start(&path, &Waker::default()).unwrap();
+
+        send(&path, &[String::from("cd /tmp"), String::from("reload")]).unwrap();
+        let mut received = Vec::new();
+        for _ in 0..500 {
+            received.extend(server.try_recv());
+            if received.len() == 2 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(received, ["cd /tmp", "reload"]);
+    }
+}
@@ -0,0 +1,1173 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    archive::{self, Format},
+    sftp::{SftpFs, Target},
+    vfs::{self, LocalFs, Vfs},
+    wake::Sender,
+};
+
+/// How far back the transfer rate is averaged. Short enough to follow a
+/// change of disk or network speed, long enough not to jump on every file.
+const RATE_WINDOW: Duration = Duration::from_secs(5);
+/// Minimum time between two progress events sent by a worker thread.
+const REPORT_INTERVAL: Duration = Duration::from_millis(100);
+/// Most files copied at once by a single transfer.
+const MAX_COPY_THREADS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobKind {
+    Copy,
+    Move,
+    /// Copies over the destination, after moving what is there aside to a
+    /// ` (Replaced)` name. It is moved back should the copy fail.
+    Restore,
+}
+
+/// Metadata kept on copied files, set with `preserve` in the config file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Preserve {
+    /// Permission bits
+    pub mode: bool,
+    /// Access and modification times
+    pub timestamps: bool,
+    /// Owner and group, which only root can hand to someone else
+    pub ownership: bool,
+}
+
+impl Default for Preserve {
+    fn default() -> Self {
+        Preserve {
+            mode: true,
+            timestamps: false,
+            ownership: false,
+        }
+    }
+}
+
+impl Preserve {
+    /// Parses a list such as `mode,timestamps`. `all` and `none` are also
+    /// accepted.
+    pub fn parse(value: &str) -> Result<Preserve, String> {
+        let mut preserve = Preserve {
+            mode: false,
+            timestamps: false,
+            ownership: false,
+        };
+        for item in value.split(|c: char| c == ',' || c.is_whitespace()) {
+            match item {
+                "" | "none" => {}
+                "mode" => preserve.mode = true,
+                "timestamps" => preserve.timestamps = true,
+                "ownership" => preserve.ownership = true,
+                "all" => {
+                    preserve = Preserve {
+                        mode: true,
+                        timestamps: true,
+                        ownership: true,
+                    }
+                }
+                _ => return Err(format!("Unknown attribute to preserve: {}", item)),
+            }
+        }
+        Ok(preserve)
+    }
+}
+
+/// Written the way `parse` reads it, as in `mode,timestamps`.
+impl fmt::Display for Preserve {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kept: Vec<&str> = [
+            (self.mode, "mode"),
+            (self.timestamps, "timestamps"),
+            (self.ownership, "ownership"),
+        ]
+        .into_iter()
+        .filter(|(kept, _)| *kept)
+        .map(|(_, name)| name)
+        .collect();
+        if kept.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", kept.join(","))
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Transfer {
+    pub from: PathBuf,
+    /// Where the copy should go. Should something be there by the time the
+    /// job starts, ` (Copy)` is added to the name as by `free_copy_name`.
+    pub to: PathBuf,
+}
+
+pub enum JobEvent {
+    Progress {
+        id: usize,
+        done: u64,
+        total: u64,
+    },
+    /// The job is done, with something to tell about it on success
+    Finished {
+        id: usize,
+        result: Result<Option<String>, String>,
+    },
+}
+
+/// What a job does, kept so that it can be started once the jobs before it
+/// are done, and started again to retry it.
+#[derive(Clone)]
+pub enum Task {
+    /// Copies (and for moves, removes) the sources. Cancelling removes what
+    /// the job has copied so far, leaving the sources, and anything else at
+    /// the destination, as they were.
+    Transfer {
+        kind: JobKind,
+        transfers: Vec<Transfer>,
+        preserve: Preserve,
+    },
+    /// Removes the paths, including everything below directories. Progress
+    /// is counted in bytes freed, and whatever has been deleted when the job
+    /// is cancelled stays deleted.
+    Delete { paths: Vec<PathBuf> },
+    /// Writes the sources into a new archive.
+    Compress {
+        archive: PathBuf,
+        format: Format,
+        sources: Vec<PathBuf>,
+    },
+    /// Copies entries on a remote host into local directories, over a
+    /// connection of its own as the one being browsed stays with the App.
+    /// Progress is counted in entries, and cancelling keeps those copied.
+    Download {
+        target: Target,
+        transfers: Vec<Transfer>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobState {
+    Queued,
+    Running,
+    Done,
+    Failed(String),
+    Cancelled,
+}
+
+/// Bytes transferred over time, used to estimate the current rate.
+#[derive(Default)]
+pub struct Throughput {
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl Throughput {
+    pub fn record(&mut self, done: u64) {
+        self.record_at(Instant::now(), done);
+    }
+
+    fn record_at(&mut self, at: Instant, done: u64) {
+        self.samples.push_back((at, done));
+        while self.samples.len() > 2 {
+            let (oldest, _) = self.samples[0];
+            if at.duration_since(oldest) <= RATE_WINDOW {
+                break;
+            }
+            self.samples.pop_front();
+        }
+    }
+
+    /// Bytes per second over the rolling window.
+    pub fn rate(&self) -> Option<f64> {
+        let (first_at, first_done) = self.samples.front()?;
+        let (last_at, last_done) = self.samples.back()?;
+        let secs = last_at.duration_since(*first_at).as_secs_f64();
+        if secs <= 0.0 {
+            return None;
+        }
+        Some(last_done.saturating_sub(*first_done) as f64 / secs)
+    }
+
+    pub fn eta(&self, done: u64, total: u64) -> Option<Duration> {
+        let rate = self.rate()?;
+        if rate <= 0.0 {
+            return None;
+        }
+        Some(Duration::from_secs_f64(
+            total.saturating_sub(done) as f64 / rate,
+        ))
+    }
+}
+
+pub struct Job {
+    pub id: usize,
+    pub description: String,
+    /// Paths created or removed by the job
+    pub paths: Vec<PathBuf>,
+    pub task: Task,
+    pub state: JobState,
+    pub done: u64,
+    pub total: u64,
+    pub throughput: Throughput,
+    /// Set to stop the worker thread at the next chunk or file
+    pub cancel: Arc<AtomicBool>,
+}
+
+impl Job {
+    pub fn new(id: usize, description: String, paths: Vec<PathBuf>, task: Task) -> Job {
+        Job {
+            id,
+            description,
+            paths,
+            task,
+            state: JobState::Queued,
+            done: 0,
+            total: 0,
+            throughput: Throughput::default(),
+            cancel: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn update(&mut self, done: u64, total: u64) {
+        self.done = done;
+        self.total = total;
+        self.throughput.record(done);
+    }
+
+    pub fn fraction(&self) -> f64 {
+        if self.total == 0 {
+            0.0
+        } else {
+            self.done as f64 / self.total as f64
+        }
+    }
+
+    pub fn eta(&self) -> Option<Duration> {
+        self.throughput.eta(self.done, self.total)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+
+    /// Whether the job is queued or running.
+    pub fn is_active(&self) -> bool {
+        matches!(self.state, JobState::Queued | JobState::Running)
+    }
+
+    /// Stops a running job at its next chunk or file, while a queued one is
+    /// never started.
+    pub fn cancel(&mut self) {
+        match self.state {
+            JobState::Queued => self.state = JobState::Cancelled,
+            JobState::Running => self.cancel.store(true, Ordering::Relaxed),
+            _ => {}
+        }
+    }
+
+    /// Queues a failed or cancelled job again. Deletes skip what has been
+    /// deleted already.
+    pub fn retry(&mut self) {
+        if let Task::Delete { paths } = &mut self.task {
+            paths.retain(|p| fs::symlink_metadata(p).is_ok());
+        }
+        self.state = JobState::Queued;
+        self.done = 0;
+        self.total = 0;
+        self.throughput = Throughput::default();
+        self.cancel = Arc::new(AtomicBool::new(false));
+    }
+}
+
+/// Formats a duration as `m:ss`, or `h:mm:ss` once it exceeds an hour.
+pub fn format_eta(eta: Duration) -> String {
+    let secs = eta.as_secs();
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+/// Runs `task` on a worker thread, reporting progress and the final result
+/// through `events`. Setting `cancel` stops it.
+pub fn spawn(id: usize, task: Task, cancel: Arc<AtomicBool>, events: Sender<JobEvent>) {
+    thread::spawn(move || {
+        let report = reporter(id, &events, &cancel);
+        let result = match &task {
+            Task::Transfer {
+                kind,
+                transfers,
+                preserve,
+            } => run_transfer(*kind, transfers, *preserve, report).map(|aside| {
+                let names: Vec<String> = aside.iter().map(|p| p.display().to_string()).collect();
+                (!names.is_empty()).then(|| format!("Kept what was replaced as {}", names.join(", ")))
+            }),
+            Task::Delete { paths } => run_delete(paths, report).map(|()| None),
+            Task::Compress {
+                archive,
+                format,
+                sources,
+            } => archive::compress(archive, *format, sources, report).map(|()| None),
+            Task::Download { target, transfers } => {
+                run_download(target, transfers, report).map(|()| None)
+            }
+        };
+        let _ = events.send(JobEvent::Finished {
+            id,
+            result: result.map_err(|e| e.to_string()),
+        });
+    });
+}
+
+/// Sends progress events for job `id`, at most once per `REPORT_INTERVAL`
+/// apart from the last one. Fails once `cancel` is set, which stops the
+/// worker calling it.
+fn reporter<'a>(
+    id: usize,
+    events: &'a Sender<JobEvent>,
+    cancel: &'a AtomicBool,
+) -> impl FnMut(u64, u64) -> io::Result<()> + Send + 'a {
+    let mut last_report: Option<Instant> = None;
+    move |done, total| {
+        if cancel.load(Ordering::Relaxed) {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "Cancelled"));
+        }
+        if last_report.is_none_or(|at| at.elapsed() >= REPORT_INTERVAL) || done >= total {
+            let _ = events.send(JobEvent::Progress { id, done, total });
+            last_report = Some(Instant::now());
+        }
+        Ok(())
+    }
+}
+
+/// Runs the transfers, returning where restores moved what they replaced.
+fn run_transfer(
+    kind: JobKind,
+    transfers: &[Transfer],
+    preserve: Preserve,
+    report: impl FnMut(u64, u64) -> io::Result<()> + Send,
+) -> io::Result<Vec<PathBuf>> {
+    let mut aside = Vec::new();
+    let result = (|| {
+        // Names are only picked now, as jobs queued before this one may have
+        // taken the ones which were free when it was added
+        let mut plan = CopyPlan::default();
+        for t in transfers {
+            let to = match kind {
+                JobKind::Restore => {
+                    move_aside(&t.to, &mut aside)?;
+                    t.to.clone()
+                }
+                JobKind::Copy | JobKind::Move => free_copy_name(t.to.clone(), t.from.is_dir()),
+            };
+            plan.add(&t.from, &to)?;
+        }
+
+        let total = plan.size();
+        let report = Mutex::new(report);
+        plan.run(preserve, |done| (report.lock().unwrap())(done, total))
+    })();
+    if let Err(e) = result {
+        // A failed copy has removed what it made, so the names are free again
+        for (live, moved) in aside.into_iter().rev() {
+            let _ = fs::rename(moved, live);
+        }
+        return Err(e);
+    }
+
+    if kind == JobKind::Move {
+        for t in transfers {
+            if t.from.is_dir() {
+                fs::remove_dir_all(&t.from)?;
+            } else {
+                fs::remove_file(&t.from)?;
+            }
+        }
+    }
+
+    Ok(aside.into_iter().map(|(_, moved)| moved).collect())
+}
+
+/// Copies the remote sources of `transfers` to free local names.
+fn run_download(
+    target: &Target,
+    transfers: &[Transfer],
+    mut report: impl FnMut(u64, u64) -> io::Result<()>,
+) -> io::Result<()> {
+    let remote = SftpFs::connect(target)?;
+    let total = transfers.len() as u64;
+    for (done, t) in transfers.iter().enumerate() {
+        report(done as u64, total)?;
+        let to = free_copy_name(t.to.clone(), remote.is_dir(&t.from));
+        vfs::copy_between(&remote, &t.from, &LocalFs, &to)?;
+    }
+    report(total, total)
+}
+
+/// Moves whatever is at `live` to a free ` (Replaced)` name, recording both
+/// in `aside`, or else makes sure the directory it goes in exists.
+fn move_aside(live: &Path, aside: &mut Vec<(PathBuf, PathBuf)>) -> io::Result<()> {
+    if fs::symlink_metadata(live).is_err() {
+        return match live.parent() {
+            Some(parent) => fs::create_dir_all(parent),
+            None => Ok(()),
+        };
+    }
+    let name = live.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} has no name", live.display()),
+        )
+    })?;
+    let mut moved = live.to_path_buf();
+    let mut replaced = name.to_os_string();
+    while fs::symlink_metadata(&moved).is_ok() {
+        replaced.push(" (Replaced)");
+        moved.set_file_name(&replaced);
+    }
+    fs::rename(live, &moved)?;
+    aside.push((live.to_path_buf(), moved));
+    Ok(())
+}
+
+fn run_delete(
+    paths: &[PathBuf],
+    mut report: impl FnMut(u64, u64) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut total = 0;
+    for p in paths {
+        total += removed_size(p)?;
+    }
+
+    let mut done = 0;
+    for p in paths {
+        remove_tree(p, &mut |removed| {
+            done += removed;
+            report(done, total)
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Size of the files at or below `path`. Symlinks are not followed, as they
+/// are removed rather than what they point to.
+fn removed_size(path: &Path) -> io::Result<u64> {
+    let md = fs::symlink_metadata(path)?;
+    if !md.is_dir() {
+        return Ok(md.len());
+    }
+    let mut size = 0;
+    for e in fs::read_dir(path)? {
+        size += removed_size(&e?.path())?;
+    }
+    Ok(size)
+}
+
+/// Removes `path` like `fs::remove_dir_all`, calling `removed` with the size
+/// of every file removed. An error from `removed` stops the removal.
+fn remove_tree(path: &Path, removed: &mut impl FnMut(u64) -> io::Result<()>) -> io::Result<()> {
+    let md = fs::symlink_metadata(path)?;
+    if md.is_dir() {
+        for e in fs::read_dir(path)? {
+            remove_tree(&e?.path(), removed)?;
+        }
+        fs::remove_dir(path)
+    } else {
+        fs::remove_file(path)?;
+        removed(md.len())
+    }
+}
+
+/* Trees are walked up front so that their files can be spread over several
+ * threads, which is much faster for many small files than copying them one
+ * at a time. Paths are only ever joined and never converted to strings, so
+ * names which are not valid UTF-8 are copied like any other.
+ */
+#[derive(Default)]
+struct CopyPlan {
+    /// Directories to create, parents before their children
+    dirs: Vec<(PathBuf, fs::Metadata)>,
+    /// Files to copy, as source, destination and metadata of the source
+    files: Vec<(PathBuf, PathBuf, fs::Metadata)>,
+    /// Symlinks to create, as target and destination
+    links: Vec<(PathBuf, PathBuf)>,
+}
+
+impl CopyPlan {
+    /// Adds the copying of `from`, and everything below it, to `to`. `from`
+    /// itself is followed when it is a symlink, while symlinks below it are
+    /// copied as symlinks, like `cp -R` does. A link back up the tree can't
+    /// make the copy recurse forever that way.
+    fn add(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        self.add_entry(from, to, fs::metadata(from)?)
+    }
+
+    fn add_entry(&mut self, from: &Path, to: &Path, md: fs::Metadata) -> io::Result<()> {
+        if md.is_dir() {
+            self.dirs.push((to.to_path_buf(), md));
+            for e in fs::read_dir(from)? {
+                let e = e?;
+                let md = fs::symlink_metadata(e.path())?;
+                self.add_entry(&e.path(), &to.join(e.file_name()), md)?;
+            }
+        } else if md.file_type().is_symlink() {
+            self.links.push((fs::read_link(from)?, to.to_path_buf()));
+        } else {
+            self.files.push((from.to_path_buf(), to.to_path_buf(), md));
+        }
+        Ok(())
+    }
+
+    /// Total size of the files to copy.
+    fn size(&self) -> u64 {
+        self.files.iter().map(|(_, _, md)| md.len()).sum()
+    }
+
+    /// Creates the directories and copies the files on up to
+    /// `MAX_COPY_THREADS` threads. `progress` is called with the number of
+    /// bytes copied so far before every file and after every chunk, and an
+    /// error from it stops the copy. Whatever the copy created is removed
+    /// again when it fails, while entries which were in the way are left
+    /// alone.
+    fn run(
+        &self,
+        preserve: Preserve,
+        progress: impl Fn(u64) -> io::Result<()> + Sync,
+    ) -> io::Result<()> {
+        let created = Mutex::new(Vec::new());
+        let result = self.copy(preserve, progress, &created);
+        if result.is_err() {
+            // Files come after the directories holding them, and children
+            // after their parents
+            for p in created.into_inner().unwrap().iter().rev() {
+                let _ = match fs::symlink_metadata(p) {
+                    Ok(md) if md.is_dir() => fs::remove_dir(p),
+                    _ => fs::remove_file(p),
+                };
+            }
+        }
+        result
+    }
+
+    fn copy(
+        &self,
+        preserve: Preserve,
+        progress: impl Fn(u64) -> io::Result<()> + Sync,
+        created: &Mutex<Vec<PathBuf>>,
+    ) -> io::Result<()> {
+        for (dir, _) in &self.dirs {
+            fs::create_dir(dir)?;
+            created.lock().unwrap().push(dir.clone());
+        }
+
+        let next = AtomicUsize::new(0);
+        let done = AtomicU64::new(0);
+        let failed = AtomicBool::new(false);
+        let worker = || -> io::Result<()> {
+            while !failed.load(Ordering::Relaxed) {
+                let (from, to, md) = match self.files.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    Some(file) => file,
+                    None => break,
+                };
+                let result = progress(done.load(Ordering::Relaxed))
+                    .and_then(|()| {
+                        copy_file(from, to, md.len(), created, &mut |n| {
+                            progress(done.fetch_add(n, Ordering::Relaxed) + n)
+                        })
+                    })
+                    .and_then(|()| copy_metadata(md, to, preserve));
+                if result.is_err() {
+                    failed.store(true, Ordering::Relaxed);
+                    return result;
+                }
+            }
+            Ok(())
+        };
+        let threads = thread::available_parallelism()
+            .map_or(1, |n| n.get())
+            .min(MAX_COPY_THREADS)
+            .min(self.files.len())
+            .max(1);
+        thread::scope(|s| {
+            let workers: Vec<_> = (0..threads).map(|_| s.spawn(worker)).collect();
+            workers
+                .into_iter()
+                .try_for_each(|w| w.join().expect("copy worker panicked"))
+        })?;
+
+        // Last, as Windows has to know whether the target is a directory
+        for (target, link) in &self.links {
+            symlink(target, link)?;
+            created.lock().unwrap().push(link.clone());
+        }
+
+        // Children before their parents, whose times change as they are filled
+        for (dir, md) in self.dirs.iter().rev() {
+            copy_metadata(md, dir, preserve)?;
+        }
+        Ok(())
+    }
+}
+
+/* Permissions come last so that read-only directories can still be filled.
+ * Changing the owner clears setuid bits, so it has to happen before the
+ * permissions are set.
+ */
+fn copy_metadata(md: &fs::Metadata, to: &Path, preserve: Preserve) -> io::Result<()> {
+    #[cfg(unix)]
+    if preserve.ownership && unsafe { libc::geteuid() } == 0 {
+        use std::os::unix::fs::MetadataExt;
+        std::os::unix::fs::chown(to, Some(md.uid()), Some(md.gid()))?;
+    }
+    if preserve.timestamps {
+        let times = fs::FileTimes::new()
+            .set_accessed(md.accessed()?)
+            .set_modified(md.modified()?);
+        open_for_times(to, md.is_dir())?.set_times(times)?;
+    }
+    if preserve.mode {
+        fs::set_permissions(to, md.permissions())?;
+    }
+    Ok(())
+}
+
+/// Opens `to` so that its times can be set. That takes write access on
+/// Windows, which Unix does not give for directories.
+#[cfg(unix)]
+fn open_for_times(to: &Path, is_dir: bool) -> io::Result<File> {
+    if is_dir {
+        File::open(to)
+    } else {
+        OpenOptions::new().write(true).open(to)
+    }
+}
+
+#[cfg(windows)]
+fn open_for_times(to: &Path, is_dir: bool) -> io::Result<File> {
+    use std::os::windows::fs::OpenOptionsExt;
+
+    // Without it directories can't be opened at all
+    const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+    let mut options = OpenOptions::new();
+    options.write(true);
+    if is_dir {
+        options.custom_flags(FILE_FLAG_BACKUP_SEMANTICS);
+    }
+    options.open(to)
+}
+
+/* Copy-on-write file systems such as btrfs, XFS and APFS can clone a file
+ * by sharing its blocks, which is instant however large the file is. Other
+ * file systems, and clones across file systems, fall back to copying.
+ * Nothing is ever written over: `to` is only created if it does not exist,
+ * and pushed to `created` once it is.
+ */
+fn copy_file(
+    from: &Path,
+    to: &Path,
+    len: u64,
+    created: &Mutex<Vec<PathBuf>>,
+    copied: &mut impl FnMut(u64) -> io::Result<()>,
+) -> io::Result<()> {
+    if clone_file(from, to).is_ok() {
+        created.lock().unwrap().push(to.to_path_buf());
+        return copied(len);
+    }
+    let mut src = File::open(from)?;
+    let mut dest = OpenOptions::new().write(true).create_new(true).open(to)?;
+    created.lock().unwrap().push(to.to_path_buf());
+    if clone_into(&src, &dest).is_ok() {
+        return copied(len);
+    }
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let read = src.read(&mut buf)?;
+        if read == 0 {
+            return Ok(());
+        }
+        dest.write_all(&buf[..read])?;
+        copied(read as u64)?;
+    }
+}
+
+/// Clones `src` into the empty file `dest`.
+#[cfg(target_os = "linux")]
+fn clone_into(src: &File, dest: &File) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    // _IOW(0x94, 9, int) from linux/fs.h
+    const FICLONE: u32 = 0x4004_9409;
+
+    if unsafe { libc::ioctl(dest.as_raw_fd(), FICLONE as _, src.as_raw_fd()) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn clone_into(_src: &File, _dest: &File) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Cloning into open files is not supported on this platform",
+    ))
+}
+
+/// Clones `from` to the new file `to`, failing if `to` exists.
+#[cfg(target_os = "macos")]
+fn clone_file(from: &Path, to: &Path) -> io::Result<()> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    extern "C" {
+        fn clonefile(src: *const libc::c_char, dst: *const libc::c_char, flags: u32)
+            -> libc::c_int;
+    }
+
+    let from = CString::new(from.as_os_str().as_bytes())?;
+    let to = CString::new(to.as_os_str().as_bytes())?;
+    if unsafe { clonefile(from.as_ptr(), to.as_ptr(), 0) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+fn clone_file(_from: &Path, _to: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Cloning to new files is not supported on this platform",
+    ))
+}
+
+#[cfg(unix)]
+pub fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+pub fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    // Windows distinguishes links to directories, resolved relative to the link
+    if link.parent().unwrap_or(link).join(target).is_dir() {
+        std::os::windows::fs::symlink_dir(target, link)
+    } else {
+        std::os::windows::fs::symlink_file(target, link)
+    }
+}
+
+/// Appends ` (Copy)` to the name of `dest`, before the extension of files,
+/// until it no longer names an existing entry.
+pub fn free_copy_name(mut dest: PathBuf, is_dir: bool) -> PathBuf {
+    while fs::symlink_metadata(&dest).is_ok() {
+        let name = match (is_dir, dest.extension()) {
+            (false, Some(ext)) => format!(
+                "{} (Copy).{}",
+                dest.file_stem().unwrap().to_string_lossy(),
+                ext.to_string_lossy()
+            ),
+            _ => format!("{} (Copy)", dest.file_name().unwrap().to_string_lossy()),
+        };
+        dest.set_file_name(name);
+    }
+    dest
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        path::Path,
+        sync::atomic::{AtomicBool, AtomicU64, Ordering},
+        time::{Duration, Instant},
+    };
+
+    use super::{format_eta, free_copy_name, CopyPlan, Preserve, Throughput};
+
+    /// Copies `from` to `to`, returning the number of bytes copied.
+    fn copy_tree(from: &Path, to: &Path, preserve: Preserve) -> u64 {
+        let mut plan = CopyPlan::default();
+        plan.add(from, to).unwrap();
+        let copied = AtomicU64::new(0);
+        plan.run(preserve, |done| {
+            copied.fetch_max(done, Ordering::Relaxed);
+            Ok(())
+        })
+        .unwrap();
+        copied.into_inner()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn trees_with_non_utf8_names_are_copied() {
+        use std::{ffi::OsStr, fs, os::unix::ffi::OsStrExt};
+
+        let root = std::env::temp_dir().join(format!("trooper_jobs_{}", std::process::id()));
+        let name = OsStr::from_bytes(b"caf\xe9");
+        fs::create_dir_all(root.join("src").join(name)).unwrap();
+        fs::write(root.join("src").join(name).join("file"), [0; 10]).unwrap();
+
+        let copied = copy_tree(&root.join("src"), &root.join("dest"), Preserve::default());
+        assert_eq!(copied, 10);
+        assert!(root.join("dest").join(name).join("file").exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn times_are_preserved_for_files_and_directories() {
+        use std::fs::{self, File};
+
+        let root = std::env::temp_dir().join(format!("trooper_times_{}", std::process::id()));
+        fs::create_dir_all(root.join("src/sub")).unwrap();
+        fs::write(root.join("src/sub/file"), "").unwrap();
+        let then = std::time::SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let times = fs::FileTimes::new().set_modified(then);
+        File::options()
+            .write(true)
+            .open(root.join("src/sub/file"))
+            .unwrap()
+            .set_times(times)
+            .unwrap();
+        let mut permissions = fs::metadata(root.join("src/sub/file"))
+            .unwrap()
+            .permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(root.join("src/sub/file"), permissions).unwrap();
+        super::open_for_times(&root.join("src/sub"), true)
+            .unwrap()
+            .set_times(times)
+            .unwrap();
+
+        copy_tree(&root.join("src"), &root.join("dest"), Preserve::parse("all").unwrap());
+        for path in ["dest/sub", "dest/sub/file"] {
+            let modified = fs::metadata(root.join(path)).unwrap().modified().unwrap();
+            assert_eq!(modified, then, "{}", path);
+        }
+
+        let mut permissions = fs::metadata(root.join("dest/sub/file"))
+            .unwrap()
+            .permissions();
+        #[allow(clippy::permissions_set_readonly_false)]
+        permissions.set_readonly(false);
+        fs::set_permissions(root.join("dest/sub/file"), permissions.clone()).unwrap();
+        fs::set_permissions(root.join("src/sub/file"), permissions).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_in_trees_are_copied_as_symlinks() {
+        use std::{fs, os::unix::fs::symlink};
+
+        let root = std::env::temp_dir().join(format!("trooper_loop_{}", std::process::id()));
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/file"), [0; 10]).unwrap();
+        symlink(".", root.join("src/loop")).unwrap();
+        symlink("file", root.join("src/link")).unwrap();
+
+        let copied = copy_tree(&root.join("src"), &root.join("dest"), Preserve::default());
+        assert_eq!(copied, 10);
+        assert_eq!(
+            fs::read_link(root.join("dest/loop")).unwrap(),
+            Path::new(".")
+        );
+        assert_eq!(
+            fs::read_link(root.join("dest/link")).unwrap(),
+            Path::new("file")
+        );
+
+        // Copying the link itself copies what it points to
+        let copied = copy_tree(
+            &root.join("src/link"),
+            &root.join("copy"),
+            Preserve::default(),
+        );
+        assert_eq!(copied, 10);
+        assert!(!fs::symlink_metadata(root.join("copy"))
+            .unwrap()
+            .is_symlink());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn files_are_copied_whether_or_not_they_can_be_cloned() {
+        use std::fs;
+
+        let root = std::env::temp_dir().join(format!("trooper_reflink_{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("src"), "contents").unwrap();
+
+        let copied = copy_tree(&root.join("src"), &root.join("dest"), Preserve::default());
+        assert_eq!(copied, 8);
+        assert_eq!(fs::read_to_string(root.join("dest")).unwrap(), "contents");
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn progress_adds_up_over_all_threads() {
+        use std::fs;
+
+        let root = std::env::temp_dir().join(format!("trooper_parallel_{}", std::process::id()));
+        for dir in ["a", "b/c"] {
+            fs::create_dir_all(root.join("src").join(dir)).unwrap();
+            for i in 0..50 {
+                fs::write(root.join("src").join(dir).join(i.to_string()), [0; 100]).unwrap();
+            }
+        }
+
+        let copied = copy_tree(&root.join("src"), &root.join("dest"), Preserve::default());
+        assert_eq!(copied, 100 * 100);
+        assert_eq!(fs::read_dir(root.join("dest/b/c")).unwrap().count(), 50);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn deleted_bytes_are_counted() {
+        use std::fs;
+
+        let root = std::env::temp_dir().join(format!("trooper_delete_{}", std::process::id()));
+        fs::create_dir_all(root.join("dir/sub")).unwrap();
+        fs::write(root.join("dir/sub/file"), [0; 10]).unwrap();
+        fs::write(root.join("file"), [0; 5]).unwrap();
+        let paths = [root.join("dir"), root.join("file")];
+
+        let mut last = (0, 0);
+        super::run_delete(&paths, |done, total| {
+            last = (done, total);
+            Ok(())
+        })
+        .unwrap();
+        assert!(fs::read_dir(&root).unwrap().next().is_none());
+        assert_eq!(last, (15, 15));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn cancelled_copies_are_cleaned_up() {
+        use std::fs;
+
+        use super::{reporter, run_transfer, JobKind, Transfer};
+        use crate::wake::{channel, Waker};
+
+        let root = std::env::temp_dir().join(format!("trooper_cancel_{}", std::process::id()));
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/file"), [0; 10]).unwrap();
+        let transfers = [Transfer {
+            from: root.join("src"),
+            to: root.join("dest"),
+        }];
+
+        let (sender, _receiver) = channel(&Waker::default());
+        let cancel = AtomicBool::new(true);
+        let report = reporter(0, &sender, &cancel);
+        let result = run_transfer(JobKind::Move, &transfers, Preserve::default(), report);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::Interrupted);
+        assert!(!root.join("dest").exists());
+        assert!(root.join("src/file").exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn restores_put_back_what_they_replace_when_they_fail() {
+        use std::fs;
+
+        use super::{reporter, run_transfer, JobKind, Transfer};
+        use crate::wake::{channel, Waker};
+
+        let root = std::env::temp_dir().join(format!("trooper_restore_{}", std::process::id()));
+        fs::create_dir_all(root.join("snapshot")).unwrap();
+        fs::write(root.join("snapshot/file"), "old").unwrap();
+        fs::write(root.join("file"), "new").unwrap();
+        let transfers = [Transfer {
+            from: root.join("snapshot/file"),
+            to: root.join("file"),
+        }];
+
+        let (sender, _receiver) = channel(&Waker::default());
+        let cancel = AtomicBool::new(true);
+        let report = reporter(0, &sender, &cancel);
+        let result = run_transfer(JobKind::Restore, &transfers, Preserve::default(), report);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::Interrupted);
+        assert_eq!(fs::read_to_string(root.join("file")).unwrap(), "new");
+        assert!(!root.join("file (Replaced)").exists());
+
+        let aside = run_transfer(JobKind::Restore, &transfers, Preserve::default(), |_, _| {
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(aside, [root.join("file (Replaced)")]);
+        assert_eq!(fs::read_to_string(root.join("file")).unwrap(), "old");
+        assert_eq!(fs::read_to_string(&aside[0]).unwrap(), "new");
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn failed_copies_leave_what_was_there_alone() {
+        use std::fs;
+
+        use super::{run_transfer, JobKind, Transfer};
+
+        let root = std::env::temp_dir().join(format!("trooper_clash_{}", std::process::id()));
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/a"), "new").unwrap();
+        fs::write(root.join("src/b"), "new").unwrap();
+        fs::create_dir_all(root.join("dest")).unwrap();
+        fs::write(root.join("dest/b"), "old").unwrap();
+
+        // As if an earlier job had taken the name after the plan was made
+        let mut plan = super::CopyPlan::default();
+        plan.add(&root.join("src/a"), &root.join("dest/a")).unwrap();
+        plan.add(&root.join("src/b"), &root.join("dest/b")).unwrap();
+        let result = plan.run(Preserve::default(), |_| Ok(()));
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        assert!(!root.join("dest/a").exists());
+        assert_eq!(fs::read_to_string(root.join("dest/b")).unwrap(), "old");
+
+        // Jobs pick a free name when they start
+        let transfers = [Transfer {
+            from: root.join("src/b"),
+            to: root.join("dest/b"),
+        }];
+        run_transfer(
+            JobKind::Copy,
+            &transfers,
+            Preserve::default(),
+            |_, _| Ok(()),
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(root.join("dest/b")).unwrap(), "old");
+        assert_eq!(
+            fs::read_to_string(root.join("dest/b (Copy)")).unwrap(),
+            "new"
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn copies_are_named_after_the_original() {
+        use std::fs;
+
+        let dir = std::env::temp_dir().join(format!("trooper_copy_name_{}", std::process::id()));
+        fs::create_dir_all(dir.join("dir.d")).unwrap();
+        fs::write(dir.join("config.ini"), "").unwrap();
+        fs::write(dir.join("config (Copy).ini"), "").unwrap();
+        fs::write(dir.join("Makefile"), "").unwrap();
+
+        assert_eq!(
+            free_copy_name(dir.join("config.ini"), false),
+            dir.join("config (Copy) (Copy).ini")
+        );
+        assert_eq!(
+            free_copy_name(dir.join("Makefile"), false),
+            dir.join("Makefile (Copy)")
+        );
+        assert_eq!(
+            free_copy_name(dir.join("dir.d"), true),
+            dir.join("dir.d (Copy)")
+        );
+        assert_eq!(free_copy_name(dir.join("new"), false), dir.join("new"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn retried_deletes_skip_what_is_gone() {
+        use std::fs;
+
+        use super::{Job, JobState, Task};
+
+        let root = std::env::temp_dir().join(format!("trooper_retry_{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("kept"), "").unwrap();
+        let task = Task::Delete {
+            paths: vec![root.join("kept"), root.join("gone")],
+        };
+        let mut job = Job::new(0, String::from("Deleting 2 items"), Vec::new(), task);
+
+        // Queued jobs are never started, so there is no worker to stop
+        job.cancel();
+        assert_eq!(job.state, JobState::Cancelled);
+        assert!(!job.is_cancelled());
+
+        job.retry();
+        assert_eq!(job.state, JobState::Queued);
+        assert!(matches!(&job.task, Task::Delete { paths } if *paths == [root.join("kept")]));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn modification_times_are_kept_when_asked_to() {
+        use std::{
+            fs::{self, File},
+            time::SystemTime,
+        };
+
+        let root = std::env::temp_dir().join(format!("trooper_preserve_{}", std::process::id()));
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src").join("file"), "").unwrap();
+        let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let times = fs::FileTimes::new().set_accessed(old).set_modified(old);
+        File::open(root.join("src").join("file"))
+            .unwrap()
+            .set_times(times)
+            .unwrap();
+
+        let preserve = Preserve::parse("mode, timestamps").unwrap();
+        copy_tree(&root.join("src"), &root.join("dest"), preserve);
+        let modified = |p: &str| fs::metadata(root.join(p)).unwrap().modified().unwrap();
+        assert_eq!(modified("dest/file"), old);
+        assert_eq!(modified("dest"), modified("src"));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn preserved_attributes_are_listed() {
+        let all = Preserve::parse("all").unwrap();
+        assert!(all.mode && all.timestamps && all.ownership);
+        assert!(!Preserve::parse("none").unwrap().mode);
+        assert!(Preserve::parse("mode,size").is_err());
+    }
+
+    #[test]
+    fn rate_only_considers_the_rolling_window() {
+        let start = Instant::now();
+        let mut throughput = Throughput::default();
+        throughput.record_at(start, 0);
+        throughput.record_at(start + Duration::from_secs(1), 10_000);
+        // A slow stretch outside of the window no longer affects the rate
+        throughput.record_at(start + Duration::from_secs(10), 11_000);
+        throughput.record_at(start + Duration::from_secs(12), 31_000);
+
+        assert_eq!(throughput.rate(), Some(10_000.0));
+        assert_eq!(throughput.eta(31_000, 61_000), Some(Duration::from_secs(3)));
+    }
+
+    #[test]
+    fn eta_is_formatted_as_clock_time() {
+        assert_eq!(format_eta(Duration::from_secs(42)), "0:42");
+        assert_eq!(format_eta(Duration::from_secs(3 * 3600 + 61)), "3:01:01");
+    }
+}
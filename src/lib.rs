@@ -0,0 +1,35 @@
+//! The file manager behind the `trooper` binary. [`app::App`] holds the
+//! state of a session and is driven by feeding it key events and ticks, or
+//! command lines through [`app::App::run_command_line`] and
+//! [`app::App::run_batch`]. It draws itself on a ratatui `Terminal`.
+
+pub mod app;
+mod archive;
+mod checksum;
+mod clipboard;
+mod devices;
+mod disk;
+mod du;
+pub mod error;
+mod filetypes;
+mod fuzzy;
+mod gitignore;
+mod glob;
+mod icons;
+pub mod ipc;
+mod jobs;
+mod lua;
+mod mode;
+pub mod paths;
+mod permissions;
+mod properties;
+mod protected;
+mod sftp;
+pub mod shell;
+mod snapshots;
+mod state;
+mod status;
+mod tail;
+mod ui;
+mod vfs;
+pub mod wake;
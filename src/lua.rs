@@ -0,0 +1,225 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use mlua::{Function, Lua, RegistryKey, Variadic};
+
+/// Something a plugin asked trooper to do, carried out once the plugin
+/// returns.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Request {
+    /// Runs the action with the given name and arguments, as with `:`
+    Action(String, Vec<String>),
+    /// Shows a message in the status line
+    Notify(String),
+}
+
+/// What plugins see of trooper while they run.
+#[derive(Debug, Default)]
+struct State {
+    cwd: PathBuf,
+    selection: Vec<PathBuf>,
+    requests: Vec<Request>,
+}
+
+/* Plugins can't borrow the App, so they are handed a copy of the state they
+ * can read and leave requests behind in it instead.
+ */
+/// Plugins written in Lua, which use the `trooper` table to add commands and
+/// status line segments:
+///
+/// | Function | Does |
+/// |----------|------|
+/// | `trooper.cwd()` | The current directory |
+/// | `trooper.selection()` | The selected entries |
+/// | `trooper.command(name, f)` | Runs `f` with the arguments of `:name` |
+/// | `trooper.status(name, f)` | Shows what `f` returns as `{name}` in the status line |
+/// | `trooper.run(action, ...)` | Runs an action, such as `ChangeDir` |
+/// | `trooper.notify(message)` | Shows a message |
+pub struct Plugins {
+    lua: Lua,
+    state: Rc<RefCell<State>>,
+    commands: Rc<RefCell<HashMap<String, RegistryKey>>>,
+    segments: Rc<RefCell<Vec<(String, RegistryKey)>>>,
+}
+
+impl Plugins {
+    pub fn new() -> mlua::Result<Plugins> {
+        let lua = Lua::new();
+        let state = Rc::new(RefCell::new(State::default()));
+        let commands = Rc::new(RefCell::new(HashMap::new()));
+        let segments = Rc::new(RefCell::new(Vec::new()));
+
+        let api = lua.create_table()?;
+        let s = state.clone();
+        api.set(
+            "cwd",
+            lua.create_function(move |_, ()| Ok(s.borrow().cwd.to_string_lossy().into_owned()))?,
+        )?;
+        let s = state.clone();
+        api.set(
+            "selection",
+            lua.create_function(move |_, ()| {
+                Ok(s.borrow()
+                    .selection
+                    .iter()
+                    .map(|p| p.to_string_lossy().into_owned())
+                    .collect::<Vec<String>>())
+            })?,
+        )?;
+        let c = commands.clone();
+        api.set(
+            "command",
+            lua.create_function(move |lua, (name, f): (String, Function)| {
+                c.borrow_mut().insert(name, lua.create_registry_value(f)?);
+                Ok(())
+            })?,
+        )?;
+        let g = segments.clone();
+        api.set(
+            "status",
+            lua.create_function(move |lua, (name, f): (String, Function)| {
+                let key = lua.create_registry_value(f)?;
+                let mut segments = g.borrow_mut();
+                segments.retain(|(n, _)| *n != name);
+                segments.push((name, key));
+                Ok(())
+            })?,
+        )?;
+        let s = state.clone();
+        api.set(
+            "run",
+            lua.create_function(move |_, (action, args): (String, Variadic<String>)| {
+                let args = args.into_iter().collect();
+                s.borrow_mut().requests.push(Request::Action(action, args));
+                Ok(())
+            })?,
+        )?;
+        let s = state.clone();
+        api.set(
+            "notify",
+            lua.create_function(move |_, message: String| {
+                s.borrow_mut().requests.push(Request::Notify(message));
+                Ok(())
+            })?,
+        )?;
+        lua.globals().set("trooper", api)?;
+
+        Ok(Plugins {
+            lua,
+            state,
+            commands,
+            segments,
+        })
+    }
+
+    /// Runs the plugin in `path`, which registers what it adds.
+    pub fn load(&self, path: &Path) -> Result<(), String> {
+        let code = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        self.lua
+            .load(code.as_str())
+            .set_name(path.display().to_string())
+            .exec()
+            .map_err(|e| e.to_string())
+    }
+
+    /// The names of the commands added by plugins.
+    pub fn commands(&self) -> Vec<String> {
+        self.commands.borrow().keys().cloned().collect()
+    }
+
+    /// Runs the command `name` and returns what it asked for.
+    pub fn run_command(
+        &self,
+        name: &str,
+        args: Vec<String>,
+        cwd: &Path,
+        selection: Vec<PathBuf>,
+    ) -> Result<Vec<Request>, String> {
+        self.set_state(cwd, selection);
+        // Not borrowed during the call, as the command may add others
+        let function = match self.commands.borrow().get(name) {
+            Some(key) => self.lua.registry_value::<Function>(key),
+            None => return Err(format!("No plugin command {}", name)),
+        };
+        let result = function.and_then(|f| f.call::<_, ()>(Variadic::from_iter(args)));
+        let requests = std::mem::take(&mut self.state.borrow_mut().requests);
+        result.map(|_| requests).map_err(|e| e.to_string())
+    }
+
+    /// The text of every segment added by plugins. A segment which fails
+    /// shows its error instead.
+    pub fn status_segments(&self, cwd: &Path, selection: Vec<PathBuf>) -> Vec<(String, String)> {
+        self.set_state(cwd, selection);
+        let functions: Vec<_> = self
+            .segments
+            .borrow()
+            .iter()
+            .map(|(name, key)| (name.clone(), self.lua.registry_value::<Function>(key)))
+            .collect();
+        let segments = functions
+            .into_iter()
+            .map(|(name, function)| {
+                let text = function
+                    .and_then(|f| f.call::<_, Option<String>>(()))
+                    .unwrap_or_else(|e| Some(e.to_string()));
+                (name, text.unwrap_or_default())
+            })
+            .collect();
+        // Segments only show things, so anything they ask for is dropped
+        self.state.borrow_mut().requests.clear();
+        segments
+    }
+
+    fn set_state(&self, cwd: &Path, selection: Vec<PathBuf>) {
+        let mut state = self.state.borrow_mut();
+        state.cwd = cwd.to_path_buf();
+        state.selection = selection;
+        state.requests.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::{Plugins, Request};
+
+    #[test]
+    fn plugins_add_commands_and_segments() {
+        let dir = std::env::temp_dir().join(format!("trooper_lua_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let plugin = r#"
+            trooper.command("up", function(times)
+                trooper.run("ChangeDir", trooper.cwd() .. "/" .. string.rep("../", times))
+            end)
+            trooper.status("count", function()
+                return #trooper.selection() .. " selected"
+            end)
+        "#;
+        fs::write(dir.join("init.lua"), plugin).unwrap();
+
+        let plugins = Plugins::new().unwrap();
+        plugins.load(&dir.join("init.lua")).unwrap();
+        assert_eq!(plugins.commands(), ["up"]);
+        assert_eq!(
+            plugins.run_command("up", vec![String::from("2")], &dir, vec![]),
+            Ok(vec![Request::Action(
+                String::from("ChangeDir"),
+                vec![format!("{}/../../", dir.display())]
+            )])
+        );
+        let selection = vec![PathBuf::from("/tmp/a"), PathBuf::from("/tmp/b")];
+        assert_eq!(
+            plugins.status_segments(&dir, selection),
+            [(String::from("count"), String::from("2 selected"))]
+        );
+        assert!(plugins.load(&dir.join("missing.lua")).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
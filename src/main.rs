@@ -1,19 +1,23 @@
-mod app;
-mod ui;
-
 use std::{
-    env, fs, io,
-    path::{Path, PathBuf},
-    time::{Duration, Instant},
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+    process::{ExitStatus, Stdio},
+    time::Duration,
 };
 
-use app::App;
-use clap::Parser;
+use clap::{CommandFactory, Parser, Subcommand};
 use crossterm::{
-    event::{DisableMouseCapture, EnableMouseCapture, Event, KeyModifiers},
+    event::{
+        DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste, EnableMouseCapture,
+        Event, EventStream, KeyboardEnhancementFlags, PopKeyboardEnhancementFlags,
+        PushKeyboardEnhancementFlags,
+    },
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
+use futures::StreamExt;
 use log::LevelFilter;
 use log4rs::{
     append::file::FileAppender,
@@ -21,25 +25,137 @@ use log4rs::{
     encode::pattern::PatternEncoder,
     Config,
 };
-use tui::{
+use ratatui::{
     backend::{Backend, CrosstermBackend},
     Terminal,
 };
+use tokio::time::{Instant, Interval, MissedTickBehavior};
+use trooper::{
+    app::{self, App, Options},
+    ipc, paths,
+    shell::{self, InitShell, ShellCommand},
+};
+
+/// Asked of terminals supporting the kitty keyboard protocol, at startup and
+/// again after a shell command had the terminal.
+const KEYBOARD_FLAGS: KeyboardEnhancementFlags =
+    KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES
+        .union(KeyboardEnhancementFlags::REPORT_EVENT_TYPES);
 
 #[derive(Parser, Debug)]
 #[command(author="Vincent Udén", version=env!("CARGO_PKG_VERSION"), about="A terminal file manager")]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Subcommands>,
+    #[arg(help = "Directory to start in, or a file to start with the cursor on")]
+    path: Option<PathBuf>,
     #[arg(long, help = "Output the last visited directory to a given file")]
     choose_dir: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Pick files with Enter and write their paths, one per line, to a given file"
+    )]
+    choose_files: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Print the default config, as a starting point for your own"
+    )]
+    print_default_config: bool,
+    #[arg(long, help = "Read the config from a given file instead of config.ini")]
+    config: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Make runs reproducible for automated tests by sandboxing persistence and ticking on input"
+    )]
+    deterministic: bool,
+    #[arg(
+        long,
+        help = "Describe changes to the file system instead of making them"
+    )]
+    dry_run: bool,
+    #[arg(
+        long,
+        help = "Draw on the terminal and print the chosen files, or the last visited directory, to stdout"
+    )]
+    stdout: bool,
+    #[arg(
+        long,
+        value_name = "SHELL",
+        help = "Print a shell function `t` which changes to the last visited directory on quit"
+    )]
+    init: Option<InitShell>,
+    #[arg(long, help = "Start with the cursor on the given file")]
+    select: Option<PathBuf>,
+    #[arg(
+        long,
+        conflicts_with_all = ["path", "select"],
+        help = "Start where the last session left off"
+    )]
+    restore: bool,
+    #[arg(long, help = "Refuse every action which writes to the file system")]
+    read_only: bool,
+    #[arg(
+        long,
+        value_name = "COMMAND",
+        help = "Run a command, such as \"cd /tmp\", in a running trooper instead of starting one"
+    )]
+    send: Vec<String>,
+    #[arg(long, help = "The process id of the trooper to send commands to")]
+    pid: Option<u32>,
+    #[arg(
+        long,
+        value_name = "SCRIPT",
+        help = "Run the commands in a file, one per line, without starting the interface"
+    )]
+    batch: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "MS",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Milliseconds between ticks, overriding tick_rate in the config"
+    )]
+    tick_rate: Option<u64>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Subcommands {
+    /// Print completions for the given shell
+    #[command(hide = true)]
+    Completions { shell: clap_complete::Shell },
 }
 
 fn main() -> Result<(), io::Error> {
     let args = Args::parse();
 
+    if let Some(Subcommands::Completions { shell }) = args.command {
+        clap_complete::generate(shell, &mut Args::command(), "trooper", &mut io::stdout());
+        return Ok(());
+    }
+
+    if args.print_default_config {
+        print!("{}", app::DEFAULT_CONFIG);
+        return Ok(());
+    }
+
+    if let Some(shell) = args.init {
+        print!("{}", shell::init_script(shell));
+        return Ok(());
+    }
+
+    if !args.send.is_empty() {
+        let socket = match args.pid {
+            Some(pid) => ipc::socket_path(pid),
+            None => ipc::find_instance().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, "No running trooper was found")
+            })?,
+        };
+        return ipc::send(&socket, &args.send);
+    }
+
     let logfile = FileAppender::builder()
         .encoder(Box::new(PatternEncoder::new("{d} [{l}] {m}\n")))
         .append(false)
-        .build("/tmp/trooper_log.txt")?;
+        .build(paths::log_file())?;
 
     let log_config = Config::builder()
         .appender(Appender::builder().build("logfile", Box::new(logfile)))
@@ -50,102 +166,233 @@ fn main() -> Result<(), io::Error> {
 
     log::info!("Starting trooper");
 
+    // A missing config.ini is fine, but one asked for by name is likely a typo
+    if let Some(config) = args.config.as_ref().filter(|c| !c.is_file()) {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No config file at {}", config.display()),
+        ));
+    }
+
+    // Being told where to start, or running a script, beats the setting
+    let restore = if args.restore {
+        Some(true)
+    } else if args.path.is_some() || args.select.is_some() || args.batch.is_some() {
+        Some(false)
+    } else {
+        None
+    };
+    let cwd = env::current_dir().unwrap_or_else(|_| paths::home_dir());
+    let start = args.path.map(|path| {
+        fs::canonicalize(cwd.join(&path))
+            .map(|path| paths::simplify(&path))
+            .unwrap_or(path)
+    });
+    // A file given in place of a directory is revealed as with --select
+    let (p, select) = match start {
+        Some(dir) if dir.is_dir() => (dir, args.select),
+        Some(file) => (cwd.clone(), args.select.or(Some(file))),
+        None => (cwd.clone(), args.select),
+    };
+    let options = Options {
+        deterministic: args.deterministic,
+        dry_run: args.dry_run,
+        read_only: args.read_only,
+        picking: args.choose_files.is_some() || args.stdout,
+        batch: args.batch.is_some(),
+        config: args.config,
+        tick_rate: args.tick_rate,
+        restore,
+    };
+
+    if let Some(script) = args.batch {
+        let script = fs::read_to_string(script)?;
+        let mut app = App::new(String::from("File Manager"), &p, options);
+        app.init();
+        if let Some(path) = select {
+            app.reveal(&cwd.join(path));
+        }
+        let result = app.run_batch(&script);
+        app.tear_down();
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     enable_raw_mode()?;
 
-    let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
+    /* With --stdout the interface is drawn straight on the terminal, leaving
+     * stdout free for the result as in `vim $(trooper --stdout)`.
+     */
+    let tty = if args.stdout {
+        Some(open_terminal()?)
+    } else {
+        None
+    };
+    let mut stdout: Box<dyn Write> = match &tty {
+        Some(tty) => Box::new(tty.try_clone()?),
+        None => Box::new(io::stdout()),
+    };
+    execute!(
+        stdout,
+        EnterAlternateScreen,
+        EnableMouseCapture,
+        EnableBracketedPaste
+    )?;
+    /* Terminals without support for the kitty keyboard protocol simply ignore
+     * the escape sequence, and the legacy Windows console returns an error. In
+     * both cases we keep going with the ambiguous legacy key codes, where
+     * <C-i> is Tab and neither <S-Enter> nor releases can be told apart.
+     */
+    let keyboard_enhanced = execute!(stdout, PushKeyboardEnhancementFlags(KEYBOARD_FLAGS)).is_ok();
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
-    let p = env::current_dir().unwrap_or(Path::new("/").to_path_buf());
-    let mut app = App::new(String::from("File Manager"), &p);
+    let mut app = App::new(String::from("File Manager"), &p, options);
     app.init();
-    run_app(&mut terminal, &mut app, Duration::from_millis(100))?;
+    app.listen();
+    if let Some(path) = select {
+        app.reveal(&cwd.join(path));
+    }
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?
+        .block_on(run_app(
+            &mut terminal,
+            &mut app,
+            keyboard_enhanced,
+            tty.as_ref(),
+        ))?;
 
+    if keyboard_enhanced {
+        execute!(terminal.backend_mut(), PopKeyboardEnhancementFlags)?;
+    }
     disable_raw_mode()?;
     execute!(
         terminal.backend_mut(),
         LeaveAlternateScreen,
         DisableMouseCapture,
+        DisableBracketedPaste,
     )?;
     terminal.show_cursor()?;
 
     match args.choose_dir {
         Some(p) => {
-            fs::write(p.as_path(), app.current_dir.to_str().unwrap_or("./"))?;
+            fs::write(p.as_path(), paths::os_to_bytes(app.current_dir.as_os_str()))?;
         }
         None => {}
     }
 
+    if let Some(p) = args.choose_files {
+        fs::write(p, path_list(&app.chosen))?;
+    }
+
+    if args.stdout {
+        let result = if app.chosen.is_empty() {
+            path_list(&[app.current_dir.to_path_buf()])
+        } else {
+            path_list(&app.chosen)
+        };
+        io::stdout().write_all(&result)?;
+    }
+
     Ok(())
 }
 
-fn run_app<B: Backend>(
+/// One path per line, as written by `--choose-files` and `--stdout`.
+fn path_list(paths: &[PathBuf]) -> Vec<u8> {
+    let mut list = Vec::new();
+    for path in paths {
+        list.extend(paths::os_to_bytes(path.as_os_str()));
+        list.push(b'\n');
+    }
+    list
+}
+
+#[cfg(unix)]
+fn open_terminal() -> io::Result<File> {
+    OpenOptions::new().read(true).write(true).open("/dev/tty")
+}
+
+#[cfg(windows)]
+fn open_terminal() -> io::Result<File> {
+    OpenOptions::new().read(true).write(true).open("CONOUT$")
+}
+
+/// What woke the event loop.
+enum Wakeup {
+    Input(Option<io::Result<Event>>),
+    Work,
+    Tick,
+}
+
+/* Background work wakes the loop as soon as it has something to show, so the
+ * tick is only needed for what follows the clock, such as the escape timeout
+ * and files being tailed.
+ */
+async fn run_app<B: Backend + Write>(
     terminal: &mut Terminal<B>,
     app: &mut App,
-    tick_rate: Duration,
+    keyboard_enhanced: bool,
+    tty: Option<&File>,
 ) -> io::Result<()> {
-    let mut last_tick = Instant::now();
+    let waker = app.waker();
+    let mut events = EventStream::new();
+    let mut ticks = tick_interval(app.tick_rate());
 
     loop {
         app.draw(terminal)?;
 
-        let timeout = tick_rate
-            .checked_sub(last_tick.elapsed())
-            .unwrap_or_else(|| Duration::from_secs(0));
-
-        if crossterm::event::poll(timeout)? {
-            if let Event::Key(key) = crossterm::event::read()? {
-                log::info!("Key pressed: {:?} {:?}", key.code, key.modifiers.bits());
-                match key.code {
-                    crossterm::event::KeyCode::Char(_) => {
-                        app.on_key(key);
-                    }
-                    /* app.on_key used to take a character instead of a KeyEvent,
-                     * thus, helper function were required for Key presses not
-                     * corresponding to a char. Is there any benefit of keeping
-                     * these as separate functions?
-                     */
-                    crossterm::event::KeyCode::Esc => {
-                        app.on_esc();
-                    }
-                    crossterm::event::KeyCode::Enter => {
-                        app.on_enter();
-                    }
-                    crossterm::event::KeyCode::Backspace => {
-                        app.on_backspace();
-                    }
-                    crossterm::event::KeyCode::Up => {
-                        app.on_up();
-                    }
-                    crossterm::event::KeyCode::Down => {
-                        app.on_down();
-                    }
-                    crossterm::event::KeyCode::Tab => {
-                        log::info!(
-                            "Tab key pressed: {:?} {:?}",
-                            key.modifiers.bits(),
-                            KeyModifiers::SHIFT
-                        );
-                        if key
-                            .modifiers
-                            .intersects(crossterm::event::KeyModifiers::SHIFT)
-                        {
-                        } else {
-                            app.on_tab();
-                        }
-                    }
-                    crossterm::event::KeyCode::BackTab => {
-                        app.on_shift_tab();
-                    }
-                    _ => {}
+        // The rate changes with :set tick_rate and while idling
+        if ticks.period() != app.tick_rate() {
+            ticks = tick_interval(app.tick_rate());
+        }
+
+        let wakeup = tokio::select! {
+            event = events.next() => Wakeup::Input(event),
+            _ = waker.woken() => Wakeup::Work,
+            _ = ticks.tick() => Wakeup::Tick,
+        };
+        let had_event = matches!(wakeup, Wakeup::Input(_));
+        match wakeup {
+            Wakeup::Input(event) => match event.transpose()? {
+                Some(Event::Key(key)) => {
+                    log::info!("Key pressed: {:?} {:?}", key.code, key.modifiers.bits());
+                    app.handle_event(key);
                 }
-            }
+                Some(Event::Paste(text)) => {
+                    app.on_paste(&text);
+                }
+                Some(Event::Mouse(mouse)) => app.on_mouse(mouse),
+                Some(Event::Resize(_, _)) => app.invalidate(),
+                Some(_) => {}
+                // The terminal is gone
+                None => app.should_quit = true,
+            },
+            Wakeup::Work | Wakeup::Tick => {}
         }
+        app.run_hooks();
 
-        if last_tick.elapsed() >= tick_rate {
+        if let Some(command) = app.take_shell_command() {
+            // The stream reads input in the background, which the command needs
+            drop(events);
+            let status = run_shell_command(terminal, &command, keyboard_enhanced, tty)?;
+            events = EventStream::new();
+            app.on_shell_command_done(status);
+        }
+
+        /* Ticking on the clock makes the outcome depend on how fast input
+         * arrives, so in deterministic mode every event is followed by exactly
+         * one tick instead.
+         */
+        if app.is_deterministic() {
+            if had_event {
+                app.on_tick();
+            }
+        } else if !had_event {
             app.on_tick();
-            last_tick = Instant::now();
         }
 
         if app.should_quit {
@@ -154,3 +401,57 @@ fn run_app<B: Backend>(
         }
     }
 }
+
+/// Ticks every `period`, starting after one has passed.
+fn tick_interval(period: Duration) -> Interval {
+    let mut ticks = tokio::time::interval_at(Instant::now() + period, period);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    ticks
+}
+
+/// Hands the terminal over to `command` until it exits and then restores the
+/// interface.
+fn run_shell_command<B: Backend + Write>(
+    terminal: &mut Terminal<B>,
+    command: &ShellCommand,
+    keyboard_enhanced: bool,
+    tty: Option<&File>,
+) -> io::Result<io::Result<ExitStatus>> {
+    if keyboard_enhanced {
+        execute!(terminal.backend_mut(), PopKeyboardEnhancementFlags)?;
+    }
+    execute!(
+        terminal.backend_mut(),
+        LeaveAlternateScreen,
+        DisableMouseCapture,
+        DisableBracketedPaste,
+    )?;
+    disable_raw_mode()?;
+    terminal.show_cursor()?;
+
+    let stdout = match tty {
+        Some(tty) => Stdio::from(tty.try_clone()?),
+        None => Stdio::inherit(),
+    };
+    let status = command.run(stdout);
+    write!(terminal.backend_mut(), "\nPress Enter to return to trooper")?;
+    Write::flush(terminal.backend_mut())?;
+    io::stdin().read_line(&mut String::new())?;
+
+    enable_raw_mode()?;
+    execute!(
+        terminal.backend_mut(),
+        EnterAlternateScreen,
+        EnableMouseCapture,
+        EnableBracketedPaste
+    )?;
+    if keyboard_enhanced {
+        execute!(
+            terminal.backend_mut(),
+            PushKeyboardEnhancementFlags(KEYBOARD_FLAGS)
+        )?;
+    }
+    terminal.clear()?;
+
+    Ok(status)
+}
@@ -0,0 +1,833 @@
+use std::collections::HashMap;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use crate::{app::AppActions, fuzzy};
+
+/// What the App should do once a mode has handled a key.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Outcome {
+    /// The mode stays active
+    Stay,
+    /// Return to normal mode without doing anything
+    Leave,
+    /// Return to normal mode and act on what was entered
+    Submit,
+    /// The keys typed complete a binding, whose action the App runs
+    Run(AppActions),
+    /// The keys typed are bound to nothing and are dropped, along with the
+    /// count typed before them
+    Unbound,
+}
+
+/// What a mode draws on top of the file list while it is active.
+#[derive(Debug, PartialEq)]
+pub enum View<'a> {
+    /// A line at the bottom of the screen starting with `prompt`, with the
+    /// completions above it
+    Line {
+        prompt: char,
+        text: &'a str,
+        /// Characters in `text` before the cursor
+        cursor: usize,
+        completions: &'a [String],
+        selected: Option<usize>,
+    },
+    /// A popup with the query above a list of labels and their keys
+    List {
+        title: &'static str,
+        query: &'a str,
+        /// Characters in `query` before the cursor
+        cursor: usize,
+        rows: Vec<(String, String)>,
+        selected: usize,
+    },
+}
+
+/* Normal and visual mode gather keys into chords until they match one of the
+ * bindings in the config. Modes which read text get every key instead. Either
+ * way each mode is a state machine of its own which the App only starts,
+ * feeds and stops.
+ */
+/// A mode which reads keys on its own, such as the command line.
+pub trait Mode {
+    /// Handles a key pressed while the mode is active.
+    fn handle_key(&mut self, key: KeyEvent) -> Outcome;
+
+    /// Inserts pasted text as a whole, so that none of it is taken for keys
+    /// which would end the mode.
+    fn paste(&mut self, text: &str);
+
+    /// What the mode adds to the screen, if anything.
+    fn view(&self) -> Option<View<'_>>;
+
+    /// The bindings of a mode driven by chords. Modes without them read text
+    /// and get every key.
+    fn bindings(&self) -> Option<&HashMap<Vec<KeyEvent>, AppActions>> {
+        None
+    }
+
+    /// The keys of a binding typed so far.
+    fn chord(&self) -> &[KeyEvent] {
+        &[]
+    }
+
+    /// Forgets the chord, once its action has run or it was interrupted.
+    fn clear_chord(&mut self) {}
+
+    /// Called when the mode becomes active.
+    fn on_enter(&mut self) {}
+
+    /// Called when the mode is left, whichever way that happens.
+    fn on_exit(&mut self) {}
+}
+
+/// A line of text being edited with the keys of readline: Left and Right,
+/// Home and End or `Ctrl-a` and `Ctrl-e` move the cursor, Backspace and
+/// Delete remove a character, `Ctrl-w` the word before the cursor and
+/// `Ctrl-u` everything before it.
+#[derive(Debug, Default, Clone)]
+pub struct Input {
+    text: String,
+    /// In bytes, always on a character boundary
+    cursor: usize,
+}
+
+impl Input {
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// How many characters come before the cursor.
+    pub fn cursor(&self) -> usize {
+        self.text[..self.cursor].chars().count()
+    }
+
+    /// Replaces the text, with the cursor at the end.
+    pub fn set(&mut self, text: String) {
+        self.cursor = text.len();
+        self.text = text;
+    }
+
+    pub fn clear(&mut self) {
+        self.set(String::new());
+    }
+
+    pub fn insert_str(&mut self, s: &str) {
+        self.text.insert_str(self.cursor, s);
+        self.cursor += s.len();
+    }
+
+    fn previous(&self) -> usize {
+        self.text[..self.cursor]
+            .char_indices()
+            .next_back()
+            .map_or(0, |(i, _)| i)
+    }
+
+    fn next(&self) -> usize {
+        self.text[self.cursor..]
+            .chars()
+            .next()
+            .map_or(self.cursor, |c| self.cursor + c.len_utf8())
+    }
+
+    /// Where the word before the cursor starts, skipping the spaces after it.
+    fn word_start(&self) -> usize {
+        let before = self.text[..self.cursor].trim_end();
+        before
+            .char_indices()
+            .rev()
+            .find(|(_, c)| c.is_whitespace())
+            .map_or(0, |(i, c)| i + c.len_utf8())
+    }
+
+    /// Edits the text for `key`, returning whether it was an editing key.
+    pub fn handle_key(&mut self, key: KeyEvent) -> bool {
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char('a') if control => self.cursor = 0,
+            KeyCode::Char('e') if control => self.cursor = self.text.len(),
+            KeyCode::Char('u') if control => {
+                self.text.drain(..self.cursor);
+                self.cursor = 0;
+            }
+            KeyCode::Char('w') if control => {
+                let start = self.word_start();
+                self.text.drain(start..self.cursor);
+                self.cursor = start;
+            }
+            KeyCode::Char(_) if control => return false,
+            KeyCode::Char(c) => self.insert_str(c.encode_utf8(&mut [0; 4])),
+            KeyCode::Backspace => {
+                let start = self.previous();
+                self.text.drain(start..self.cursor);
+                self.cursor = start;
+            }
+            KeyCode::Delete => {
+                let end = self.next();
+                self.text.drain(self.cursor..end);
+            }
+            KeyCode::Left => self.cursor = self.previous(),
+            KeyCode::Right => self.cursor = self.next(),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = self.text.len(),
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// The `:` command line, with completion of command names on Tab and the
+/// earlier lines on Up and Down. The `/` search line is one as well, with a
+/// history of its own and nothing to complete.
+#[derive(Debug)]
+pub struct CommandLine {
+    prompt: char,
+    /// The command names which are completed
+    pub commands: Vec<String>,
+    buffer: Input,
+    /// The buffer from before completing or going through the history
+    buffer_tmp: String,
+    history: Vec<String>,
+    history_index: Option<usize>,
+    matches: Vec<String>,
+    completion_index: Option<usize>,
+}
+
+impl Default for CommandLine {
+    fn default() -> Self {
+        CommandLine::new(':')
+    }
+}
+
+impl CommandLine {
+    pub fn new(prompt: char) -> CommandLine {
+        CommandLine {
+            prompt,
+            commands: Vec::new(),
+            buffer: Input::default(),
+            buffer_tmp: String::new(),
+            history: Vec::new(),
+            history_index: None,
+            matches: Vec::new(),
+            completion_index: None,
+        }
+    }
+
+    pub fn line(&self) -> &str {
+        self.buffer.text()
+    }
+
+    /// Replaces what has been typed, such as to fill in a command.
+    pub fn set_line(&mut self, line: String) {
+        self.stop_completing();
+        self.buffer.set(line);
+    }
+
+    /// The lines entered so far, oldest first.
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    /// Adds `line` to the history, unless it repeats the last line.
+    pub fn remember(&mut self, line: String) {
+        if self.history.last() != Some(&line) {
+            self.history.push(line);
+        }
+    }
+
+    fn stop_completing(&mut self) {
+        self.matches.clear();
+        self.buffer_tmp.clear();
+        self.completion_index = None;
+    }
+
+    /// Moves through the completions, where one step past either end is
+    /// what was typed before completing.
+    fn scroll_completion(&mut self, forward: bool) {
+        if self.completion_index.is_none() {
+            self.buffer_tmp = self.line().to_string();
+            self.matches = self
+                .commands
+                .iter()
+                .filter(|c| c.starts_with(self.buffer.text()))
+                .cloned()
+                .collect();
+            self.matches.sort();
+        }
+        let len = self.matches.len();
+        self.completion_index = match (self.completion_index, forward) {
+            (None, true) if len > 0 => Some(0),
+            (None, false) if len > 0 => Some(len - 1),
+            (Some(i), true) if i + 1 < len => Some(i + 1),
+            (Some(i), false) if i > 0 => Some(i - 1),
+            _ => None,
+        };
+        match self.completion_index {
+            Some(i) => self.buffer.set(self.matches[i].clone()),
+            None => self.buffer.set(std::mem::take(&mut self.buffer_tmp)),
+        }
+    }
+
+    /// Moves through the history, newest first. Going past the newest line
+    /// brings back what was being typed.
+    fn scroll_history(&mut self, older: bool) {
+        let len = self.history.len();
+        let index = match (self.history_index, older) {
+            (None, true) if len > 0 => {
+                self.buffer_tmp = self.line().to_string();
+                Some(0)
+            }
+            (Some(i), true) if i + 1 < len => Some(i + 1),
+            (Some(i), false) => i.checked_sub(1),
+            _ => return,
+        };
+        self.history_index = index;
+        match index {
+            Some(i) => self.buffer.set(self.history[len - i - 1].clone()),
+            None => self.buffer.set(std::mem::take(&mut self.buffer_tmp)),
+        }
+    }
+}
+
+impl Mode for CommandLine {
+    fn handle_key(&mut self, key: KeyEvent) -> Outcome {
+        match key.code {
+            KeyCode::Tab if !key.modifiers.intersects(KeyModifiers::SHIFT) => {
+                self.scroll_completion(true)
+            }
+            KeyCode::BackTab => self.scroll_completion(false),
+            KeyCode::Up if self.completion_index.is_none() => self.scroll_history(true),
+            KeyCode::Down if self.completion_index.is_none() => self.scroll_history(false),
+            // Esc and Enter end completing before they end the mode
+            KeyCode::Esc if self.completion_index.is_some() => {
+                self.buffer.set(self.buffer_tmp.clone());
+                self.stop_completing();
+            }
+            KeyCode::Enter if self.completion_index.is_some() => self.stop_completing(),
+            KeyCode::Esc => return Outcome::Leave,
+            KeyCode::Enter => {
+                self.remember(self.line().to_string());
+                return Outcome::Submit;
+            }
+            _ => {
+                if self.buffer.handle_key(key) {
+                    self.stop_completing();
+                }
+            }
+        }
+        Outcome::Stay
+    }
+
+    fn paste(&mut self, text: &str) {
+        self.stop_completing();
+        self.buffer.insert_str(text);
+    }
+
+    fn view(&self) -> Option<View<'_>> {
+        Some(View::Line {
+            prompt: self.prompt,
+            text: self.buffer.text(),
+            cursor: self.buffer.cursor(),
+            completions: &self.matches,
+            selected: self.completion_index,
+        })
+    }
+
+    fn on_enter(&mut self) {
+        self.buffer.clear();
+        self.history_index = None;
+    }
+
+    fn on_exit(&mut self) {
+        self.stop_completing();
+    }
+}
+
+/// An action offered by the palette, with the commands running it and the
+/// keys it is bound to in normal mode.
+#[derive(Debug, Clone)]
+pub struct PaletteEntry {
+    pub action: AppActions,
+    pub name: String,
+    pub commands: Vec<String>,
+    pub keys: Vec<String>,
+}
+
+/// The command palette, which fuzzy finds actions by name or command.
+#[derive(Debug, Default)]
+pub struct Palette {
+    /// Every action which can be found
+    pub entries: Vec<PaletteEntry>,
+    query: Input,
+    index: usize,
+}
+
+impl Palette {
+    /// The entries matching the query, best first.
+    pub fn matches(&self) -> Vec<&PaletteEntry> {
+        let mut matches: Vec<(i32, &PaletteEntry)> = self
+            .entries
+            .iter()
+            .filter_map(|e| {
+                let score = std::iter::once(&e.name)
+                    .chain(e.commands.iter())
+                    .filter_map(|n| fuzzy::score(self.query.text(), n))
+                    .max()?;
+                Some((score, e))
+            })
+            .collect();
+        matches.sort_by(|(a, x), (b, y)| b.cmp(a).then_with(|| x.name.cmp(&y.name)));
+        matches.into_iter().map(|(_, e)| e).collect()
+    }
+
+    pub fn selected(&self) -> Option<&PaletteEntry> {
+        self.matches().into_iter().nth(self.index)
+    }
+}
+
+impl Mode for Palette {
+    fn handle_key(&mut self, key: KeyEvent) -> Outcome {
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char('n') if control => self.handle_key(KeyEvent::from(KeyCode::Down)),
+            KeyCode::Char('p') if control => self.handle_key(KeyEvent::from(KeyCode::Up)),
+            KeyCode::Down => {
+                let count = self.matches().len();
+                self.index = std::cmp::min(self.index + 1, count.saturating_sub(1));
+                Outcome::Stay
+            }
+            KeyCode::Up => {
+                self.index = self.index.saturating_sub(1);
+                Outcome::Stay
+            }
+            KeyCode::Esc => Outcome::Leave,
+            KeyCode::Enter => Outcome::Submit,
+            _ => {
+                if self.query.handle_key(key) {
+                    self.index = 0;
+                }
+                Outcome::Stay
+            }
+        }
+    }
+
+    fn paste(&mut self, text: &str) {
+        self.query.insert_str(text);
+        self.index = 0;
+    }
+
+    fn view(&self) -> Option<View<'_>> {
+        let rows = self
+            .matches()
+            .into_iter()
+            .map(|e| {
+                let mut label = e.name.clone();
+                for c in &e.commands {
+                    label.push_str(&format!(" :{}", c));
+                }
+                (label, e.keys.join("  "))
+            })
+            .collect();
+        Some(View::List {
+            title: " Actions ",
+            query: self.query.text(),
+            cursor: self.query.cursor(),
+            rows,
+            selected: self.index,
+        })
+    }
+
+    fn on_enter(&mut self) {
+        self.query.clear();
+        self.index = 0;
+    }
+}
+
+/// Narrows the listing to the entries matching what is typed, as it is
+/// typed. The App does the narrowing after every key.
+#[derive(Debug, Default)]
+pub struct FilterLine {
+    query: Input,
+}
+
+impl FilterLine {
+    pub fn query(&self) -> &str {
+        self.query.text()
+    }
+}
+
+impl Mode for FilterLine {
+    fn handle_key(&mut self, key: KeyEvent) -> Outcome {
+        match key.code {
+            KeyCode::Esc => Outcome::Leave,
+            KeyCode::Enter => Outcome::Submit,
+            _ => {
+                self.query.handle_key(key);
+                Outcome::Stay
+            }
+        }
+    }
+
+    fn paste(&mut self, text: &str) {
+        self.query.insert_str(text);
+    }
+
+    fn view(&self) -> Option<View<'_>> {
+        Some(View::Line {
+            prompt: '>',
+            text: self.query.text(),
+            cursor: self.query.cursor(),
+            completions: &[],
+            selected: None,
+        })
+    }
+
+    fn on_enter(&mut self) {
+        self.query.clear();
+    }
+}
+
+/// Earlier command lines, newest first, found by fuzzy searching them. Enter
+/// runs the chosen line again, while `Ctrl-e` chooses it for editing first.
+#[derive(Debug, Default)]
+pub struct HistoryPicker {
+    /// The lines which can be chosen, newest first
+    pub lines: Vec<String>,
+    query: Input,
+    index: usize,
+    editing: bool,
+}
+
+impl HistoryPicker {
+    /// The lines matching the query, best first and otherwise newest first.
+    pub fn matches(&self) -> Vec<&String> {
+        let mut matches: Vec<(i32, &String)> = self
+            .lines
+            .iter()
+            .filter_map(|l| Some((fuzzy::score(self.query.text(), l)?, l)))
+            .collect();
+        // Stable, so equal scores keep their order
+        matches.sort_by(|(a, _), (b, _)| b.cmp(a));
+        matches.into_iter().map(|(_, l)| l).collect()
+    }
+
+    pub fn selected(&self) -> Option<&String> {
+        self.matches().into_iter().nth(self.index)
+    }
+
+    /// Whether the line was chosen with `Ctrl-e`, to be edited before it runs.
+    pub fn editing(&self) -> bool {
+        self.editing
+    }
+}
+
+impl Mode for HistoryPicker {
+    fn handle_key(&mut self, key: KeyEvent) -> Outcome {
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char('n') if control => self.handle_key(KeyEvent::from(KeyCode::Down)),
+            KeyCode::Char('p') if control => self.handle_key(KeyEvent::from(KeyCode::Up)),
+            KeyCode::Char('e') if control => {
+                self.editing = true;
+                Outcome::Submit
+            }
+            KeyCode::Down => {
+                let count = self.matches().len();
+                self.index = std::cmp::min(self.index + 1, count.saturating_sub(1));
+                Outcome::Stay
+            }
+            KeyCode::Up => {
+                self.index = self.index.saturating_sub(1);
+                Outcome::Stay
+            }
+            KeyCode::Esc => Outcome::Leave,
+            KeyCode::Enter => Outcome::Submit,
+            _ => {
+                if self.query.handle_key(key) {
+                    self.index = 0;
+                }
+                Outcome::Stay
+            }
+        }
+    }
+
+    fn paste(&mut self, text: &str) {
+        self.query.insert_str(text);
+        self.index = 0;
+    }
+
+    fn view(&self) -> Option<View<'_>> {
+        let rows = self
+            .matches()
+            .into_iter()
+            .map(|l| (format!(":{}", l), String::new()))
+            .collect();
+        Some(View::List {
+            title: " History ",
+            query: self.query.text(),
+            cursor: self.query.cursor(),
+            rows,
+            selected: self.index,
+        })
+    }
+
+    fn on_enter(&mut self) {
+        self.query.clear();
+        self.index = 0;
+        self.editing = false;
+    }
+}
+
+/// Normal or visual mode, which gathers keys into a chord until it is bound
+/// to an action in the config.
+#[derive(Debug, Default)]
+pub struct ChordMode {
+    pub bindings: HashMap<Vec<KeyEvent>, AppActions>,
+    /// The commands of the keys bound to `ShellBinding`
+    pub shell: HashMap<Vec<KeyEvent>, String>,
+    chord: Vec<KeyEvent>,
+}
+
+impl ChordMode {
+    pub fn new(
+        bindings: HashMap<Vec<KeyEvent>, AppActions>,
+        shell: HashMap<Vec<KeyEvent>, String>,
+    ) -> Self {
+        ChordMode {
+            bindings,
+            shell,
+            chord: Vec::new(),
+        }
+    }
+
+    /// The command bound to the chord typed, while its `ShellBinding` runs.
+    pub fn shell_command(&self) -> Option<&String> {
+        self.shell.get(&self.chord)
+    }
+}
+
+impl Mode for ChordMode {
+    fn handle_key(&mut self, key: KeyEvent) -> Outcome {
+        self.chord.push(key);
+        if let Some(action) = self.bindings.get(&self.chord) {
+            return Outcome::Run(*action);
+        }
+        if continuations(&self.bindings, &self.chord).is_empty() {
+            self.chord.clear();
+            return Outcome::Unbound;
+        }
+        Outcome::Stay
+    }
+
+    // Pastes are ignored rather than taken for keys
+    fn paste(&mut self, _text: &str) {}
+
+    fn view(&self) -> Option<View<'_>> {
+        None
+    }
+
+    fn bindings(&self) -> Option<&HashMap<Vec<KeyEvent>, AppActions>> {
+        Some(&self.bindings)
+    }
+
+    fn chord(&self) -> &[KeyEvent] {
+        &self.chord
+    }
+
+    fn clear_chord(&mut self) {
+        self.chord.clear();
+    }
+}
+
+/// The bindings which `chord` is the start of, but not all of.
+pub fn continuations<'a>(
+    bindings: &'a HashMap<Vec<KeyEvent>, AppActions>,
+    chord: &[KeyEvent],
+) -> Vec<(&'a [KeyEvent], AppActions)> {
+    bindings
+        .iter()
+        .filter(|(keys, _)| keys.len() > chord.len() && keys.starts_with(chord))
+        .map(|(keys, action)| (keys.as_slice(), *action))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+    use std::collections::HashMap;
+
+    use super::{
+        ChordMode, CommandLine, HistoryPicker, Input, Mode, Outcome, Palette, PaletteEntry, View,
+    };
+    use crate::app::AppActions;
+
+    fn type_keys(mode: &mut dyn Mode, keys: &[KeyCode]) -> Vec<Outcome> {
+        keys.iter()
+            .map(|k| mode.handle_key(KeyEvent::new(*k, KeyModifiers::empty())))
+            .collect()
+    }
+
+    fn text(mode: &dyn Mode) -> String {
+        match mode.view().unwrap() {
+            View::Line { text, .. } => text.to_string(),
+            View::List { query, .. } => query.to_string(),
+        }
+    }
+
+    #[test]
+    fn commands_are_completed_and_remembered() {
+        let mut line = CommandLine {
+            commands: vec![
+                String::from("delete"),
+                String::from("cd"),
+                String::from("dup"),
+            ],
+            ..Default::default()
+        };
+        line.on_enter();
+        type_keys(&mut line, &[KeyCode::Char('d'), KeyCode::Tab]);
+        assert_eq!(line.line(), "delete");
+        type_keys(&mut line, &[KeyCode::Tab, KeyCode::Tab]);
+        assert_eq!(line.line(), "d");
+        type_keys(&mut line, &[KeyCode::BackTab]);
+        assert_eq!(line.line(), "dup");
+        // Enter only accepts the completion
+        assert_eq!(type_keys(&mut line, &[KeyCode::Enter]), [Outcome::Stay]);
+        assert_eq!(type_keys(&mut line, &[KeyCode::Enter]), [Outcome::Submit]);
+        line.on_exit();
+
+        line.on_enter();
+        type_keys(&mut line, &[KeyCode::Char('c'), KeyCode::Up]);
+        assert_eq!(line.line(), "dup");
+        type_keys(&mut line, &[KeyCode::Down]);
+        assert_eq!(text(&line), "c");
+        assert_eq!(type_keys(&mut line, &[KeyCode::Esc]), [Outcome::Leave]);
+    }
+
+    #[test]
+    fn lines_are_edited_like_readline() {
+        let control = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL);
+        let mut input = Input::default();
+        input.set(String::from("mv old name"));
+        assert_eq!(input.cursor(), 11);
+
+        input.handle_key(control('w'));
+        assert_eq!(input.text(), "mv old ");
+        input.insert_str("névé");
+        for _ in 0..2 {
+            input.handle_key(KeyEvent::from(KeyCode::Left));
+        }
+        input.handle_key(KeyEvent::from(KeyCode::Backspace));
+        input.handle_key(KeyEvent::from(KeyCode::Char('x')));
+        input.handle_key(KeyEvent::from(KeyCode::Delete));
+        assert_eq!(input.text(), "mv old nxé");
+        assert_eq!(input.cursor(), 9);
+
+        input.handle_key(control('a'));
+        input.handle_key(KeyEvent::from(KeyCode::Delete));
+        assert_eq!(input.text(), "v old nxé");
+        input.handle_key(control('e'));
+        assert_eq!(input.cursor(), 9);
+        // Other control keys are left to the mode
+        assert!(!input.handle_key(control('n')));
+        input.handle_key(KeyEvent::from(KeyCode::Left));
+        input.handle_key(control('u'));
+        assert_eq!((input.text(), input.cursor()), ("é", 0));
+    }
+
+    #[test]
+    fn pastes_are_inserted_whole() {
+        let mut line = CommandLine {
+            commands: vec![String::from("cd")],
+            ..Default::default()
+        };
+        line.on_enter();
+        type_keys(&mut line, &[KeyCode::Char('c'), KeyCode::Tab]);
+        line.paste(" ~/My Documents");
+        assert_eq!(line.line(), "cd ~/My Documents");
+        // The completion was accepted, so Enter runs the line
+        assert_eq!(type_keys(&mut line, &[KeyCode::Enter]), [Outcome::Submit]);
+    }
+
+    #[test]
+    fn history_is_searched_newest_first() {
+        let mut picker = HistoryPicker {
+            lines: vec![
+                String::from("mkdir logs"),
+                String::from("cd /var/log"),
+                String::from("mkdir build"),
+            ],
+            ..Default::default()
+        };
+        picker.on_enter();
+        type_keys(&mut picker, &[KeyCode::Char('m'), KeyCode::Char('k')]);
+        assert_eq!(picker.matches(), ["mkdir logs", "mkdir build"]);
+        type_keys(&mut picker, &[KeyCode::Down]);
+        assert_eq!(picker.selected().unwrap(), "mkdir build");
+        assert!(!picker.editing());
+
+        let edit = KeyEvent::new(KeyCode::Char('e'), KeyModifiers::CONTROL);
+        assert_eq!(picker.handle_key(edit), Outcome::Submit);
+        assert!(picker.editing());
+        picker.on_enter();
+        assert!(!picker.editing());
+    }
+
+    #[test]
+    fn palette_finds_actions_by_name_or_command() {
+        let entry = |action, name: &str, command: &str| PaletteEntry {
+            action,
+            name: String::from(name),
+            commands: vec![String::from(command)],
+            keys: vec![],
+        };
+        let mut palette = Palette {
+            entries: vec![
+                entry(AppActions::MoveDown, "MoveDown", "down"),
+                entry(AppActions::DeleteFile, "DeleteFile", "rm"),
+            ],
+            ..Default::default()
+        };
+        palette.on_enter();
+        type_keys(&mut palette, &[KeyCode::Char('r'), KeyCode::Char('m')]);
+        assert_eq!(palette.matches().len(), 1);
+        assert_eq!(palette.selected().unwrap().action, AppActions::DeleteFile);
+
+        type_keys(&mut palette, &[KeyCode::Backspace, KeyCode::Backspace]);
+        assert_eq!(text(&palette), "");
+        type_keys(&mut palette, &[KeyCode::Down, KeyCode::Down]);
+        assert_eq!(palette.selected().unwrap().action, AppActions::MoveDown);
+        assert_eq!(
+            type_keys(&mut palette, &[KeyCode::Enter]),
+            [Outcome::Submit]
+        );
+    }
+
+    #[test]
+    fn chords_run_their_action_once_complete() {
+        let keys = |s: &str| s.chars().map(|c| KeyEvent::from(KeyCode::Char(c))).collect();
+        let mut normal = ChordMode::new(
+            HashMap::from([(keys("gg"), AppActions::MoveToTop)]),
+            HashMap::new(),
+        );
+        let chars = |s: &str| s.chars().map(KeyCode::Char).collect::<Vec<_>>();
+        assert_eq!(type_keys(&mut normal, &chars("g")), [Outcome::Stay]);
+        assert_eq!(normal.chord(), keys("g").as_slice());
+        assert_eq!(
+            type_keys(&mut normal, &chars("g")),
+            [Outcome::Run(AppActions::MoveToTop)]
+        );
+        // The chord stays until its action has run
+        assert_eq!(normal.chord().len(), 2);
+        normal.clear_chord();
+        assert_eq!(
+            type_keys(&mut normal, &chars("gx")),
+            [Outcome::Stay, Outcome::Unbound]
+        );
+        assert!(normal.chord().is_empty());
+        assert!(normal.view().is_none());
+    }
+}
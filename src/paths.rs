@@ -0,0 +1,161 @@
+use std::{
+    ffi::{OsStr, OsString},
+    path::{Path, PathBuf},
+};
+
+/// The home directory, or the root of the file system if there is none.
+pub fn home_dir() -> PathBuf {
+    dirs::home_dir().unwrap_or_else(root_dir)
+}
+
+#[cfg(windows)]
+pub fn root_dir() -> PathBuf {
+    PathBuf::from(std::env::var("SystemDrive").unwrap_or(String::from("C:")) + "\\")
+}
+
+#[cfg(not(windows))]
+pub fn root_dir() -> PathBuf {
+    PathBuf::from("/")
+}
+
+/// Where config.ini is read from, `~/.config/trooper` on Unix and
+/// `%APPDATA%\trooper` on Windows. Older versions used the Unix location
+/// everywhere, so it is kept on Windows when it already exists.
+pub fn config_dir() -> PathBuf {
+    let unix_style = home_dir().join(".config").join("trooper");
+    if cfg!(windows) && !unix_style.exists() {
+        dirs::config_dir()
+            .unwrap_or_else(std::env::temp_dir)
+            .join("trooper")
+    } else {
+        unix_style
+    }
+}
+
+/// The config file in `config_dir`, which is `config.toml` if there is one
+/// and `config.ini` otherwise.
+pub fn config_file() -> PathBuf {
+    let toml = config_dir().join("config.toml");
+    if toml.exists() {
+        toml
+    } else {
+        config_dir().join("config.ini")
+    }
+}
+
+pub fn bookmark_file() -> PathBuf {
+    dirs::home_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join(".trooper")
+        .join("bookmarks.txt")
+}
+
+/// Where what is remembered between sessions, such as the width of the
+/// bookmark panel, is kept.
+pub fn state_file() -> PathBuf {
+    dirs::home_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join(".trooper")
+        .join("state.json")
+}
+
+pub fn yank_register() -> PathBuf {
+    std::env::temp_dir().join("rust_fm_yank.txt")
+}
+
+pub fn log_file() -> PathBuf {
+    std::env::temp_dir().join("trooper_log.txt")
+}
+
+/// Expands a leading `~` to the home directory.
+pub fn expand_home(path: &str) -> PathBuf {
+    match path.strip_prefix('~') {
+        Some("") => home_dir(),
+        Some(rest) if rest.starts_with(std::path::is_separator) => {
+            home_dir().join(rest.trim_start_matches(std::path::is_separator))
+        }
+        _ => PathBuf::from(path),
+    }
+}
+
+/// The raw bytes of `s`, for writing paths to files without requiring them
+/// to be valid UTF-8. Windows paths are written as UTF-8, replacing any
+/// unpaired surrogates.
+#[cfg(unix)]
+pub fn os_to_bytes(s: &OsStr) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    s.as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+pub fn os_to_bytes(s: &OsStr) -> Vec<u8> {
+    s.to_string_lossy().into_owned().into_bytes()
+}
+
+#[cfg(unix)]
+pub fn os_from_bytes(bytes: &[u8]) -> OsString {
+    use std::os::unix::ffi::OsStrExt;
+    OsStr::from_bytes(bytes).to_owned()
+}
+
+#[cfg(not(unix))]
+pub fn os_from_bytes(bytes: &[u8]) -> OsString {
+    OsString::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/* Canonical paths on Windows are verbatim paths such as \\?\C:\Users, which
+ * look odd in the title and are rejected by cmd.exe as working directories.
+ * Ordinary drive paths are turned back into their usual form.
+ */
+#[cfg(windows)]
+pub fn simplify(path: &Path) -> PathBuf {
+    use std::path::{Component, Prefix};
+
+    let mut components = path.components();
+    match components.next() {
+        Some(Component::Prefix(p)) => match p.kind() {
+            Prefix::VerbatimDisk(drive) => {
+                let mut simple = PathBuf::from(format!("{}:\\", drive as char));
+                simple.extend(components.filter(|c| *c != Component::RootDir));
+                simple
+            }
+            _ => path.to_path_buf(),
+        },
+        _ => path.to_path_buf(),
+    }
+}
+
+#[cfg(not(windows))]
+pub fn simplify(path: &Path) -> PathBuf {
+    path.to_path_buf()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{expand_home, home_dir};
+
+    #[test]
+    fn home_is_only_expanded_at_the_start() {
+        assert_eq!(expand_home("~"), home_dir());
+        assert_eq!(expand_home("~/notes"), home_dir().join("notes"));
+        assert_eq!(expand_home("~notes"), Path::new("~notes"));
+        assert_eq!(expand_home("notes/~"), Path::new("notes/~"));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn verbatim_drive_paths_are_simplified() {
+        use super::simplify;
+
+        assert_eq!(
+            simplify(Path::new(r"\\?\C:\Users\trooper")),
+            Path::new(r"C:\Users\trooper")
+        );
+        assert_eq!(
+            simplify(Path::new(r"\\?\UNC\server\share")),
+            Path::new(r"\\?\UNC\server\share")
+        );
+    }
+}
@@ -0,0 +1,148 @@
+/// Applies a chmod style mode, either octal such as `644` or symbolic such as
+/// `u+x,go-w`, to the permission bits `mode`.
+pub fn apply_mode(spec: &str, mode: u32, is_dir: bool) -> Result<u32, String> {
+    if !spec.is_empty() && spec.chars().all(|c| c.is_digit(8)) {
+        return u32::from_str_radix(spec, 8)
+            .ok()
+            .filter(|m| *m <= 0o7777)
+            .ok_or(format!("Invalid mode: {}", spec));
+    }
+
+    let mut mode = mode;
+    for clause in spec.split(',') {
+        let who_len = clause
+            .find(|c| !matches!(c, 'u' | 'g' | 'o' | 'a'))
+            .ok_or(format!("Invalid mode: {}", spec))?;
+        let mut who = 0;
+        for c in clause[..who_len].chars() {
+            who |= match c {
+                'u' => 0o4700,
+                'g' => 0o2070,
+                'o' => 0o1007,
+                _ => 0o7777,
+            };
+        }
+        if who == 0 {
+            who = 0o7777;
+        }
+
+        let mut ops = clause[who_len..].chars().peekable();
+        if ops.peek().is_none() {
+            return Err(format!("Invalid mode: {}", spec));
+        }
+        while let Some(op) = ops.next() {
+            if !matches!(op, '+' | '-' | '=') {
+                return Err(format!("Invalid mode: {}", spec));
+            }
+            let mut perms = 0;
+            while let Some(c) = ops.next_if(|c| !matches!(c, '+' | '-' | '=')) {
+                perms |= match c {
+                    'r' => 0o444,
+                    'w' => 0o222,
+                    'x' => 0o111,
+                    // Execute only for directories and files already executable by someone
+                    'X' if is_dir || mode & 0o111 != 0 => 0o111,
+                    'X' => 0,
+                    's' => 0o6000,
+                    't' => 0o1000,
+                    _ => return Err(format!("Invalid mode: {}", spec)),
+                };
+            }
+            let bits = perms & who;
+            match op {
+                '+' => mode |= bits,
+                '-' => mode &= !bits,
+                _ => mode = (mode & !who) | bits,
+            }
+        }
+    }
+
+    Ok(mode)
+}
+
+/// Resolves an owner spec such as `user`, `user:group` or `:group` to user and
+/// group ids. Names are looked up in the users database and plain numbers are
+/// taken as ids.
+#[cfg(unix)]
+pub fn parse_owner(spec: &str) -> Result<(Option<u32>, Option<u32>), String> {
+    let (user, group) = match spec.split_once(':') {
+        Some((user, group)) => (user, group),
+        None => (spec, ""),
+    };
+    if user.is_empty() && group.is_empty() {
+        return Err(format!("Invalid owner: {}", spec));
+    }
+
+    let uid = match user {
+        "" => None,
+        _ => Some(user_id(user).ok_or(format!("Unknown user: {}", user))?),
+    };
+    let gid = match group {
+        "" => None,
+        _ => Some(group_id(group).ok_or(format!("Unknown group: {}", group))?),
+    };
+    Ok((uid, gid))
+}
+
+#[cfg(unix)]
+fn user_id(name: &str) -> Option<u32> {
+    if let Ok(id) = name.parse() {
+        return Some(id);
+    }
+    let c_name = std::ffi::CString::new(name).ok()?;
+    let pw = unsafe { libc::getpwnam(c_name.as_ptr()) };
+    if pw.is_null() {
+        None
+    } else {
+        Some(unsafe { (*pw).pw_uid })
+    }
+}
+
+#[cfg(unix)]
+fn group_id(name: &str) -> Option<u32> {
+    if let Ok(id) = name.parse() {
+        return Some(id);
+    }
+    let c_name = std::ffi::CString::new(name).ok()?;
+    let gr = unsafe { libc::getgrnam(c_name.as_ptr()) };
+    if gr.is_null() {
+        None
+    } else {
+        Some(unsafe { (*gr).gr_gid })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn octal_modes_replace_the_permissions() {
+        assert_eq!(apply_mode("644", 0o777, false), Ok(0o644));
+        assert_eq!(apply_mode("4755", 0o644, false), Ok(0o4755));
+        assert!(apply_mode("17777", 0o644, false).is_err());
+    }
+
+    #[test]
+    fn symbolic_modes_modify_the_permissions() {
+        assert_eq!(apply_mode("u+x", 0o644, false), Ok(0o744));
+        assert_eq!(apply_mode("go-w", 0o666, false), Ok(0o644));
+        assert_eq!(apply_mode("+x", 0o644, false), Ok(0o755));
+        assert_eq!(apply_mode("u=rw,o=", 0o777, false), Ok(0o670));
+        assert_eq!(apply_mode("u+x-w", 0o644, false), Ok(0o544));
+        assert_eq!(apply_mode("a+X", 0o644, true), Ok(0o755));
+        assert_eq!(apply_mode("a+X", 0o644, false), Ok(0o644));
+        assert!(apply_mode("u+q", 0o644, false).is_err());
+        assert!(apply_mode("u", 0o644, false).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn owners_are_resolved_by_name_or_id() {
+        assert_eq!(parse_owner("root"), Ok((Some(0), None)));
+        assert_eq!(parse_owner("1000:100"), Ok((Some(1000), Some(100))));
+        assert_eq!(parse_owner(":100"), Ok((None, Some(100))));
+        assert!(parse_owner(":").is_err());
+        assert!(parse_owner("no-such-user-here").is_err());
+    }
+}
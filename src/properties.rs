@@ -0,0 +1,221 @@
+use std::{
+    fs::{self, File, Metadata},
+    io::{self, Read},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::disk::human_size;
+
+/// Labelled metadata of `path`, in the order it is displayed.
+pub fn properties(path: &Path) -> io::Result<Vec<(&'static str, String)>> {
+    let md = fs::symlink_metadata(path)?;
+    let is_symlink = md.file_type().is_symlink();
+    let absolute = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir()?.join(path)
+    };
+
+    let mut rows = vec![("Path", absolute.display().to_string())];
+    let kind = if is_symlink {
+        "Symbolic link"
+    } else if md.is_dir() {
+        "Directory"
+    } else if md.is_file() {
+        "File"
+    } else {
+        "Special file"
+    };
+    rows.push(("Type", String::from(kind)));
+    if is_symlink {
+        let target = fs::read_link(path)?;
+        rows.push(("Target", target.display().to_string()));
+    }
+    rows.push((
+        "Size",
+        format!("{} ({} bytes)", human_size(md.len()), md.len()),
+    ));
+    rows.extend(ownership(&md));
+    rows.push(("Modified", format_time(md.modified())));
+    rows.push(("Accessed", format_time(md.accessed())));
+    rows.push(("Created", format_time(md.created())));
+    rows.push(("MIME type", String::from(mime_type(path, &md))));
+
+    Ok(rows)
+}
+
+#[cfg(unix)]
+fn ownership(md: &Metadata) -> Vec<(&'static str, String)> {
+    use std::{ffi::CStr, os::unix::fs::MetadataExt};
+
+    let mode = md.mode();
+    let mut permissions = String::new();
+    for shift in [6, 3, 0] {
+        let bits = mode >> shift;
+        permissions.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        permissions.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        permissions.push(if bits & 0o1 != 0 { 'x' } else { '-' });
+    }
+
+    /* getpwuid and getgrgid return pointers into static storage, which is
+     * fine as long as only the ui thread looks up names.
+     */
+    let owner = unsafe {
+        let pw = libc::getpwuid(md.uid());
+        if pw.is_null() {
+            md.uid().to_string()
+        } else {
+            CStr::from_ptr((*pw).pw_name).to_string_lossy().into_owned()
+        }
+    };
+    let group = unsafe {
+        let gr = libc::getgrgid(md.gid());
+        if gr.is_null() {
+            md.gid().to_string()
+        } else {
+            CStr::from_ptr((*gr).gr_name).to_string_lossy().into_owned()
+        }
+    };
+
+    vec![
+        (
+            "Permissions",
+            format!("{} ({:04o})", permissions, mode & 0o7777),
+        ),
+        ("Owner", owner),
+        ("Group", group),
+    ]
+}
+
+#[cfg(not(unix))]
+fn ownership(md: &Metadata) -> Vec<(&'static str, String)> {
+    let permissions = if md.permissions().readonly() {
+        "Read-only"
+    } else {
+        "Read-write"
+    };
+    vec![("Permissions", String::from(permissions))]
+}
+
+fn format_time(time: io::Result<SystemTime>) -> String {
+    match time.map(|t| t.duration_since(UNIX_EPOCH)) {
+        Ok(Ok(since_epoch)) => format_timestamp(since_epoch.as_secs()),
+        _ => String::from("Unknown"),
+    }
+}
+
+/// Formats seconds since the epoch as `YYYY-MM-DD HH:MM:SS UTC`.
+fn format_timestamp(secs: u64) -> String {
+    // Converts days since the epoch to a date in the proleptic Gregorian
+    // calendar, following http://howardhinnant.github.io/date_algorithms.html
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    let time_of_day = secs % 86400;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        time_of_day / 3600,
+        time_of_day / 60 % 60,
+        time_of_day % 60
+    )
+}
+
+/// Guesses the MIME type from the first bytes of the file, falling back to
+/// the extension.
+fn mime_type(path: &Path, md: &Metadata) -> &'static str {
+    if md.file_type().is_symlink() {
+        return "inode/symlink";
+    } else if md.is_dir() {
+        return "inode/directory";
+    } else if !md.is_file() {
+        return "inode/x-special";
+    } else if md.len() == 0 {
+        return "inode/x-empty";
+    }
+
+    let mut head = [0; 512];
+    let len = File::open(path)
+        .and_then(|mut f| f.read(&mut head))
+        .unwrap_or(0);
+    let head = &head[..len];
+
+    const MAGIC: [(&[u8], &str); 10] = [
+        (b"\x89PNG", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF8", "image/gif"),
+        (b"%PDF", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"BZh", "application/x-bzip2"),
+        (b"\xfd7zXZ\x00", "application/x-xz"),
+        (b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+        (b"\x7fELF", "application/x-executable"),
+    ];
+    for (magic, mime) in MAGIC {
+        if head.starts_with(magic) {
+            return mime;
+        }
+    }
+
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase());
+    let by_extension = match extension.as_deref() {
+        Some("html" | "htm") => Some("text/html"),
+        Some("css") => Some("text/css"),
+        Some("js") => Some("text/javascript"),
+        Some("json") => Some("application/json"),
+        Some("xml") => Some("application/xml"),
+        Some("md") => Some("text/markdown"),
+        Some("csv") => Some("text/csv"),
+        Some("svg") => Some("image/svg+xml"),
+        Some("mp3") => Some("audio/mpeg"),
+        Some("mp4") => Some("video/mp4"),
+        _ => None,
+    };
+    if let Some(mime) = by_extension {
+        return mime;
+    }
+
+    if head.starts_with(b"#!") {
+        "text/x-shellscript"
+    } else if !head.contains(&0) && is_utf8(head) {
+        "text/plain"
+    } else {
+        "application/octet-stream"
+    }
+}
+
+/// Like `str::from_utf8(bytes).is_ok()`, but accepts a multibyte character
+/// cut off at the end.
+fn is_utf8(bytes: &[u8]) -> bool {
+    match std::str::from_utf8(bytes) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_timestamp;
+
+    #[test]
+    fn timestamps_are_formatted_as_utc_dates() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_timestamp(951782400), "2000-02-29 00:00:00 UTC");
+        assert_eq!(format_timestamp(1700000000), "2023-11-14 22:13:20 UTC");
+    }
+}
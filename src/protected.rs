@@ -0,0 +1,120 @@
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::{glob, paths};
+
+/// What happens when deleting or overwriting a protected path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protection {
+    /// The action has to be repeated before it is carried out
+    Confirm,
+    /// The action is not carried out at all
+    Refuse,
+}
+
+impl Protection {
+    pub fn parse(s: &str) -> Option<Protection> {
+        match s.trim().to_lowercase().as_str() {
+            "confirm" => Some(Protection::Confirm),
+            "refuse" => Some(Protection::Refuse),
+            _ => None,
+        }
+    }
+}
+
+/// Paths guarded against deletion and overwrites, read from the `[protected]`
+/// section of the config.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProtectedPaths {
+    rules: Vec<(PathBuf, Protection)>,
+}
+
+impl ProtectedPaths {
+    pub fn insert(&mut self, pattern: &str, protection: Protection) {
+        let pattern = paths::expand_home(pattern);
+        self.rules.retain(|(p, _)| *p != pattern);
+        self.rules.push((pattern, protection));
+    }
+
+    /// The strictest protection of any of `paths`, along with the path it
+    /// applies to. Deleting a directory deletes everything in it, so the
+    /// directories holding protected paths are protected as well.
+    pub fn protection<'a>(&self, paths: &'a [PathBuf]) -> Option<(Protection, &'a Path)> {
+        paths
+            .iter()
+            .filter_map(|path| {
+                self.rules
+                    .iter()
+                    .filter(|(pattern, _)| {
+                        glob::matches_path(pattern, path)
+                            || literal_prefix(pattern).starts_with(path)
+                    })
+                    .map(|(_, protection)| (*protection, path.as_path()))
+                    .max_by_key(|(protection, _)| *protection)
+            })
+            .max_by_key(|(protection, _)| *protection)
+    }
+}
+
+/// The leading components of `pattern` without wildcards, which every path
+/// it matches is in.
+fn literal_prefix(pattern: &Path) -> PathBuf {
+    pattern
+        .components()
+        .take_while(|c| !c.as_os_str().to_string_lossy().contains(['*', '?']))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{ProtectedPaths, Protection};
+
+    #[test]
+    fn refusing_wins_over_confirming() {
+        let mut protected = ProtectedPaths::default();
+        protected.insert("/srv/**", Protection::Confirm);
+        protected.insert("/srv/backups/**", Protection::Refuse);
+
+        let paths = [PathBuf::from("/tmp/a"), PathBuf::from("/srv/www")];
+        assert_eq!(
+            protected.protection(&paths),
+            Some((Protection::Confirm, Path::new("/srv/www")))
+        );
+        let paths = [PathBuf::from("/srv/www"), PathBuf::from("/srv/backups/1")];
+        assert_eq!(
+            protected.protection(&paths),
+            Some((Protection::Refuse, Path::new("/srv/backups/1")))
+        );
+        assert_eq!(protected.protection(&[PathBuf::from("/tmp/a")]), None);
+    }
+
+    #[test]
+    fn directories_holding_protected_paths_are_protected() {
+        let mut protected = ProtectedPaths::default();
+        protected.insert("/home/user/Documents/**", Protection::Refuse);
+        protected.insert("/srv/*.db", Protection::Confirm);
+
+        for parent in ["/home/user/Documents", "/home/user", "/"] {
+            let paths = [PathBuf::from(parent)];
+            assert_eq!(
+                protected.protection(&paths),
+                Some((Protection::Refuse, Path::new(parent)))
+            );
+        }
+        let paths = [PathBuf::from("/srv")];
+        assert_eq!(
+            protected.protection(&paths),
+            Some((Protection::Confirm, Path::new("/srv")))
+        );
+        // Siblings are not in the way
+        assert_eq!(
+            protected.protection(&[PathBuf::from("/home/user/Music")]),
+            None
+        );
+        assert_eq!(protected.protection(&[PathBuf::from("/home/use")]), None);
+    }
+}
@@ -0,0 +1,230 @@
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    net::TcpStream,
+    path::{Path, PathBuf},
+};
+
+use ssh2::{CheckResult, FileStat, KnownHostFileKind, OpenFlags, OpenType, Session, Sftp};
+
+use crate::{
+    paths,
+    vfs::{self, Entry, Vfs},
+};
+
+/// A remote location given to `:sftp` as `[user@]host[:path]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub user: String,
+    pub host: String,
+    /// Relative paths start in the home directory of the user
+    pub path: Option<PathBuf>,
+}
+
+impl Target {
+    pub fn parse(spec: &str) -> Option<Target> {
+        let (user, rest) = match spec.split_once('@') {
+            Some((user, rest)) => (String::from(user), rest),
+            None => (std::env::var("USER").ok()?, spec),
+        };
+        let (host, path) = match rest.split_once(':') {
+            Some((host, path)) if !path.is_empty() => (host, Some(PathBuf::from(path))),
+            Some((host, _)) => (host, None),
+            None => (rest, None),
+        };
+        if user.is_empty() || host.is_empty() {
+            return None;
+        }
+        Some(Target {
+            user,
+            host: String::from(host),
+            path,
+        })
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}", self.user, self.host)
+    }
+}
+
+/// Files on a remote host, reached over SSH.
+pub struct SftpFs {
+    sftp: Sftp,
+    // The SFTP channel only works while its session is alive
+    _session: Session,
+}
+
+impl SftpFs {
+    /* The host key has to be known already since there is nowhere to ask
+     * whether a new one should be trusted. Authentication uses the SSH agent
+     * and falls back to the default key files.
+     */
+    pub fn connect(target: &Target) -> io::Result<SftpFs> {
+        let mut session = Session::new()?;
+        session.set_tcp_stream(TcpStream::connect((target.host.as_str(), 22))?);
+        session.handshake()?;
+
+        let (key, _) = session
+            .host_key()
+            .ok_or(io::Error::other("No host key"))?;
+        let mut known_hosts = session.known_hosts()?;
+        let ssh_dir = paths::home_dir().join(".ssh");
+        // A missing file just means that no host is known
+        let _ = known_hosts.read_file(&ssh_dir.join("known_hosts"), KnownHostFileKind::OpenSSH);
+        match known_hosts.check(&target.host, key) {
+            CheckResult::Match => {}
+            CheckResult::Mismatch => {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("Host key of {} has changed", target.host),
+                ))
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("{} is not in ~/.ssh/known_hosts", target.host),
+                ))
+            }
+        }
+
+        if session.userauth_agent(&target.user).is_err() {
+            for key in ["id_ed25519", "id_ecdsa", "id_rsa"] {
+                let key = ssh_dir.join(key);
+                if key.exists()
+                    && session
+                        .userauth_pubkey_file(&target.user, None, &key, None)
+                        .is_ok()
+                {
+                    break;
+                }
+            }
+        }
+        if !session.authenticated() {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("Could not authenticate as {}", target.user),
+            ));
+        }
+
+        Ok(SftpFs {
+            sftp: session.sftp()?,
+            _session: session,
+        })
+    }
+
+    /// The absolute path of `path` on the remote host.
+    pub fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        Ok(self.sftp.realpath(path)?)
+    }
+
+    fn to_entry(&self, path: PathBuf, stat: &FileStat) -> Entry {
+        let is_symlink = stat.file_type().is_symlink();
+        let target = if is_symlink {
+            self.sftp.stat(&path).ok()
+        } else {
+            Some(stat.clone())
+        };
+        let perm = target.as_ref().and_then(|t| t.perm).unwrap_or(0);
+
+        Entry {
+            is_dir: target.as_ref().is_some_and(|t| t.is_dir()),
+            is_symlink,
+            link_target: if is_symlink {
+                self.sftp.readlink(&path).ok()
+            } else {
+                None
+            },
+            broken_link: is_symlink && target.is_none(),
+            len: stat.size.unwrap_or(0),
+            executable: target.as_ref().is_some_and(|t| t.is_file()) && perm & 0o111 != 0,
+            // Without knowing our groups on the remote host this is a guess
+            read_only: perm & 0o222 == 0,
+            hidden: vfs::is_dotfile(&path),
+            ignored: false,
+            path,
+        }
+    }
+}
+
+impl Vfs for SftpFs {
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<Entry>> {
+        Ok(self
+            .sftp
+            .readdir(dir)?
+            .into_iter()
+            .map(|(path, stat)| self.to_entry(path, &stat))
+            .collect())
+    }
+
+    fn entry(&self, path: &Path) -> io::Result<Entry> {
+        let stat = self.sftp.lstat(path)?;
+        Ok(self.to_entry(path.to_path_buf(), &stat))
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        let mut missing: Vec<&Path> = path
+            .ancestors()
+            .take_while(|p| self.sftp.lstat(p).is_err())
+            .collect();
+        missing.reverse();
+        for p in missing {
+            self.sftp.mkdir(p, 0o755)?;
+        }
+        Ok(())
+    }
+
+    fn create_file(&self, path: &Path) -> io::Result<()> {
+        self.sftp.open_mode(
+            path,
+            OpenFlags::WRITE | OpenFlags::APPEND | OpenFlags::CREATE,
+            0o644,
+            OpenType::File,
+        )?;
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        Ok(self.sftp.rename(from, to, None)?)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + '_>> {
+        Ok(Box::new(self.sftp.open(path)?))
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn Write + '_>> {
+        Ok(Box::new(self.sftp.create(path)?))
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        let stat = self.sftp.lstat(path)?;
+        if stat.is_dir() {
+            for (child, _) in self.sftp.readdir(path)? {
+                self.remove(&child)?;
+            }
+            Ok(self.sftp.rmdir(path)?)
+        } else {
+            Ok(self.sftp.unlink(path)?)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::Target;
+
+    #[test]
+    fn targets_are_parsed_with_optional_paths() {
+        let target = Target::parse("alice@example.com:/srv/www").unwrap();
+        assert_eq!(target.user, "alice");
+        assert_eq!(target.host, "example.com");
+        assert_eq!(target.path, Some(PathBuf::from("/srv/www")));
+        assert_eq!(target.to_string(), "alice@example.com");
+
+        assert_eq!(Target::parse("alice@example.com:").unwrap().path, None);
+        assert!(Target::parse("alice@").is_none());
+    }
+}
@@ -0,0 +1,318 @@
+use std::{
+    ffi::{OsStr, OsString},
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::paths;
+
+/// A command entered with `:sh` or `:!`, together with the environment
+/// describing the state of trooper when it was entered.
+pub struct ShellCommand {
+    pub command: OsString,
+    env: Vec<(&'static str, OsString)>,
+    list_file: PathBuf,
+}
+
+static LISTS: AtomicUsize = AtomicUsize::new(0);
+
+impl ShellCommand {
+    /* Environment variables can't hold NUL bytes, so the NUL-separated
+     * selection is written to a file whose path is exported instead. Unlike
+     * TROOPER_SELECTED it survives file names containing newlines.
+     */
+    pub fn new(
+        command: impl AsRef<OsStr>,
+        cwd: &Path,
+        selected: &[PathBuf],
+    ) -> io::Result<ShellCommand> {
+        let dir = private_dir()?;
+        // A trooper which crashed may have left a list with our process id
+        let (list_file, mut file) = loop {
+            let n = LISTS.fetch_add(1, Ordering::Relaxed);
+            let path = dir.join(format!("selection-{}-{}", std::process::id(), n));
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => break (path, file),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        };
+        let mut list = Vec::new();
+        for p in selected {
+            list.extend_from_slice(&paths::os_to_bytes(p.as_os_str()));
+            list.push(0);
+        }
+        if let Err(e) = file.write_all(&list) {
+            let _ = fs::remove_file(&list_file);
+            return Err(e);
+        }
+
+        let mut lines = OsString::new();
+        for (i, p) in selected.iter().enumerate() {
+            if i > 0 {
+                lines.push("\n");
+            }
+            lines.push(p.as_os_str());
+        }
+
+        Ok(ShellCommand {
+            command: command.as_ref().to_owned(),
+            env: vec![
+                ("TROOPER_CWD", cwd.as_os_str().to_owned()),
+                ("TROOPER_SELECTED", lines),
+                ("TROOPER_SELECTED_LIST", list_file.as_os_str().to_owned()),
+                ("TROOPER_SELECTED_COUNT", selected.len().to_string().into()),
+            ],
+            list_file,
+        })
+    }
+
+    /// Runs the command in the platform shell, inheriting the terminal. Its
+    /// output goes to `stdout`, which differs from our own with `--stdout`.
+    pub fn run(&self, stdout: Stdio) -> io::Result<ExitStatus> {
+        let status = self.shell(&self.command).stdout(stdout).status();
+        let _ = fs::remove_file(&self.list_file);
+        status
+    }
+
+    /// Runs the command to completion without any output reaching the
+    /// terminal.
+    pub fn run_quietly(&self) -> io::Result<ExitStatus> {
+        let status = self
+            .shell(&self.command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+        let _ = fs::remove_file(&self.list_file);
+        status
+    }
+
+    /* The command is started in the background of a shell which exits right
+     * away, so it is not left behind as a zombie when it finishes before
+     * trooper does. The list file is left for it to read, and removed by the
+     * background shell once the command is done.
+     */
+    /// Starts the command without waiting for it or giving it the terminal.
+    pub fn spawn(&self) -> io::Result<()> {
+        let (before, after) = if cfg!(windows) {
+            (
+                "start \"\" /B cmd /C \"",
+                " & del /Q \"%TROOPER_SELECTED_LIST%\"\"",
+            )
+        } else {
+            ("(trap 'rm -f -- \"$TROOPER_SELECTED_LIST\"' EXIT; ", "\n) &")
+        };
+        let mut command = OsString::from(before);
+        command.push(&self.command);
+        command.push(after);
+        self.shell(&command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map(|_| ())
+    }
+
+    fn shell(&self, command: &OsStr) -> Command {
+        let mut shell = if cfg!(windows) {
+            let mut c = Command::new("cmd");
+            c.arg("/C");
+            c
+        } else {
+            let mut c = Command::new("sh");
+            c.arg("-c");
+            c
+        };
+        shell
+            .arg(command)
+            .envs(self.env.iter().map(|(k, v)| (k, v)));
+        shell
+    }
+}
+
+/// The directory the selection lists are written to, which only we can read.
+#[cfg(unix)]
+fn private_dir() -> io::Result<PathBuf> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+    let uid = unsafe { libc::geteuid() };
+    let dir = std::env::temp_dir().join(format!("trooper-{}", uid));
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&dir)?;
+    // Anyone could have made it before us
+    let md = fs::symlink_metadata(&dir)?;
+    if !md.is_dir() || md.uid() != uid || md.mode() & 0o077 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} is not private", dir.display()),
+        ));
+    }
+    Ok(dir)
+}
+
+/// The temporary directory is already one of our own.
+#[cfg(windows)]
+fn private_dir() -> io::Result<PathBuf> {
+    Ok(std::env::temp_dir())
+}
+
+/// Replaces `%f` in a bound command with the first selected entry, which is
+/// the one under the cursor outside visual mode, `%s`
+/// with every selected entry and `%d` with the current directory, quoted for
+/// the shell. `%%` is a plain `%`.
+pub fn expand_placeholders(command: &str, cwd: &Path, selected: &[PathBuf]) -> OsString {
+    let mut expanded = OsString::new();
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c.encode_utf8(&mut [0; 4]));
+            continue;
+        }
+        match chars.next() {
+            Some('f') => {
+                if let Some(p) = selected.first() {
+                    expanded.push(quote(p));
+                }
+            }
+            Some('s') => {
+                for (i, p) in selected.iter().enumerate() {
+                    if i > 0 {
+                        expanded.push(" ");
+                    }
+                    expanded.push(quote(p));
+                }
+            }
+            Some('d') => expanded.push(quote(cwd)),
+            Some('%') => expanded.push("%"),
+            Some(other) => {
+                expanded.push("%");
+                expanded.push(other.encode_utf8(&mut [0; 4]));
+            }
+            None => expanded.push("%"),
+        }
+    }
+    expanded
+}
+
+/* cmd expands %VAR% between double quotes too, where ^ escapes nothing, so
+ * each % is moved out of the quotes and escaped there.
+ */
+#[cfg(windows)]
+fn quote(path: &Path) -> OsString {
+    let path = path.display().to_string().replace('%', "\"^%\"");
+    OsString::from(format!("\"{}\"", path))
+}
+
+/// Quotes the bytes of `path` as they are, as names needn't be UTF-8.
+#[cfg(not(windows))]
+fn quote(path: &Path) -> OsString {
+    let mut quoted = vec![b'\''];
+    for b in paths::os_to_bytes(path.as_os_str()) {
+        match b {
+            b'\'' => quoted.extend_from_slice(b"'\\''"),
+            b => quoted.push(b),
+        }
+    }
+    quoted.push(b'\'');
+    paths::os_from_bytes(&quoted)
+}
+
+/// Shells which `--init` can print a cd-on-exit function for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum InitShell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// A function named `t` which runs trooper through `--choose-dir` and enters
+/// the last visited directory once it quits.
+pub fn init_script(shell: InitShell) -> &'static str {
+    match shell {
+        InitShell::Bash | InitShell::Zsh => include_str!("../assets/init/trooper.sh"),
+        InitShell::Fish => include_str!("../assets/init/trooper.fish"),
+    }
+}
+
+/// The command starting the user's interactive shell, run by a bare `:sh`.
+pub fn interactive_shell() -> String {
+    if cfg!(windows) {
+        std::env::var("COMSPEC").unwrap_or(String::from("cmd"))
+    } else {
+        std::env::var("SHELL").unwrap_or(String::from("sh"))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{
+        ffi::OsStr,
+        fs,
+        os::unix::{ffi::OsStrExt, fs::PermissionsExt},
+        path::{Path, PathBuf},
+        process::Stdio,
+        time::{Duration, Instant},
+    };
+
+    use super::{expand_placeholders, ShellCommand};
+
+    #[test]
+    fn selection_is_exported_to_the_command() {
+        let selected = [PathBuf::from("/tmp/a b"), PathBuf::from("/tmp/c\nd")];
+        let command = ShellCommand::new(
+            "[ \"$TROOPER_CWD\" = /tmp ] && [ \"$TROOPER_SELECTED_COUNT\" = 2 ] \
+             && tr '\\0' '\\n' < \"$TROOPER_SELECTED_LIST\" | grep -qx '/tmp/a b'",
+            Path::new("/tmp"),
+            &selected,
+        )
+        .unwrap();
+
+        assert!(command.run(Stdio::inherit()).unwrap().success());
+    }
+
+    #[test]
+    fn selection_lists_are_private_and_removed() {
+        let selected = [PathBuf::from("/tmp/a")];
+        let first = ShellCommand::new("true", Path::new("/tmp"), &selected).unwrap();
+        let second = ShellCommand::new("true", Path::new("/tmp"), &selected).unwrap();
+        assert_ne!(first.list_file, second.list_file);
+        let dir = first.list_file.parent().unwrap();
+        assert_eq!(fs::metadata(dir).unwrap().permissions().mode() & 0o777, 0o700);
+
+        first.run_quietly().unwrap();
+        assert!(!first.list_file.exists());
+        // Commands started with & read the list after we are done with it
+        second.spawn().unwrap();
+        let started = Instant::now();
+        while second.list_file.exists() && started.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(!second.list_file.exists());
+    }
+
+    #[test]
+    fn placeholders_are_quoted() {
+        let selected = [PathBuf::from("/tmp/it's"), PathBuf::from("/tmp/b")];
+        assert_eq!(
+            expand_placeholders("mv %s %d/old; echo 100%% %x", Path::new("/tmp"), &selected),
+            "mv '/tmp/it'\\''s' '/tmp/b' '/tmp'/old; echo 100% %x"
+        );
+        assert_eq!(
+            expand_placeholders("xdg-open %f", Path::new("/tmp"), &selected[1..]),
+            "xdg-open '/tmp/b'"
+        );
+
+        // Names which aren't UTF-8 reach the shell as they are
+        let name = PathBuf::from(OsStr::from_bytes(b"/tmp/caf\xe9"));
+        assert_eq!(
+            expand_placeholders("cat %f", Path::new("/tmp"), &[name]).as_bytes(),
+            b"cat '/tmp/caf\xe9'"
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// A read-only copy of a filesystem made by btrfs (through snapper) or ZFS.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub name: String,
+    /// Where the snapshot shows the contents of `live_root`
+    pub root: PathBuf,
+    pub live_root: PathBuf,
+}
+
+impl Snapshot {
+    /// The path inside the snapshot corresponding to `live` in the live tree.
+    pub fn snapshot_path(&self, live: &Path) -> Option<PathBuf> {
+        live.strip_prefix(&self.live_root)
+            .ok()
+            .map(|rel| self.root.join(rel))
+    }
+
+    /// The path in the live tree corresponding to `path` inside the snapshot.
+    pub fn live_path(&self, path: &Path) -> Option<PathBuf> {
+        path.strip_prefix(&self.root)
+            .ok()
+            .map(|rel| self.live_root.join(rel))
+    }
+}
+
+/// Snapshots of the closest filesystem above `dir` which has any. ZFS exposes
+/// them in `.zfs/snapshot/<name>` and snapper in `.snapshots/<n>/snapshot`.
+pub fn find_snapshots(dir: &Path) -> Vec<Snapshot> {
+    for live_root in dir.ancestors() {
+        let mut snapshots = Vec::new();
+
+        if let Ok(entries) = fs::read_dir(live_root.join(".zfs/snapshot")) {
+            for e in entries.flatten() {
+                snapshots.push(Snapshot {
+                    name: e.file_name().to_string_lossy().into_owned(),
+                    root: e.path(),
+                    live_root: live_root.to_path_buf(),
+                });
+            }
+        }
+
+        if let Ok(entries) = fs::read_dir(live_root.join(".snapshots")) {
+            for e in entries.flatten() {
+                let root = e.path().join("snapshot");
+                if root.is_dir() {
+                    snapshots.push(Snapshot {
+                        name: e.file_name().to_string_lossy().into_owned(),
+                        root,
+                        live_root: live_root.to_path_buf(),
+                    });
+                }
+            }
+        }
+
+        if !snapshots.is_empty() {
+            // Snapper numbers its snapshots, which should not sort as text
+            snapshots.sort_by(
+                |a, b| match (a.name.parse::<u64>(), b.name.parse::<u64>()) {
+                    (Ok(a), Ok(b)) => a.cmp(&b),
+                    _ => a.name.cmp(&b.name),
+                },
+            );
+            return snapshots;
+        }
+    }
+
+    Vec::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use super::find_snapshots;
+
+    #[test]
+    fn snapper_snapshots_are_found_from_subdirectories() {
+        let root = std::env::temp_dir().join(format!("trooper_snapshots_{}", std::process::id()));
+        for n in ["2", "10"] {
+            fs::create_dir_all(root.join(".snapshots").join(n).join("snapshot/home")).unwrap();
+        }
+        fs::create_dir_all(root.join("home/user")).unwrap();
+
+        let snapshots = find_snapshots(&root.join("home/user"));
+        let names: Vec<&str> = snapshots.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["2", "10"]);
+
+        let snapshot = &snapshots[1];
+        let inside = snapshot.snapshot_path(&root.join("home/user")).unwrap();
+        assert_eq!(inside, root.join(".snapshots/10/snapshot/home/user"));
+        assert_eq!(
+            snapshot.live_path(&inside).as_deref(),
+            Some(Path::new(&root.join("home/user")))
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
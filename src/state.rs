@@ -0,0 +1,89 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app::ActivePanel,
+    error::{AppError, Context},
+};
+
+/// What is remembered from one session to the next, besides the bookmarks
+/// and the yank register.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct State {
+    /// The width the bookmark panel was resized to, fitting the bookmarks
+    /// when not set
+    #[serde(default)]
+    pub bookmark_width: Option<u16>,
+    /// Where the last session left off, for `--restore`
+    #[serde(default)]
+    pub session: Option<Session>,
+}
+
+/// Where a session was left, to pick up from with `--restore`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    pub dir: PathBuf,
+    /// The name of the entry under the cursor
+    pub entry: Option<String>,
+    /// Whether hidden files were shown
+    pub hidden: bool,
+    pub panel: ActivePanel,
+    /// The row of the cursor in the bookmark panel
+    pub bookmark: usize,
+}
+
+impl State {
+    /// Reads the state saved in `path`. A missing or unreadable file gives
+    /// the defaults, as there is nothing worth reporting then.
+    pub fn load(path: &Path) -> State {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), AppError> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).context("create", dir)?;
+        }
+        fs::write(path, serde_json::to_string(self).unwrap()).context("save state to", path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{Session, State};
+    use crate::app::ActivePanel;
+
+    #[test]
+    fn state_survives_a_round_trip() {
+        let dir = std::env::temp_dir().join(format!("trooper_state_{}", std::process::id()));
+        let path = dir.join("state.json");
+        assert_eq!(State::load(&path), State::default());
+
+        let state = State {
+            bookmark_width: Some(24),
+            session: Some(Session {
+                dir: dir.clone(),
+                entry: Some(String::from("state.json")),
+                hidden: true,
+                panel: ActivePanel::Bookmarks,
+                bookmark: 2,
+            }),
+        };
+        state.save(&path).unwrap();
+        assert_eq!(State::load(&path), state);
+
+        // Files from older versions lack what was added since
+        fs::write(&path, "{}").unwrap();
+        assert_eq!(State::load(&path), State::default());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
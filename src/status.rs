@@ -0,0 +1,81 @@
+/* The status line is described by a format string such as
+ * "{mode} {message}%={chord}". Segments in braces are replaced by their
+ * current value when drawing, everything else is printed verbatim and "%="
+ * splits the line into a left and a right aligned half.
+ */
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StatusToken {
+    Text(String),
+    Segment(String),
+}
+
+pub fn parse_status_format(format: &str) -> (Vec<StatusToken>, Vec<StatusToken>) {
+    let (left, right) = match format.split_once("%=") {
+        Some((l, r)) => (l, r),
+        None => (format, ""),
+    };
+
+    (parse_tokens(left), parse_tokens(right))
+}
+
+fn parse_tokens(s: &str) -> Vec<StatusToken> {
+    let mut output = vec![];
+    let mut rest = s;
+
+    while let Some(start) = rest.find('{') {
+        match rest[start..].find('}') {
+            Some(len) => {
+                if start > 0 {
+                    output.push(StatusToken::Text(rest[..start].to_string()));
+                }
+                output.push(StatusToken::Segment(
+                    rest[start + 1..start + len].to_string(),
+                ));
+                rest = &rest[start + len + 1..];
+            }
+            None => break,
+        }
+    }
+
+    if !rest.is_empty() {
+        output.push(StatusToken::Text(rest.to_string()));
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_status_format, StatusToken};
+
+    #[test]
+    fn format_is_split_into_text_and_segments() {
+        let (left, right) = parse_status_format("{mode} [{message}]%={chord}");
+
+        assert_eq!(
+            left,
+            vec![
+                StatusToken::Segment(String::from("mode")),
+                StatusToken::Text(String::from(" [")),
+                StatusToken::Segment(String::from("message")),
+                StatusToken::Text(String::from("]")),
+            ]
+        );
+        assert_eq!(right, vec![StatusToken::Segment(String::from("chord"))]);
+    }
+
+    #[test]
+    fn unterminated_segment_is_kept_as_text() {
+        let (left, right) = parse_status_format("{mode} {oops");
+
+        assert_eq!(
+            left,
+            vec![
+                StatusToken::Segment(String::from("mode")),
+                StatusToken::Text(String::from(" {oops")),
+            ]
+        );
+        assert!(right.is_empty());
+    }
+}
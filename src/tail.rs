@@ -0,0 +1,70 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use crate::vfs;
+
+/* Only the end of the file is ever read, which keeps refreshing cheap even
+ * for log files which have grown very large.
+ */
+const TAIL_BYTES: u64 = 64 * 1024;
+const TAIL_LINES: usize = 500;
+
+pub struct Tail {
+    pub path: PathBuf,
+    pub lines: Vec<String>,
+    /// Whether we may not write to the file, which reading doesn't need
+    pub read_only: bool,
+    len: u64,
+}
+
+impl Tail {
+    pub fn new(path: &Path) -> io::Result<Tail> {
+        let mut tail = Tail {
+            path: path.to_path_buf(),
+            lines: Vec::new(),
+            read_only: vfs::is_read_only(path),
+            len: 0,
+        };
+        tail.reload()?;
+        Ok(tail)
+    }
+
+    /// Re-reads the end of the file if its size has changed since the last read.
+    pub fn refresh(&mut self) -> io::Result<bool> {
+        let len = fs::metadata(&self.path)?.len();
+        if len == self.len {
+            return Ok(false);
+        }
+        self.reload()?;
+        Ok(true)
+    }
+
+    fn reload(&mut self) -> io::Result<()> {
+        let mut f = File::open(&self.path)?;
+        let len = f.metadata()?.len();
+        let start = len.saturating_sub(TAIL_BYTES);
+        f.seek(SeekFrom::Start(start))?;
+
+        let mut buf = Vec::new();
+        f.read_to_end(&mut buf)?;
+
+        let mut lines: Vec<String> = String::from_utf8_lossy(&buf)
+            .lines()
+            .map(String::from)
+            .collect();
+        // The first line is most likely cut in half when starting mid-file
+        if start > 0 && !lines.is_empty() {
+            lines.remove(0);
+        }
+        if lines.len() > TAIL_LINES {
+            lines.drain(..lines.len() - TAIL_LINES);
+        }
+
+        self.lines = lines;
+        self.len = len;
+        Ok(())
+    }
+}
@@ -1,15 +1,148 @@
-use std::{fs::DirEntry, io};
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    ffi::{OsStr, OsString},
+    fmt, io,
+    path::PathBuf,
+};
 
-use tui::{
+use ratatui::{
     backend::Backend,
     layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
-    text::Span,
-    widgets::{Block, Borders, List, ListItem, Paragraph, Wrap},
+    text::{Line, Span, Text},
+    widgets::{Block, Borders, Clear, List, ListItem, Paragraph},
     Terminal,
 };
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use crate::{
+    app::{ActiveMode, ActivePanel, Bookmark, Settings},
+    devices::Device,
+    disk::{human_size, DiskSpace},
+    du::DirSizes,
+    icons,
+    jobs::{format_eta, Job, JobState},
+    mode::View,
+    snapshots::Snapshot,
+    status::{parse_status_format, StatusToken},
+    tail::Tail,
+    vfs::Entry,
+};
+
+/// Labelled values shown in a box over the file list.
+pub struct Popup {
+    pub title: String,
+    pub rows: Vec<(String, String)>,
+    /// Rows scrolled past at the top
+    pub scroll: u16,
+}
+
+/// Everything of the App which is drawn, borrowed for one frame.
+pub(crate) struct AppView<'a> {
+    pub title: &'a str,
+    pub bookmarks: &'a [Bookmark],
+    pub devices: &'a [Device],
+    pub dir_contents: &'a [Entry],
+    /// What the active mode adds to the screen
+    pub view: Option<View<'a>>,
+    pub active_panel: &'a ActivePanel,
+    pub active_mode: &'a ActiveMode,
+    pub selection_start: i32,
+    pub marked: &'a BTreeSet<PathBuf>,
+    pub selection: Option<SelectionStats>,
+    /// The register, count and keys typed so far
+    pub key_chord: &'a str,
+    pub settings: &'a Settings,
+    pub tail: Option<&'a Tail>,
+    pub disk_space: Option<DiskSpace>,
+    pub jobs: &'a [Job],
+    pub dir_sizes: &'a DirSizes,
+    pub recording: Option<char>,
+    pub popup: Option<&'a Popup>,
+    pub chord_hints: Option<&'a Popup>,
+    pub snapshot_picker: Option<(&'a [Snapshot], usize)>,
+    pub snapshot: Option<&'a Snapshot>,
+}
+
+/// What an operation on the selection would touch, for the status line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SelectionStats {
+    pub count: usize,
+    /// The combined size of the files, and of the directories whose size is known
+    pub size: u64,
+    /// Whether directories of unknown size were left out of `size`
+    pub partial: bool,
+}
+
+impl fmt::Display for SelectionStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} selected, {}", self.count, human_size(self.size))?;
+        if self.partial {
+            write!(f, "+")?;
+        }
+        Ok(())
+    }
+}
+
+/// Cuts `text` down to `width` columns, ending it with `…` if anything was
+/// cut. Wide characters, such as those of CJK scripts, take up two columns and
+/// combining marks stay with the character they follow.
+pub(crate) fn truncate(text: &str, width: usize) -> String {
+    if text.width() <= width {
+        return text.to_string();
+    }
+    let mut used = 0;
+    let mut cut = String::new();
+    for c in text.chars() {
+        let w = c.width().unwrap_or(0);
+        if used + w + 1 > width {
+            break;
+        }
+        used += w;
+        cut.push(c);
+    }
+    if width > 0 {
+        cut.push('…');
+    }
+    cut
+}
+
+/// The number shown before entry `i` of the file list, as with the `number`
+/// and `relativenumber` options of vim.
+fn line_number(i: i32, cursor: i32, number: bool, relative: bool) -> Option<i32> {
+    match (number, relative) {
+        (_, true) if i != cursor => Some((i - cursor).abs()),
+        (true, _) => Some(i + 1),
+        (false, true) => Some(0),
+        (false, false) => None,
+    }
+}
+
+/// How many messages `:messages` remembers.
+const MESSAGE_HISTORY: usize = 200;
+
+/// The narrowest the bookmark panel can be resized to.
+const MIN_BOOKMARK_WIDTH: u16 = 5;
+
+/// The file list is never narrower than this.
+const MIN_LIST_WIDTH: u16 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Info,
+    Warn,
+    Error,
+}
 
-use crate::app::{ActiveMode, ActivePanel, Bookmark};
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Level::Info => write!(f, "info"),
+            Level::Warn => write!(f, "warn"),
+            Level::Error => write!(f, "error"),
+        }
+    }
+}
 
 pub struct Ui {
     pub cursor_y: i32,
@@ -18,79 +151,138 @@ pub struct Ui {
     pub bookmark_y: i32,
     pub bookmark_scroll_y: i32,
 
+    pub device_y: i32,
+
+    pub job_y: i32,
+
+    pub selection_y: i32,
+
+    /// Rows kept visible above and below the cursor in the file list
+    pub scrolloff: i32,
+
     /* This position can be off screen */
     pub visual_intitial_y: i32,
 
     inside: Rect,
+    // Where the panels were last drawn, to find what is clicked
+    pub(crate) bookmark_area: Rect,
+    pub(crate) device_area: Option<Rect>,
+    pub(crate) list_area: Rect,
 
     layout: Layout,
 
-    pub last_name: String,
+    pub last_name: OsString,
     pub bookmark_width: u16,
 
-    pub debug_msg: String,
+    pub message: String,
+    pub message_level: Level,
+    /// Every message shown so far, oldest first
+    pub messages: VecDeque<(Level, String)>,
+    /// Status line segments added by plugins, by name
+    pub plugin_segments: Vec<(String, String)>,
 }
 
 impl Ui {
-    pub(crate) fn new(start_dir: &str) -> Ui {
+    pub(crate) fn new<S: AsRef<OsStr>>(start_dir: S) -> Ui {
         Ui {
             cursor_y: 0,
             scroll_y: 0,
 
             bookmark_y: 0,
             bookmark_scroll_y: 0,
+            device_y: 0,
+            job_y: 0,
+            selection_y: 0,
+            scrolloff: 0,
 
             visual_intitial_y: 0,
 
             inside: Rect::new(0, 0, 0, 0),
+            bookmark_area: Rect::new(0, 0, 0, 0),
+            device_area: None,
+            list_area: Rect::new(0, 0, 0, 0),
             layout: Layout::default()
                 .direction(Direction::Horizontal)
-                .constraints([Constraint::Length(15), Constraint::Min(20)]),
-            last_name: String::from(start_dir),
+                .constraints([Constraint::Length(15), Constraint::Min(MIN_LIST_WIDTH)]),
+            last_name: start_dir.as_ref().to_owned(),
             bookmark_width: 15,
-            debug_msg: String::new(),
+            message: String::new(),
+            message_level: Level::Info,
+            messages: VecDeque::new(),
+            plugin_segments: Vec::new(),
         }
     }
 
     pub(crate) fn draw_app<B: Backend>(
         &mut self,
         term: &mut Terminal<B>,
-        title: &str,
-        bookmarks: &Vec<Bookmark>,
-        dir_contents: &Vec<DirEntry>,
-        command_mode: bool,
-        command_buffer: &str,
-        command_completions: &Vec<String>,
-        command_completion_index: i32,
-        active_panel: &ActivePanel,
-        active_mode: &ActiveMode,
-        selection_start: i32,
-        key_chord: &String,
+        app: AppView,
     ) -> io::Result<()> {
+        let AppView {
+            title,
+            bookmarks,
+            devices,
+            dir_contents,
+            view,
+            active_panel,
+            active_mode,
+            selection_start,
+            marked,
+            selection,
+            key_chord,
+            settings,
+            tail,
+            disk_space,
+            jobs,
+            dir_sizes,
+            recording,
+            popup,
+            chord_hints,
+            snapshot_picker,
+            snapshot,
+        } = app;
         term.draw(|f| {
             self.layout = Layout::default()
                 .direction(Direction::Horizontal)
-                .constraints([Constraint::Length(self.bookmark_width), Constraint::Min(20)]);
+                .constraints([
+                    Constraint::Length(self.bookmark_width),
+                    Constraint::Min(MIN_LIST_WIDTH),
+                ]);
 
             // Border
             let size = f.size();
             let block = Block::default()
                 .title(Span::styled(
-                    title.replace("\\", "/"),
+                    truncate(title, size.width.saturating_sub(4) as usize),
                     Style::default().add_modifier(Modifier::BOLD),
                 ))
-                .title_alignment(tui::layout::Alignment::Center)
+                .title_alignment(ratatui::layout::Alignment::Center)
                 .borders(Borders::ALL);
 
             // Layout
             self.inside = block.inner(size);
             self.inside.x = self.inside.x + 1;
             self.inside.width = self.inside.width - 2;
+            // Leave room for the status and command lines
+            self.inside.height = self.inside.height.saturating_sub(2);
+            self.scrolloff = settings.scrolloff as i32;
 
             let chunks = self.layout.split(self.inside);
             let main_block = Block::default()
                 .borders(Borders::LEFT)
                 .border_style(Style::default().fg(Color::DarkGray));
+            let (list_area, tail_area) = match tail {
+                Some(_) => {
+                    let halves = Layout::default()
+                        .direction(Direction::Horizontal)
+                        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+                        .split(chunks[1]);
+                    (halves[0], Some(halves[1]))
+                }
+                None => (chunks[1], None),
+            };
+
+            let inner_main_block = main_block.inner(list_area);
 
             // Bookmarks
             let mut bookmarks_disp = vec![];
@@ -107,55 +299,183 @@ impl Ui {
                         .add_modifier(Modifier::BOLD);
                 }
 
-                bookmarks_disp.push(ListItem::new(b.name.clone()).style(s));
+                let name = if settings.icons {
+                    format!("{} {}", icons::bookmark_icon(), b.name)
+                } else {
+                    b.name.clone()
+                };
+                let name = truncate(&name, chunks[0].width as usize);
+                bookmarks_disp.push(ListItem::new(name).style(s));
 
                 i = i + 1;
             }
             let bookmark_list = List::new(bookmarks_disp);
 
+            // Devices
+            let device_items: Vec<ListItem> = devices
+                .iter()
+                .enumerate()
+                .map(|(i, d)| {
+                    let mut s = Style::default();
+                    if d.mount_point.is_none() {
+                        s = s.fg(Color::DarkGray);
+                    }
+                    if i as i32 == self.device_y && *active_panel == ActivePanel::Devices {
+                        s = s
+                            .fg(Color::Black)
+                            .bg(Color::Blue)
+                            .add_modifier(Modifier::BOLD);
+                    }
+                    ListItem::new(d.name.clone()).style(s)
+                })
+                .collect();
+            let device_list = List::new(device_items).block(
+                Block::default()
+                    .borders(Borders::TOP)
+                    .border_style(Style::default().fg(Color::DarkGray))
+                    .title(Span::styled(
+                        if cfg!(windows) { "Drives" } else { "Devices" },
+                        Style::default().add_modifier(Modifier::BOLD),
+                    )),
+            );
+
             // File list
+            let show_sizes = dir_contents.iter().any(|p| {
+                let path = p.path();
+                dir_sizes.get(&path).is_some() || dir_sizes.is_pending(&path)
+            });
             let mut items = vec![];
+            let mut sizes = vec![];
+            let number_width = dir_contents.len().to_string().len();
+            let name_width = if show_sizes {
+                inner_main_block.width.saturating_sub(8)
+            } else {
+                inner_main_block.width
+            };
             i = 0;
             for p in dir_contents {
+                let path = p.path();
+                let is_symlink = p.is_symlink;
+                let is_dir = p.is_dir;
+                let is_executable = !is_dir && p.executable;
+
                 let mut s = Style::default();
-                if p.file_type().unwrap().is_dir() {
+                if is_dir {
                     s = s.fg(Color::Blue).add_modifier(Modifier::BOLD);
+                } else if is_executable {
+                    s = s.fg(Color::Green).add_modifier(Modifier::BOLD);
+                }
+                let file_name = p.file_name();
+                if let Some(style) = settings.filetypes.style_for(&file_name.to_string_lossy()) {
+                    s = s.patch(style);
+                }
+                // Names which are not valid UTF-8 are shown with replacement
+                // characters, and set in italics to tell them apart
+                if file_name.to_str().is_none() {
+                    s = s.add_modifier(Modifier::ITALIC);
+                }
+                if p.hidden || p.ignored {
+                    s = s.add_modifier(Modifier::DIM);
+                }
+                if is_symlink {
+                    if p.broken_link {
+                        s = s.fg(Color::Red);
+                    } else {
+                        s = s.fg(Color::Cyan);
+                    }
+                }
 
-                    if ((i <= self.scroll_y + self.cursor_y && i >= selection_start)
-                        || (i >= self.scroll_y + self.cursor_y && i <= selection_start))
-                        && *active_panel == ActivePanel::Main
-                    {
-                        s = s
-                            .fg(Color::Black)
-                            .bg(Color::Blue)
-                            .add_modifier(Modifier::BOLD);
+                if marked.contains(&path) {
+                    s = s.fg(Color::Yellow).add_modifier(Modifier::BOLD);
+                }
+
+                if ((i <= self.scroll_y + self.cursor_y && i >= selection_start)
+                    || (i >= self.scroll_y + self.cursor_y && i <= selection_start))
+                    && *active_panel == ActivePanel::Main
+                {
+                    s = s.fg(Color::Black).bg(Color::Blue);
+                }
+
+                if i >= self.scroll_y && i - self.scroll_y < self.inside.height as i32 {
+                    let mut name = file_name.to_string_lossy().into_owned();
+                    if settings.icons {
+                        name = format!("{} {}", icons::icon_for(&path, is_dir), name);
                     }
-                } else {
-                    if ((i <= self.scroll_y + self.cursor_y && i >= selection_start)
-                        || (i >= self.scroll_y + self.cursor_y && i <= selection_start))
-                        && *active_panel == ActivePanel::Main
+                    if settings.classify {
+                        if is_symlink {
+                            name.push('@');
+                        } else if is_dir {
+                            name.push('/');
+                        } else if is_executable {
+                            name.push('*');
+                        }
+                    }
+                    if is_symlink {
+                        if let Some(target) = &p.link_target {
+                            name = format!("{} -> {}", name, target.display());
+                        }
+                    }
+                    let cursor = self.scroll_y + self.cursor_y;
+                    if let Some(n) =
+                        line_number(i, cursor, settings.number, settings.relativenumber)
                     {
-                        s = s.fg(Color::Black).bg(Color::Blue);
+                        name = format!("{:>w$} {}", n, name, w = number_width);
                     }
-                }
+                    let name = truncate(&name, name_width as usize);
+                    items.push(ListItem::new(name).style(s));
 
-                if i >= self.scroll_y && i - self.scroll_y < self.inside.height as i32 {
-                    items.push(ListItem::new(p.file_name().into_string().unwrap()).style(s));
+                    if show_sizes {
+                        let size = if is_symlink {
+                            String::new()
+                        } else if is_dir {
+                            match dir_sizes.get(&path) {
+                                Some(size) => human_size(size),
+                                None if dir_sizes.is_pending(&path) => String::from("..."),
+                                None => String::new(),
+                            }
+                        } else {
+                            human_size(p.len)
+                        };
+                        sizes.push(ListItem::new(format!("{:>7}", size)).style(s));
+                    }
                 }
                 i = i + 1;
             }
             let item_list = List::new(items);
 
-            // Command mode
-            let cmd_text = Span::styled(format!(":{}█", command_buffer), Style::default());
-            let cmd_line = Paragraph::new(cmd_text)
-                .block(Block::default())
-                .wrap(Wrap { trim: true });
+            // Modes such as the command line, which draw a line at the bottom
+            let (
+                command_mode,
+                prompt,
+                command_buffer,
+                cursor,
+                command_completions,
+                completion_index,
+            ) = match &view {
+                Some(View::Line {
+                    prompt,
+                    text,
+                    cursor,
+                    completions,
+                    selected,
+                }) => (true, *prompt, *text, *cursor, *completions, *selected),
+                _ => (false, ':', "", 0, &[][..], None),
+            };
+            let (cmd_text, cmd_cursor) = edited_line(prompt.to_string(), command_buffer, cursor);
+            let cmd_area = Rect {
+                x: 1,
+                y: size.height - 2,
+                width: size.width - 1,
+                height: 1,
+            };
+            // Long lines scroll sideways to keep the cursor in view
+            let cmd_scroll = cmd_cursor.saturating_sub(cmd_area.width.saturating_sub(1));
+            let cmd_line = Paragraph::new(cmd_text).scroll((0, cmd_scroll));
             let mut cmd_comp_disp = vec![];
             let mut longest_cmd = 0;
             for (i, cmd) in command_completions.iter().enumerate() {
                 let mut s = Style::default();
-                if i as i32 == command_completion_index {
+                if Some(i) == completion_index {
                     s = s.bg(Color::Blue).add_modifier(Modifier::BOLD);
                 } else {
                     s = s.bg(Color::DarkGray).add_modifier(Modifier::BOLD);
@@ -168,34 +488,237 @@ impl Ui {
             }
             let cmd_comp_list = List::new(cmd_comp_disp.clone());
 
-            let inner_main_block = main_block.inner(chunks[1]);
             f.render_widget(block, size);
-            f.render_widget(bookmark_list.clone(), chunks[0]);
-            f.render_widget(main_block, chunks[1]);
-            f.render_widget(item_list.clone(), inner_main_block);
+            if devices.is_empty() {
+                f.render_widget(bookmark_list.clone(), chunks[0]);
+                self.bookmark_area = chunks[0];
+                self.device_area = None;
+            } else {
+                let left = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints([
+                        Constraint::Min(1),
+                        Constraint::Length(devices.len() as u16 + 1),
+                    ])
+                    .split(chunks[0]);
+                f.render_widget(bookmark_list.clone(), left[0]);
+                f.render_widget(device_list, left[1]);
+                self.bookmark_area = left[0];
+                self.device_area = Some(left[1]);
+            }
+            self.list_area = inner_main_block;
+            f.render_widget(main_block, list_area);
+            if show_sizes {
+                let columns = Layout::default()
+                    .direction(Direction::Horizontal)
+                    .constraints([Constraint::Min(1), Constraint::Length(8)])
+                    .split(inner_main_block);
+                f.render_widget(item_list.clone(), columns[0]);
+                f.render_widget(List::new(sizes), columns[1]);
+            } else {
+                f.render_widget(item_list.clone(), inner_main_block);
+            }
 
-            let debug_text = Span::styled(&self.debug_msg, Style::default());
-            let debug_line = Paragraph::new(debug_text);
-            f.render_widget(
-                debug_line,
-                Rect {
-                    x: ((size.width as usize) - self.debug_msg.len() - 2) as u16,
-                    y: 2,
-                    width: self.debug_msg.len() as u16,
-                    height: 1,
-                },
-            );
+            // Tail
+            if let (Some(tail), Some(area)) = (tail, tail_area) {
+                let mut title = vec![Span::styled(
+                    format!(
+                        " tail {} ",
+                        tail.path.file_name().unwrap_or_default().to_string_lossy()
+                    ),
+                    Style::default().add_modifier(Modifier::BOLD),
+                )];
+                if tail.read_only {
+                    title.push(Span::styled("[RO] ", Style::default().fg(Color::Yellow)));
+                }
+                let tail_block = Block::default()
+                    .borders(Borders::LEFT)
+                    .border_style(Style::default().fg(Color::DarkGray))
+                    .title(Line::from(title));
+                let visible = tail_block.inner(area).height as usize;
+                let first = tail.lines.len().saturating_sub(visible);
+                let tail_text = Text::raw(tail.lines[first..].join("\n"));
+                f.render_widget(Paragraph::new(tail_text).block(tail_block), area);
+            }
 
-            if command_mode {
+            // Popup
+            if let Some(popup) = popup {
+                let lines = popup_lines(popup);
+                let text_width = lines.iter().map(|l| l.width()).max().unwrap_or(0) as u16;
+                let area = self.popup_area(text_width + 4, lines.len() as u16 + 2);
+                f.render_widget(Clear, area);
+                let title = format!(" {} ", popup.title);
                 f.render_widget(
-                    cmd_line,
-                    Rect {
-                        x: 1,
-                        y: size.height - 2,
-                        width: size.width - 1,
-                        height: 1,
-                    },
+                    Paragraph::new(lines)
+                        .block(popup_block(&title))
+                        .scroll((popup.scroll, 0)),
+                    area,
                 );
+            }
+
+            // Continuations of a pending key chord, in the bottom right corner
+            if let Some(hints) = chord_hints {
+                let lines = popup_lines(hints);
+                let text_width = lines.iter().map(|l| l.width()).max().unwrap_or(0) as u16;
+                let width = std::cmp::min(text_width + 4, self.inside.width);
+                let height = std::cmp::min(lines.len() as u16 + 2, self.inside.height);
+                let area = Rect {
+                    x: self.inside.x + self.inside.width - width,
+                    y: self.inside.y + self.inside.height - height,
+                    width,
+                    height,
+                };
+                f.render_widget(Clear, area);
+                let title = format!(" {} ", hints.title);
+                f.render_widget(Paragraph::new(lines).block(popup_block(&title)), area);
+            }
+
+            // Snapshot picker
+            if let Some((snapshots, selected)) = snapshot_picker {
+                let items: Vec<ListItem> = snapshots
+                    .iter()
+                    .enumerate()
+                    .map(|(i, s)| {
+                        let style = if i == selected {
+                            Style::default()
+                                .fg(Color::Black)
+                                .bg(Color::Blue)
+                                .add_modifier(Modifier::BOLD)
+                        } else {
+                            Style::default()
+                        };
+                        ListItem::new(format!(" {} ", s.name)).style(style)
+                    })
+                    .collect();
+                let name_width = snapshots.iter().map(|s| s.name.len()).max().unwrap_or(0);
+                let area = self.popup_area(
+                    std::cmp::max(name_width as u16 + 4, 13),
+                    snapshots.len() as u16 + 2,
+                );
+                // Keep the selection in view when there are more snapshots than rows
+                let visible = area.height.saturating_sub(2) as usize;
+                let first = (selected + 1).saturating_sub(visible);
+                f.render_widget(Clear, area);
+                f.render_widget(
+                    List::new(items[first..].to_vec()).block(popup_block(" Snapshots ")),
+                    area,
+                );
+            }
+
+            // Jobs panel
+            if *active_panel == ActivePanel::Jobs {
+                let lines: Vec<String> = jobs.iter().map(format_job).collect();
+                let items: Vec<ListItem> = lines
+                    .iter()
+                    .enumerate()
+                    .map(|(i, line)| {
+                        let mut style = match jobs[i].state {
+                            JobState::Failed(_) => Style::default().fg(Color::Red),
+                            JobState::Done | JobState::Cancelled => {
+                                Style::default().fg(Color::DarkGray)
+                            }
+                            _ => Style::default(),
+                        };
+                        if i as i32 == self.job_y {
+                            style = style
+                                .fg(Color::Black)
+                                .bg(Color::Blue)
+                                .add_modifier(Modifier::BOLD);
+                        }
+                        ListItem::new(format!(" {} ", line)).style(style)
+                    })
+                    .collect();
+                let width = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+                let area =
+                    self.popup_area(std::cmp::max(width as u16 + 4, 40), jobs.len() as u16 + 2);
+                let visible = area.height.saturating_sub(2) as usize;
+                let first = (self.job_y as usize + 1).saturating_sub(visible);
+                f.render_widget(Clear, area);
+                f.render_widget(
+                    List::new(items[first..].to_vec()).block(popup_block(" Jobs ")),
+                    area,
+                );
+            }
+
+            // Selection panel
+            if *active_panel == ActivePanel::Selection {
+                let lines: Vec<String> = marked.iter().map(|p| p.display().to_string()).collect();
+                let items: Vec<ListItem> = lines
+                    .iter()
+                    .enumerate()
+                    .map(|(i, line)| {
+                        let mut style = Style::default();
+                        if i as i32 == self.selection_y {
+                            style = style
+                                .fg(Color::Black)
+                                .bg(Color::Blue)
+                                .add_modifier(Modifier::BOLD);
+                        }
+                        ListItem::new(format!(" {} ", line)).style(style)
+                    })
+                    .collect();
+                let width = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+                let area =
+                    self.popup_area(std::cmp::max(width as u16 + 4, 40), lines.len() as u16 + 2);
+                let visible = area.height.saturating_sub(2) as usize;
+                let first = (self.selection_y as usize + 1).saturating_sub(visible);
+                f.render_widget(Clear, area);
+                f.render_widget(
+                    List::new(items[first..].to_vec()).block(popup_block(" Selection ")),
+                    area,
+                );
+            }
+
+            // Modes such as the palette, with the query above the matching rows
+            if let Some(View::List {
+                title,
+                query,
+                cursor,
+                rows: entries,
+                selected,
+            }) = &view
+            {
+                let selected = *selected;
+                let label_width = entries
+                    .iter()
+                    .map(|(l, _)| l.chars().count())
+                    .max()
+                    .unwrap_or(0)
+                    + 2;
+                let area = self.popup_area(
+                    std::cmp::max(label_width as u16 + 16, 40),
+                    entries.len() as u16 + 3,
+                );
+                let visible = area.height.saturating_sub(3) as usize;
+                let first = (selected + 1).saturating_sub(visible);
+                let (query_line, query_cursor) = edited_line(String::from("> "), query, *cursor);
+                let mut lines = vec![query_line];
+                for (i, (label, keys)) in entries.iter().enumerate().skip(first) {
+                    let style = if i == selected {
+                        Style::default().fg(Color::Black).bg(Color::Blue)
+                    } else {
+                        Style::default()
+                    };
+                    lines.push(Line::from(vec![
+                        Span::styled(
+                            format!("{:<width$}", label, width = label_width),
+                            style.add_modifier(Modifier::BOLD),
+                        ),
+                        Span::styled(keys.as_str(), style),
+                    ]));
+                }
+                f.render_widget(Clear, area);
+                f.render_widget(Paragraph::new(lines).block(popup_block(title)), area);
+                let inner = popup_block(title).inner(area);
+                f.set_cursor(
+                    inner.x + std::cmp::min(query_cursor, inner.width.saturating_sub(1)),
+                    inner.y,
+                );
+            }
+
+            if command_mode {
+                f.render_widget(cmd_line, cmd_area);
+                f.set_cursor(cmd_area.x + cmd_cursor - cmd_scroll, cmd_area.y);
                 if command_completions.len() > 0 {
                     f.render_widget(
                         cmd_comp_list,
@@ -209,84 +732,276 @@ impl Ui {
                 }
             }
 
+            // Status line
             let mode_style = Style::default()
                 .add_modifier(Modifier::BOLD)
                 .fg(match active_mode {
                     ActiveMode::Normal => Color::Green,
                     ActiveMode::Command => Color::Magenta,
                     ActiveMode::Visual => Color::Blue,
+                    ActiveMode::Palette => Color::Yellow,
+                    ActiveMode::History => Color::Cyan,
+                    ActiveMode::Search => Color::Magenta,
+                    ActiveMode::Filter => Color::Magenta,
                 });
-            let active_mode_text = Span::styled(format!("{}", active_mode), mode_style);
-            let mode_width = active_mode_text.width() as u16;
-            let active_mode_line = Paragraph::new(active_mode_text)
-                .block(Block::default())
-                .wrap(Wrap { trim: true });
+            let selection = selection.map(|s| s.to_string()).unwrap_or_default();
+
+            let mut segments = HashMap::new();
+            // Inserted first so that plugins can't replace the built in segments
+            for (name, text) in &self.plugin_segments {
+                segments.insert(name.as_str(), Span::raw(text.clone()));
+            }
+            segments.insert("mode", Span::styled(format!("{}", active_mode), mode_style));
+            segments.insert("path", Span::raw(title));
+            segments.insert("chord", Span::raw(key_chord.to_string()));
+            let message_style = match self.message_level {
+                Level::Info => Style::default(),
+                Level::Warn => Style::default().fg(Color::Yellow),
+                Level::Error => Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            };
+            segments.insert("message", Span::styled(self.message.clone(), message_style));
+            segments.insert("selection", Span::raw(selection));
+            let position = if dir_contents.is_empty() {
+                0
+            } else {
+                self.scroll_y + self.cursor_y + 1
+            };
+            segments.insert(
+                "position",
+                Span::raw(format!("{}/{}", position, dir_contents.len())),
+            );
+            if let Some(space) = disk_space {
+                segments.insert(
+                    "free",
+                    Span::raw(format!(
+                        "{}/{} free",
+                        human_size(space.available),
+                        human_size(space.total)
+                    )),
+                );
+            }
+            if let Some(register) = recording {
+                segments.insert(
+                    "recording",
+                    Span::styled(
+                        format!("recording @{}", register),
+                        Style::default().add_modifier(Modifier::BOLD),
+                    ),
+                );
+            }
+            if let Some(s) = snapshot {
+                segments.insert(
+                    "snapshot",
+                    Span::styled(
+                        format!("[snapshot {}]", s.name),
+                        Style::default().fg(Color::Magenta),
+                    ),
+                );
+            }
+            if let Some(job) = jobs.iter().find(|j| j.state == JobState::Running) {
+                let active = jobs.iter().filter(|j| j.is_active()).count();
+                segments.insert("progress", Span::raw(format_progress(job, active)));
+            }
+            let read_only = dir_contents
+                .get((self.scroll_y + self.cursor_y) as usize)
+                .map(|entry| entry.read_only)
+                .unwrap_or(false);
+            if read_only {
+                segments.insert(
+                    "readonly",
+                    Span::styled("[RO]", Style::default().fg(Color::Yellow)),
+                );
+            }
+
+            let (left, right) = parse_status_format(&settings.status_format);
+            let to_spans = |tokens: Vec<StatusToken>| {
+                Line::from(
+                    tokens
+                        .into_iter()
+                        .map(|t| match t {
+                            StatusToken::Text(text) => Span::raw(text),
+                            StatusToken::Segment(name) => segments
+                                .get(name.as_str())
+                                .cloned()
+                                .unwrap_or(Span::raw("")),
+                        })
+                        .collect::<Vec<Span>>(),
+                )
+            };
+            let left_spans = to_spans(left);
+            let right_spans = to_spans(right);
+            let right_width = std::cmp::min(right_spans.width() as u16, self.inside.width);
+
             f.render_widget(
-                active_mode_line,
+                Paragraph::new(left_spans),
                 Rect {
-                    x: 2,
+                    x: self.inside.x,
                     y: size.height - 3,
-                    width: mode_width,
+                    width: self.inside.width - right_width,
                     height: 1,
                 },
             );
-
-            let chord_text = Span::styled(key_chord, Style::default());
-            let chord_width = chord_text.width() as u16;
-            let chord_line = Paragraph::new(chord_text)
-                .block(Block::default())
-                .wrap(Wrap { trim: true });
             f.render_widget(
-                chord_line,
+                Paragraph::new(right_spans),
                 Rect {
-                    x: size.width - chord_width - 2,
+                    x: self.inside.x + self.inside.width - right_width,
                     y: size.height - 3,
-                    width: chord_width,
+                    width: right_width,
                     height: 1,
                 },
-            )
+            );
         })?;
 
         Ok(())
     }
 
+    /// Shows `message` in the status line until the next one replaces it,
+    /// and keeps it in the history shown by `:messages`.
+    pub(crate) fn notify<S: Into<String>>(&mut self, level: Level, message: S) {
+        self.message = message.into();
+        self.message_level = level;
+        if self.messages.len() == MESSAGE_HISTORY {
+            self.messages.pop_front();
+        }
+        self.messages.push_back((level, self.message.clone()));
+    }
+
+    pub(crate) fn info<S: Into<String>>(&mut self, message: S) {
+        self.notify(Level::Info, message);
+    }
+
+    /// For refused actions, where nothing went wrong as such.
+    pub(crate) fn warn<S: Into<String>>(&mut self, message: S) {
+        self.notify(Level::Warn, message);
+    }
+
+    pub(crate) fn error<S: Into<String>>(&mut self, message: S) {
+        self.notify(Level::Error, message);
+    }
+
     pub(crate) fn scroll(&mut self, y: i32, max: i32, active_panel: &ActivePanel) {
         match active_panel {
             ActivePanel::Main => {
-                self.cursor_y = std::cmp::min(self.cursor_y + y, max - 1);
-
-                if self.cursor_y < 0 {
-                    self.cursor_y = 0;
-                    self.scroll_y = self.scroll_y + y;
+                let height = self.inside.height as i32;
+                let position = (self.scroll_y + self.cursor_y + y).min(max - 1).max(0);
+                // Like in vim, half the rows at most so the cursor can still move
+                let off = self.scrolloff.min((height - 1) / 2).max(0);
 
-                    if self.scroll_y < 0 {
-                        self.scroll_y = 0;
-                    }
-                } else if self.cursor_y >= self.inside.height as i32 {
-                    let diff = self.scroll_y + self.cursor_y - self.inside.height as i32 + 1;
-                    self.cursor_y = self.inside.height as i32 - 1;
-                    self.scroll_y = std::cmp::min(diff, max - self.inside.height as i32);
+                // Scrolls by as much as the cursor went past either margin
+                let mut scroll = self.scroll_y;
+                if position - scroll < off {
+                    scroll = position - off;
+                } else if position - scroll > height - 1 - off {
+                    scroll = position - (height - 1 - off);
                 }
+                self.scroll_y = scroll.min(max - height).max(0);
+                self.cursor_y = position - self.scroll_y;
             }
             ActivePanel::Bookmarks => {
                 self.bookmark_y = std::cmp::min(self.bookmark_y + y, max - 1);
 
                 if self.bookmark_y < 0 {
+                    self.bookmark_scroll_y =
+                        std::cmp::max(self.bookmark_scroll_y + self.bookmark_y, 0);
                     self.bookmark_y = 0;
-                    self.bookmark_scroll_y = self.bookmark_y + y;
-
-                    if self.bookmark_scroll_y < 0 {
-                        self.bookmark_scroll_y = 0;
-                    }
                 } else if self.bookmark_y >= self.inside.height as i32 {
                     self.bookmark_y = self.inside.height as i32 - 1;
                     self.bookmark_scroll_y =
                         std::cmp::min(self.bookmark_scroll_y + y, max - self.inside.height as i32);
                 }
             }
+            ActivePanel::Devices => {
+                self.device_y = (self.device_y + y).min(max - 1).max(0);
+            }
+            ActivePanel::Jobs => {
+                self.job_y = (self.job_y + y).min(max - 1).max(0);
+            }
+            ActivePanel::Selection => {
+                self.selection_y = (self.selection_y + y).min(max - 1).max(0);
+            }
+        }
+    }
+
+    /// Moves the cursor `y` entries, continuing from the other end of the
+    /// list when `wrap` is set and the move would leave it.
+    pub(crate) fn step(&mut self, y: i32, max: i32, active_panel: &ActivePanel, wrap: bool) {
+        let position = match active_panel {
+            ActivePanel::Main => self.scroll_y + self.cursor_y,
+            ActivePanel::Bookmarks => self.bookmark_scroll_y + self.bookmark_y,
+            ActivePanel::Devices => self.device_y,
+            ActivePanel::Jobs => self.job_y,
+            ActivePanel::Selection => self.selection_y,
+        };
+
+        if wrap && max > 0 && position + y >= max {
+            self.scroll(-max, max, active_panel);
+        } else if wrap && max > 0 && position + y < 0 {
+            self.scroll(max, max, active_panel);
+        } else {
+            self.scroll(y, max, active_panel);
         }
     }
 
+    /// How many rows of a popup fit on screen.
+    pub(crate) fn popup_rows(&self) -> usize {
+        self.inside.height.saturating_sub(2) as usize
+    }
+
+    /// A rectangle of at most `width` by `height` centered in the main area.
+    fn popup_area(&self, width: u16, height: u16) -> Rect {
+        let width = std::cmp::min(width, self.inside.width);
+        let height = std::cmp::min(height, self.inside.height);
+        Rect {
+            x: self.inside.x + (self.inside.width - width) / 2,
+            y: self.inside.y + (self.inside.height - height) / 2,
+            width,
+            height,
+        }
+    }
+
+    /// The panel at `column` and `row` of the screen, and which of its rows
+    /// that is counting from the top of the panel.
+    pub(crate) fn panel_at(&self, column: u16, row: u16) -> Option<(ActivePanel, i32)> {
+        let contains = |area: Rect| {
+            column >= area.x
+                && column < area.x + area.width
+                && row >= area.y
+                && row < area.y + area.height
+        };
+        if contains(self.list_area) {
+            return Some((ActivePanel::Main, (row - self.list_area.y) as i32));
+        }
+        if contains(self.bookmark_area) {
+            return Some((ActivePanel::Bookmarks, (row - self.bookmark_area.y) as i32));
+        }
+        match self.device_area {
+            // The first row is the title
+            Some(area) if contains(area) && row > area.y => {
+                Some((ActivePanel::Devices, (row - area.y - 1) as i32))
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether `column` and `row` are on the divider between the bookmarks
+    /// and the file list.
+    pub(crate) fn on_divider(&self, column: u16, row: u16) -> bool {
+        column + 1 == self.list_area.x
+            && row >= self.list_area.y
+            && row < self.list_area.y + self.list_area.height
+    }
+
+    /// Sets the width of the bookmark panel, leaving room for the file list.
+    pub(crate) fn resize_bookmarks(&mut self, width: u16) {
+        let mut width = std::cmp::max(width, MIN_BOOKMARK_WIDTH);
+        // Nothing is known about the room before the first draw
+        let room = self.inside.width.saturating_sub(MIN_LIST_WIDTH);
+        if room >= MIN_BOOKMARK_WIDTH {
+            width = std::cmp::min(width, room);
+        }
+        self.bookmark_width = width;
+    }
+
     pub(crate) fn scroll_abs(&mut self, y: i32, max: i32, active_panel: &ActivePanel) {
         self.cursor_y = 0;
         self.scroll_y = 0;
@@ -294,11 +1009,125 @@ impl Ui {
     }
 }
 
+/// Renders a job as e.g. `Copying foo [====      ] 40% 2.0G/5.0G 12.5M/s 0:42`,
+/// with the number of other running jobs appended.
+fn format_progress(job: &Job, job_count: usize) -> String {
+    const BAR_WIDTH: usize = 10;
+
+    let fraction = job.fraction();
+    let filled = ((fraction * BAR_WIDTH as f64) as usize).min(BAR_WIDTH);
+    let mut progress = format!(
+        "{} [{}{}] {:.0}%",
+        job.description,
+        "=".repeat(filled),
+        " ".repeat(BAR_WIDTH - filled),
+        fraction * 100.0
+    );
+    if job.total > 0 {
+        progress.push_str(&format!(
+            " {}/{}",
+            human_size(job.done),
+            human_size(job.total)
+        ));
+    }
+    if let Some(rate) = job.throughput.rate() {
+        progress.push_str(&format!(" {}/s", human_size(rate as u64)));
+    }
+    if let Some(eta) = job.eta() {
+        progress.push_str(&format!(" {}", format_eta(eta)));
+    }
+    if job_count > 1 {
+        progress.push_str(&format!(" (+{})", job_count - 1));
+    }
+    progress
+}
+
+/// A row of the jobs panel, e.g. `failed    Copying foo: Permission denied`.
+fn format_job(job: &Job) -> String {
+    match &job.state {
+        JobState::Queued => format!("queued    {}", job.description),
+        JobState::Running => format!("running   {}", format_progress(job, 1)),
+        JobState::Done => format!("done      {}", job.description),
+        JobState::Failed(e) => format!("failed    {}: {}", job.description, e),
+        JobState::Cancelled => format!("cancelled {}", job.description),
+    }
+}
+
+/// `text` after `prefix`, and the column the terminal cursor goes in when it
+/// is after `cursor` characters of `text`. Wide characters take up two.
+fn edited_line(prefix: String, text: &str, cursor: usize) -> (Line<'static>, u16) {
+    let before: String = text.chars().take(cursor).collect();
+    let column = Span::raw(format!("{}{}", prefix, before)).width() as u16;
+    (Line::from(prefix + text), column)
+}
+
+/// The rows of `popup` with the labels lined up in a bold column.
+fn popup_lines(popup: &Popup) -> Vec<Line<'_>> {
+    let label_width = popup
+        .rows
+        .iter()
+        .map(|(l, _)| l.chars().count())
+        .max()
+        .unwrap_or(0)
+        + 2;
+    popup
+        .rows
+        .iter()
+        .map(|(label, value)| {
+            Line::from(vec![
+                Span::styled(
+                    format!("{:<width$}", label, width = label_width),
+                    Style::default().add_modifier(Modifier::BOLD),
+                ),
+                Span::raw(value.as_str()),
+            ])
+        })
+        .collect()
+}
+
+fn popup_block(title: &str) -> Block<'_> {
+    Block::default()
+        .title(Span::styled(
+            title,
+            Style::default().add_modifier(Modifier::BOLD),
+        ))
+        .borders(Borders::ALL)
+}
+
 #[cfg(test)]
 mod tests {
+    use ratatui::layout::Rect;
+
     use crate::app::ActivePanel;
 
-    use super::Ui;
+    use super::{
+        format_job, format_progress, line_number, truncate, Level, SelectionStats, Ui,
+        MESSAGE_HISTORY,
+    };
+    use crate::jobs::{Job, JobState, Task};
+
+    #[test]
+    fn progress_shows_the_bytes_done() {
+        let task = Task::Delete { paths: Vec::new() };
+        let mut job = Job::new(0, String::from("Deleting foo"), Vec::new(), task);
+        job.update(512, 2048);
+        assert_eq!(
+            format_progress(&job, 2),
+            "Deleting foo [==        ] 25% 512B/2.0K (+1)"
+        );
+    }
+
+    #[test]
+    fn failed_jobs_show_their_error() {
+        let task = Task::Delete { paths: Vec::new() };
+        let mut job = Job::new(0, String::from("Deleting foo"), Vec::new(), task);
+        assert_eq!(format_job(&job), "queued    Deleting foo");
+        job.state = JobState::Failed(String::from("Permission denied"));
+        assert_eq!(
+            format_job(&job),
+            "failed    Deleting foo: Permission denied"
+        );
+    }
 
     #[test]
     fn scroll_past_end() {
@@ -319,4 +1148,103 @@ mod tests {
             ui.scroll_y + ui.cursor_y
         );
     }
+
+    #[test]
+    fn long_names_are_cut_at_a_column() {
+        assert_eq!(truncate("notes.txt", 9), "notes.txt");
+        assert_eq!(truncate("notes.txt", 6), "notes…");
+        assert_eq!(truncate("notes.txt", 0), "");
+        // Two columns each, so a third would not fit before the ellipsis
+        assert_eq!(truncate("日本語のファイル", 7), "日本語…");
+        assert_eq!(truncate("日本語のファイル", 8), "日本語…");
+        assert_eq!(truncate("cafe\u{301}s and more", 6), "cafe\u{301}s…");
+    }
+
+    #[test]
+    fn entries_are_numbered_like_lines_in_vim() {
+        assert_eq!(line_number(4, 2, false, false), None);
+        assert_eq!(line_number(4, 2, true, false), Some(5));
+        assert_eq!(line_number(4, 2, false, true), Some(2));
+        assert_eq!(line_number(0, 2, false, true), Some(2));
+        assert_eq!(line_number(2, 2, false, true), Some(0));
+        assert_eq!(line_number(2, 2, true, true), Some(3));
+    }
+
+    #[test]
+    fn unknown_directory_sizes_are_marked() {
+        let mut stats = SelectionStats {
+            count: 3,
+            size: 2048,
+            partial: false,
+        };
+        assert_eq!(stats.to_string(), "3 selected, 2.0K");
+        stats.partial = true;
+        assert_eq!(stats.to_string(), "3 selected, 2.0K+");
+    }
+
+    #[test]
+    fn the_cursor_keeps_its_distance_from_the_edges() {
+        let mut ui = Ui::new(".");
+        ui.inside.height = 10;
+        ui.scrolloff = 3;
+
+        ui.scroll(6, 100, &ActivePanel::Main);
+        assert_eq!((ui.scroll_y, ui.cursor_y), (0, 6));
+        ui.scroll(1, 100, &ActivePanel::Main);
+        assert_eq!((ui.scroll_y, ui.cursor_y), (1, 6));
+        ui.scroll(-4, 100, &ActivePanel::Main);
+        assert_eq!((ui.scroll_y, ui.cursor_y), (0, 3));
+        // Except at either end of the list
+        ui.scroll(100, 100, &ActivePanel::Main);
+        assert_eq!((ui.scroll_y, ui.cursor_y), (90, 9));
+        ui.scroll(-100, 100, &ActivePanel::Main);
+        assert_eq!((ui.scroll_y, ui.cursor_y), (0, 0));
+    }
+
+    #[test]
+    fn clicks_are_found_in_their_panel() {
+        let mut ui = Ui::new(".");
+        ui.bookmark_area = Rect::new(2, 1, 15, 10);
+        ui.device_area = Some(Rect::new(2, 11, 15, 3));
+        ui.list_area = Rect::new(18, 1, 40, 13);
+
+        assert_eq!(ui.panel_at(20, 1), Some((ActivePanel::Main, 0)));
+        assert_eq!(ui.panel_at(57, 5), Some((ActivePanel::Main, 4)));
+        assert_eq!(ui.panel_at(2, 3), Some((ActivePanel::Bookmarks, 2)));
+        // The title of the devices
+        assert_eq!(ui.panel_at(2, 11), None);
+        assert_eq!(ui.panel_at(2, 12), Some((ActivePanel::Devices, 0)));
+        assert_eq!(ui.panel_at(17, 3), None);
+        assert_eq!(ui.panel_at(20, 14), None);
+    }
+
+    #[test]
+    fn bookmarks_leave_room_for_the_file_list() {
+        let mut ui = Ui::new(".");
+        ui.resize_bookmarks(30);
+        assert_eq!(ui.bookmark_width, 30);
+        ui.resize_bookmarks(1);
+        assert_eq!(ui.bookmark_width, 5);
+
+        ui.inside.width = 40;
+        ui.resize_bookmarks(30);
+        assert_eq!(ui.bookmark_width, 20);
+    }
+
+    #[test]
+    fn only_recent_messages_are_kept() {
+        let mut ui = Ui::new(".");
+        for i in 0..MESSAGE_HISTORY + 5 {
+            ui.info(format!("message {}", i));
+        }
+        ui.error("failed");
+
+        assert_eq!(ui.messages.len(), MESSAGE_HISTORY);
+        assert_eq!(ui.messages[0].1, "message 6");
+        assert_eq!(
+            ui.messages.back(),
+            Some(&(Level::Error, String::from("failed")))
+        );
+        assert_eq!(ui.message_level, Level::Error);
+    }
 }
@@ -0,0 +1,276 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File, Metadata},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+/// A directory entry as listed by a `Vfs`, with everything the file list
+/// needs to draw it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub path: PathBuf,
+    /// Whether the entry is a directory or a symlink to one
+    pub is_dir: bool,
+    pub is_symlink: bool,
+    pub link_target: Option<PathBuf>,
+    pub broken_link: bool,
+    pub len: u64,
+    pub executable: bool,
+    /// Whether we may not write to the entry, as opposed to nobody at all
+    pub read_only: bool,
+    /// Dotfiles, and on Windows and macOS entries with the hidden attribute
+    pub hidden: bool,
+    /// Ignored by git, for the `gitignore` setting to dim. Set by the file
+    /// list rather than the `Vfs`.
+    pub ignored: bool,
+}
+
+impl Entry {
+    pub fn path(&self) -> PathBuf {
+        self.path.clone()
+    }
+
+    pub fn file_name(&self) -> OsString {
+        self.path.file_name().unwrap_or_default().to_owned()
+    }
+}
+
+/// Where directory listings come from and where file operations go. The
+/// local file system is the default, while other backends such as archives
+/// or remote hosts implement the same operations for their own paths.
+pub trait Vfs {
+    /// The entries of `dir` in no particular order.
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<Entry>>;
+
+    /// The entry at `path`, which is not followed if it is a symlink.
+    fn entry(&self, path: &Path) -> io::Result<Entry>;
+
+    /// Creates `path` along with any missing parents.
+    fn create_dir(&self, path: &Path) -> io::Result<()>;
+
+    /// Creates an empty file unless one by that name already exists.
+    fn create_file(&self, path: &Path) -> io::Result<()>;
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + '_>>;
+
+    /// Opens `path` for writing, creating or truncating it.
+    fn create(&self, path: &Path) -> io::Result<Box<dyn Write + '_>>;
+
+    /// Removes `path` and, for directories, everything below it. Symlinks are
+    /// removed rather than their targets.
+    fn remove(&self, path: &Path) -> io::Result<()>;
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.entry(path).map(|e| e.is_dir).unwrap_or(false)
+    }
+}
+
+pub struct LocalFs;
+
+impl Vfs for LocalFs {
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<Entry>> {
+        fs::read_dir(dir)?.map(|e| self.entry(&e?.path())).collect()
+    }
+
+    fn entry(&self, path: &Path) -> io::Result<Entry> {
+        let md = fs::symlink_metadata(path)?;
+        let is_symlink = md.file_type().is_symlink();
+        // The kind of a symlink is that of its target, if there is one
+        let target = if is_symlink {
+            fs::metadata(path).ok()
+        } else {
+            Some(md.clone())
+        };
+
+        Ok(Entry {
+            path: path.to_path_buf(),
+            is_dir: target.as_ref().is_some_and(|t| t.is_dir()),
+            is_symlink,
+            link_target: if is_symlink {
+                fs::read_link(path).ok()
+            } else {
+                None
+            },
+            broken_link: is_symlink && target.is_none(),
+            len: md.len(),
+            executable: target.as_ref().is_some_and(|t| is_executable(path, t)),
+            read_only: is_read_only(path),
+            hidden: is_dotfile(path) || has_hidden_attribute(&md),
+            ignored: false,
+        })
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn create_file(&self, path: &Path) -> io::Result<()> {
+        fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)?;
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + '_>> {
+        Ok(Box::new(File::open(path)?))
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn Write + '_>> {
+        Ok(Box::new(File::create(path)?))
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        if fs::symlink_metadata(path)?.is_dir() {
+            fs::remove_dir_all(path)
+        } else {
+            fs::remove_file(path)
+        }
+    }
+}
+
+/// Copies `from` in `src` to `to` in `dest`, including everything below it
+/// for directories. Symlinks to files are copied as files, while symlinks to
+/// directories are skipped so that link cycles can't recurse forever.
+pub fn copy_between(src: &dyn Vfs, from: &Path, dest: &dyn Vfs, to: &Path) -> io::Result<()> {
+    let entry = src.entry(from)?;
+    if entry.is_dir {
+        if !entry.is_symlink {
+            dest.create_dir(to)?;
+            for child in src.read_dir(from)? {
+                copy_between(src, &child.path, dest, &to.join(child.file_name()))?;
+            }
+        }
+        Ok(())
+    } else {
+        io::copy(&mut src.open(from)?, &mut dest.create(to)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(_path: &Path, md: &Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    md.is_file() && md.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path, md: &Metadata) -> bool {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) => {
+            md.is_file()
+                && ["exe", "bat", "cmd", "com", "ps1"].contains(&ext.to_lowercase().as_str())
+        }
+        None => false,
+    }
+}
+
+/// Whether the name of `path` starts with a dot, which hides it on Unix.
+pub fn is_dotfile(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+}
+
+#[cfg(windows)]
+fn has_hidden_attribute(md: &Metadata) -> bool {
+    use std::os::windows::fs::MetadataExt;
+
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+    md.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0
+}
+
+// Set by `chflags hidden`, which is how Finder hides entries
+#[cfg(target_os = "macos")]
+fn has_hidden_attribute(md: &Metadata) -> bool {
+    use std::os::macos::fs::MetadataExt;
+
+    const UF_HIDDEN: u32 = 0x8000;
+    md.st_flags() & UF_HIDDEN != 0
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+fn has_hidden_attribute(_md: &Metadata) -> bool {
+    false
+}
+
+/* Permissions::readonly only checks if anyone at all may write to the file,
+ * which says nothing about whether we may.
+ */
+#[cfg(unix)]
+pub fn is_read_only(path: &Path) -> bool {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    match CString::new(path.as_os_str().as_bytes()) {
+        Ok(c_path) => unsafe { libc::access(c_path.as_ptr(), libc::W_OK) != 0 },
+        Err(_) => false,
+    }
+}
+
+#[cfg(not(unix))]
+pub fn is_read_only(path: &Path) -> bool {
+    fs::symlink_metadata(path)
+        .map(|md| md.permissions().readonly())
+        .unwrap_or(false)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{fs, os::unix::fs::symlink};
+
+    use super::{copy_between, LocalFs, Vfs};
+
+    #[test]
+    fn symlinks_are_listed_as_their_targets() {
+        let root = std::env::temp_dir().join(format!("trooper_vfs_{}", std::process::id()));
+        fs::create_dir_all(root.join("dir")).unwrap();
+        symlink("dir", root.join("to_dir")).unwrap();
+        symlink("missing", root.join("broken")).unwrap();
+
+        let to_dir = LocalFs.entry(&root.join("to_dir")).unwrap();
+        assert!(to_dir.is_dir && to_dir.is_symlink && !to_dir.broken_link);
+        assert_eq!(to_dir.link_target.as_deref(), Some("dir".as_ref()));
+
+        let broken = LocalFs.entry(&root.join("broken")).unwrap();
+        assert!(!broken.is_dir && broken.broken_link);
+        assert!(!broken.hidden);
+        fs::write(root.join(".hidden"), "").unwrap();
+        assert!(LocalFs.entry(&root.join(".hidden")).unwrap().hidden);
+        fs::remove_file(root.join(".hidden")).unwrap();
+
+        let mut names: Vec<_> = LocalFs
+            .read_dir(&root)
+            .unwrap()
+            .iter()
+            .map(|e| e.file_name())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["broken", "dir", "to_dir"]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn directories_are_copied_without_following_linked_directories() {
+        let root = std::env::temp_dir().join(format!("trooper_vfs_copy_{}", std::process::id()));
+        fs::create_dir_all(root.join("dir/nested")).unwrap();
+        fs::write(root.join("dir/nested/file"), "contents").unwrap();
+        symlink("..", root.join("dir/cycle")).unwrap();
+
+        copy_between(&LocalFs, &root.join("dir"), &LocalFs, &root.join("copy")).unwrap();
+        assert_eq!(
+            fs::read_to_string(root.join("copy/nested/file")).unwrap(),
+            "contents"
+        );
+        assert!(!root.join("copy/cycle").exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,61 @@
+use std::sync::{
+    mpsc::{self, Receiver, SendError},
+    Arc,
+};
+
+use tokio::sync::Notify;
+
+/// Wakes the event loop, so that work finished in the background is shown
+/// right away rather than on the next tick.
+#[derive(Debug, Clone, Default)]
+pub struct Waker(Arc<Notify>);
+
+impl Waker {
+    pub fn wake(&self) {
+        self.0.notify_one();
+    }
+
+    /// Resolves once woken. A wake while nobody was waiting is kept for the
+    /// next call, so none are lost.
+    pub async fn woken(&self) {
+        self.0.notified().await
+    }
+}
+
+/// The sending half of a channel which wakes the event loop on every send.
+#[derive(Debug)]
+pub struct Sender<T> {
+    sender: mpsc::Sender<T>,
+    waker: Waker,
+}
+
+impl<T> Sender<T> {
+    pub fn send(&self, t: T) -> Result<(), SendError<T>> {
+        self.sender.send(t)?;
+        self.waker.wake();
+        Ok(())
+    }
+}
+
+// Derived, this would require T to be Clone
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        Sender {
+            sender: self.sender.clone(),
+            waker: self.waker.clone(),
+        }
+    }
+}
+
+/// A channel like `mpsc::channel`, whose receiver is polled by the App once
+/// `waker` has woken the event loop.
+pub fn channel<T>(waker: &Waker) -> (Sender<T>, Receiver<T>) {
+    let (sender, receiver) = mpsc::channel();
+    (
+        Sender {
+            sender,
+            waker: waker.clone(),
+        },
+        receiver,
+    )
+}
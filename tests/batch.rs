@@ -0,0 +1,31 @@
+use std::fs;
+
+use trooper::app::{App, Options};
+
+#[test]
+fn files_are_moved_by_a_script() {
+    let dir = std::env::temp_dir().join(format!("trooper_script_{}", std::process::id()));
+    fs::create_dir_all(dir.join("archive")).unwrap();
+    fs::write(dir.join("notes.txt"), "notes").unwrap();
+    let mut app = App::new(
+        String::from("test"),
+        &dir,
+        Options {
+            deterministic: true,
+            ..Default::default()
+        },
+    );
+    app.init();
+
+    let script = "select notes.txt\ncut\ncd archive\npaste\n";
+    assert_eq!(app.run_batch(script), Ok(()));
+    assert!(!dir.join("notes.txt").exists());
+    assert_eq!(
+        fs::read_to_string(dir.join("archive").join("notes.txt")).unwrap(),
+        "notes"
+    );
+    assert_eq!(*app.current_dir, dir.join("archive"));
+
+    app.tear_down();
+    fs::remove_dir_all(&dir).unwrap();
+}